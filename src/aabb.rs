@@ -0,0 +1,127 @@
+use crate::interval::{interval, Interval, INTERVAL_EMPTY, INTERVAL_UNIVERSE};
+use crate::ray::Ray;
+use crate::vec3::*;
+
+pub fn aabb(x: Interval, y: Interval, z: Interval) -> Aabb {
+    Aabb { x, y, z }
+}
+
+/// Axis-aligned bounding box, given by an interval on each axis.
+#[derive(Debug, Clone, Copy)]
+pub struct Aabb {
+    pub x: Interval,
+    pub y: Interval,
+    pub z: Interval,
+}
+
+impl Default for Aabb {
+    fn default() -> Self {
+        AABB_EMPTY
+    }
+}
+
+impl Aabb {
+    pub fn from_points(a: &Vec3, b: &Vec3) -> Aabb {
+        // Treat the two points as extrema of the bounding box, so we don't require a particular
+        // minimum/maximum coordinate order.
+        aabb(
+            interval(a.x().min(b.x()), a.x().max(b.x())),
+            interval(a.y().min(b.y()), a.y().max(b.y())),
+            interval(a.z().min(b.z()), a.z().max(b.z())),
+        )
+    }
+
+    pub fn from_boxes(a: &Aabb, b: &Aabb) -> Aabb {
+        aabb(
+            Interval::enclosing(&a.x, &b.x),
+            Interval::enclosing(&a.y, &b.y),
+            Interval::enclosing(&a.z, &b.z),
+        )
+    }
+
+    pub fn padded(&self) -> Aabb {
+        // Flat boxes, like the ones of axis aligned triangles, are given a minimal thickness so
+        // rays can still hit them.
+        let delta = 1e-4;
+        let pad = |x: Interval| if x.size() < delta { x.expand(delta) } else { x };
+        aabb(pad(self.x), pad(self.y), pad(self.z))
+    }
+
+    pub fn longest_axis(&self) -> usize {
+        if self.x.size() > self.y.size() {
+            if self.x.size() > self.z.size() {
+                0
+            } else {
+                2
+            }
+        } else if self.y.size() > self.z.size() {
+            1
+        } else {
+            2
+        }
+    }
+
+    pub fn axis_interval(&self, n: usize) -> Interval {
+        match n {
+            1 => self.y,
+            2 => self.z,
+            _ => self.x,
+        }
+    }
+
+    pub fn transformed(&self, f: impl Fn(&Vec3) -> Vec3) -> Aabb {
+        // The box around the eight transformed corners, for affine transformations `f`.
+        let mut bbox = AABB_EMPTY;
+        for corner in 0..8 {
+            let x = if corner & 1 == 0 { self.x.min } else { self.x.max };
+            let y = if corner & 2 == 0 { self.y.min } else { self.y.max };
+            let z = if corner & 4 == 0 { self.z.min } else { self.z.max };
+            let p = f(&point3(x, y, z));
+            bbox = Aabb::from_boxes(&bbox, &Aabb::from_points(&p, &p));
+        }
+        bbox
+    }
+
+    pub fn hit(&self, r: &Ray, mut ray_t: Interval) -> bool {
+        let ray_orig = r.origin();
+        let ray_dir = r.direction();
+
+        for axis in 0..3 {
+            let ax = self.axis_interval(axis);
+            let adinv = 1.0 / ray_dir[axis];
+
+            let t0 = (ax.min - ray_orig[axis]) * adinv;
+            let t1 = (ax.max - ray_orig[axis]) * adinv;
+
+            ray_t.min = ray_t.min.max(t0.min(t1));
+            ray_t.max = ray_t.max.min(t0.max(t1));
+
+            if ray_t.max <= ray_t.min {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+pub const AABB_EMPTY: Aabb = Aabb {
+    x: INTERVAL_EMPTY,
+    y: INTERVAL_EMPTY,
+    z: INTERVAL_EMPTY,
+};
+pub const AABB_UNIVERSE: Aabb = Aabb {
+    x: INTERVAL_UNIVERSE,
+    y: INTERVAL_UNIVERSE,
+    z: INTERVAL_UNIVERSE,
+};
+
+#[test]
+fn aabb_hit_test() {
+    let bbox = Aabb::from_points(&point3(1.0, 1.0, 1.0), &point3(-1.0, -1.0, -1.0));
+    let full = interval(0.001, f64::INFINITY);
+
+    assert!(bbox.hit(&Ray::new(&point3(0.0, 0.0, 5.0), &vec3(0.0, 0.0, -1.0)), full));
+    assert!(!bbox.hit(&Ray::new(&point3(0.0, 2.0, 5.0), &vec3(0.0, 0.0, -1.0)), full));
+    assert!(!bbox.hit(&Ray::new(&point3(0.0, 0.0, 5.0), &vec3(0.0, 0.0, 1.0)), full));
+    assert!(!bbox.hit(&Ray::new(&point3(0.0, 0.0, 5.0), &vec3(0.0, 0.0, -1.0)), interval(0.001, 3.0)));
+}
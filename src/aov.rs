@@ -0,0 +1,242 @@
+use std::io;
+use std::path::Path;
+
+use crate::hittable::HitRecord;
+use crate::image::Image;
+use crate::vec3::*;
+
+/// Arbitrary output variables: additional render passes taken from the first hit of each
+/// camera ray, next to the final beauty image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aov {
+    Depth,      // Distance along the camera view axis, infinite for the background
+    Position,   // World space position
+    Normal,     // World space shading normal, facing the camera
+    Albedo,     // Surface color of the material
+    MaterialId, // Id of the material, 0 for the background
+    ObjectId,   // Index of the object in the world list plus one, 0 for the background
+    Direct,     // Light that reached the camera after at most one scattering event
+    Indirect,   // Light that scattered two or more times
+    Cost,       // Intersection tests and BVH node visits spent on the whole path
+}
+
+pub const ALL_AOVS: [Aov; 9] = [
+    Aov::Depth,
+    Aov::Position,
+    Aov::Normal,
+    Aov::Albedo,
+    Aov::MaterialId,
+    Aov::ObjectId,
+    Aov::Direct,
+    Aov::Indirect,
+    Aov::Cost,
+];
+
+impl Aov {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Depth => "depth",
+            Aov::Position => "position",
+            Aov::Normal => "normal",
+            Aov::Albedo => "albedo",
+            Aov::MaterialId => "material_id",
+            Aov::ObjectId => "object_id",
+            Aov::Direct => "direct",
+            Aov::Indirect => "indirect",
+            Aov::Cost => "cost",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Aov> {
+        ALL_AOVS.iter().copied().find(|aov| aov.name() == name)
+    }
+
+    fn is_splatted(&self) -> bool {
+        // Passes of light, which light traced to the camera adds to any pixel.
+        matches!(self, Aov::Direct | Aov::Indirect)
+    }
+
+    fn is_filtered(&self) -> bool {
+        // Ids can't be blended and an average depth across a silhouette is a depth that
+        // doesn't exist in the scene.
+        !matches!(self, Aov::Depth | Aov::MaterialId | Aov::ObjectId)
+    }
+}
+
+/// Values of all passes for a single camera sample.
+#[derive(Debug, Clone, Copy)]
+pub struct AovSample {
+    pub depth: f64,
+    pub position: Vec3,
+    pub normal: Vec3,
+    pub albedo: Vec3,
+    pub material_id: usize,
+    pub object_id: usize,
+    pub direct: Vec3,
+    pub indirect: Vec3,
+    pub cost: u64,
+}
+
+impl Default for AovSample {
+    fn default() -> Self {
+        Self {
+            depth: f64::INFINITY,
+            position: Vec3::default(),
+            normal: Vec3::default(),
+            albedo: Vec3::default(),
+            material_id: 0,
+            object_id: 0,
+            direct: Vec3::default(),
+            indirect: Vec3::default(),
+            cost: 0,
+        }
+    }
+}
+
+impl AovSample {
+    pub fn record_hit(&mut self, rec: &HitRecord, depth: f64, material_id: usize) {
+        self.depth = depth;
+        self.position = rec.p;
+        self.normal = rec.normal;
+        self.albedo = rec.mat.albedo(rec);
+        self.material_id = material_id;
+        self.object_id = rec.object_id + 1;
+    }
+
+    pub fn add_light(&mut self, depth: usize, light: Vec3) {
+        // Light found at the given path depth, seen directly or after a single bounce counts as
+        // direct light.
+        if is_direct(depth) {
+            self.direct = self.direct + light;
+        } else {
+            self.indirect = self.indirect + light;
+        }
+    }
+
+    pub fn value(&self, aov: Aov) -> Vec3 {
+        match aov {
+            Aov::Depth => vec3(self.depth, self.depth, self.depth),
+            Aov::Position => self.position,
+            Aov::Normal => self.normal,
+            Aov::Albedo => self.albedo,
+            Aov::MaterialId => {
+                let id = self.material_id as f64;
+                vec3(id, id, id)
+            }
+            Aov::ObjectId => {
+                let id = self.object_id as f64;
+                vec3(id, id, id)
+            }
+            Aov::Direct => self.direct,
+            Aov::Indirect => self.indirect,
+            Aov::Cost => {
+                let cost = self.cost as f64;
+                vec3(cost, cost, cost)
+            }
+        }
+    }
+}
+
+/// Output of a render: the beauty image and one image per requested pass.
+#[derive(Debug, Clone)]
+pub struct RenderBuffers {
+    pub beauty: Image,
+    pub aovs: Vec<(Aov, Image)>,
+}
+
+impl RenderBuffers {
+    pub fn new(width: usize, height: usize, aovs: &[Aov]) -> RenderBuffers {
+        RenderBuffers {
+            beauty: Image::new(width, height),
+            aovs: aovs.iter().map(|aov| (*aov, Image::new(width, height))).collect(),
+        }
+    }
+
+    pub fn aov(&self, aov: Aov) -> Option<&Image> {
+        self.aovs.iter().find(|(a, _)| *a == aov).map(|(_, image)| image)
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, beauty: Vec3, samples: &[AovSample]) {
+        // Filtered passes are averaged over all samples of the pixel, the others are taken from
+        // the first sample. Light splatted onto the pixel before is kept.
+        self.beauty.set(x, y, beauty + self.beauty.get(x, y));
+
+        let scale = 1.0 / samples.len().max(1) as f64;
+        for (aov, image) in self.aovs.iter_mut() {
+            let mut value = if aov.is_filtered() {
+                samples.iter().fold(Vec3::default(), |acc, s| acc + s.value(*aov)) * scale
+            } else {
+                samples.first().map(|s| s.value(*aov)).unwrap_or_default()
+            };
+            if aov.is_splatted() {
+                value = value + image.get(x, y);
+            }
+            image.set(x, y, value);
+        }
+    }
+
+    pub fn splat(&mut self, x: usize, y: usize, depth: usize, light: Vec3) {
+        // Adds light traced from the lights to the camera that found its way at the given path
+        // depth to the pixel, also to the light passes.
+        self.beauty.set(x, y, self.beauty.get(x, y) + light);
+        let pass = if is_direct(depth) { Aov::Direct } else { Aov::Indirect };
+        if let Some((_, image)) = self.aovs.iter_mut().find(|(aov, _)| *aov == pass) {
+            image.set(x, y, image.get(x, y) + light);
+        }
+    }
+
+    pub fn write_aovs(&self, prefix: &str) -> io::Result<()> {
+        // Every pass is written as linear float map `<prefix>_<name>.pfm`.
+        for (aov, image) in &self.aovs {
+            let path = format!("{}_{}.pfm", prefix, aov.name());
+            image.write_pfm(Path::new(&path))?;
+        }
+        Ok(())
+    }
+}
+
+fn is_direct(depth: usize) -> bool {
+    depth <= 1
+}
+
+#[test]
+fn direct_plus_indirect_is_beauty_test() {
+    use crate::camera::{Camera, ALL_INTEGRATORS};
+    use crate::hittable::Hittable;
+    use crate::hittable_list::HittableList;
+    use crate::light::point_light;
+    use crate::material::lambertian;
+    use crate::sphere::sphere;
+
+    let material = lambertian(vec3(0.5, 0.5, 0.5));
+    let objects: Vec<Box<dyn Hittable>> = vec![
+        Box::new(sphere(&point3(0.0, 0.0, -1.0), 0.5, &material)),
+        Box::new(sphere(&point3(0.0, -100.5, -1.0), 100.0, &material)),
+    ];
+    let world = HittableList::new(objects);
+
+    let mut cam = Camera::default();
+    cam.image_width = 8;
+    cam.samples_per_pixel = 4;
+    cam.aovs = vec![Aov::Direct, Aov::Indirect, Aov::ObjectId, Aov::Depth];
+    cam.lights = vec![point_light(point3(1.0, 2.0, 0.0), vec3(2.0, 2.0, 2.0))];
+
+    // Also with light traced from the lights and splatted onto the pixels.
+    for integrator in ALL_INTEGRATORS {
+        cam.integrator = integrator;
+        let buffers = cam.render_buffers(&world);
+        let direct = buffers.aov(Aov::Direct).unwrap();
+        let indirect = buffers.aov(Aov::Indirect).unwrap();
+        for (index, beauty) in buffers.beauty.pixels.iter().enumerate() {
+            let sum = direct.pixels[index] + indirect.pixels[index];
+            assert!((sum - *beauty).length() < 1e-9, "{:?} instead of {:?} with {}", sum, beauty, integrator.name());
+        }
+    }
+    let buffers = cam.render_buffers(&world);
+
+    // The center pixel sees the small sphere, the top row only the sky.
+    let object_id = buffers.aov(Aov::ObjectId).unwrap();
+    assert_eq!(object_id.get(4, 4).x(), 1.0);
+    assert_eq!(object_id.get(4, 0).x(), 0.0);
+    assert!((buffers.aov(Aov::Depth).unwrap().get(4, 4).x() - 0.5).abs() < 0.05);
+}
@@ -0,0 +1,147 @@
+use std::f64::consts::PI;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::color::luminance;
+use crate::image::{invalid_data, Image};
+use crate::rtweekend::{degrees_to_radians, random_double};
+use crate::vec3::*;
+
+/// Shape of the lens opening. Samples are returned in the unit disk and are scaled by the
+/// camera's defocus disk vectors, so the aperture size stays controlled by `defocus_angle`.
+#[derive(Debug, Clone, Default)]
+pub enum Aperture {
+    #[default]
+    Circular,
+    // Regular polygon with the rotation in degrees, circular with less than three blades.
+    Polygon { blades: usize, rotation: f64 },
+    Mask(Arc<ApertureMask>),
+}
+
+pub fn polygon_aperture(blades: usize, rotation: f64) -> Aperture {
+    // A polygon needs at least three blades, anything below falls back to a circle.
+    if blades < 3 {
+        return Aperture::Circular;
+    }
+    Aperture::Polygon { blades, rotation }
+}
+
+pub fn mask_aperture(path: impl AsRef<Path>) -> io::Result<Aperture> {
+    Ok(Aperture::Mask(Arc::new(ApertureMask::read_ppm(path)?)))
+}
+
+impl Aperture {
+    pub fn sample(&self) -> Vec3 {
+        match self {
+            Aperture::Polygon { blades, rotation } if *blades >= 3 => {
+                random_in_polygon(*blades, degrees_to_radians(*rotation))
+            }
+            Aperture::Circular | Aperture::Polygon { .. } => random_in_unit_disk(),
+            Aperture::Mask(mask) => mask.sample(),
+        }
+    }
+}
+
+fn random_in_polygon(blades: usize, rotation: f64) -> Vec3 {
+    // The regular polygon is made of `blades` congruent triangles around the center, so pick
+    // one uniformly and then sample a point uniformly inside it.
+    let k = ((random_double() * blades as f64) as usize).min(blades - 1);
+    let phi_0 = rotation + 2.0 * PI * k as f64 / blades as f64;
+    let phi_1 = rotation + 2.0 * PI * (k + 1) as f64 / blades as f64;
+    let a = vec3(phi_0.cos(), phi_0.sin(), 0.0);
+    let b = vec3(phi_1.cos(), phi_1.sin(), 0.0);
+
+    let mut s = random_double();
+    let mut t = random_double();
+    if s + t > 1.0 {
+        s = 1.0 - s;
+        t = 1.0 - t;
+    }
+    s * a + t * b
+}
+
+/// Image based aperture. The image covers the square [-1,1]x[-1,1] of the lens and its
+/// luminance is used as the transmission of the aperture at that point.
+#[derive(Debug)]
+pub struct ApertureMask {
+    width: usize,
+    height: usize,
+    cdf: Vec<f64>,
+}
+
+impl ApertureMask {
+    pub fn read_ppm(path: impl AsRef<Path>) -> io::Result<ApertureMask> {
+        ApertureMask::from_image(&Image::read_ppm(path)?)
+    }
+
+    pub fn from_image(image: &Image) -> io::Result<ApertureMask> {
+        let mut cdf = Vec::with_capacity(image.pixels.len());
+        let mut total = 0.0;
+        for pixel in &image.pixels {
+            total += luminance(pixel).max(0.0);
+            cdf.push(total);
+        }
+
+        if total <= 0.0 {
+            return Err(invalid_data("aperture mask is completely black".to_string()));
+        }
+        for value in cdf.iter_mut() {
+            *value /= total;
+        }
+
+        Ok(ApertureMask {
+            width: image.width,
+            height: image.height,
+            cdf,
+        })
+    }
+
+    pub fn sample(&self) -> Vec3 {
+        // Choose a pixel proportional to its transmission and jitter inside of it.
+        let xi = random_double();
+        let index = self.cdf.partition_point(|&c| c < xi).min(self.cdf.len() - 1);
+        let px = (index % self.width) as f64 + random_double();
+        let py = (index / self.width) as f64 + random_double();
+
+        // Image rows run downwards while the lens v axis points up.
+        vec3(
+            2.0 * px / self.width as f64 - 1.0,
+            1.0 - 2.0 * py / self.height as f64,
+            0.0,
+        )
+    }
+}
+
+#[test]
+fn polygon_aperture_test() {
+    // Every sample of a square aperture rotated by 45 degrees lies inside |x| + |y| <= 1.
+    let aperture = polygon_aperture(4, 0.0);
+    for _ in 0..1000 {
+        let p = aperture.sample();
+        assert!(p.x().abs() + p.y().abs() <= 1.0 + 1e-9);
+    }
+
+    // Built directly the rotation is in degrees as well, and too few blades give a circle.
+    let aperture = Aperture::Polygon { blades: 4, rotation: 45.0 };
+    for _ in 0..1000 {
+        let p = aperture.sample();
+        assert!(p.x().abs().max(p.y().abs()) <= 0.5f64.sqrt() + 1e-9);
+    }
+    for blades in 0..3 {
+        let p = Aperture::Polygon { blades, rotation: 0.0 }.sample();
+        assert!(p.length() <= 1.0);
+    }
+}
+
+#[test]
+fn mask_aperture_test() {
+    // Only the left column of the mask is open.
+    let image = Image::parse_ppm(b"P2 2 2 1\n1 0\n1 0\n").unwrap();
+    let mask = ApertureMask::from_image(&image).unwrap();
+    for _ in 0..1000 {
+        let p = mask.sample();
+        assert!(p.x() <= 0.0 && p.x() >= -1.0);
+        assert!(p.y().abs() <= 1.0);
+    }
+}
@@ -0,0 +1,720 @@
+use std::f64::consts::PI;
+
+use crate::aov::{AovSample, RenderBuffers};
+use crate::camera::{Camera, MAX_BOUNCES};
+use crate::color::color;
+use crate::hittable::{HitRecord, Hittable};
+use crate::hittable_list::HittableList;
+use crate::interval::interval;
+use crate::light::Light;
+use crate::light_sampler::AliasTable;
+use crate::ray::Ray;
+use crate::rtweekend::random_double;
+use crate::stats::{count_path, count_ray, RayKind};
+use crate::vec3::*;
+
+/// Bidirectional path tracing after Veach, "Robust Monte Carlo Methods for Light Transport
+/// Simulation" (1997), as in pbrt-v3. Every camera sample traces a path from the camera and one
+/// from a light, and connects each vertex of the one to each vertex of the other. The light of
+/// all ways to build a path from the two is weighted with the balance heuristic, so caustics
+/// are found from the lights while diffuse light is still found from the camera. Paths from
+/// the lights that are connected to the camera land on other pixels of the image.
+///
+/// Light paths scatter off the materials the way camera paths do, which assumes that the
+/// materials reflect the same both ways. Fuzzy metal and coatings only roughly do.
+pub struct Bidirectional<'a> {
+    camera: &'a Camera,
+    lights: &'a [Light],
+    light_distribution: AliasTable, // Lights chosen by power to start paths at and to connect to
+    view_axis: Vec3,
+    scene_center: Vec3,
+    scene_radius: f64,
+}
+
+#[derive(Clone)]
+enum VertexKind {
+    Camera,
+    Light(usize),                               // Index of the light
+    Surface { rec: Box<HitRecord>, r_in: Ray }, // Scattering at a surface or in a volume, reached by `r_in`
+    Escaped,                                    // Camera ray leaving the scene, one unit along the ray
+}
+
+#[derive(Clone)]
+struct Vertex {
+    kind: VertexKind,
+    p: Vec3,
+    ng: Vec3,     // Geometric normal on surfaces, zero everywhere else
+    beta: Vec3,   // Throughput of the subpath up to and including the vertex
+    delta: bool,  // Specular scattering, which can't be connected to
+    pdf_fwd: f64, // Density of sampling the vertex by its own subpath, per area
+    pdf_rev: f64, // Density of sampling it by the other subpath, per area
+}
+
+impl Vertex {
+    fn densities(&self) -> (f64, f64, bool) {
+        (self.pdf_fwd, self.pdf_rev, self.delta)
+    }
+
+    fn is_on_surface(&self) -> bool {
+        !self.ng.near_zero()
+    }
+}
+
+impl<'a> Bidirectional<'a> {
+    pub fn new(camera: &'a Camera, world: &HittableList) -> Bidirectional<'a> {
+        let bounds = world.bounding_box();
+        let corner = |interval: crate::interval::Interval| (interval.min, interval.max);
+        let ((x0, x1), (y0, y1), (z0, z1)) = (corner(bounds.x), corner(bounds.y), corner(bounds.z));
+        let scene_center = point3((x0 + x1) / 2.0, (y0 + y1) / 2.0, (z0 + z1) / 2.0);
+        let scene_radius = vec3(x1 - x0, y1 - y0, z1 - z0).length() / 2.0;
+
+        let lights = camera.sampled_lights();
+        let powers: Vec<f64> = lights.iter().map(|light| light.power(scene_radius)).collect();
+        Bidirectional {
+            camera,
+            lights,
+            light_distribution: AliasTable::new(&powers),
+            view_axis: unit_vector(&(camera.look_at - camera.look_from)),
+            scene_center,
+            scene_radius,
+        }
+    }
+
+    pub fn radiance(&self, r: &Ray, world: &HittableList, mut aov: Option<&mut AovSample>, splats: &mut RenderBuffers) -> Vec3 {
+        // Light arriving along the camera ray. Light traced to the camera is splatted onto
+        // `splats` instead, scaled for the samples per pixel. If given, `aov` receives the
+        // first hit and the light split into direct and indirect.
+        // Subpaths end by russian roulette, the depth is only limited as a safety net.
+        let max_depth = MAX_BOUNCES;
+        let pinhole = self.camera.is_pinhole();
+        let mut camera_path = vec![Vertex {
+            kind: VertexKind::Camera,
+            p: r.origin(),
+            ng: Vec3::default(),
+            beta: color(1.0, 1.0, 1.0),
+            // Lenses can't be reached from the lights.
+            delta: !pinhole,
+            pdf_fwd: 0.0,
+            pdf_rev: 0.0,
+        }];
+        let pdf = if pinhole { self.camera.direction_pdf(&r.direction()) } else { 0.0 };
+        let mut segments =
+            self.random_walk(world, *r, color(1.0, 1.0, 1.0), pdf, max_depth + 2, &mut camera_path, aov.as_deref_mut());
+        let mut light_path = Vec::new();
+        segments += self.light_subpath(world, max_depth + 1, &mut light_path);
+        count_path(segments);
+
+        // Connecting to a light sampled at the camera vertex works without a light subpath.
+        let max_s = light_path.len().max(usize::from(!self.lights.is_empty()));
+        let mut radiance = color(0.0, 0.0, 0.0);
+        for t in 1..=camera_path.len() {
+            for s in 0..=max_s {
+                let depth = (s + t) as isize - 2;
+                if (s == 1 && t == 1) || depth < 0 || depth > max_depth as isize {
+                    continue;
+                }
+                if t == 1 {
+                    if let Some((x, y, light)) = self.connect_to_camera(world, &camera_path, &light_path, s) {
+                        splats.splat(x, y, depth as usize, light * self.camera.pixel_samples_scale());
+                    }
+                    continue;
+                }
+                let light = self.connect(world, &camera_path, &light_path, s, t);
+                if light.near_zero() {
+                    continue;
+                }
+                if let Some(aov) = aov.as_deref_mut() {
+                    aov.add_light(depth as usize, light);
+                }
+                radiance = radiance + light;
+            }
+        }
+        radiance
+    }
+
+    fn light_subpath(&self, world: &HittableList, max_vertices: usize, path: &mut Vec<Vertex>) -> usize {
+        // Path starting at a light chosen by power, returns the number of rays traced.
+        let Some(index) = self.light_distribution.sample(random_double()) else {
+            return 0;
+        };
+        let light = &self.lights[index];
+        let pmf = self.light_distribution.pmf(index);
+        let emission = light.sample_emission(&self.scene_center, self.scene_radius);
+        if pmf <= 0.0 || emission.pdf_position <= 0.0 || emission.pdf_direction <= 0.0 || emission.radiance.near_zero() {
+            return 0;
+        }
+
+        path.push(Vertex {
+            kind: VertexKind::Light(index),
+            p: emission.origin,
+            ng: light.normal_at(&emission.origin),
+            beta: emission.radiance,
+            delta: false,
+            pdf_fwd: emission.pdf_position * pmf,
+            pdf_rev: 0.0,
+        });
+        let beta = emission.radiance / (pmf * emission.pdf_position * emission.pdf_direction);
+        let ray = Ray::new(&emission.origin, &emission.direction);
+        let segments = self.random_walk(world, ray, beta, emission.pdf_direction, max_vertices, path, None);
+
+        // Light from far away arrives with the density of the starting points on the disk.
+        if light.is_infinite() && path.len() > 1 {
+            let cos_theta = if path[1].is_on_surface() { dot(&path[1].ng, &emission.direction).abs() } else { 1.0 };
+            path[1].pdf_fwd = emission.pdf_position * cos_theta;
+        }
+        segments
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn random_walk(
+        &self,
+        world: &HittableList,
+        mut ray: Ray,
+        mut beta: Vec3,
+        mut pdf_fwd: f64,
+        max_vertices: usize,
+        path: &mut Vec<Vertex>,
+        mut aov: Option<&mut AovSample>,
+    ) -> usize {
+        // Extends the subpath by scattering until it has `max_vertices` vertices, the ray leaves
+        // the scene or russian roulette ends it. `pdf_fwd` is the density of the ray's
+        // direction per solid angle. Returns the number of rays traced.
+        let from_camera = matches!(path[0].kind, VertexKind::Camera);
+        let mut throughput = color(1.0, 1.0, 1.0);
+        let mut segments = 0;
+
+        while path.len() < max_vertices {
+            count_ray(if from_camera && path.len() == 1 { RayKind::Primary } else { RayKind::Secondary });
+            segments += 1;
+
+            let mut rec = HitRecord::default();
+            if !world.hit(&ray, interval(0.001, f64::INFINITY), &mut rec) {
+                if from_camera {
+                    path.push(Vertex {
+                        kind: VertexKind::Escaped,
+                        p: ray.origin() + unit_vector(&ray.direction()),
+                        ng: Vec3::default(),
+                        beta,
+                        delta: false,
+                        pdf_fwd,
+                        pdf_rev: 0.0,
+                    });
+                }
+                break;
+            }
+
+            if let Some(medium) = rec.mat.interior_medium(&rec) {
+                match medium.random_walk(&mut ray, &mut rec, world) {
+                    Some(weight) => {
+                        beta = beta * weight;
+                        throughput = throughput * weight;
+                    }
+                    None => break,
+                }
+            }
+
+            let ng = if rec.mat.is_volume() { Vec3::default() } else { rec.normal };
+            rec.normal = rec.mat.shading_normal(&rec);
+            if from_camera && path.len() == 1 {
+                if let Some(aov) = aov.as_deref_mut() {
+                    aov.record_hit(&rec, dot(&(rec.p - self.camera.center()), &self.view_axis), world.material_id(&rec.mat));
+                }
+            }
+
+            let delta = rec.mat.eval(&ray, &rec, &rec.normal).is_none();
+            let scattering = (path.len() + 1 < max_vertices).then(|| rec.mat.scatter(&ray, &rec));
+            let scattering = scattering.filter(|(scattered, _, _)| *scattered).map(|(_, attenuation, scattered)| {
+                // Densities of sampling the scattered direction and of sampling the reverse
+                // direction for light arriving along the scattered ray.
+                if delta {
+                    return (attenuation, scattered, 0.0, 0.0);
+                }
+                let reverse_in = Ray::new(&(rec.p + scattered.direction()), &-scattered.direction());
+                let pdf = rec.mat.scattering_pdf(&ray, &rec, &scattered);
+                let pdf_rev = rec.mat.scattering_pdf(&reverse_in, &rec, &Ray::new(&rec.p, &-ray.direction()));
+                (attenuation, scattered, pdf, pdf_rev)
+            });
+
+            let mut vertex = Vertex {
+                p: rec.p,
+                kind: VertexKind::Surface { rec: Box::new(rec), r_in: ray },
+                ng,
+                beta,
+                delta,
+                pdf_fwd: 0.0,
+                pdf_rev: 0.0,
+            };
+            let last = path.len() - 1;
+            vertex.pdf_fwd = self.convert_density(&path[last], pdf_fwd, &vertex);
+            let Some((attenuation, scattered, pdf, pdf_rev)) = scattering else {
+                path.push(vertex);
+                break;
+            };
+            path[last].pdf_rev = self.convert_density(&vertex, pdf_rev, &path[last]);
+            path.push(vertex);
+
+            beta = beta * attenuation;
+            throughput = throughput * attenuation;
+            ray = scattered;
+            pdf_fwd = pdf;
+
+            // Russian roulette as in the path tracer, on the attenuation of the subpath alone.
+            let largest = throughput.x().max(throughput.y()).max(throughput.z());
+            if let Some(survival) = self.camera.survival(path.len() - 1, largest) {
+                if random_double() >= survival {
+                    break;
+                }
+                beta = beta / survival;
+                throughput = throughput / survival;
+            }
+        }
+        segments
+    }
+
+    fn connect(&self, world: &HittableList, camera_path: &[Vertex], light_path: &[Vertex], s: usize, t: usize) -> Vec3 {
+        // Light of the path made of the first `s` vertices from the light and the first `t`
+        // from the camera (t >= 2), weighted for all strategies that could have made it.
+        let pt = &camera_path[t - 1];
+        if s == 0 {
+            return self.emitted(camera_path, light_path, t);
+        }
+        if pt.delta || matches!(pt.kind, VertexKind::Escaped) {
+            return Vec3::default();
+        }
+
+        if s == 1 {
+            // Connect to a point on a light sampled from the camera vertex.
+            let Some(index) = self.light_distribution.sample(random_double()) else {
+                return Vec3::default();
+            };
+            let light = &self.lights[index];
+            let pmf = self.light_distribution.pmf(index);
+            let sample = light.sample(&pt.p);
+            if pmf <= 0.0 || sample.pdf <= 0.0 || sample.radiance.near_zero() {
+                return Vec3::default();
+            }
+            let f = self.eval(pt, &sample.direction);
+            if f.near_zero() || self.occluded(world, &pt.p, &sample.direction, sample.distance) {
+                return Vec3::default();
+            }
+            let distance = if light.is_infinite() { 1.0 } else { sample.distance };
+            let p = pt.p + distance * sample.direction;
+            let mut sampled = Vertex {
+                kind: VertexKind::Light(index),
+                p,
+                ng: light.normal_at(&p),
+                beta: sample.radiance / (sample.pdf * pmf),
+                delta: false,
+                pdf_fwd: 0.0,
+                pdf_rev: 0.0,
+            };
+            sampled.pdf_fwd = self.pdf_light_origin(&sampled, pt);
+            return pt.beta * f * sampled.beta * self.mis_weight(camera_path, light_path, Some(&sampled), s, t);
+        }
+
+        let qs = &light_path[s - 1];
+        if qs.delta {
+            return Vec3::default();
+        }
+        let offset = pt.p - qs.p;
+        let distance = offset.length();
+        let direction = offset / distance;
+        let light = qs.beta * self.eval(qs, &direction) * self.eval(pt, &-direction) * pt.beta / (distance * distance);
+        if light.near_zero() || self.occluded(world, &qs.p, &direction, distance) {
+            return Vec3::default();
+        }
+        light * self.mis_weight(camera_path, light_path, None, s, t)
+    }
+
+    fn connect_to_camera(
+        &self,
+        world: &HittableList,
+        camera_path: &[Vertex],
+        light_path: &[Vertex],
+        s: usize,
+    ) -> Option<(usize, usize, Vec3)> {
+        // Light of the light subpath with `s` vertices seen by the pinhole camera, and the pixel
+        // it lands on.
+        let qs = &light_path[s - 1];
+        if !self.camera.is_pinhole() || qs.delta {
+            return None;
+        }
+        let (x, y) = self.camera.raster_position(&qs.p)?;
+        let offset = self.camera.center() - qs.p;
+        let distance = offset.length();
+        let direction = offset / distance;
+
+        // The importance of the camera is the direction density over the cosine to the view
+        // axis, sampling the camera position from `qs` adds the squared distance over the
+        // cosine.
+        let beta = color(1.0, 1.0, 1.0) * (self.camera.direction_pdf(&-offset) / (distance * distance));
+        let light = qs.beta * self.eval(qs, &direction) * beta;
+        if light.near_zero() || self.occluded(world, &qs.p, &direction, distance) {
+            return None;
+        }
+        let sampled = Vertex {
+            kind: VertexKind::Camera,
+            p: self.camera.center(),
+            ng: Vec3::default(),
+            beta,
+            delta: false,
+            pdf_fwd: 0.0,
+            pdf_rev: 0.0,
+        };
+        Some((x, y, light * self.mis_weight(camera_path, light_path, Some(&sampled), s, 1)))
+    }
+
+    fn emitted(&self, camera_path: &[Vertex], light_path: &[Vertex], t: usize) -> Vec3 {
+        // Light found by the camera subpath alone. Only the lights can be reached from the
+        // other side, the background and glowing materials other than area lights, like
+        // emissive volumes, count fully.
+        let pt = &camera_path[t - 1];
+        match &pt.kind {
+            VertexKind::Surface { rec, .. } => {
+                let emitted = pt.beta * rec.mat.emitted(rec);
+                if rec.light.is_none() || emitted.near_zero() {
+                    return emitted;
+                }
+                emitted * self.mis_weight(camera_path, light_path, None, 0, t)
+            }
+            VertexKind::Escaped => {
+                let ray = Ray::new(&camera_path[t - 2].p, &(pt.p - camera_path[t - 2].p));
+                let lights = self
+                    .lights
+                    .iter()
+                    .fold(Vec3::default(), |sum, light| sum + light.escaped(&ray.direction()));
+                let mut light = self.camera.background(&ray);
+                if !lights.near_zero() {
+                    light = light + lights * self.mis_weight(camera_path, light_path, None, 0, t);
+                }
+                pt.beta * light
+            }
+            _ => Vec3::default(),
+        }
+    }
+
+    fn mis_weight(&self, camera_path: &[Vertex], light_path: &[Vertex], sampled: Option<&Vertex>, s: usize, t: usize) -> f64 {
+        // Balance heuristic weight of the strategy with `s` light and `t` camera vertices. The
+        // densities of the other strategies follow from the ratios of the reverse and forward
+        // densities of the vertices, walking away from the connection on either side. A
+        // sampled light or camera vertex replaces the end of its subpath.
+        if s + t == 2 {
+            return 1.0;
+        }
+        let pt = if t == 1 { sampled.expect("sampled camera vertex") } else { &camera_path[t - 1] };
+        let qs = match s {
+            0 => None,
+            1 => Some(sampled.expect("sampled light vertex")),
+            _ => Some(&light_path[s - 1]),
+        };
+        let pt_minus = (t > 1).then(|| &camera_path[t - 2]);
+        let qs_minus = (s > 1).then(|| &light_path[s - 2]);
+
+        // Forward and reverse densities and delta flags with the changes of this strategy.
+        let mut camera: Vec<(f64, f64, bool)> =
+            (0..t).map(|i| if i == t - 1 { pt.densities() } else { camera_path[i].densities() }).collect();
+        let mut light: Vec<(f64, f64, bool)> =
+            (0..s).map(|i| if i == s - 1 { qs.unwrap().densities() } else { light_path[i].densities() }).collect();
+        camera[t - 1].2 = false;
+        camera[t - 1].1 = match qs {
+            Some(qs) => self.pdf(qs, qs_minus, pt),
+            None => self.pdf_light_origin(pt, pt_minus.unwrap()),
+        };
+        if let Some(pt_minus) = pt_minus {
+            camera[t - 2].1 = match qs {
+                Some(qs) => self.pdf(pt, Some(qs), pt_minus),
+                None => self.pdf_light(pt, pt_minus),
+            };
+        }
+        if let Some(qs) = qs {
+            light[s - 1].2 = false;
+            light[s - 1].1 = self.pdf(pt, pt_minus, qs);
+            if let Some(qs_minus) = qs_minus {
+                light[s - 2].1 = self.pdf(qs, Some(pt), qs_minus);
+            }
+        }
+
+        let remap = |pdf: f64| if pdf != 0.0 { pdf } else { 1.0 };
+        let mut sum = 0.0;
+        let mut ratio = 1.0;
+        for i in (1..t).rev() {
+            ratio *= remap(camera[i].1) / remap(camera[i].0);
+            if !camera[i].2 && !camera[i - 1].2 {
+                sum += ratio;
+            }
+        }
+        ratio = 1.0;
+        for i in (0..s).rev() {
+            ratio *= remap(light[i].1) / remap(light[i].0);
+            let delta_before = if i > 0 {
+                light[i - 1].2
+            } else {
+                let first = if s == 1 { qs.unwrap() } else { &light_path[0] };
+                self.is_delta_light(first)
+            };
+            if !light[i].2 && !delta_before {
+                sum += ratio;
+            }
+        }
+        1.0 / (1.0 + sum)
+    }
+
+    fn pdf(&self, vertex: &Vertex, prev: Option<&Vertex>, next: &Vertex) -> f64 {
+        // Density per area at `next` of sampling it from `vertex`, which was reached from
+        // `prev`.
+        match &vertex.kind {
+            VertexKind::Light(_) | VertexKind::Escaped => self.pdf_light(vertex, next),
+            VertexKind::Camera => self.convert_density(vertex, self.camera.direction_pdf(&(next.p - vertex.p)), next),
+            VertexKind::Surface { rec, .. } => {
+                let Some(prev) = prev.filter(|_| !vertex.delta) else {
+                    return 0.0;
+                };
+                let r_in = Ray::new(&prev.p, &(vertex.p - prev.p));
+                let pdf = rec.mat.scattering_pdf(&r_in, rec, &Ray::new(&vertex.p, &(next.p - vertex.p)));
+                self.convert_density(vertex, pdf, next)
+            }
+        }
+    }
+
+    fn pdf_light(&self, light: &Vertex, next: &Vertex) -> f64 {
+        // Density per area at `next` of light paths starting at the light vertex.
+        let offset = next.p - light.p;
+        let distance2 = offset.length_squared();
+        let mut pdf = if self.is_infinite(light) {
+            1.0 / (PI * self.scene_radius * self.scene_radius)
+        } else {
+            match self.light_index(light) {
+                Some(index) => self.lights[index].emission_pdf(&light.p, &offset) / distance2,
+                None => 0.0,
+            }
+        };
+        if next.is_on_surface() {
+            pdf *= dot(&next.ng, &offset).abs() / distance2.sqrt();
+        }
+        pdf
+    }
+
+    fn pdf_light_origin(&self, light: &Vertex, next: &Vertex) -> f64 {
+        // Density of light paths starting at the light vertex, chosen by power, per area on area
+        // lights. For light from far away it's the density of the direction towards `next`,
+        // summed over the lights that could have sent it.
+        if self.is_infinite(light) {
+            let direction = light.p - next.p;
+            return self
+                .lights
+                .iter()
+                .enumerate()
+                .filter(|(_, l)| l.is_infinite())
+                .map(|(index, l)| self.light_distribution.pmf(index) * l.pdf(&next.p, &direction))
+                .sum();
+        }
+        match self.light_index(light) {
+            Some(index) => self.light_distribution.pmf(index) * self.lights[index].emission_position_pdf(),
+            None => 0.0,
+        }
+    }
+
+    fn light_index(&self, vertex: &Vertex) -> Option<usize> {
+        // Light the vertex is on, also for camera vertices that hit an area light.
+        match &vertex.kind {
+            VertexKind::Light(index) => Some(*index),
+            VertexKind::Surface { rec, .. } => rec.light.map(|light| self.camera.first_area_light() + light),
+            _ => None,
+        }
+    }
+
+    fn convert_density(&self, from: &Vertex, pdf: f64, to: &Vertex) -> f64 {
+        // Density per solid angle at `from` to density per area at `to`. Vertices infinitely
+        // far away keep the density per solid angle.
+        if self.is_infinite(to) {
+            return pdf;
+        }
+        let offset = to.p - from.p;
+        let distance2 = offset.length_squared();
+        if distance2 == 0.0 {
+            return 0.0;
+        }
+        let mut pdf = pdf / distance2;
+        if to.is_on_surface() {
+            pdf *= dot(&to.ng, &offset).abs() / distance2.sqrt();
+        }
+        pdf
+    }
+
+    fn is_infinite(&self, vertex: &Vertex) -> bool {
+        match vertex.kind {
+            VertexKind::Escaped => true,
+            VertexKind::Light(index) => self.lights[index].is_infinite(),
+            _ => false,
+        }
+    }
+
+    fn is_delta_light(&self, vertex: &Vertex) -> bool {
+        match vertex.kind {
+            VertexKind::Light(index) => self.lights[index].is_delta(),
+            _ => false,
+        }
+    }
+
+    fn eval(&self, vertex: &Vertex, direction: &Vec3) -> Vec3 {
+        // Scattering at the vertex towards `direction` including the cosine.
+        match &vertex.kind {
+            VertexKind::Surface { rec, r_in } => rec.mat.eval(r_in, rec, direction).unwrap_or_default(),
+            _ => Vec3::default(),
+        }
+    }
+
+    fn occluded(&self, world: &HittableList, p: &Vec3, direction: &Vec3, distance: f64) -> bool {
+        count_ray(RayKind::Shadow);
+        world.occluded(&Ray::new(p, direction), interval(0.001, distance - 0.001))
+    }
+}
+
+#[test]
+fn bdpt_test() {
+    use crate::camera::ALL_INTEGRATORS;
+    use crate::hittable::Hittable;
+    use crate::light::{point_light, spot_light};
+    use crate::material::lambertian;
+    use crate::sphere::sphere;
+
+    // Without specular materials the path tracer finds all light as well, both integrators
+    // agree on the image inside a diffuse dome lit by a point and a spot light.
+    let objects: Vec<Box<dyn Hittable>> = vec![
+        Box::new(sphere(&point3(0.0, -1000.0, 0.0), 1000.0, &lambertian(color(0.5, 0.5, 0.5)))),
+        Box::new(sphere(&point3(-0.6, 0.5, 0.0), 0.5, &lambertian(color(0.7, 0.3, 0.2)))),
+        Box::new(sphere(&point3(0.6, 0.5, 0.0), 0.5, &lambertian(color(0.8, 0.8, 0.8)))),
+        Box::new(sphere(&point3(0.0, 0.0, 0.0), 20.0, &lambertian(color(0.3, 0.3, 0.3)))),
+    ];
+    let world = HittableList::new(objects);
+
+    let mut cam = Camera::default();
+    cam.image_width = 8;
+    cam.samples_per_pixel = 1024;
+    cam.max_depth = 4;
+    cam.seed = Some(3);
+    cam.vfov = 40.0;
+    cam.look_from = point3(0.0, 1.5, 4.0);
+    cam.look_at = point3(0.0, 0.4, 0.0);
+    cam.lights = vec![
+        point_light(point3(0.5, 2.0, 1.0), vec3(3.0, 3.0, 3.0)),
+        spot_light(point3(-1.0, 2.5, 1.0), vec3(0.3, -1.0, -0.3), vec3(6.0, 6.0, 6.0), 20.0, 35.0),
+    ];
+
+    let mut means = Vec::new();
+    for integrator in ALL_INTEGRATORS {
+        cam.integrator = integrator;
+        let image = cam.render_buffers(&world).beauty;
+        means.push(image.pixels.iter().fold(Vec3::default(), |sum, p| sum + *p) / image.pixels.len() as f64);
+    }
+    for channel in 0..3 {
+        let (path, bdpt) = (means[0][channel], means[1][channel]);
+        assert!((bdpt - path).abs() < 0.03 * path, "{:?} with bdpt, {:?} with path tracing", means[1], means[0]);
+    }
+}
+
+#[test]
+fn bdpt_caustic_test() {
+    use crate::camera::ALL_INTEGRATORS;
+    use crate::hittable::Hittable;
+    use crate::light::point_light;
+    use crate::material::{dielectric, lambertian};
+    use crate::sphere::sphere;
+
+    // A glass sphere focuses a point light on the ground below. The path tracer can't connect
+    // the ground through the glass to the light and sees only the shadow, light traced from
+    // the light to the camera finds the bright caustic.
+    let objects: Vec<Box<dyn Hittable>> = vec![
+        Box::new(sphere(&point3(0.0, -1000.0, 0.0), 1000.0, &lambertian(color(0.5, 0.5, 0.5)))),
+        Box::new(sphere(&point3(0.0, 0.8, 0.0), 0.5, &dielectric(1.5))),
+    ];
+    let world = HittableList::new(objects);
+
+    let mut cam = Camera::default();
+    cam.image_width = 8;
+    cam.samples_per_pixel = 64;
+    cam.max_depth = 4;
+    cam.seed = Some(7);
+    cam.vfov = 8.0;
+    cam.look_from = point3(0.0, 0.5, 2.0);
+    cam.look_at = point3(0.0, 0.0, 0.0);
+    cam.lights = vec![point_light(point3(0.0, 4.0, 0.0), vec3(10.0, 10.0, 10.0))];
+
+    let mut centers = Vec::new();
+    for integrator in ALL_INTEGRATORS {
+        cam.integrator = integrator;
+        let image = cam.render_buffers(&world).beauty;
+        centers.push(image.get(3, 3) + image.get(4, 3) + image.get(3, 4) + image.get(4, 4));
+    }
+    assert!(centers[1].x() > 5.0 * centers[0].x(), "{:?} with bdpt, {:?} with path tracing", centers[1], centers[0]);
+}
+
+#[test]
+fn bdpt_area_light_test() {
+    use crate::camera::{Integrator, ALL_INTEGRATORS};
+    use crate::hittable::Hittable;
+    use crate::material::{diffuse_light, lambertian};
+    use crate::mesh::{mesh, MeshData};
+    use crate::sphere::sphere;
+
+    // Glowing geometry starts light paths and is connected to like the other lights. The
+    // ground right below a glowing sphere of radius r at the distance d of its center reflects
+    // albedo * L * r^2 / d^2, and a scene lit by a sphere and a quad renders the same with both
+    // integrators.
+    let ground = || Box::new(sphere(&point3(0.0, -1000.0, 0.0), 1000.0, &lambertian(color(0.5, 0.5, 0.5))));
+    let dome = || Box::new(sphere(&point3(0.0, 0.0, 0.0), 20.0, &lambertian(color(0.0, 0.0, 0.0))));
+    let lamp = sphere(&point3(0.0, 1.0, 0.0), 0.5, &diffuse_light(color(4.0, 4.0, 4.0)));
+    let world = HittableList::new(vec![ground(), dome(), Box::new(lamp)]);
+
+    let mut cam = Camera::default();
+    cam.image_width = 8;
+    cam.samples_per_pixel = 256;
+    cam.seed = Some(5);
+    cam.vfov = 1.0;
+    cam.look_from = point3(2.0, 1.0, 0.0);
+    cam.look_at = point3(0.0, 0.0, 0.0);
+    cam.integrator = Integrator::Bidirectional;
+    let image = cam.render_buffers(&world).beauty;
+    let mean = image.pixels.iter().map(|p| p.x()).sum::<f64>() / image.pixels.len() as f64;
+    let expected = 0.5 * 4.0 * 0.25;
+    assert!((mean - expected).abs() < 0.03 * expected, "{} instead of {}", mean, expected);
+
+    let quad = MeshData {
+        positions: vec![
+            point3(-1.0, 2.0, -0.5),
+            point3(-0.2, 2.0, -0.5),
+            point3(-0.2, 2.0, 0.5),
+            point3(-1.0, 2.0, 0.5),
+        ],
+        triangles: vec![[0, 1, 2], [0, 2, 3]],
+        ..Default::default()
+    };
+    let objects: Vec<Box<dyn Hittable>> = vec![
+        ground(),
+        Box::new(sphere(&point3(20.0, 0.0, 0.0), 40.0, &lambertian(color(0.3, 0.3, 0.3)))),
+        Box::new(sphere(&point3(0.6, 0.5, 0.0), 0.5, &lambertian(color(0.8, 0.8, 0.8)))),
+        Box::new(sphere(&point3(0.6, 1.4, 0.6), 0.2, &diffuse_light(color(8.0, 6.0, 4.0)))),
+        Box::new(mesh(quad, &diffuse_light(color(1.0, 1.0, 2.0)))),
+    ];
+    let world = HittableList::new(objects);
+    assert_eq!(world.area_lights().len(), 3);
+
+    let mut cam = Camera::default();
+    cam.image_width = 8;
+    cam.samples_per_pixel = 1024;
+    cam.max_depth = 4;
+    cam.seed = Some(3);
+    cam.vfov = 40.0;
+    cam.look_from = point3(0.0, 1.0, 4.0);
+    cam.look_at = point3(0.0, 0.6, 0.0);
+    let mut means = Vec::new();
+    for integrator in ALL_INTEGRATORS {
+        cam.integrator = integrator;
+        let image = cam.render_buffers(&world).beauty;
+        means.push(image.pixels.iter().fold(Vec3::default(), |sum, p| sum + *p) / image.pixels.len() as f64);
+    }
+    for channel in 0..3 {
+        let (path, bdpt) = (means[0][channel], means[1][channel]);
+        assert!((bdpt - path).abs() < 0.03 * path, "{:?} with bdpt, {:?} with path tracing", means[1], means[0]);
+    }
+}
@@ -0,0 +1,124 @@
+use std::cmp::Ordering;
+
+use crate::aabb::{Aabb, AABB_EMPTY};
+use crate::hittable::{HitRecord, Hittable, SceneIndex};
+use crate::interval::{interval, Interval};
+use crate::ray::Ray;
+use crate::stats::count_bvh_node_visit;
+
+/// Bounding volume hierarchy over a set of objects. Each node splits its objects in half along
+/// the longest axis of its bounding box.
+pub struct BvhNode {
+    left: Box<dyn Hittable>,
+    right: Option<Box<dyn Hittable>>,
+    bbox: Aabb,
+}
+
+impl BvhNode {
+    pub fn new(mut objects: Vec<Box<dyn Hittable>>) -> BvhNode {
+        assert!(!objects.is_empty(), "a bvh node needs at least one object");
+
+        let bbox = objects
+            .iter()
+            .fold(AABB_EMPTY, |bbox, object| Aabb::from_boxes(&bbox, &object.bounding_box()));
+        let axis = bbox.longest_axis();
+
+        let (left, right): (Box<dyn Hittable>, Option<Box<dyn Hittable>>) = match objects.len() {
+            1 => (objects.pop().unwrap(), None),
+            2 => {
+                let right = objects.pop().unwrap();
+                (objects.pop().unwrap(), Some(right))
+            }
+            _ => {
+                objects.sort_by(|a, b| box_compare(a.as_ref(), b.as_ref(), axis));
+                let right = objects.split_off(objects.len() / 2);
+                (Box::new(BvhNode::new(objects)), Some(Box::new(BvhNode::new(right))))
+            }
+        };
+
+        BvhNode { left, right, bbox }
+    }
+}
+
+fn box_compare(a: &dyn Hittable, b: &dyn Hittable, axis: usize) -> Ordering {
+    // Orders by the centers of the bounding boxes along the axis.
+    let a_axis = a.bounding_box().axis_interval(axis);
+    let b_axis = b.bounding_box().axis_interval(axis);
+    (a_axis.min + a_axis.max).total_cmp(&(b_axis.min + b_axis.max))
+}
+
+impl Hittable for BvhNode {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        count_bvh_node_visit();
+        if !self.bbox.hit(r, ray_t) {
+            return false;
+        }
+
+        let hit_left = self.left.hit(r, ray_t, rec);
+        let t_max = if hit_left { rec.t } else { ray_t.max };
+        let hit_right = match &self.right {
+            Some(right) => right.hit(r, interval(ray_t.min, t_max), rec),
+            None => false,
+        };
+
+        hit_left || hit_right
+    }
+
+    fn occluded(&self, r: &Ray, ray_t: Interval) -> bool {
+        count_bvh_node_visit();
+        if !self.bbox.hit(r, ray_t) {
+            return false;
+        }
+        self.left.occluded(r, ray_t) || self.right.as_ref().is_some_and(|right| right.occluded(r, ray_t))
+    }
+
+    fn index_scene(&mut self, scene: &mut SceneIndex) {
+        self.left.index_scene(scene);
+        if let Some(right) = &mut self.right {
+            right.index_scene(scene);
+        }
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
+
+#[test]
+fn bvh_test() {
+    use crate::hittable_list::HittableList;
+    use crate::material::lambertian;
+    use crate::rtweekend::{random_double_intv, seed_random};
+    use crate::sphere::sphere;
+    use crate::vec3::*;
+
+    // The hierarchy finds the same closest hits as testing every object, and the same
+    // occlusion of shadow rays ending before the closest hit or just after it.
+    seed_random(7);
+    let material = lambertian(vec3(0.5, 0.5, 0.5));
+    let random_spheres = || -> Vec<Box<dyn Hittable>> {
+        (0..50)
+            .map(|i| {
+                let center = point3(i as f64 * 0.3 - 7.5, (i % 7) as f64 * 0.4 - 1.2, -((i % 5) as f64));
+                Box::new(sphere(&center, 0.1 + 0.05 * (i % 3) as f64, &material)) as Box<dyn Hittable>
+            })
+            .collect()
+    };
+    let list = HittableList::new(random_spheres());
+    let bvh = BvhNode::new(random_spheres());
+
+    for _ in 0..1000 {
+        let origin = point3(random_double_intv(-8.0, 8.0), random_double_intv(-2.0, 2.0), 5.0);
+        let direction = vec3(random_double_intv(-0.5, 0.5), random_double_intv(-0.5, 0.5), -1.0);
+        let r = Ray::new(&origin, &direction);
+        let (mut rec_list, mut rec_bvh) = (HitRecord::default(), HitRecord::default());
+        let full = interval(0.001, f64::INFINITY);
+        assert_eq!(list.hit(&r, full, &mut rec_list), bvh.hit(&r, full, &mut rec_bvh));
+        assert_eq!(rec_list.t, rec_bvh.t);
+        for t_max in [rec_list.t * 0.99, rec_list.t * 1.01] {
+            let shadow = interval(0.001, t_max);
+            assert_eq!(list.occluded(&r, shadow), bvh.occluded(&r, shadow));
+            assert_eq!(bvh.occluded(&r, shadow), rec_bvh.t != 0.0 && t_max > rec_bvh.t);
+        }
+    }
+}
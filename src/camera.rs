@@ -1,21 +1,75 @@
-use std::f64::INFINITY;
-
+use std::fs;
+use std::path::Path;
+use std::time::Instant;
+
+use crate::aov::{Aov, AovSample, RenderBuffers};
+use crate::aperture::Aperture;
+use crate::bdpt::Bidirectional;
+use crate::checkpoint::{setup_hash, Checkpoint, CheckpointOptions, RenderSettings};
 use crate::hittable::{HitRecord, Hittable};
 use crate::hittable_list::HittableList;
 
 use crate::color::{color, write_color};
+use crate::denoise::{denoise, DenoiseOptions};
 use crate::interval::interval;
+use crate::light::{power_heuristic, Light};
+use crate::light_sampler::{LightSampler, LightSampling};
+use crate::openexr::{write_exr, ExrOptions};
+use crate::preview::{Preview, PreviewOptions, Progress};
 use crate::ray::Ray;
-
-use crate::rtweekend::{degrees_to_radians, random_double};
+use crate::sky::Sky;
+use crate::spectrum::{SampledWavelengths, Spectrum, WAVELENGTHS};
+use crate::stats::{
+    count_path, count_ray, heatmap, record_phase, render_stats, reset_stats, traversal_cost, Phase, RayKind,
+    StatsOptions,
+};
+
+use crate::rtweekend::{degrees_to_radians, random_double, random_state, restore_random_state, seed_random};
 use crate::vec3::*;
 
+// Aperture samples tried per ray for one inside the lens barrel (optical vignetting).
+const MAX_APERTURE_ATTEMPTS: usize = 64;
+// Beyond `max_depth` paths survive russian roulette at most with this probability.
+const DEEP_SURVIVAL: f64 = 0.5;
+// Hard cap on the bounces of any path, whatever `max_depth` is. Longer paths are truncated and
+// their light is lost, but past `max_depth` paths only get this long with vanishing probability.
+pub(crate) const MAX_BOUNCES: usize = 1024;
+
+/// How the light arriving along camera rays is estimated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Integrator {
+    PathTracing,   // Paths traced from the camera, lights sampled at every scattering
+    Bidirectional, // Paths from the camera and from the lights connected at all their vertices
+}
+
+pub const ALL_INTEGRATORS: [Integrator; 2] = [Integrator::PathTracing, Integrator::Bidirectional];
+
+impl Integrator {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Integrator::PathTracing => "path",
+            Integrator::Bidirectional => "bdpt",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Integrator> {
+        ALL_INTEGRATORS.iter().copied().find(|integrator| integrator.name() == name)
+    }
+}
+
 #[derive(Debug)]
 pub struct Camera {
     pub aspect_ratio: f64,        // Ratio of image width over height
     pub image_width: u64,         // Rendered image width in pixel count
     pub samples_per_pixel: usize, // Count of random samples for each pixel
-    pub max_depth: usize,         // Maximum number of ray bounces into scene
+    pub max_depth: usize,         // Bounces after which paths survive at most half the time, see `MAX_BOUNCES`
+    pub rr_min_depth: usize,      // Bounces before paths may be terminated by russian roulette
+    pub seed: Option<u64>,        // Seed for reproducible renders, random if not set
+    pub spectral: bool,           // Trace wavelengths instead of RGB, e.g. for dispersion
+    pub integrator: Integrator,   // Path tracing or bidirectional path tracing (RGB only, ignores `spectral`)
+    pub sky: Option<Sky>,         // Physical sky with the sun instead of the gradient background
+    pub lights: Vec<Light>,       // Point, spot and directional lights
+    pub light_sampling: LightSampling, // How the lights are chosen at every scattering
 
     pub vfov: f64, // Vertical view angle (field of view)
     pub look_from: Vec3,
@@ -25,6 +79,21 @@ pub struct Camera {
     pub defocus_angle: f64,
     pub focus_dist: f64,
 
+    pub aperture: Aperture,    // Shape of the lens opening used for defocus blur
+    pub vignetting: f64,       // Strength of optical (cat's eye) vignetting, 0 disables it
+    pub focus_tilt: f64,       // Rotation of the focus plane around the horizontal axis in degrees
+    pub focus_swing: f64,      // Rotation of the focus plane around the vertical axis in degrees
+    pub lens_shift_u: f64,     // Horizontal lens shift as a fraction of the viewport width
+    pub lens_shift_v: f64,     // Vertical lens shift as a fraction of the viewport height
+
+    pub aovs: Vec<Aov>,     // Additional render passes written next to the beauty image
+    pub aov_prefix: String, // Path prefix of the files the render passes are written to
+    pub exr: Option<ExrOptions>, // Write beauty and passes into one OpenEXR file instead
+    pub denoise: Option<DenoiseOptions>, // Denoise the beauty image using albedo and normal passes
+    pub stats: Option<StatsOptions>,     // Report ray counts, timings and a cost heatmap
+    pub checkpoint: Option<CheckpointOptions>, // Save progress periodically and resume from it
+    pub preview: Option<PreviewOptions>,       // Show the image in the terminal while rendering
+
     image_height: u64,
     pixel_samples_scale: f64,
     center: Vec3,
@@ -38,6 +107,10 @@ pub struct Camera {
 
     defocus_disk_u: Vec3,   // Defocus disk horizontal radius
     defocus_disk_v: Vec3,   // Defocus disk vertical radius
+    focus_normal: Vec3,     // Normal of the (possibly tilted) plane of focus
+    sampled_lights: Vec<Light>, // Lights sampled at every scattering, with the sun and the area lights
+    first_area_light: usize,    // Index of the first area light of the world in `sampled_lights`
+    light_sampler: LightSampler,
 }
 
 impl Default for Camera {
@@ -47,6 +120,13 @@ impl Default for Camera {
             image_width: 100,
             samples_per_pixel: 10,
             max_depth: 10,
+            rr_min_depth: 3,
+            seed: None,
+            spectral: false,
+            integrator: Integrator::PathTracing,
+            sky: None,
+            lights: Vec::new(),
+            light_sampling: LightSampling::All,
 
             vfov: 90.0,
             look_from: vec3(0.0, 0.0, 0.0),
@@ -56,6 +136,21 @@ impl Default for Camera {
             defocus_angle: 0.0,
             focus_dist: 10.0,
 
+            aperture: Aperture::Circular,
+            vignetting: 0.0,
+            focus_tilt: 0.0,
+            focus_swing: 0.0,
+            lens_shift_u: 0.0,
+            lens_shift_v: 0.0,
+
+            aovs: Vec::new(),
+            aov_prefix: String::from("aov"),
+            exr: None,
+            denoise: None,
+            stats: None,
+            checkpoint: None,
+            preview: None,
+
             image_height: 100,
             pixel_samples_scale: 0.1,
             center: Vec3::default(),
@@ -69,31 +164,236 @@ impl Default for Camera {
 
             defocus_disk_u: Vec3::default(),
             defocus_disk_v: Vec3::default(),
-        
+            focus_normal: Vec3::default(),
+            sampled_lights: Vec::new(),
+            first_area_light: 0,
+            light_sampler: LightSampler::All,
         }
     }
 }
 
 impl Camera {
     pub fn render(&mut self, world: &HittableList) {
-        self.initialize();
+        let mut buffers = self.render_all_buffers(world);
+
+        let output_start = Instant::now();
+        if let Some(path) = self.stats.as_ref().and_then(|s| s.heatmap.as_ref()) {
+            let cost = buffers.aov(Aov::Cost).unwrap();
+            if let Err(err) = heatmap(cost).write_ppm(path) {
+                eprintln!("Failed to write cost heatmap: {}", err);
+            }
+        }
+        buffers.aovs.retain(|(aov, _)| self.aovs.contains(aov));
 
         print!("P3\n{} {}\n255\n", self.image_width, self.image_height);
+        for pixel in &buffers.beauty.pixels {
+            write_color(pixel);
+        }
+
+        let result = match &self.exr {
+            Some(options) => write_exr(options, &buffers),
+            None => buffers.write_aovs(&self.aov_prefix),
+        };
+        if let Err(err) = result {
+            eprintln!("Failed to write render passes: {}", err);
+        }
+        record_phase(Phase::Output, output_start.elapsed());
+
+        if let Some(options) = &self.stats {
+            let stats = render_stats();
+            if options.summary {
+                eprint!("{}", stats.summary());
+            }
+            if let Some(path) = &options.json {
+                if let Err(err) = stats.write_json(path) {
+                    eprintln!("Failed to write render statistics: {}", err);
+                }
+            }
+        }
+    }
+
+    pub fn render_buffers(&mut self, world: &HittableList) -> RenderBuffers {
+        let mut buffers = self.render_all_buffers(world);
+        buffers.aovs.retain(|(aov, _)| self.aovs.contains(aov));
+        buffers
+    }
+
+    fn render_all_buffers(&mut self, world: &HittableList) -> RenderBuffers {
+        // Renders the requested passes plus the ones needed internally: the denoiser needs the
+        // albedo and normal passes and the heatmap the cost pass.
+        let render_start = Instant::now();
+        reset_stats();
+        self.initialize();
+        self.first_area_light = self.sampled_lights.len();
+        self.sampled_lights.extend(world.area_lights().iter().cloned());
+        self.light_sampler = LightSampler::new(self.light_sampling, &self.sampled_lights, &world.bounding_box());
+
+        let mut aovs = self.aovs.clone();
+        let mut required = Vec::new();
+        if self.denoise.is_some() {
+            required.extend([Aov::Albedo, Aov::Normal]);
+        }
+        if self.stats.as_ref().is_some_and(|s| s.heatmap.is_some()) {
+            required.push(Aov::Cost);
+        }
+        for aov in required {
+            if !aovs.contains(&aov) {
+                aovs.push(aov);
+            }
+        }
+
+        let settings = RenderSettings {
+            width: self.image_width as usize,
+            height: self.image_height as usize,
+            samples_per_pixel: self.samples_per_pixel,
+            max_depth: self.max_depth,
+            seed: self.seed,
+            spectral: self.spectral,
+            integrator: self.integrator,
+            aovs,
+            setup_hash: self.setup_hash(world),
+        };
+        let (mut buffers, completed_pixels) = match self.resume(&settings) {
+            Some(checkpoint) => {
+                restore_random_state(&checkpoint.random_state);
+                (checkpoint.buffers, checkpoint.completed_pixels)
+            }
+            None => (RenderBuffers::new(settings.width, settings.height, &settings.aovs), 0),
+        };
+        let aovs = &settings.aovs;
+        let mut aov_samples = Vec::with_capacity(self.samples_per_pixel);
+        let mut last_save = Instant::now();
+        let mut preview = self.preview.map(Preview::new);
+        let rays_before = render_stats().rays();
+        let bidirectional = (self.integrator == Integrator::Bidirectional).then(|| Bidirectional::new(self, world));
 
         for j in 0..self.image_height {
-            eprint!("\rScanlines remaining: {} ", (self.image_height - 1) - j);
+            if preview.is_none() {
+                eprint!("\rScanlines remaining: {} ", (self.image_height - 1) - j);
+            }
             for i in 0..self.image_width {
+                let pixel_index = (j * self.image_width + i) as usize;
+                if pixel_index < completed_pixels {
+                    continue;
+                }
+
                 let mut pixel_color = color(0.0, 0.0, 0.0);
+                aov_samples.clear();
+
+                if let Some(seed) = self.seed {
+                    seed_random(self.pixel_seed(seed, i, j));
+                }
 
                 for _sample in 0..self.samples_per_pixel {
                     let r = self.get_ray(i, j);
-                    pixel_color = pixel_color + self.ray_color(&r, self.max_depth, world);
+                    let mut aov_sample = (!aovs.is_empty()).then(AovSample::default);
+                    let cost_before = traversal_cost();
+                    pixel_color = pixel_color
+                        + match &bidirectional {
+                            Some(bdpt) => bdpt.radiance(&r, world, aov_sample.as_mut(), &mut buffers),
+                            None => self.ray_color(&r, world, aov_sample.as_mut()),
+                        };
+                    if let Some(mut aov_sample) = aov_sample {
+                        aov_sample.cost = traversal_cost() - cost_before;
+                        aov_samples.push(aov_sample);
+                    }
+                }
+
+                buffers.set_pixel(i as usize, j as usize, pixel_color * self.pixel_samples_scale, &aov_samples);
+
+                if let Some(options) = &self.checkpoint {
+                    if last_save.elapsed() >= options.interval {
+                        let checkpoint = Checkpoint {
+                            settings: settings.clone(),
+                            completed_pixels: pixel_index + 1,
+                            random_state: random_state(),
+                            buffers: buffers.clone(),
+                        };
+                        if let Err(err) = checkpoint.write(&options.path) {
+                            eprintln!("\nFailed to write checkpoint: {}", err);
+                        }
+                        last_save = Instant::now();
+                    }
                 }
+            }
 
-                write_color(&(pixel_color * self.pixel_samples_scale));
+            // The preview is refreshed as rows are completed.
+            if let Some(preview) = preview.as_mut() {
+                let progress = Progress {
+                    completed_pixels: ((j + 1) * self.image_width) as usize,
+                    total_pixels: settings.width * settings.height,
+                    started_pixels: completed_pixels,
+                    samples_per_pixel: self.samples_per_pixel,
+                    rays: render_stats().rays() - rays_before,
+                };
+                if progress.completed_pixels > completed_pixels {
+                    preview.update(&buffers.beauty, &progress);
+                }
             }
         }
         eprintln!("\nDone");
+
+        if let Some(options) = &self.checkpoint {
+            // The finished render doesn't need its checkpoint anymore.
+            let _ = fs::remove_file(&options.path);
+        }
+
+        if let Some(options) = &self.denoise {
+            let albedo = buffers.aov(Aov::Albedo).unwrap();
+            let normal = buffers.aov(Aov::Normal).unwrap();
+            buffers.beauty = denoise(&buffers.beauty, albedo, normal, options);
+        }
+        record_phase(Phase::Render, render_start.elapsed());
+
+        buffers
+    }
+
+    pub(crate) fn setup_hash(&self, world: &HittableList) -> u64 {
+        // Everything else that changes the image of a checkpointed render: where the camera
+        // looks from and through which lens, how the scene is lit and the scene itself.
+        let setup = format!(
+            "{:?} {:?} {:?} {} {} {} {:?} {} {} {} {} {} {} {:?} {:?} {:?} {}",
+            self.look_from,
+            self.look_at,
+            self.v_up,
+            self.vfov,
+            self.defocus_angle,
+            self.focus_dist,
+            self.aperture,
+            self.vignetting,
+            self.focus_tilt,
+            self.focus_swing,
+            self.lens_shift_u,
+            self.lens_shift_v,
+            self.rr_min_depth,
+            self.lights,
+            self.sky,
+            self.light_sampling,
+            world.fingerprint(),
+        );
+        setup_hash(&setup)
+    }
+
+    fn resume(&self, settings: &RenderSettings) -> Option<Checkpoint> {
+        // Loads the checkpoint to continue from, if there is one written by the same render.
+        let path = &self.checkpoint.as_ref()?.path;
+        if !Path::new(path).exists() {
+            return None;
+        }
+        match Checkpoint::read(path) {
+            Ok(checkpoint) if checkpoint.settings == *settings => {
+                eprintln!("Resuming from checkpoint {} at pixel {}", path, checkpoint.completed_pixels);
+                Some(checkpoint)
+            }
+            Ok(_) => {
+                eprintln!("Checkpoint {} was written with different render settings, starting over", path);
+                None
+            }
+            Err(err) => {
+                eprintln!("Failed to read checkpoint {}: {}, starting over", path, err);
+                None
+            }
+        }
     }
 
     fn initialize(&mut self) {
@@ -127,14 +427,83 @@ impl Camera {
         self.pixel_delta_u = viewport_u / self.image_width as f64;
         self.pixel_delta_v = viewport_v / self.image_height as f64;
 
-        // Calculate the location of the upper left pixel.
-        let viewport_upper_left = self.center - (self.focus_dist * self.w) - viewport_u / 2. - viewport_v / 2.;
+        // Calculate the location of the upper left pixel, moved by the lens shift.
+        let viewport_upper_left = self.center - (self.focus_dist * self.w) - viewport_u / 2. - viewport_v / 2.
+            + self.lens_shift_u * viewport_u
+            - self.lens_shift_v * viewport_v;
         self.pixel00_loc = viewport_upper_left + 0.5 * (self.pixel_delta_u + self.pixel_delta_v);
 
         // Calculate the camera defocus disk basis vectors
         let defocus_radius = self.focus_dist * degrees_to_radians(self.defocus_angle / 2.0).tan();
         self.defocus_disk_u = self.u * defocus_radius;
-        self.defocus_disk_v = self.v * defocus_radius
+        self.defocus_disk_v = self.v * defocus_radius;
+
+        // Tilt the plane of focus around u, then swing it around v (Scheimpflug principle).
+        let tilt = degrees_to_radians(self.focus_tilt);
+        let swing = degrees_to_radians(self.focus_swing);
+        let tilted = tilt.cos() * self.w + tilt.sin() * self.v;
+        self.focus_normal = unit_vector(&(swing.cos() * tilted + swing.sin() * self.u));
+
+        self.sampled_lights = self.lights.clone();
+        self.sampled_lights.extend(self.sky.iter().filter_map(|sky| sky.sun_light()));
+    }
+
+    pub(crate) fn center(&self) -> Vec3 {
+        self.center
+    }
+
+    pub(crate) fn is_pinhole(&self) -> bool {
+        self.defocus_angle <= 0.0
+    }
+
+    pub(crate) fn sampled_lights(&self) -> &[Light] {
+        &self.sampled_lights
+    }
+
+    pub(crate) fn first_area_light(&self) -> usize {
+        self.first_area_light
+    }
+
+    pub(crate) fn pixel_samples_scale(&self) -> f64 {
+        self.pixel_samples_scale
+    }
+
+    pub(crate) fn raster_position(&self, p: &Vec3) -> Option<(usize, usize)> {
+        // Pixel the pinhole camera sees the point in, none if it's outside of the image.
+        let offset = *p - self.center;
+        let depth = dot(&offset, &-self.w);
+        if depth <= 0.0 {
+            return None;
+        }
+        let on_viewport = self.center + offset * (self.focus_dist / depth);
+        let upper_left = self.pixel00_loc - 0.5 * (self.pixel_delta_u + self.pixel_delta_v);
+        let x = dot(&(on_viewport - upper_left), &self.pixel_delta_u) / self.pixel_delta_u.length_squared();
+        let y = dot(&(on_viewport - upper_left), &self.pixel_delta_v) / self.pixel_delta_v.length_squared();
+        if x < 0.0 || y < 0.0 || x >= self.image_width as f64 || y >= self.image_height as f64 {
+            return None;
+        }
+        Some((x as usize, y as usize))
+    }
+
+    pub(crate) fn direction_pdf(&self, direction: &Vec3) -> f64 {
+        // Density of pinhole camera rays in the direction, per solid angle: uniform on the
+        // viewport, which has the area `a` at distance 1, so 1 / (a cos^3) with the angle to
+        // the view axis. 0 outside of the image.
+        if self.raster_position(&(self.center + *direction)).is_none() {
+            return 0.0;
+        }
+        let cos_theta = dot(&unit_vector(direction), &-self.w);
+        let area = (self.pixel_delta_u.length() * self.image_width as f64)
+            * (self.pixel_delta_v.length() * self.image_height as f64)
+            / (self.focus_dist * self.focus_dist);
+        1.0 / (area * cos_theta * cos_theta * cos_theta)
+    }
+
+    fn pixel_seed(&self, seed: u64, i: u64, j: u64) -> u64 {
+        // Each pixel gets its own random sequence, so a pixel always looks the same no matter
+        // in which order the image is rendered.
+        let pixel_index = j * self.image_width + i;
+        seed ^ pixel_index.wrapping_add(1).wrapping_mul(0x9E37_79B9_7F4A_7C15)
     }
 
     fn get_ray(&self, i: u64, j: u64) -> Ray {
@@ -146,48 +515,439 @@ impl Camera {
             + ((i as f64 + offset.x()) * self.pixel_delta_u)
             + ((j as f64 + offset.y()) * self.pixel_delta_v);
 
-        let ray_origin;
         if self.defocus_angle <= 0.0 {
-            ray_origin = self.center;
-        } else {
-            ray_origin = self.defocus_disk_sample();
+            return Ray::new(&self.center, &(pixel_sample - self.center));
         }
 
-        let ray_direction = pixel_sample - ray_origin;
+        // The pinhole ray through the pixel sample meets the plane of focus at the point where
+        // all rays from the lens converge. Without tilt this is the pixel sample itself.
+        // Strongly tilted planes are parallel to the pinhole ray or behind the camera, the ray
+        // is then focused at infinity.
+        let pinhole_direction = pixel_sample - self.center;
+        let ray_origin = self.defocus_disk_sample(i, j);
+        let cos_focus = dot(&-unit_vector(&pinhole_direction), &self.focus_normal);
+        let focus_plane = self.focus_dist * dot(&self.w, &self.focus_normal);
+        let ray_direction = if cos_focus > 1e-3 && focus_plane > 0.0 {
+            let t = focus_plane / dot(&-pinhole_direction, &self.focus_normal);
+            self.center + t * pinhole_direction - ray_origin
+        } else {
+            pinhole_direction
+        };
 
         Ray::new(&ray_origin, &ray_direction)
     }
 
     fn sample_square(&self) -> Vec3 {
         // Returns the vector to a random point in the [-.5,-.5]-[+.5,+.5] unit square.
-        return vec3(random_double() - 0.5, random_double() - 0.5, 0.0);
+        vec3(random_double() - 0.5, random_double() - 0.5, 0.0)
     }
-    
-    fn defocus_disk_sample(&self) -> Vec3 {
-        let p = random_in_unit_disk();
+
+    fn defocus_disk_sample(&self, i: u64, j: u64) -> Vec3 {
+        let p = self.aperture_sample(i, j);
         self.center + (p[0] * self.defocus_disk_u) + (p[1] * self.defocus_disk_v)
     }
 
-    fn ray_color(&self, r: &Ray, depth: usize, world: &HittableList) -> Vec3 {
-        // If we've exceeded the ray bounce limit, no more light is gathered.
-        if depth <= 0 {
-            return color(0.0, 0.0, 0.0);
+    fn aperture_sample(&self, i: u64, j: u64) -> Vec3 {
+        if self.vignetting <= 0.0 {
+            return self.aperture.sample();
+        }
+
+        // Optical vignetting: towards the image border the lens barrel cuts off part of the
+        // aperture, which turns round highlights into cat's eyes. The barrel opening is modeled
+        // as a unit circle shifted along the pixel's offset from the image center.
+        let half_width = self.image_width as f64 / 2.0;
+        let half_height = self.image_height as f64 / 2.0;
+        let half_diagonal = (half_width * half_width + half_height * half_height).sqrt();
+        let offset = vec3(i as f64 + 0.5 - half_width, half_height - (j as f64 + 0.5), 0.0) / half_diagonal;
+        let barrel_center = self.vignetting.min(1.0) * offset;
+
+        // Apertures open only outside of the barrel opening never give a sample inside, those
+        // pixels get the sample closest to it.
+        let mut closest = self.aperture.sample();
+        for _ in 0..MAX_APERTURE_ATTEMPTS {
+            if (closest - barrel_center).length_squared() <= 1.0 {
+                break;
+            }
+            let p = self.aperture.sample();
+            if (p - barrel_center).length_squared() < (closest - barrel_center).length_squared() {
+                closest = p;
+            }
+        }
+        closest
+    }
+
+    fn ray_color(&self, r: &Ray, world: &HittableList, mut aov: Option<&mut AovSample>) -> Vec3 {
+        // Iterative path tracing loop. `throughput` is the product of all attenuations along the
+        // path so far, i.e. the weight of light found at the current path vertex. If given,
+        // `aov` receives the first hit and the light split into direct and indirect.
+        if self.spectral {
+            return self.ray_color_spectral(r, world, aov);
+        }
+
+        let mut ray = *r;
+        let mut throughput = color(1.0, 1.0, 1.0);
+        let mut radiance = color(0.0, 0.0, 0.0);
+        let mut segments = 0;
+        // Density of the last scattered direction and the normal the lights were chosen with,
+        // if the lights were sampled there as well.
+        let mut scattering_pdf = None;
+
+        // Paths end by russian roulette, the bounce limit is only a safety net.
+        for depth in 0..MAX_BOUNCES {
+            let mut rec: HitRecord = HitRecord::default();
+
+            count_ray(if depth == 0 { RayKind::Primary } else { RayKind::Secondary });
+            segments += 1;
+
+            if !world.hit(&ray, interval(0.001, f64::INFINITY), &mut rec) {
+                let contribution = throughput * self.escaped(&ray, scattering_pdf);
+                if let Some(aov) = aov.as_deref_mut() {
+                    aov.add_light(depth, contribution);
+                }
+                radiance = radiance + contribution;
+                break;
+            }
+
+            // Rays hitting the inside of a surface with a medium have travelled through it.
+            if let Some(medium) = rec.mat.interior_medium(&rec) {
+                match medium.random_walk(&mut ray, &mut rec, world) {
+                    Some(weight) => throughput = throughput * weight,
+                    None => break,
+                }
+            }
+
+            rec.normal = rec.mat.shading_normal(&rec);
+            if depth == 0 {
+                if let Some(aov) = aov.as_deref_mut() {
+                    aov.record_hit(&rec, dot(&(rec.p - self.center), &-self.w), world.material_id(&rec.mat));
+                }
+            }
+
+            let emitted = self.emitted(&ray, &rec, scattering_pdf);
+            if !emitted.near_zero() {
+                let contribution = throughput * emitted;
+                if let Some(aov) = aov.as_deref_mut() {
+                    aov.add_light(depth, contribution);
+                }
+                radiance = radiance + contribution;
+            }
+
+            let direct = self.sample_lights(&ray, &rec, world);
+            if let Some(direct) = direct {
+                let contribution = throughput * direct;
+                if let Some(aov) = aov.as_deref_mut() {
+                    aov.add_light(depth + 1, contribution);
+                }
+                radiance = radiance + contribution;
+            }
+
+            let (hit_bool, attenuation, scattered) = rec.mat.scatter(&ray, &rec);
+            if !hit_bool {
+                break;
+            }
+
+            scattering_pdf = direct.map(|_| (rec.mat.scattering_pdf(&ray, &rec, &scattered), light_sampling_normal(&rec)));
+            throughput = throughput * attenuation;
+            ray = scattered;
+
+            // Russian roulette: after the minimum depth, continue the path with a probability
+            // following its throughput and boost the survivors by the inverse, which keeps the
+            // estimate unbiased while dark paths are terminated early.
+            let largest = throughput.x().max(throughput.y()).max(throughput.z());
+            if let Some(survival) = self.survival(depth + 1, largest) {
+                if random_double() >= survival {
+                    break;
+                }
+                throughput = throughput / survival;
+            }
+        }
+
+        count_path(segments);
+        radiance
+    }
+
+    fn ray_color_spectral(&self, r: &Ray, world: &HittableList, mut aov: Option<&mut AovSample>) -> Vec3 {
+        // The same path tracing loop with the throughput at the sampled wavelengths. The
+        // background is upsampled to a spectrum, the light found is converted back to RGB.
+        let wavelengths = SampledWavelengths::sample();
+        let mut ray = *r;
+        let mut throughput: Spectrum = [1.0; WAVELENGTHS];
+        let mut radiance = color(0.0, 0.0, 0.0);
+        let mut segments = 0;
+        let mut scattering_pdf = None;
+
+        for depth in 0..MAX_BOUNCES {
+            let mut rec: HitRecord = HitRecord::default();
+
+            count_ray(if depth == 0 { RayKind::Primary } else { RayKind::Secondary });
+            segments += 1;
+
+            if !world.hit(&ray, interval(0.001, f64::INFINITY), &mut rec) {
+                let background = wavelengths.upsample(&self.escaped(&ray, scattering_pdf));
+                let mut light = throughput;
+                for (l, b) in light.iter_mut().zip(background) {
+                    *l *= b;
+                }
+                let contribution = wavelengths.to_rgb(&light);
+                if let Some(aov) = aov.as_deref_mut() {
+                    aov.add_light(depth, contribution);
+                }
+                radiance = radiance + contribution;
+                break;
+            }
+
+            if let Some(medium) = rec.mat.interior_medium(&rec) {
+                match medium.random_walk(&mut ray, &mut rec, world) {
+                    Some(weight) => {
+                        for (t, w) in throughput.iter_mut().zip(wavelengths.upsample(&weight)) {
+                            *t *= w;
+                        }
+                    }
+                    None => break,
+                }
+            }
+
+            rec.normal = rec.mat.shading_normal(&rec);
+            if depth == 0 {
+                if let Some(aov) = aov.as_deref_mut() {
+                    aov.record_hit(&rec, dot(&(rec.p - self.center), &-self.w), world.material_id(&rec.mat));
+                }
+            }
+
+            let emitted = self.emitted(&ray, &rec, scattering_pdf);
+            if !emitted.near_zero() {
+                let mut light = wavelengths.upsample(&emitted);
+                for (l, t) in light.iter_mut().zip(throughput) {
+                    *l *= t;
+                }
+                let contribution = wavelengths.to_rgb(&light);
+                if let Some(aov) = aov.as_deref_mut() {
+                    aov.add_light(depth, contribution);
+                }
+                radiance = radiance + contribution;
+            }
+
+            // Dispersion sends every wavelength its own way. Only the hero wavelength is
+            // followed further, weighted up for the dropped ones to keep the estimate unbiased.
+            if rec.mat.is_dispersive() && throughput[1..].iter().any(|t| *t != 0.0) {
+                throughput[0] *= WAVELENGTHS as f64;
+                throughput[1..].fill(0.0);
+            }
+
+            let direct = self.sample_lights(&ray, &rec, world);
+            if let Some(direct) = direct {
+                let mut light = wavelengths.upsample(&direct);
+                for (l, t) in light.iter_mut().zip(throughput) {
+                    *l *= t;
+                }
+                let contribution = wavelengths.to_rgb(&light);
+                if let Some(aov) = aov.as_deref_mut() {
+                    aov.add_light(depth + 1, contribution);
+                }
+                radiance = radiance + contribution;
+            }
+
+            let (hit_bool, attenuation, scattered) = rec.mat.scatter_spectral(&ray, &rec, &wavelengths);
+            if !hit_bool {
+                break;
+            }
+
+            scattering_pdf = direct.map(|_| (rec.mat.scattering_pdf(&ray, &rec, &scattered), light_sampling_normal(&rec)));
+
+            for (t, a) in throughput.iter_mut().zip(attenuation) {
+                *t *= a;
+            }
+            ray = scattered;
+
+            let largest = throughput.iter().fold(0.0, |max: f64, t| max.max(*t));
+            if let Some(survival) = self.survival(depth + 1, largest) {
+                if random_double() >= survival {
+                    break;
+                }
+                for t in throughput.iter_mut() {
+                    *t /= survival;
+                }
+            }
         }
 
-        let mut rec: HitRecord = HitRecord::default();
+        count_path(segments);
+        radiance
+    }
 
-        if world.hit(r, interval(0.001, INFINITY), &mut rec) {
-            let (hit_bool, attenuation, scattered) = rec.mat.scatter(r, &rec);
+    pub(crate) fn survival(&self, bounces: usize, throughput: f64) -> Option<f64> {
+        // Probability of a path with the largest throughput component `throughput` to continue
+        // after `bounces` bounces, none before russian roulette starts. Past `max_depth` the
+        // paths are ended sooner, which costs noise instead of the light of the longer paths.
+        if bounces < self.rr_min_depth.min(self.max_depth) {
+            return None;
+        }
+        let limit = if bounces < self.max_depth { 0.95 } else { DEEP_SURVIVAL };
+        Some(throughput.min(limit))
+    }
 
-            if hit_bool {
-                return attenuation * self.ray_color(&scattered, depth - 1, world);
+    fn sample_lights(&self, r_in: &Ray, rec: &HitRecord, world: &HittableList) -> Option<Vec3> {
+        // Light arriving directly from the lights and scattered along the path, one shadow ray
+        // per light or for a single chosen light. `None` for materials scattering (partly)
+        // specularly, which can't be lit this way.
+        if self.sampled_lights.is_empty() {
+            return None;
+        }
+        if let LightSampler::All = self.light_sampler {
+            let mut direct = Vec3::default();
+            for light in &self.sampled_lights {
+                direct = direct + self.light_contribution(light, 1.0, r_in, rec, world)?;
             }
+            return Some(direct);
+        }
 
-            return color(0.0, 0.0, 0.0);
+        match self.light_sampler.sample(&rec.p, &light_sampling_normal(rec)) {
+            Some((index, pmf)) => self.light_contribution(&self.sampled_lights[index], pmf, r_in, rec, world),
+            // No light reaches the point, but it still matters whether it could have.
+            None => rec.mat.eval(r_in, rec, &rec.normal).map(|_| Vec3::default()),
         }
+    }
 
+    fn light_contribution(&self, light: &Light, pmf: f64, r_in: &Ray, rec: &HitRecord, world: &HittableList) -> Option<Vec3> {
+        // Light from a single light chosen with probability `pmf`.
+        let sample = light.sample(&rec.p);
+        let value = rec.mat.eval(r_in, rec, &sample.direction)?;
+        if value.near_zero() || sample.pdf <= 0.0 {
+            return Some(Vec3::default());
+        }
+        count_ray(RayKind::Shadow);
+        let shadow_ray = Ray::new(&rec.p, &sample.direction);
+        if world.occluded(&shadow_ray, interval(0.001, sample.distance - 0.001)) {
+            return Some(Vec3::default());
+        }
+        // Delta lights can only be found by sampling them.
+        let pdf = pmf * sample.pdf;
+        let weight = if light.is_delta() {
+            1.0
+        } else {
+            power_heuristic(pdf, rec.mat.scattering_pdf(r_in, rec, &shadow_ray))
+        };
+        Some(weight * value * sample.radiance / pdf)
+    }
+
+    fn emitted(&self, r: &Ray, rec: &HitRecord, scattering_pdf: Option<(f64, Vec3)>) -> Vec3 {
+        // Light emitted at the hit. Area lights found this way, which could have been sampled at
+        // the last scattering, share their contribution with that sample. Other glowing
+        // materials, like fire, are only found by scattering and count fully.
+        let emitted = rec.mat.emitted(rec);
+        let (Some(light), Some((pdf, normal))) = (rec.light, scattering_pdf) else {
+            return emitted;
+        };
+        let index = self.first_area_light + light;
+        let pmf = self.light_sampler.pmf(&r.origin(), &normal, index);
+        emitted * power_heuristic(pdf, pmf * self.sampled_lights[index].pdf(&r.origin(), &r.direction()))
+    }
+
+    fn escaped(&self, r: &Ray, scattering_pdf: Option<(f64, Vec3)>) -> Vec3 {
+        // Light arriving along a ray leaving the scene. Lights found this way, which could have
+        // been sampled at the last scattering, share their contribution with that sample.
+        let mut light = self.background(r);
+        for (index, source) in self.sampled_lights.iter().enumerate() {
+            let radiance = source.escaped(&r.direction());
+            if radiance.near_zero() {
+                continue;
+            }
+            // Only infinite lights are found this way, and they are chosen independently of the
+            // shading point.
+            let weight = match scattering_pdf {
+                Some((pdf, _)) => {
+                    let pmf = self.light_sampler.pmf(&r.origin(), &Vec3::default(), index);
+                    power_heuristic(pdf, pmf * source.pdf(&r.origin(), &r.direction()))
+                }
+                None => 1.0,
+            };
+            light = light + weight * radiance;
+        }
+        light
+    }
+
+    pub(crate) fn background(&self, r: &Ray) -> Vec3 {
+        if let Some(sky) = &self.sky {
+            return sky.radiance(&r.direction());
+        }
         let unit_direction = unit_vector(&r.direction());
         let a = 0.5 * (unit_direction.y() + 1.0);
         (1.0 - a) * color(1.0, 1.0, 1.0) + a * color(0.5, 0.7, 1.0)
     }
 }
+
+fn light_sampling_normal(rec: &HitRecord) -> Vec3 {
+    // Normal of the shading point to choose lights with, zero in volumes which receive light
+    // from all directions.
+    if rec.mat.is_volume() {
+        Vec3::default()
+    } else {
+        rec.normal
+    }
+}
+
+#[test]
+fn lens_test() {
+    use crate::aperture::ApertureMask;
+    use crate::image::Image;
+    use std::sync::Arc;
+
+    // A mask open only at its left edge lies completely outside the barrel opening in the top
+    // right corner. Sampling gives up there instead of looping forever.
+    let mask = ApertureMask::from_image(&Image::parse_ppm(b"P2 4 1 1\n1 0 0 0\n").unwrap()).unwrap();
+    let mut cam = Camera {
+        image_width: 8,
+        defocus_angle: 5.0,
+        aperture: Aperture::Mask(Arc::new(mask)),
+        vignetting: 1.0,
+        ..Camera::default()
+    };
+    cam.initialize();
+    let p = cam.aperture_sample(7, 0);
+    assert!(p.x() <= -0.5 && p.x() >= -1.0);
+
+    // Planes of focus tilted beyond the view directions focus at infinity, rays still go
+    // forward.
+    for tilt in [60.0, 89.0, 120.0] {
+        cam.focus_tilt = tilt;
+        cam.initialize();
+        for (i, j) in [(0, 0), (7, 7), (3, 4)] {
+            let ray = cam.get_ray(i, j);
+            let forward = dot(&unit_vector(&ray.direction()), &-cam.w);
+            assert!(forward.is_finite() && forward > 0.0, "tilt {}: {}", tilt, forward);
+        }
+    }
+}
+
+#[test]
+fn depth_test() {
+    use std::f64::consts::PI;
+
+    use crate::light::point_light;
+    use crate::material::lambertian;
+    use crate::sphere::sphere;
+
+    // Inside a diffuse sphere (an integrating sphere) lit by a point light at its center, all
+    // light reflected once is spread evenly over the walls, which then have the radiance
+    // albedo / (1 - albedo) * I / (pi r^2) of all bounces. A small maximum depth only ends
+    // paths sooner, it doesn't lose the light of the longer ones (which would be 1.44 here).
+    let albedo = 0.8;
+    let walls = lambertian(color(albedo, albedo, albedo));
+    let world = HittableList::new(vec![Box::new(sphere(&point3(0.0, 0.0, 0.0), 1.0, &walls))]);
+    let mut cam = Camera {
+        image_width: 8,
+        samples_per_pixel: 256,
+        max_depth: 2,
+        seed: Some(9),
+        look_from: point3(0.2, 0.3, 0.0),
+        look_at: point3(0.2, 0.3, -1.0),
+        lights: vec![point_light(point3(0.0, 0.0, 0.0), color(PI, PI, PI))],
+        ..Camera::default()
+    };
+    let expected = albedo / (1.0 - albedo);
+    for integrator in ALL_INTEGRATORS {
+        cam.integrator = integrator;
+        let image = cam.render_buffers(&world).beauty;
+        let mean = image.pixels.iter().map(|p| p.x()).sum::<f64>() / image.pixels.len() as f64;
+        assert!((mean - expected).abs() < 0.03 * expected, "{} instead of {} with {}", mean, expected, integrator.name());
+    }
+}
@@ -0,0 +1,301 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::aov::{Aov, RenderBuffers};
+use crate::camera::Integrator;
+use crate::image::invalid_data;
+use crate::rtweekend::RandomState;
+use crate::vec3::*;
+
+const MAGIC: &[u8] = b"RTCHECKPOINT2\n";
+
+/// Where and how often a render saves its progress. A render finding a checkpoint at the
+/// path continues from it, the file is removed once the render is complete.
+#[derive(Debug, Clone)]
+pub struct CheckpointOptions {
+    pub path: String,
+    pub interval: Duration, // Minimum time between two saves
+}
+
+pub fn checkpoint_options(path: &str) -> CheckpointOptions {
+    CheckpointOptions {
+        path: path.to_string(),
+        interval: Duration::from_secs(60),
+    }
+}
+
+/// Settings a checkpoint can only be resumed with, anything else would change the image.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderSettings {
+    pub width: usize,
+    pub height: usize,
+    pub samples_per_pixel: usize,
+    pub max_depth: usize,
+    pub seed: Option<u64>,
+    pub spectral: bool,
+    pub integrator: Integrator,
+    pub aovs: Vec<Aov>,
+    pub setup_hash: u64, // Hash of the camera pose, the lighting and the scene, see `setup_hash`
+}
+
+pub fn setup_hash(setup: &str) -> u64 {
+    // 64 bit FNV-1a of a description of the camera and lighting setup. Unlike the hashers of
+    // the standard library it's the same in every build, checkpoints outlive the binary.
+    setup.bytes().fold(0xcbf29ce484222325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+}
+
+/// Progress of a render. Pixels are rendered row by row, the first `completed_pixels` hold all
+/// of their samples, the others none. Bidirectional renders also splat light traced from the
+/// lights onto any pixel, the others hold what landed on them so far. The random state is the
+/// one to continue with at the next pixel, which only matters for renders without a seed.
+#[derive(Debug, Clone)]
+pub struct Checkpoint {
+    pub settings: RenderSettings,
+    pub completed_pixels: usize,
+    pub random_state: RandomState,
+    pub buffers: RenderBuffers,
+}
+
+impl Checkpoint {
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        // Written next to the target and renamed over it, so a render killed while saving
+        // still leaves the previous checkpoint behind.
+        let path = path.as_ref();
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(".tmp");
+        fs::write(&temp, self.to_bytes())?;
+        fs::rename(&temp, path)
+    }
+
+    pub fn read<P: AsRef<Path>>(path: P) -> io::Result<Checkpoint> {
+        Checkpoint::parse(&fs::read(path)?)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        // Little endian binary, the pixels as 64 bit floats to continue exactly.
+        let settings = &self.settings;
+        let mut out = MAGIC.to_vec();
+        for value in [settings.width, settings.height, settings.samples_per_pixel, settings.max_depth] {
+            out.extend((value as u64).to_le_bytes());
+        }
+        out.push(settings.seed.is_some() as u8);
+        out.extend(settings.seed.unwrap_or(0).to_le_bytes());
+        out.push(settings.spectral as u8);
+        out.push(settings.integrator.name().len() as u8);
+        out.extend(settings.integrator.name().as_bytes());
+        out.extend(settings.setup_hash.to_le_bytes());
+        out.extend((settings.aovs.len() as u64).to_le_bytes());
+        for aov in &settings.aovs {
+            out.push(aov.name().len() as u8);
+            out.extend(aov.name().as_bytes());
+        }
+
+        out.extend((self.completed_pixels as u64).to_le_bytes());
+        out.extend(self.random_state.seed);
+        out.extend(self.random_state.stream.to_le_bytes());
+        out.extend(self.random_state.word_pos.to_le_bytes());
+
+        let images = std::iter::once(&self.buffers.beauty).chain(self.buffers.aovs.iter().map(|(_, image)| image));
+        for image in images {
+            for pixel in &image.pixels {
+                for c in 0..3 {
+                    out.extend(pixel[c].to_le_bytes());
+                }
+            }
+        }
+        out
+    }
+
+    pub fn parse(bytes: &[u8]) -> io::Result<Checkpoint> {
+        let mut reader = Reader { bytes, pos: 0 };
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(invalid_data("not a render checkpoint".to_string()));
+        }
+
+        let width = reader.u64()? as usize;
+        let height = reader.u64()? as usize;
+        let samples_per_pixel = reader.u64()? as usize;
+        let max_depth = reader.u64()? as usize;
+        let has_seed = reader.take(1)?[0] != 0;
+        let seed = reader.u64()?;
+        let spectral = reader.take(1)?[0] != 0;
+        let length = reader.take(1)?[0] as usize;
+        let name = String::from_utf8_lossy(reader.take(length)?).to_string();
+        let integrator =
+            Integrator::from_name(&name).ok_or_else(|| invalid_data(format!("unknown integrator '{}'", name)))?;
+        let setup_hash = reader.u64()?;
+        let aov_count = reader.u64()? as usize;
+        let mut aovs = Vec::new();
+        for _ in 0..aov_count {
+            let length = reader.take(1)?[0] as usize;
+            let name = String::from_utf8_lossy(reader.take(length)?).to_string();
+            aovs.push(Aov::from_name(&name).ok_or_else(|| invalid_data(format!("unknown pass '{}'", name)))?);
+        }
+
+        let completed_pixels = reader.u64()? as usize;
+        let pixel_count = width
+            .checked_mul(height)
+            .ok_or_else(|| invalid_data(format!("{}x{} image is too large", width, height)))?;
+        if completed_pixels > pixel_count {
+            return Err(invalid_data(format!("{} completed pixels in a {}x{} image", completed_pixels, width, height)));
+        }
+        let random_state = RandomState {
+            seed: reader.take(32)?.try_into().unwrap(),
+            stream: reader.u64()?,
+            word_pos: u128::from_le_bytes(reader.take(16)?.try_into().unwrap()),
+        };
+
+        let raster_size = (aovs.len() + 1).checked_mul(pixel_count).and_then(|n| n.checked_mul(24));
+        if raster_size != Some(bytes.len() - reader.pos) {
+            return Err(invalid_data("checkpoint size doesn't match the image size".to_string()));
+        }
+        let mut buffers = RenderBuffers::new(width, height, &aovs);
+        let images = std::iter::once(&mut buffers.beauty).chain(buffers.aovs.iter_mut().map(|(_, image)| image));
+        for image in images {
+            for pixel in image.pixels.iter_mut() {
+                *pixel = vec3(reader.f64()?, reader.f64()?, reader.f64()?);
+            }
+        }
+
+        Ok(Checkpoint {
+            settings: RenderSettings {
+                width,
+                height,
+                samples_per_pixel,
+                max_depth,
+                seed: has_seed.then_some(seed),
+                spectral,
+                integrator,
+                aovs,
+                setup_hash,
+            },
+            completed_pixels,
+            random_state,
+            buffers,
+        })
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> io::Result<&'a [u8]> {
+        let slice = self
+            .bytes
+            .get(self.pos..self.pos + count)
+            .ok_or_else(|| invalid_data("truncated checkpoint".to_string()))?;
+        self.pos += count;
+        Ok(slice)
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn f64(&mut self) -> io::Result<f64> {
+        Ok(f64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
+
+#[test]
+fn resume_checkpoint_test() {
+    use crate::camera::Camera;
+    use crate::hittable::Hittable;
+    use crate::hittable_list::HittableList;
+    use crate::material::lambertian;
+    use crate::rtweekend::random_state;
+    use crate::sphere::sphere;
+
+    let material = lambertian(vec3(0.5, 0.5, 0.5));
+    let objects: Vec<Box<dyn Hittable>> = vec![
+        Box::new(sphere(&point3(0.0, 0.0, -1.0), 0.5, &material)),
+        Box::new(sphere(&point3(0.0, -100.5, -1.0), 100.0, &material)),
+    ];
+    let world = HittableList::new(objects);
+
+    let mut cam = Camera::default();
+    cam.image_width = 8;
+    cam.samples_per_pixel = 4;
+    cam.seed = Some(5);
+    cam.aovs = vec![Aov::Albedo];
+    let expected = cam.render_buffers(&world);
+
+    // A render killed after 20 pixels. The first pixel is marked to tell whether the finished
+    // pixels are taken from the checkpoint.
+    let mut partial = RenderBuffers::new(8, 8, &cam.aovs);
+    for index in 0..20 {
+        partial.beauty.pixels[index] = expected.beauty.pixels[index];
+        partial.aovs[0].1.pixels[index] = expected.aovs[0].1.pixels[index];
+    }
+    partial.beauty.pixels[0] = vec3(-1.0, -1.0, -1.0);
+    let mut checkpoint = Checkpoint {
+        settings: RenderSettings {
+            width: 8,
+            height: 8,
+            samples_per_pixel: 4,
+            max_depth: cam.max_depth,
+            seed: Some(5),
+            spectral: false,
+            integrator: cam.integrator,
+            aovs: vec![Aov::Albedo],
+            setup_hash: cam.setup_hash(&world),
+        },
+        completed_pixels: 20,
+        random_state: random_state(),
+        buffers: partial,
+    };
+
+    let parsed = Checkpoint::parse(&checkpoint.to_bytes()).unwrap();
+    assert_eq!(parsed.settings, checkpoint.settings);
+    assert_eq!(parsed.buffers.beauty, checkpoint.buffers.beauty);
+    assert!(Checkpoint::parse(&checkpoint.to_bytes()[..100]).is_err());
+
+    let path = std::env::temp_dir().join(format!("checkpoint_test_{}.bin", std::process::id()));
+    let path_string = path.to_str().unwrap();
+    checkpoint.write(&path).unwrap();
+    cam.checkpoint = Some(checkpoint_options(path_string));
+    let resumed = cam.render_buffers(&world);
+
+    assert_eq!(resumed.beauty.pixels[0], vec3(-1.0, -1.0, -1.0));
+    assert_eq!(resumed.beauty.pixels[1..], expected.beauty.pixels[1..]);
+    assert_eq!(resumed.aovs[0].1, expected.aovs[0].1);
+    assert!(!path.exists());
+
+    // A checkpoint of a different render is ignored, also if only the view, the lights or the
+    // scene changed.
+    checkpoint.settings.samples_per_pixel = 8;
+    checkpoint.write(&path).unwrap();
+    let restarted = cam.render_buffers(&world);
+    assert_eq!(restarted.beauty, expected.beauty);
+
+    checkpoint.settings.samples_per_pixel = 4;
+    cam.look_from = point3(0.0, 0.1, 0.0);
+    checkpoint.write(&path).unwrap();
+    let moved = cam.render_buffers(&world);
+    assert_ne!(moved.beauty.pixels[0], vec3(-1.0, -1.0, -1.0));
+    assert!(!path.exists());
+
+    cam.look_from = point3(0.0, 0.0, 0.0);
+    let objects: Vec<Box<dyn Hittable>> = vec![
+        Box::new(sphere(&point3(0.2, 0.0, -1.0), 0.5, &material)),
+        Box::new(sphere(&point3(0.0, -100.5, -1.0), 100.0, &material)),
+    ];
+    let edited = HittableList::new(objects);
+    // The same scene built again, with materials made anew, still continues.
+    let copy = lambertian(vec3(0.5, 0.5, 0.5));
+    let objects: Vec<Box<dyn Hittable>> = vec![
+        Box::new(sphere(&point3(0.0, 0.0, -1.0), 0.5, &copy)),
+        Box::new(sphere(&point3(0.0, -100.5, -1.0), 100.0, &copy)),
+    ];
+    assert_eq!(cam.setup_hash(&HittableList::new(objects)), cam.setup_hash(&world));
+    assert_ne!(cam.setup_hash(&edited), cam.setup_hash(&world));
+    checkpoint.write(&path).unwrap();
+    let rebuilt = cam.render_buffers(&edited);
+    assert_ne!(rebuilt.beauty.pixels[0], vec3(-1.0, -1.0, -1.0));
+    assert!(!path.exists());
+}
@@ -0,0 +1,295 @@
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::aov::{Aov, RenderBuffers};
+use crate::camera::{Camera, Integrator};
+use crate::checkpoint::checkpoint_options;
+use crate::denoise::{denoise, DenoiseOptions};
+use crate::gltf_import::read_gltf;
+use crate::hittable::Hittable;
+use crate::image::Image;
+use crate::openexr::{exr_options, read_exr, write_exr};
+use crate::preview::{PreviewMode, PreviewOptions};
+use crate::sky::{sun_direction, Sky};
+use crate::stats::{record_phase, Phase, StatsOptions};
+use crate::vec3::*;
+
+const USAGE: &str = "usage:
+  ray_tracing_in_one_weekend_rs                    render the scene in main.rs to stdout
+  ray_tracing_in_one_weekend_rs render [options] <scene.gltf|scene.glb>
+  ray_tracing_in_one_weekend_rs denoise [options] <input.exr> <output>
+  ray_tracing_in_one_weekend_rs denoise [options] <beauty.pfm> <albedo.pfm> <normal.pfm> <output>
+
+render options (the image is written to stdout):
+  --camera <i>          camera of the scene to look through (default 0)
+  --width <n>           image width (default 400)
+  --samples <n>         samples per pixel (default 100)
+  --depth <n>           bounces after which paths survive at most half the time (default 50)
+  --seed <n>            seed for a reproducible render
+  --spectral            trace wavelengths instead of RGB, for dispersion in glass (with the
+                        path tracer only)
+  --integrator <name>   'path' for path tracing (default) or 'bdpt' for bidirectional path
+                        tracing, which finds caustics from small lights much faster
+  --sky <elevation>     light the scene by a physical sky with the sun at the elevation in degrees
+  --sun-azimuth <deg>   direction of the sun from -z towards +x (default 0)
+  --turbidity <t>       haze of the sky, 2 for very clear to 10 for hazy (default 3)
+  --ground-albedo <a>   reflectance of the ground below the horizon (default 0.3)
+  --stats               print ray counts and timings to stderr
+  --stats-json <path>   write ray counts and timings as JSON
+  --heatmap <path>      write the traversal cost per pixel as false color image
+  --checkpoint <path>   save the progress to the file and resume from it if it exists
+  --checkpoint-interval <seconds>  time between two saves (default 60)
+  --preview <mode>      show the render in progress in the terminal, 'blocks' or 'sixel'
+  --preview-width <n>   preview width in columns for blocks or pixels for sixel
+  --aov <names>         render passes to write as '<prefix>_<name>.pfm', separated by commas:
+                        depth, position, normal, albedo, material_id, object_id, direct,
+                        indirect and cost
+  --aov-prefix <path>   path prefix of the render pass files (default 'aov')
+  --exr <path>          write the image and the render passes into one OpenEXR file instead
+  --denoise             denoise the image with the default denoise options
+
+denoise options:
+  --iterations <n>      number of filter passes (default 5)
+  --sigma-color <s>     color edge stopping (default 1.0)
+  --sigma-normal <s>    normal edge stopping (default 0.3)
+  --sigma-albedo <s>    albedo edge stopping (default 0.1)
+
+The output format is chosen by its extension: .exr, .pfm or .ppm";
+
+/// Runs a command given on the command line (without the program name).
+pub fn run(args: &[String]) -> Result<(), String> {
+    match args[0].as_str() {
+        "render" => run_render(&args[1..]),
+        "denoise" => run_denoise(&args[1..]),
+        "help" | "--help" | "-h" => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        command => Err(format!("unknown command '{}'\n\n{}", command, USAGE)),
+    }
+}
+
+fn run_render(args: &[String]) -> Result<(), String> {
+    let mut camera_index = 0;
+    let (mut width, mut samples, mut depth, mut seed) = (400, 100, 50, None);
+    let mut stats: Option<StatsOptions> = None;
+    let mut spectral = false;
+    let mut integrator = Integrator::PathTracing;
+    let mut sun_elevation: Option<f64> = None;
+    let (mut sun_azimuth, mut turbidity, mut ground_albedo) = (0.0, 3.0, 0.3);
+    let (mut checkpoint, mut checkpoint_interval) = (None, None);
+    let (mut preview, mut preview_width): (Option<PreviewOptions>, Option<usize>) = (None, None);
+    let (mut aovs, mut aov_prefix, mut exr, mut denoise) = (Vec::new(), None, None, None);
+    let mut files = Vec::new();
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if !arg.starts_with("--") {
+            files.push(arg.as_str());
+            continue;
+        }
+        if arg == "--stats" {
+            stats.get_or_insert_with(quiet_stats).summary = true;
+            continue;
+        }
+        if arg == "--spectral" {
+            spectral = true;
+            continue;
+        }
+        if arg == "--denoise" {
+            denoise = Some(DenoiseOptions::default());
+            continue;
+        }
+        let value = iter.next().ok_or_else(|| format!("missing value for '{}'", arg))?;
+        match arg.as_str() {
+            "--camera" => camera_index = parse(arg, value)?,
+            "--aov" => aovs = parse_aovs(value)?,
+            "--aov-prefix" => aov_prefix = Some(value.clone()),
+            "--exr" => exr = Some(exr_options(value)),
+            "--stats-json" => stats.get_or_insert_with(quiet_stats).json = Some(value.clone()),
+            "--heatmap" => stats.get_or_insert_with(quiet_stats).heatmap = Some(value.clone()),
+            "--checkpoint" => checkpoint = Some(checkpoint_options(value)),
+            "--checkpoint-interval" => checkpoint_interval = Some(parse::<f64>(arg, value)?),
+            "--preview" => preview = Some(preview_options(value)?),
+            "--preview-width" => preview_width = Some(parse(arg, value)?),
+            "--width" => width = parse_count(arg, value)?,
+            "--samples" => samples = parse_count(arg, value)?,
+            "--depth" => depth = parse(arg, value)?,
+            "--seed" => seed = Some(parse(arg, value)?),
+            "--integrator" => {
+                integrator = Integrator::from_name(value)
+                    .ok_or_else(|| format!("unknown integrator '{}', expected 'path' or 'bdpt'", value))?
+            }
+            "--sky" => sun_elevation = Some(parse(arg, value)?),
+            "--sun-azimuth" => sun_azimuth = parse(arg, value)?,
+            "--turbidity" => turbidity = parse(arg, value)?,
+            "--ground-albedo" => ground_albedo = parse(arg, value)?,
+            _ => return Err(format!("unknown option '{}'\n\n{}", arg, USAGE)),
+        }
+    }
+
+    let [path] = files.as_slice() else {
+        return Err(USAGE.to_string());
+    };
+    if spectral && integrator == Integrator::Bidirectional {
+        return Err("'--spectral' needs the path tracer, 'bdpt' only renders RGB".to_string());
+    }
+    let build_start = Instant::now();
+    let mut scene = read_gltf(path).map_err(|e| format!("{}: {}", path, e))?;
+    record_phase(Phase::SceneBuild, build_start.elapsed());
+
+    let mut cam = if scene.cameras.is_empty() {
+        default_camera(&scene.world)
+    } else if camera_index < scene.cameras.len() {
+        scene.cameras.swap_remove(camera_index)
+    } else {
+        return Err(format!("{} has {} cameras", path, scene.cameras.len()));
+    };
+    cam.image_width = width;
+    cam.samples_per_pixel = samples;
+    cam.max_depth = depth;
+    cam.seed = seed;
+    cam.spectral = spectral;
+    cam.integrator = integrator;
+    cam.sky = sun_elevation.map(|elevation| {
+        Sky::new(sun_direction(elevation, sun_azimuth), turbidity, vec3(ground_albedo, ground_albedo, ground_albedo))
+    });
+    cam.stats = stats;
+    if let (Some(options), Some(seconds)) = (&mut checkpoint, checkpoint_interval) {
+        options.interval = Duration::from_secs_f64(seconds.max(0.0));
+    }
+    cam.checkpoint = checkpoint;
+    if let (Some(options), Some(width)) = (&mut preview, preview_width) {
+        options.width = width;
+    }
+    cam.preview = preview;
+    cam.aovs = aovs;
+    if let Some(prefix) = aov_prefix {
+        cam.aov_prefix = prefix;
+    }
+    cam.exr = exr;
+    cam.denoise = denoise;
+
+    cam.render(&scene.world);
+    Ok(())
+}
+
+fn preview_options(mode: &str) -> Result<PreviewOptions, String> {
+    // Sixel previews are measured in pixels, so they get a larger default width.
+    match mode {
+        "blocks" => Ok(PreviewOptions::default()),
+        "sixel" => Ok(PreviewOptions {
+            mode: PreviewMode::Sixel,
+            width: 480,
+            ..PreviewOptions::default()
+        }),
+        _ => Err(format!("unknown preview mode '{}', expected 'blocks' or 'sixel'", mode)),
+    }
+}
+
+fn parse_aovs(names: &str) -> Result<Vec<Aov>, String> {
+    names
+        .split(',')
+        .map(|name| Aov::from_name(name.trim()).ok_or_else(|| format!("unknown render pass '{}'", name)))
+        .collect()
+}
+
+fn quiet_stats() -> StatsOptions {
+    // Statistics requested only as file, without the summary on stderr.
+    StatsOptions {
+        summary: false,
+        ..StatsOptions::default()
+    }
+}
+
+fn default_camera(world: &dyn Hittable) -> Camera {
+    // Looks at the whole scene from the front, for scenes without a camera.
+    let bbox = world.bounding_box();
+    let center = point3(
+        0.5 * (bbox.x.min + bbox.x.max),
+        0.5 * (bbox.y.min + bbox.y.max),
+        0.5 * (bbox.z.min + bbox.z.max),
+    );
+    let radius = 0.5 * vec3(bbox.x.size(), bbox.y.size(), bbox.z.size()).length();
+
+    let mut cam = Camera::default();
+    cam.aspect_ratio = 16.0 / 9.0;
+    cam.vfov = 40.0;
+    cam.look_at = center;
+    cam.look_from = center + vec3(0.0, 0.0, radius / 20f64.to_radians().sin());
+    cam
+}
+
+fn run_denoise(args: &[String]) -> Result<(), String> {
+    let mut options = DenoiseOptions::default();
+    let mut files = Vec::new();
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if !arg.starts_with("--") {
+            files.push(arg.as_str());
+            continue;
+        }
+        let value = iter.next().ok_or_else(|| format!("missing value for '{}'", arg))?;
+        match arg.as_str() {
+            "--iterations" => options.iterations = parse(arg, value)?,
+            "--sigma-color" => options.sigma_color = parse(arg, value)?,
+            "--sigma-normal" => options.sigma_normal = parse(arg, value)?,
+            "--sigma-albedo" => options.sigma_albedo = parse(arg, value)?,
+            _ => return Err(format!("unknown option '{}'\n\n{}", arg, USAGE)),
+        }
+    }
+
+    let (mut buffers, output) = match files.as_slice() {
+        [input, output] => (read_exr(input).map_err(|e| format!("{}: {}", input, e))?, *output),
+        [beauty, albedo, normal, output] => {
+            let beauty = read_pfm(beauty)?;
+            let mut buffers = RenderBuffers::new(beauty.width, beauty.height, &[Aov::Albedo, Aov::Normal]);
+            buffers.beauty = beauty;
+            buffers.aovs[0].1 = read_pfm(albedo)?;
+            buffers.aovs[1].1 = read_pfm(normal)?;
+            (buffers, *output)
+        }
+        _ => return Err(USAGE.to_string()),
+    };
+
+    let albedo = buffers.aov(Aov::Albedo).ok_or("input has no albedo pass")?;
+    let normal = buffers.aov(Aov::Normal).ok_or("input has no normal pass")?;
+    for feature in [albedo, normal] {
+        if feature.width != buffers.beauty.width || feature.height != buffers.beauty.height {
+            return Err("passes don't have the size of the beauty image".to_string());
+        }
+    }
+
+    buffers.beauty = denoise(&buffers.beauty, albedo, normal, &options);
+    write_output(&buffers, output)
+}
+
+fn write_output(buffers: &RenderBuffers, path: &str) -> Result<(), String> {
+    let result = match Path::new(path).extension().and_then(|e| e.to_str()) {
+        Some("exr") => write_exr(&exr_options(path), buffers),
+        Some("pfm") => buffers.beauty.write_pfm(path),
+        Some("ppm") => buffers.beauty.write_ppm(path),
+        _ => return Err(format!("unknown output format of '{}'", path)),
+    };
+    result.map_err(|e| format!("{}: {}", path, e))
+}
+
+fn read_pfm(path: &str) -> Result<Image, String> {
+    Image::read_pfm(path).map_err(|e| format!("{}: {}", path, e))
+}
+
+fn parse_count<T: std::str::FromStr + PartialOrd + From<u8>>(option: &str, value: &str) -> Result<T, String> {
+    // Sizes and sample counts, of which an image needs at least one.
+    let count = parse(option, value)?;
+    if count < T::from(1) {
+        return Err(format!("'{}' must be at least 1", option));
+    }
+    Ok(count)
+}
+
+fn parse<T: std::str::FromStr>(option: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value '{}' for '{}'", value, option))
+}
@@ -5,14 +5,33 @@ pub fn color(x: f64, y: f64, z: f64) -> Vec3 {
     Vec3::builder().xyz(x, y, z).build()
 }
 
+pub fn luminance(c: &Vec3) -> f64 {
+    0.2126 * c.x() + 0.7152 * c.y() + 0.0722 * c.z()
+}
+
+pub fn srgb_to_linear(encoded: f64) -> f64 {
+    // Decodes an 8 bit style sRGB color component in [0,1], as used by most asset formats.
+    if encoded <= 0.04045 {
+        encoded / 12.92
+    } else {
+        ((encoded + 0.055) / 1.055).powf(2.4)
+    }
+}
+
 fn linear_to_gamma(linear_component: f64) -> f64 {
     if linear_component > 0.0 {
         return linear_component.sqrt();
     }
-    return 0.0;
+    0.0
 }
 
 pub fn write_color(pixel_color: &Vec3) {
+    let [rbyte, gbyte, bbyte] = color_to_bytes(pixel_color);
+
+    println!("{} {} {}", rbyte, gbyte, bbyte);
+}
+
+pub fn color_to_bytes(pixel_color: &Vec3) -> [u8; 3] {
     let mut r = pixel_color.x();
     let mut g = pixel_color.y();
     let mut b = pixel_color.z();
@@ -22,9 +41,9 @@ pub fn write_color(pixel_color: &Vec3) {
     b = linear_to_gamma(b);
 
     let intensity: Interval = interval(0.000, 0.999);
-    let rbyte = (256.0 * intensity.clamp(r)) as i32;
-    let gbyte = (256.0 * intensity.clamp(g)) as i32;
-    let bbyte = (256.0 * intensity.clamp(b)) as i32;
+    let rbyte = (256.0 * intensity.clamp(r)) as u8;
+    let gbyte = (256.0 * intensity.clamp(g)) as u8;
+    let bbyte = (256.0 * intensity.clamp(b)) as u8;
 
-    print!("{} {} {}\n", rbyte, gbyte, bbyte);
+    [rbyte, gbyte, bbyte]
 }
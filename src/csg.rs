@@ -0,0 +1,203 @@
+use crate::aabb::{aabb, Aabb};
+use crate::hittable::{HitRecord, HitSpan, Hittable, SceneIndex};
+use crate::interval::{interval, Interval};
+use crate::ray::Ray;
+use crate::stats::{count_intersection_test, Primitive};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CsgOperation {
+    Union,
+    Intersection,
+    Difference,
+}
+
+impl CsgOperation {
+    fn inside(&self, inside_a: bool, inside_b: bool) -> bool {
+        match self {
+            CsgOperation::Union => inside_a || inside_b,
+            CsgOperation::Intersection => inside_a && inside_b,
+            CsgOperation::Difference => inside_a && !inside_b,
+        }
+    }
+}
+
+pub fn csg_union(a: Box<dyn Hittable>, b: Box<dyn Hittable>) -> Csg {
+    Csg::new(CsgOperation::Union, a, b)
+}
+
+pub fn csg_intersection(a: Box<dyn Hittable>, b: Box<dyn Hittable>) -> Csg {
+    Csg::new(CsgOperation::Intersection, a, b)
+}
+
+pub fn csg_difference(a: Box<dyn Hittable>, b: Box<dyn Hittable>) -> Csg {
+    // Shape `a` with shape `b` carved out of it.
+    Csg::new(CsgOperation::Difference, a, b)
+}
+
+/// Constructive solid geometry node combining two closed shapes. Operands without an inside
+/// (which return no spans) count as empty.
+pub struct Csg {
+    operation: CsgOperation,
+    a: Box<dyn Hittable>,
+    b: Box<dyn Hittable>,
+}
+
+impl Csg {
+    pub fn new(operation: CsgOperation, a: Box<dyn Hittable>, b: Box<dyn Hittable>) -> Csg {
+        Csg { operation, a, b }
+    }
+}
+
+impl Hittable for Csg {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        // The nearest span boundary inside the interval, an entry or an exit.
+        let spans = self.hit_spans(r, ray_t).unwrap_or_default();
+        for span in spans {
+            for boundary in [span.enter, span.exit] {
+                if ray_t.surrounds(boundary.t) {
+                    *rec = boundary;
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    fn hit_spans(&self, r: &Ray, ray_t: Interval) -> Option<Vec<HitSpan>> {
+        count_intersection_test(Primitive::Csg);
+        let spans_a = self.a.hit_spans(r, ray_t).unwrap_or_default();
+        let spans_b = self.b.hit_spans(r, ray_t).unwrap_or_default();
+
+        // Sweep over all boundaries along the ray and keep track of being inside of each
+        // operand. The result has a boundary wherever the combined state changes.
+        let mut events: Vec<(HitRecord, bool, bool)> = Vec::with_capacity(2 * (spans_a.len() + spans_b.len()));
+        for (spans, is_a) in [(&spans_a, true), (&spans_b, false)] {
+            for span in spans.iter() {
+                events.push((span.enter.clone(), is_a, true));
+                events.push((span.exit.clone(), is_a, false));
+            }
+        }
+        events.sort_by(|x, y| x.0.t.total_cmp(&y.0.t));
+
+        let (mut inside_a, mut inside_b, mut inside) = (false, false, false);
+        let mut enter = HitRecord::default();
+        let mut result = Vec::new();
+
+        for (mut boundary, is_a, entering) in events {
+            if is_a {
+                inside_a = entering;
+            } else {
+                inside_b = entering;
+            }
+
+            if self.operation.inside(inside_a, inside_b) == inside {
+                continue;
+            }
+            inside = !inside;
+
+            // Hit normals always face the incoming ray, so they stay valid when a surface of
+            // `b` bounds the result from the other side. Only the facing has to follow the result.
+            boundary.front_face = inside;
+            if inside {
+                enter = boundary;
+            } else {
+                result.push(HitSpan {
+                    enter: enter.clone(),
+                    exit: boundary,
+                });
+            }
+        }
+
+        Some(result)
+    }
+
+    fn index_scene(&mut self, scene: &mut SceneIndex) {
+        scene.index_partial(|scene| {
+            self.a.index_scene(scene);
+            self.b.index_scene(scene);
+        });
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let (a, b) = (self.a.bounding_box(), self.b.bounding_box());
+        match self.operation {
+            CsgOperation::Union => Aabb::from_boxes(&a, &b),
+            CsgOperation::Intersection => {
+                let overlap = |x: Interval, y: Interval| interval(x.min.max(y.min), x.max.min(y.max));
+                aabb(overlap(a.x, b.x), overlap(a.y, b.y), overlap(a.z, b.z))
+            }
+            CsgOperation::Difference => a,
+        }
+    }
+}
+
+#[test]
+fn csg_test() {
+    use crate::interval::interval;
+    use crate::material::lambertian;
+    use crate::sphere::sphere;
+    use crate::vec3::*;
+
+    let material = lambertian(vec3(0.5, 0.5, 0.5));
+    let big = || Box::new(sphere(&point3(0.0, 0.0, 0.0), 1.0, &material));
+    let small = || Box::new(sphere(&point3(0.0, 0.0, 0.0), 0.5, &material));
+    let shifted = || Box::new(sphere(&point3(0.0, 0.0, 1.0), 0.5, &material));
+
+    let r = Ray::new(&point3(0.0, 0.0, 5.0), &vec3(0.0, 0.0, -1.0));
+    let full = interval(0.001, f64::INFINITY);
+    let spans = |shape: &Csg| -> Vec<(f64, f64)> {
+        shape
+            .hit_spans(&r, full)
+            .unwrap()
+            .iter()
+            .map(|s| (s.enter.t, s.exit.t))
+            .collect()
+    };
+
+    // A hollow sphere has two walls along the ray.
+    let hollow = csg_difference(big(), small());
+    assert_eq!(spans(&hollow), vec![(4.0, 4.5), (5.5, 6.0)]);
+    assert_eq!(spans(&csg_union(big(), shifted())), vec![(3.5, 6.0)]);
+    assert_eq!(spans(&csg_intersection(big(), shifted())), vec![(4.0, 4.5)]);
+
+    // Inside the first wall the next hit is the inner surface, which faces away from the hole.
+    let mut rec = HitRecord::default();
+    assert!(hollow.hit(&r, interval(4.2, f64::INFINITY), &mut rec));
+    assert_eq!(rec.t, 4.5);
+    assert!(!rec.front_face);
+    assert_eq!(rec.normal, vec3(0.0, 0.0, 1.0));
+
+    // Coming out of the hole, the ray enters the far wall through the inner surface.
+    assert!(hollow.hit(&r, interval(5.0, f64::INFINITY), &mut rec));
+    assert_eq!(rec.t, 5.5);
+    assert!(rec.front_face);
+    assert_eq!(rec.normal, vec3(0.0, 0.0, 1.0));
+
+    // Nested nodes, the sphere with a hole and the shifted sphere cut out of it.
+    let nested = csg_difference(Box::new(hollow), shifted());
+    assert_eq!(spans(&nested), vec![(5.5, 6.0)]);
+}
+
+#[test]
+fn csg_sdf_test() {
+    use crate::interval::interval;
+    use crate::material::lambertian;
+    use crate::sdf::{round_box, sdf_object};
+    use crate::sphere::sphere;
+    use crate::vec3::*;
+
+    // A rounded cube with a spherical hole, traced through the hole.
+    let material = lambertian(vec3(0.5, 0.5, 0.5));
+    let cube = sdf_object(Box::new(round_box(vec3(1.0, 1.0, 1.0), 0.1)), &material);
+    let hole = sphere(&point3(0.0, 0.0, 0.0), 0.5, &material);
+    let shape = csg_difference(Box::new(cube), Box::new(hole));
+
+    let r = Ray::new(&point3(0.0, 0.0, 5.0), &vec3(0.0, 0.0, -1.0));
+    let spans = shape.hit_spans(&r, interval(0.001, f64::INFINITY)).unwrap();
+    let expected = [(4.0, 4.5), (5.5, 6.0)];
+    assert_eq!(spans.len(), expected.len());
+    for (span, (enter, exit)) in spans.iter().zip(expected) {
+        assert!((span.enter.t - enter).abs() < 1e-3);
+        assert!((span.exit.t - exit).abs() < 1e-3);
+    }
+}
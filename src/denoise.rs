@@ -0,0 +1,156 @@
+use crate::image::Image;
+use crate::vec3::*;
+
+/// Settings of the edge-avoiding À-Trous wavelet filter.
+#[derive(Debug, Clone, Copy)]
+pub struct DenoiseOptions {
+    pub iterations: usize,  // Number of filter passes, the kernel footprint doubles with each pass
+    pub sigma_color: f64,   // Edge stopping on the noisy color, halved in every pass
+    pub sigma_normal: f64,  // Edge stopping on the shading normal
+    pub sigma_albedo: f64,  // Edge stopping on the surface albedo
+}
+
+impl Default for DenoiseOptions {
+    fn default() -> Self {
+        Self {
+            iterations: 5,
+            sigma_color: 1.0,
+            sigma_normal: 0.3,
+            sigma_albedo: 0.1,
+        }
+    }
+}
+
+// B3 spline kernel of the À-Trous transform.
+const KERNEL: [f64; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+const ALBEDO_EPSILON: f64 = 1e-3;
+
+pub fn denoise(beauty: &Image, albedo: &Image, normal: &Image, options: &DenoiseOptions) -> Image {
+    // The filter runs on the illumination only: dividing by the albedo keeps texture detail
+    // out of the blur, multiplying it back in at the end restores it.
+    let mut illumination = beauty.clone();
+    for (pixel, a) in illumination.pixels.iter_mut().zip(&albedo.pixels) {
+        *pixel = *pixel / demodulation(a);
+    }
+
+    // Once the step reaches the image size only the center pixel is left and the passes stop
+    // changing anything, so any number of iterations can be asked for.
+    let mut sigma_color = options.sigma_color;
+    for iteration in 0..options.iterations {
+        let step = 1usize.checked_shl(iteration as u32).unwrap_or(usize::MAX);
+        if step >= beauty.width.max(beauty.height) {
+            break;
+        }
+        illumination = atrous_pass(&illumination, albedo, normal, step, sigma_color, options);
+        sigma_color /= 2.0;
+    }
+
+    for (pixel, a) in illumination.pixels.iter_mut().zip(&albedo.pixels) {
+        *pixel = *pixel * demodulation(a);
+    }
+    illumination
+}
+
+fn demodulation(albedo: &Vec3) -> Vec3 {
+    // Black albedo (background, pure glass or mirrors in the albedo pass) is left untouched.
+    let component = |a: f64| if a > ALBEDO_EPSILON { a } else { 1.0 };
+    vec3(component(albedo.x()), component(albedo.y()), component(albedo.z()))
+}
+
+fn atrous_pass(
+    input: &Image,
+    albedo: &Image,
+    normal: &Image,
+    step: usize,
+    sigma_color: f64,
+    options: &DenoiseOptions,
+) -> Image {
+    let mut output = Image::new(input.width, input.height);
+
+    for y in 0..input.height {
+        for x in 0..input.width {
+            let c_p = input.get(x, y);
+            let n_p = normal.get(x, y);
+            let a_p = albedo.get(x, y);
+
+            let mut sum = Vec3::default();
+            let mut weight_sum = 0.0;
+
+            for (ky, hy) in KERNEL.iter().enumerate() {
+                for (kx, hx) in KERNEL.iter().enumerate() {
+                    let qx = x as i64 + (kx as i64 - 2) * step as i64;
+                    let qy = y as i64 + (ky as i64 - 2) * step as i64;
+                    if qx < 0 || qy < 0 || qx >= input.width as i64 || qy >= input.height as i64 {
+                        continue;
+                    }
+                    let (qx, qy) = (qx as usize, qy as usize);
+
+                    let c_q = input.get(qx, qy);
+                    let w_color = edge_weight((c_p - c_q).length_squared(), sigma_color);
+                    let w_normal = edge_weight((n_p - normal.get(qx, qy)).length_squared(), options.sigma_normal);
+                    let w_albedo = edge_weight((a_p - albedo.get(qx, qy)).length_squared(), options.sigma_albedo);
+
+                    let weight = hx * hy * w_color * w_normal * w_albedo;
+                    sum = sum + weight * c_q;
+                    weight_sum += weight;
+                }
+            }
+
+            // The center pixel always has a positive weight, so the sum can't be zero.
+            output.set(x, y, sum / weight_sum);
+        }
+    }
+
+    output
+}
+
+fn edge_weight(distance_squared: f64, sigma: f64) -> f64 {
+    if sigma <= 0.0 {
+        return if distance_squared > 0.0 { 0.0 } else { 1.0 };
+    }
+    (-distance_squared / (sigma * sigma)).exp()
+}
+
+#[test]
+fn denoise_test() {
+    use crate::color::color;
+    use crate::rtweekend::random_double_intv;
+
+    // Noisy gray on the left half, noisy white on the right half with a different albedo.
+    let (width, height) = (32, 16);
+    let mut beauty = Image::new(width, height);
+    let mut albedo = Image::new(width, height);
+    let mut normal = Image::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let a = if x < width / 2 { 0.2 } else { 0.8 };
+            let noise = random_double_intv(-0.1, 0.1);
+            beauty.set(x, y, color(a + noise, a + noise, a + noise));
+            albedo.set(x, y, color(a, a, a));
+            normal.set(x, y, vec3(0.0, 0.0, 1.0));
+        }
+    }
+
+    let result = denoise(&beauty, &albedo, &normal, &DenoiseOptions::default());
+
+    let error = |image: &Image| {
+        let mut sum = 0.0;
+        for y in 0..height {
+            for x in 0..width {
+                let expected = albedo.get(x, y);
+                sum += (image.get(x, y) - expected).length_squared();
+            }
+        }
+        sum
+    };
+    assert!(error(&result) < 0.1 * error(&beauty));
+
+    // The albedo edge in the middle isn't blurred.
+    assert!((result.get(width / 2 - 1, 8).x() - 0.2).abs() < 0.05);
+    assert!((result.get(width / 2, 8).x() - 0.8).abs() < 0.05);
+
+    // Passes beyond the image size change nothing, even absurdly many.
+    let passes = |iterations| denoise(&beauty, &albedo, &normal, &DenoiseOptions { iterations, ..DenoiseOptions::default() });
+    assert_eq!(passes(6).pixels, passes(200).pixels);
+}
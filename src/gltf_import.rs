@@ -0,0 +1,359 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use gltf::camera::Projection;
+use gltf::image::Format;
+use gltf::mesh::Mode;
+
+use crate::camera::Camera;
+use crate::color::{color, srgb_to_linear};
+use crate::hittable::Hittable;
+use crate::hittable_list::HittableList;
+use crate::image::{invalid_data, Image};
+use crate::material::{metallic_roughness_texture, Material};
+use crate::mesh::{mesh, MeshData};
+use crate::texture::{image_texture, solid_color, vertex_color, Texture};
+use crate::vec3::*;
+
+// Import of glTF 2.0 scenes (.gltf with external or embedded buffers, and .glb). The node
+// hierarchy of the default scene is flattened into world space meshes, perspective cameras are
+// turned into cameras and materials into metallic-roughness materials. Only the first set of
+// texture coordinates is used, texture samplers always repeat and filter bilinearly.
+
+/// Scene read from a glTF file.
+pub struct GltfScene {
+    pub world: HittableList,
+    pub cameras: Vec<Camera>,
+}
+
+pub fn read_gltf<P: AsRef<Path>>(path: P) -> io::Result<GltfScene> {
+    let (document, buffers, images) = gltf::import(path).map_err(|e| invalid_data(e.to_string()))?;
+
+    let mut importer = Importer {
+        buffers: &buffers,
+        images: &images,
+        textures: HashMap::new(),
+        materials: HashMap::new(),
+        scene: GltfScene {
+            world: HittableList::new(Vec::new()),
+            cameras: Vec::new(),
+        },
+    };
+
+    let scene = document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+        .ok_or_else(|| invalid_data("glTF file contains no scene".to_string()))?;
+    for node in scene.nodes() {
+        importer.import_node(&node, &IDENTITY)?;
+    }
+
+    Ok(importer.scene)
+}
+
+// Column major 4x4 matrix, as stored by glTF.
+type Matrix = [[f64; 4]; 4];
+
+const IDENTITY: Matrix = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+
+fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    let mut m = [[0.0; 4]; 4];
+    for (col, m_col) in m.iter_mut().enumerate() {
+        for (row, value) in m_col.iter_mut().enumerate() {
+            *value = (0..4).map(|k| a[k][row] * b[col][k]).sum();
+        }
+    }
+    m
+}
+
+fn transform_point(m: &Matrix, p: &Vec3) -> Vec3 {
+    transform_vector(m, p) + vec3(m[3][0], m[3][1], m[3][2])
+}
+
+fn transform_vector(m: &Matrix, v: &Vec3) -> Vec3 {
+    vec3(
+        m[0][0] * v.x() + m[1][0] * v.y() + m[2][0] * v.z(),
+        m[0][1] * v.x() + m[1][1] * v.y() + m[2][1] * v.z(),
+        m[0][2] * v.x() + m[1][2] * v.y() + m[2][2] * v.z(),
+    )
+}
+
+fn normal_matrix(m: &Matrix) -> (Matrix, f64) {
+    // Inverse transpose of the upper 3x3 part, which transforms normals, and its determinant.
+    // The cofactor matrix is the inverse transpose up to the factor 1/det, which doesn't matter
+    // for normals that get normalized anyway, except for its sign.
+    let c = |col: usize, row: usize| m[col][row];
+    let cofactor = |col: usize, row: usize| {
+        let (c0, c1) = ((col + 1) % 3, (col + 2) % 3);
+        let (r0, r1) = ((row + 1) % 3, (row + 2) % 3);
+        c(c0, r0) * c(c1, r1) - c(c1, r0) * c(c0, r1)
+    };
+    let det = c(0, 0) * cofactor(0, 0) + c(1, 0) * cofactor(1, 0) + c(2, 0) * cofactor(2, 0);
+
+    let mut n = IDENTITY;
+    for (col, n_col) in n.iter_mut().enumerate().take(3) {
+        for (row, value) in n_col.iter_mut().enumerate().take(3) {
+            *value = det.signum() * cofactor(col, row);
+        }
+    }
+    (n, det)
+}
+
+struct Importer<'a> {
+    buffers: &'a [gltf::buffer::Data],
+    images: &'a [gltf::image::Data],
+    // Converted images by index and whether they hold sRGB colors.
+    textures: HashMap<(usize, bool), Arc<Image>>,
+    materials: HashMap<Option<usize>, Material>,
+    scene: GltfScene,
+}
+
+impl Importer<'_> {
+    fn import_node(&mut self, node: &gltf::Node, parent: &Matrix) -> io::Result<()> {
+        let local = node.transform().matrix().map(|col| col.map(|v| v as f64));
+        let transform = multiply(parent, &local);
+
+        if let Some(gltf_mesh) = node.mesh() {
+            for primitive in gltf_mesh.primitives() {
+                // Points and lines have no surface to render.
+                if primitive.mode() != Mode::Triangles {
+                    continue;
+                }
+                let object = self
+                    .import_primitive(&primitive, &transform)
+                    .map_err(|e| invalid_data(format!("mesh {}: {}", gltf_mesh.index(), e)))?;
+                self.scene.world.add(object);
+            }
+        }
+
+        if let Some(camera) = node.camera() {
+            if let Projection::Perspective(perspective) = camera.projection() {
+                // glTF cameras look along -z with y pointing up.
+                let mut cam = Camera::default();
+                cam.look_from = transform_point(&transform, &point3(0.0, 0.0, 0.0));
+                cam.look_at = cam.look_from + unit_vector(&transform_vector(&transform, &vec3(0.0, 0.0, -1.0)));
+                cam.v_up = transform_vector(&transform, &vec3(0.0, 1.0, 0.0));
+                cam.vfov = (perspective.yfov() as f64).to_degrees();
+                if let Some(aspect_ratio) = perspective.aspect_ratio() {
+                    cam.aspect_ratio = aspect_ratio as f64;
+                }
+                self.scene.cameras.push(cam);
+            }
+        }
+
+        for child in node.children() {
+            self.import_node(&child, &transform)?;
+        }
+        Ok(())
+    }
+
+    fn import_primitive(&mut self, primitive: &gltf::Primitive, transform: &Matrix) -> io::Result<Box<dyn Hittable>> {
+        let reader = primitive.reader(|buffer| self.buffers.get(buffer.index()).map(|data| &data.0[..]));
+        let (normal_transform, det) = normal_matrix(transform);
+
+        let mut data = MeshData {
+            positions: reader
+                .read_positions()
+                .ok_or_else(|| invalid_data("primitive without positions".to_string()))?
+                .map(|p| transform_point(transform, &point3(p[0] as f64, p[1] as f64, p[2] as f64)))
+                .collect(),
+            ..MeshData::default()
+        };
+        if let Some(normals) = reader.read_normals() {
+            data.normals = normals
+                .map(|n| unit_vector(&transform_vector(&normal_transform, &vec3(n[0] as f64, n[1] as f64, n[2] as f64))))
+                .collect();
+        }
+        if let Some(uvs) = reader.read_tex_coords(0) {
+            // glTF puts the origin of the texture coordinates at the top left.
+            data.uvs = uvs.into_f32().map(|[u, v]| (u as f64, 1.0 - v as f64)).collect();
+        }
+        if let Some(colors) = reader.read_colors(0) {
+            data.colors = colors
+                .into_rgb_f32()
+                .map(|[r, g, b]| color(r as f64, g as f64, b as f64))
+                .collect();
+        }
+
+        let indices: Vec<usize> = match reader.read_indices() {
+            Some(indices) => indices.into_u32().map(|i| i as usize).collect(),
+            None => (0..data.positions.len()).collect(),
+        };
+        data.triangles = indices
+            .chunks_exact(3)
+            .map(|t| {
+                // Mirroring transforms flip the winding order, which defines the outside.
+                if det < 0.0 {
+                    [t[0], t[2], t[1]]
+                } else {
+                    [t[0], t[1], t[2]]
+                }
+            })
+            .collect();
+        data.validate()?;
+
+        let material = self.import_material(&primitive.material(), &mut data)?;
+        Ok(Box::new(mesh(data, &material)))
+    }
+
+    fn import_material(&mut self, material: &gltf::Material, data: &mut MeshData) -> io::Result<Material> {
+        let pbr = material.pbr_metallic_roughness();
+        let [r, g, b, _] = pbr.base_color_factor();
+        let base_color_factor = color(r as f64, g as f64, b as f64);
+
+        // Vertex colors multiply the base color. They are used without a base color texture
+        // only, which makes the material specific to the mesh.
+        if !data.colors.is_empty() && pbr.base_color_texture().is_none() {
+            for c in data.colors.iter_mut() {
+                *c = *c * base_color_factor;
+            }
+            let metallic_roughness = self.metallic_roughness(&pbr)?;
+            return Ok(metallic_roughness_texture(vertex_color(), metallic_roughness));
+        }
+
+        if let Some(material) = self.materials.get(&material.index()) {
+            return Ok(material.clone());
+        }
+        let base_color = match pbr.base_color_texture() {
+            Some(info) => image_texture(self.texture(&info.texture(), true)?, base_color_factor),
+            None => solid_color(base_color_factor),
+        };
+        let imported = metallic_roughness_texture(base_color, self.metallic_roughness(&pbr)?);
+        self.materials.insert(material.index(), imported.clone());
+        Ok(imported)
+    }
+
+    fn metallic_roughness(&mut self, pbr: &gltf::material::PbrMetallicRoughness) -> io::Result<Texture> {
+        let factor = color(0.0, pbr.roughness_factor() as f64, pbr.metallic_factor() as f64);
+        match pbr.metallic_roughness_texture() {
+            Some(info) => Ok(image_texture(self.texture(&info.texture(), false)?, factor)),
+            None => Ok(solid_color(factor)),
+        }
+    }
+
+    fn texture(&mut self, texture: &gltf::Texture, srgb: bool) -> io::Result<Arc<Image>> {
+        let index = texture.source().index();
+        if let Some(image) = self.textures.get(&(index, srgb)) {
+            return Ok(image.clone());
+        }
+        let data = self
+            .images
+            .get(index)
+            .ok_or_else(|| invalid_data(format!("missing image {}", index)))?;
+        let image = Arc::new(convert_image(data, srgb).map_err(|e| invalid_data(format!("image {}: {}", index, e)))?);
+        self.textures.insert((index, srgb), image.clone());
+        Ok(image)
+    }
+}
+
+fn convert_image(data: &gltf::image::Data, srgb: bool) -> io::Result<Image> {
+    // Expands the decoded image to linear RGB, alpha is dropped.
+    let (channels, bytes_per_channel) = match data.format {
+        Format::R8 => (1, 1),
+        Format::R8G8 => (2, 1),
+        Format::R8G8B8 => (3, 1),
+        Format::R8G8B8A8 => (4, 1),
+        Format::R16 => (1, 2),
+        Format::R16G16 => (2, 2),
+        Format::R16G16B16 => (3, 2),
+        Format::R16G16B16A16 => (4, 2),
+        Format::R32G32B32FLOAT => (3, 4),
+        Format::R32G32B32A32FLOAT => (4, 4),
+    };
+    let (width, height) = (data.width as usize, data.height as usize);
+    if data.pixels.len() != width * height * channels * bytes_per_channel {
+        return Err(invalid_data("pixel data doesn't match the image size".to_string()));
+    }
+
+    let channel = |bytes: &[u8]| match bytes_per_channel {
+        1 => bytes[0] as f64 / 255.0,
+        2 => u16::from_ne_bytes([bytes[0], bytes[1]]) as f64 / 65535.0,
+        _ => f32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+    };
+    let decode = |value: f64| if srgb && bytes_per_channel < 4 { srgb_to_linear(value) } else { value };
+
+    let mut image = Image::new(width, height);
+    for (pixel, bytes) in image
+        .pixels
+        .iter_mut()
+        .zip(data.pixels.chunks_exact(channels * bytes_per_channel))
+    {
+        let value = |c: usize| decode(channel(&bytes[c * bytes_per_channel..]));
+        *pixel = if channels < 3 {
+            // Grey, possibly with alpha.
+            color(value(0), value(0), value(0))
+        } else {
+            color(value(0), value(1), value(2))
+        };
+    }
+    Ok(image)
+}
+
+#[test]
+fn read_gltf_test() {
+    use crate::hittable::HitRecord;
+    use crate::interval::interval;
+    use crate::ray::Ray;
+
+    // A triangle below a translated and a scaled node, and a camera turned to the left.
+    let gltf = r#"{
+        "asset": {"version": "2.0"},
+        "scene": 0,
+        "scenes": [{"nodes": [0, 2]}],
+        "nodes": [
+            {"translation": [0, 0, -2], "children": [1]},
+            {"mesh": 0, "scale": [2, 2, 2]},
+            {"camera": 0, "translation": [0, 0, 5], "rotation": [0, 0.70710678, 0, 0.70710678]}
+        ],
+        "meshes": [{"primitives": [{"attributes": {"POSITION": 0}, "indices": 1, "material": 0}]}],
+        "materials": [{"pbrMetallicRoughness": {"baseColorFactor": [0.8, 0.2, 0.1, 1], "roughnessFactor": 0.5}}],
+        "cameras": [{"type": "perspective", "perspective": {"yfov": 0.8, "znear": 0.1, "aspectRatio": 1.5}}],
+        "buffers": [{"uri": "triangle.bin", "byteLength": 42}],
+        "bufferViews": [
+            {"buffer": 0, "byteOffset": 0, "byteLength": 36},
+            {"buffer": 0, "byteOffset": 36, "byteLength": 6}
+        ],
+        "accessors": [
+            {"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+             "min": [-0.5, -0.5, 0], "max": [0.5, 0.5, 0]},
+            {"bufferView": 1, "componentType": 5123, "count": 3, "type": "SCALAR"}
+        ]
+    }"#;
+    let mut buffer = Vec::new();
+    for c in [-0.5f32, -0.5, 0.0, 0.5, -0.5, 0.0, 0.0, 0.5, 0.0] {
+        buffer.extend(c.to_le_bytes());
+    }
+    for i in [0u16, 1, 2] {
+        buffer.extend(i.to_le_bytes());
+    }
+
+    let dir = std::env::temp_dir().join(format!("read_gltf_test_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("scene.gltf"), gltf).unwrap();
+    std::fs::write(dir.join("triangle.bin"), &buffer).unwrap();
+    let scene = read_gltf(dir.join("scene.gltf"));
+    std::fs::remove_dir_all(&dir).unwrap();
+    let scene = scene.unwrap();
+
+    let mut rec = HitRecord::default();
+    let r = Ray::new(&point3(0.9, -0.9, 0.0), &vec3(0.0, 0.0, -1.0));
+    assert!(scene.world.hit(&r, interval(0.001, f64::INFINITY), &mut rec));
+    assert!((rec.t - 2.0).abs() < 1e-6);
+    assert!(rec.front_face);
+    assert!((rec.mat.albedo(&rec) - color(0.8, 0.2, 0.1)).length() < 1e-6);
+
+    assert_eq!(scene.cameras.len(), 1);
+    let cam = &scene.cameras[0];
+    assert!((cam.look_from - point3(0.0, 0.0, 5.0)).length() < 1e-6);
+    assert!((cam.look_at - point3(-1.0, 0.0, 5.0)).length() < 1e-6);
+    assert!((cam.v_up - vec3(0.0, 1.0, 0.0)).length() < 1e-6);
+    assert!((cam.vfov - 0.8f64.to_degrees()).abs() < 1e-4);
+    assert_eq!(cam.aspect_ratio, 1.5);
+}
@@ -0,0 +1,669 @@
+// Golden image regression tests: small scenes rendered with a fixed seed are compared against
+// the reference images in tests/golden. On a mismatch the rendered image and a difference
+// image are written to target/golden. After an intended change of the rendered result, the
+// references are updated with `UPDATE_GOLDEN=1 cargo test golden`.
+
+use std::fs;
+use std::path::PathBuf;
+
+use crate::aperture::polygon_aperture;
+use crate::camera::{Camera, Integrator};
+use crate::color::{color, color_to_bytes};
+use crate::csg::{csg_difference, csg_intersection};
+use crate::denoise::DenoiseOptions;
+use crate::hittable::Hittable;
+use crate::aabb::Aabb;
+use crate::hittable_list::HittableList;
+use crate::image::Image;
+use crate::light::{point_light, spot_light};
+use crate::material::{
+    bump_map, clearcoat, dielectric, dispersive_dielectric, lambertian, lambertian_texture, metal, metallic_roughness,
+    metallic_roughness_texture, normal_map, principled, subsurface, thin_film,
+};
+use crate::mesh::{mesh, MeshData};
+use crate::ply::parse_ply;
+use crate::principled::Principled;
+use crate::quadric::{capsule, cone, cylinder, hyperboloid, paraboloid};
+use crate::sdf::{round_box, sdf_object, sdf_sphere, smooth_union, subtraction, torus, translate, twist};
+use crate::medium::PhaseFunction;
+use crate::sky::{sun_direction, Sky};
+use crate::spectrum::DENSE_FLINT;
+use crate::sphere::sphere;
+use crate::texture::{image_texture, scalar_texture, solid_color, vertex_color};
+use crate::vec3::*;
+use crate::volume::{emissive_volume, volume, Grid};
+
+// Root mean square error of the 8 bit display values, normalized to [0,1].
+const RMSE_TOLERANCE: f64 = 0.02;
+
+fn golden_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden")
+}
+
+fn output_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target").join("golden")
+}
+
+fn three_spheres() -> HittableList {
+    let material_ground = lambertian(color(0.8, 0.8, 0.0));
+    let material_center = lambertian(color(0.1, 0.2, 0.5));
+    let material_left = dielectric(1.5);
+    let material_right = metal(color(0.8, 0.6, 0.2), 0.2);
+
+    let objects: Vec<Box<dyn Hittable>> = vec![
+        Box::new(sphere(&point3(0.0, -100.5, -1.0), 100.0, &material_ground)),
+        Box::new(sphere(&point3(0.0, 0.0, -1.2), 0.5, &material_center)),
+        Box::new(sphere(&point3(-1.0, 0.0, -1.0), 0.5, &material_left)),
+        Box::new(sphere(&point3(1.0, 0.0, -1.0), 0.5, &material_right)),
+    ];
+    HittableList::new(objects)
+}
+
+fn test_camera() -> Camera {
+    let mut cam = Camera::default();
+    cam.aspect_ratio = 16.0 / 9.0;
+    cam.image_width = 64;
+    cam.samples_per_pixel = 16;
+    cam.max_depth = 10;
+    cam.seed = Some(42);
+    cam
+}
+
+fn display_values(pixel: &Vec3) -> Vec3 {
+    let [r, g, b] = color_to_bytes(pixel);
+    vec3(r as f64, g as f64, b as f64) / 255.0
+}
+
+fn assert_matches_golden(name: &str, image: &Image) {
+    let reference_path = golden_dir().join(format!("{}.ppm", name));
+
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::create_dir_all(golden_dir()).unwrap();
+        image.write_ppm(&reference_path).unwrap();
+        return;
+    }
+
+    let reference = Image::read_ppm(&reference_path)
+        .unwrap_or_else(|e| panic!("can't read reference image {}: {}", reference_path.display(), e));
+    assert_eq!((reference.width, reference.height), (image.width, image.height));
+
+    let mut squared_error = 0.0;
+    let mut diff = Image::new(image.width, image.height);
+    for (index, pixel) in image.pixels.iter().enumerate() {
+        let difference = display_values(pixel) - reference.pixels[index];
+        squared_error += difference.length_squared();
+
+        // The difference is amplified and squared, because write_ppm applies the display gamma.
+        let d = vec3(difference.x().abs(), difference.y().abs(), difference.z().abs()) * 4.0;
+        diff.pixels[index] = d * d;
+    }
+    let rmse = (squared_error / (3 * image.pixels.len()) as f64).sqrt();
+
+    if rmse > RMSE_TOLERANCE {
+        fs::create_dir_all(output_dir()).unwrap();
+        let actual_path = output_dir().join(format!("{}_actual.ppm", name));
+        let diff_path = output_dir().join(format!("{}_diff.ppm", name));
+        image.write_ppm(&actual_path).unwrap();
+        diff.write_ppm(&diff_path).unwrap();
+        panic!(
+            "{} differs from its reference image (rmse {:.4} > {}), see {} and {}",
+            name,
+            rmse,
+            RMSE_TOLERANCE,
+            actual_path.display(),
+            diff_path.display()
+        );
+    }
+}
+
+#[test]
+fn golden_three_spheres() {
+    let world = three_spheres();
+    let mut cam = test_camera();
+    cam.look_from = point3(-2.0, 2.0, 1.0);
+    cam.look_at = point3(0.0, 0.0, -1.0);
+    cam.vfov = 30.0;
+
+    assert_matches_golden("three_spheres", &cam.render_buffers(&world).beauty);
+}
+
+#[test]
+fn golden_bokeh() {
+    let world = three_spheres();
+    let mut cam = test_camera();
+    cam.look_from = point3(-2.0, 2.0, 1.0);
+    cam.look_at = point3(0.0, 0.0, -1.0);
+    cam.vfov = 30.0;
+    cam.defocus_angle = 10.0;
+    cam.focus_dist = 3.4;
+    cam.aperture = polygon_aperture(6, 15.0);
+    cam.vignetting = 0.5;
+
+    assert_matches_golden("bokeh", &cam.render_buffers(&world).beauty);
+}
+
+#[test]
+fn golden_denoised() {
+    let world = three_spheres();
+    let mut cam = test_camera();
+    cam.samples_per_pixel = 4;
+    cam.denoise = Some(DenoiseOptions::default());
+
+    assert_matches_golden("denoised", &cam.render_buffers(&world).beauty);
+}
+
+#[test]
+fn golden_sdf() {
+    let ground = lambertian(color(0.8, 0.8, 0.0));
+    let blue = lambertian(color(0.1, 0.2, 0.5));
+    let gold = metal(color(0.8, 0.6, 0.2), 0.1);
+
+    let carved_box = subtraction(
+        Box::new(round_box(vec3(0.4, 0.4, 0.4), 0.08)),
+        Box::new(sdf_sphere(0.5)),
+    );
+    let blob = smooth_union(
+        Box::new(translate(Box::new(carved_box), point3(-0.6, 0.0, -1.0))),
+        Box::new(translate(Box::new(sdf_sphere(0.3)), point3(-0.1, -0.2, -1.0))),
+        0.2,
+    );
+    let twisted_torus = translate(Box::new(twist(Box::new(torus(0.35, 0.12)), 2.0)), point3(0.7, 0.0, -1.0));
+
+    let objects: Vec<Box<dyn Hittable>> = vec![
+        Box::new(sphere(&point3(0.0, -100.5, -1.0), 100.0, &ground)),
+        Box::new(sdf_object(Box::new(blob), &blue)),
+        Box::new(sdf_object(Box::new(twisted_torus), &gold).step_scale(0.7)),
+    ];
+    let world = HittableList::new(objects);
+
+    let mut cam = test_camera();
+    cam.look_from = point3(0.0, 0.8, 1.0);
+    cam.look_at = point3(0.0, 0.0, -1.0);
+    cam.vfov = 60.0;
+
+    assert_matches_golden("sdf", &cam.render_buffers(&world).beauty);
+}
+
+#[test]
+fn golden_csg() {
+    let ground = lambertian(color(0.8, 0.8, 0.0));
+    let blue = lambertian(color(0.1, 0.2, 0.5));
+    let gold = metal(color(0.8, 0.6, 0.2), 0.1);
+    let white = lambertian(color(0.9, 0.9, 0.9));
+
+    // A sphere with a bite taken out of it, where the carved surface keeps the material of the
+    // cutter, and a rounded cube intersected with a sphere.
+    let bitten = csg_difference(
+        Box::new(sphere(&point3(-0.55, 0.0, -1.0), 0.45, &blue)),
+        Box::new(sphere(&point3(-0.35, 0.2, -0.6), 0.35, &white)),
+    );
+    let rounded = csg_intersection(
+        Box::new(sdf_object(
+            Box::new(translate(Box::new(round_box(vec3(0.3, 0.3, 0.3), 0.02)), point3(0.55, 0.0, -1.0))),
+            &gold,
+        )),
+        Box::new(sphere(&point3(0.55, 0.0, -1.0), 0.4, &gold)),
+    );
+
+    let objects: Vec<Box<dyn Hittable>> = vec![
+        Box::new(sphere(&point3(0.0, -100.5, -1.0), 100.0, &ground)),
+        Box::new(bitten),
+        Box::new(rounded),
+    ];
+    let world = HittableList::new(objects);
+
+    let mut cam = test_camera();
+    cam.look_from = point3(0.0, 0.8, 1.0);
+    cam.look_at = point3(0.0, 0.0, -1.0);
+    cam.vfov = 50.0;
+
+    assert_matches_golden("csg", &cam.render_buffers(&world).beauty);
+}
+
+#[test]
+fn golden_quadrics() {
+    let ground = lambertian(color(0.8, 0.8, 0.0));
+    let blue = lambertian(color(0.1, 0.2, 0.5));
+    let red = lambertian(color(0.6, 0.1, 0.1));
+    let gold = metal(color(0.8, 0.6, 0.2), 0.1);
+    let glass = dielectric(1.5);
+
+    let objects: Vec<Box<dyn Hittable>> = vec![
+        Box::new(sphere(&point3(0.0, -100.5, -1.0), 100.0, &ground)),
+        Box::new(cylinder(&point3(-1.2, -0.5, -1.2), &point3(-1.2, 0.1, -1.2), 0.25, &blue)),
+        Box::new(cone(&point3(-0.6, -0.5, -1.0), &point3(-0.6, 0.2, -1.0), 0.25, 0.0, &red)),
+        Box::new(paraboloid(&point3(0.0, -0.5, -1.3), &point3(0.0, 0.1, -1.3), 0.3, &gold)),
+        Box::new(hyperboloid(&point3(0.6, -0.5, -1.0), &point3(0.6, 0.1, -1.0), 0.12, 0.25, &blue)),
+        Box::new(crate::quadric::torus(&point3(0.0, -0.2, -0.6), &vec3(0.3, 1.0, 0.2), 0.22, 0.07, &gold)),
+        Box::new(capsule(&point3(1.0, -0.35, -1.5), &point3(1.4, 0.0, -1.2), 0.15, &glass)),
+    ];
+    let world = HittableList::new(objects);
+
+    let mut cam = test_camera();
+    cam.look_from = point3(0.0, 0.8, 1.0);
+    cam.look_at = point3(0.0, -0.2, -1.0);
+    cam.vfov = 55.0;
+
+    assert_matches_golden("quadrics", &cam.render_buffers(&world).beauty);
+}
+
+#[test]
+fn golden_mesh() {
+    // Octahedron with a color at each vertex.
+    let ply = "ply
+format ascii 1.0
+element vertex 6
+property float x
+property float y
+property float z
+property uchar red
+property uchar green
+property uchar blue
+element face 8
+property list uchar int vertex_indices
+end_header
+1 0 0 255 0 0
+-1 0 0 0 255 255
+0 1 0 255 255 255
+0 -1 0 40 40 40
+0 0 1 0 255 0
+0 0 -1 0 0 255
+3 0 2 4
+3 4 2 1
+3 1 2 5
+3 5 2 0
+3 4 3 0
+3 1 3 4
+3 5 3 1
+3 0 3 5
+";
+    let mut octahedron = parse_ply(ply.as_bytes()).unwrap();
+    octahedron.transform(0.45, &point3(0.0, 0.0, -1.0));
+
+    let objects: Vec<Box<dyn Hittable>> = vec![
+        Box::new(sphere(&point3(0.0, -100.5, -1.0), 100.0, &lambertian(color(0.8, 0.8, 0.0)))),
+        Box::new(mesh(octahedron, &lambertian_texture(vertex_color()))),
+    ];
+    let world = HittableList::new(objects);
+
+    let mut cam = test_camera();
+    cam.look_from = point3(0.6, 0.6, 1.0);
+    cam.look_at = point3(0.0, 0.0, -1.0);
+    cam.vfov = 40.0;
+
+    assert_matches_golden("mesh", &cam.render_buffers(&world).beauty);
+}
+
+#[test]
+fn golden_pbr() {
+    // Metallic-roughness spheres from rough plastic to polished metal, and one with a checker
+    // image as base color.
+    let mut checker = Image::new(8, 4);
+    for j in 0..4 {
+        for i in 0..8 {
+            let c = if (i + j) % 2 == 0 { color(0.9, 0.9, 0.9) } else { color(0.1, 0.3, 0.1) };
+            checker.set(i, j, c);
+        }
+    }
+    let textured = metallic_roughness_texture(
+        image_texture(std::sync::Arc::new(checker), color(1.0, 1.0, 1.0)),
+        solid_color(color(0.0, 0.4, 0.0)),
+    );
+
+    let mut objects: Vec<Box<dyn Hittable>> = vec![
+        Box::new(sphere(&point3(0.0, -100.5, -1.0), 100.0, &lambertian(color(0.5, 0.5, 0.5)))),
+        Box::new(sphere(&point3(0.0, 0.0, -1.6), 0.45, &textured)),
+    ];
+    for (index, (metallic, roughness)) in [(0.0, 1.0), (0.0, 0.2), (1.0, 0.5), (1.0, 0.05)].iter().enumerate() {
+        let material = metallic_roughness(color(0.9, 0.4, 0.1), *metallic, *roughness);
+        let center = point3(-1.2 + 0.8 * index as f64, -0.25, -0.6);
+        objects.push(Box::new(sphere(&center, 0.25, &material)));
+    }
+    let world = HittableList::new(objects);
+
+    let mut cam = test_camera();
+    cam.look_from = point3(0.0, 0.6, 1.2);
+    cam.look_at = point3(0.0, -0.1, -1.0);
+    cam.vfov = 55.0;
+
+    assert_matches_golden("pbr", &cam.render_buffers(&world).beauty);
+}
+
+#[test]
+fn golden_principled() {
+    // Principled materials, from left to right: brushed metal, velvet, car paint, frosted glass
+    // and plastic with a checker texture as roughness.
+    let mut checker = Image::new(8, 4);
+    for j in 0..4 {
+        for i in 0..8 {
+            let r = if (i + j) % 2 == 0 { 0.05 } else { 0.8 };
+            checker.set(i, j, color(r, r, r));
+        }
+    }
+    let brushed = principled(Principled {
+        base_color: solid_color(color(0.9, 0.9, 0.9)),
+        metallic: scalar_texture(1.0),
+        roughness: scalar_texture(0.4),
+        anisotropy: scalar_texture(0.9),
+        ..Principled::default()
+    });
+    let velvet = principled(Principled {
+        base_color: solid_color(color(0.3, 0.02, 0.1)),
+        roughness: scalar_texture(1.0),
+        sheen: scalar_texture(1.0),
+        ..Principled::default()
+    });
+    let paint = principled(Principled {
+        base_color: solid_color(color(0.05, 0.2, 0.6)),
+        metallic: scalar_texture(0.5),
+        roughness: scalar_texture(0.4),
+        clearcoat: scalar_texture(1.0),
+        ..Principled::default()
+    });
+    let frosted = principled(Principled {
+        base_color: solid_color(color(0.9, 1.0, 0.95)),
+        roughness: scalar_texture(0.3),
+        transmission: scalar_texture(1.0),
+        ..Principled::default()
+    });
+    let plastic = principled(Principled {
+        base_color: solid_color(color(0.9, 0.5, 0.1)),
+        roughness: image_texture(std::sync::Arc::new(checker), color(1.0, 1.0, 1.0)),
+        ..Principled::default()
+    });
+
+    let mut objects: Vec<Box<dyn Hittable>> = vec![Box::new(sphere(
+        &point3(0.0, -100.5, -1.0),
+        100.0,
+        &lambertian(color(0.5, 0.5, 0.5)),
+    ))];
+    for (index, material) in [brushed, velvet, paint, frosted, plastic].iter().enumerate() {
+        let center = point3(-1.2 + 0.6 * index as f64, -0.25, -1.0);
+        objects.push(Box::new(sphere(&center, 0.25, material)));
+    }
+    let world = HittableList::new(objects);
+
+    let mut cam = test_camera();
+    cam.look_from = point3(0.0, 0.4, 1.0);
+    cam.look_at = point3(0.0, -0.2, -1.0);
+    cam.vfov = 50.0;
+
+    assert_matches_golden("principled", &cam.render_buffers(&world).beauty);
+}
+
+#[test]
+fn golden_spectral() {
+    // A flint glass prism in front of colored spheres, rendered spectrally: the edges seen
+    // through the prism get rainbow fringes.
+    let (z0, z1) = (-1.4, -0.6);
+    let corners = [(-0.45, -0.5), (0.45, -0.5), (0.0, 0.2)];
+    let mut prism = MeshData::default();
+    for z in [z1, z0] {
+        prism.positions.extend(corners.iter().map(|(x, y)| point3(*x, *y, z)));
+    }
+    prism.triangles = vec![[0, 1, 2], [3, 5, 4]];
+    for (p, q) in [(0, 1), (1, 2), (2, 0)] {
+        prism.triangles.push([p, p + 3, q + 3]);
+        prism.triangles.push([p, q + 3, q]);
+    }
+
+    let world = HittableList::new(vec![
+        Box::new(sphere(&point3(0.0, -100.5, -1.0), 100.0, &lambertian(color(0.8, 0.8, 0.8)))),
+        Box::new(mesh(prism, &dispersive_dielectric(DENSE_FLINT))),
+        Box::new(sphere(&point3(-0.8, -0.2, -2.2), 0.3, &lambertian(color(0.8, 0.1, 0.1)))),
+        Box::new(sphere(&point3(0.0, -0.2, -2.4), 0.3, &lambertian(color(0.1, 0.8, 0.1)))),
+        Box::new(sphere(&point3(0.8, -0.2, -2.2), 0.3, &lambertian(color(0.1, 0.1, 0.8)))),
+    ]);
+
+    let mut cam = test_camera();
+    cam.spectral = true;
+    cam.look_from = point3(0.0, 0.3, 1.0);
+    cam.look_at = point3(0.0, -0.1, -1.0);
+    cam.vfov = 50.0;
+
+    assert_matches_golden("spectral", &cam.render_buffers(&world).beauty);
+}
+
+#[test]
+fn golden_coatings() {
+    // From left to right: a soap bubble (a film with air on both sides), oily dark metal,
+    // red paint and gold under clearcoat.
+    let bubble = thin_film(dielectric(1.0), 400.0, 1.33);
+    let oily = thin_film(metal(color(0.1, 0.1, 0.1), 0.0), 350.0, 1.5);
+    let paint = clearcoat(lambertian(color(0.7, 0.05, 0.05)), 1.5, 0.0);
+    let gold = clearcoat(metal(color(0.9, 0.6, 0.2), 0.3), 1.5, 0.0);
+
+    let mut objects: Vec<Box<dyn Hittable>> = vec![Box::new(sphere(
+        &point3(0.0, -100.5, -1.0),
+        100.0,
+        &lambertian(color(0.5, 0.5, 0.5)),
+    ))];
+    for (index, material) in [bubble, oily, paint, gold].iter().enumerate() {
+        let center = point3(-1.2 + 0.8 * index as f64, -0.15, -1.0);
+        objects.push(Box::new(sphere(&center, 0.35, material)));
+    }
+    let world = HittableList::new(objects);
+
+    let mut cam = test_camera();
+    cam.look_from = point3(0.0, 0.4, 1.0);
+    cam.look_at = point3(0.0, -0.1, -1.0);
+    cam.vfov = 55.0;
+
+    assert_matches_golden("coatings", &cam.render_buffers(&world).beauty);
+}
+
+#[test]
+fn golden_bump() {
+    // A sphere with ridges from a height map, a metal sphere with a normal map of tilted tiles and
+    // a displaced wall behind them.
+    let mut ridges = Image::new(64, 8);
+    for j in 0..8 {
+        for i in 0..64 {
+            let h = 0.5 + 0.5 * (i as f64 * std::f64::consts::PI / 4.0).sin();
+            ridges.set(i, j, color(h, h, h));
+        }
+    }
+    let mut tiles = Image::new(16, 8);
+    for j in 0..8 {
+        for i in 0..16 {
+            let x = if i % 2 == 0 { 0.15 } else { 0.85 };
+            let y = if j % 2 == 0 { 0.15 } else { 0.85 };
+            tiles.set(i, j, color(x, y, 0.9));
+        }
+    }
+    let mut bumps = Image::new(8, 8);
+    for j in 0..8 {
+        for i in 0..8 {
+            let h = if (i + j) % 2 == 0 { 1.0 } else { 0.0 };
+            bumps.set(i, j, color(h, h, h));
+        }
+    }
+    let white = color(1.0, 1.0, 1.0);
+
+    let mut wall = MeshData {
+        positions: vec![
+            point3(-2.0, -0.5, -2.0),
+            point3(2.0, -0.5, -2.0),
+            point3(2.0, 1.5, -2.0),
+            point3(-2.0, 1.5, -2.0),
+        ],
+        uvs: vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)],
+        triangles: vec![[0, 1, 2], [0, 2, 3]],
+        ..MeshData::default()
+    };
+    wall.displace(&image_texture(std::sync::Arc::new(bumps), white), 0.15, 5);
+
+    let ridged = bump_map(
+        lambertian(color(0.8, 0.4, 0.2)),
+        image_texture(std::sync::Arc::new(ridges), white),
+        0.04,
+    );
+    let tiled = normal_map(metal(color(0.8, 0.8, 0.9), 0.1), image_texture(std::sync::Arc::new(tiles), white));
+
+    let world = HittableList::new(vec![
+        Box::new(sphere(&point3(0.0, -100.5, -1.0), 100.0, &lambertian(color(0.5, 0.5, 0.5)))),
+        Box::new(mesh(wall, &lambertian(color(0.3, 0.5, 0.7)))),
+        Box::new(sphere(&point3(-0.5, -0.1, -1.0), 0.4, &ridged)),
+        Box::new(sphere(&point3(0.5, -0.1, -1.0), 0.4, &tiled)),
+    ]);
+
+    let mut cam = test_camera();
+    cam.look_from = point3(0.0, 0.3, 1.0);
+    cam.look_at = point3(0.0, 0.0, -1.0);
+    cam.vfov = 55.0;
+
+    assert_matches_golden("bump", &cam.render_buffers(&world).beauty);
+}
+
+#[test]
+fn golden_subsurface() {
+    // Light scattering below the surface, from left to right: skin, a jade cube, marble and wax.
+    let skin = subsurface(color(0.9, 0.65, 0.5), color(0.08, 0.03, 0.015), 1.4);
+    let jade = subsurface(color(0.6, 0.95, 0.7), color(0.2, 0.1, 0.2), 1.6);
+    let marble = subsurface(color(0.99, 0.99, 0.98), color(0.04, 0.04, 0.04), 1.5);
+    let wax = subsurface(color(0.95, 0.8, 0.5), color(0.15, 0.08, 0.03), 1.45);
+
+    let mut cube = MeshData::default();
+    for i in 0..8 {
+        let corner = |bit: usize| if i & bit == 0 { -0.2 } else { 0.2 };
+        cube.positions.push(point3(-0.35 + corner(1), -0.3 + corner(2), -1.0 + corner(4)));
+    }
+    cube.triangles = vec![
+        [0, 2, 3], [0, 3, 1], [4, 5, 7], [4, 7, 6], [0, 1, 5], [0, 5, 4],
+        [2, 6, 7], [2, 7, 3], [0, 4, 6], [0, 6, 2], [1, 3, 7], [1, 7, 5],
+    ];
+
+    let world = HittableList::new(vec![
+        Box::new(sphere(&point3(0.0, -100.5, -1.0), 100.0, &lambertian(color(0.5, 0.5, 0.5)))),
+        Box::new(sphere(&point3(-1.0, -0.2, -1.0), 0.3, &skin)),
+        Box::new(mesh(cube, &jade)),
+        Box::new(sphere(&point3(0.35, -0.2, -1.0), 0.3, &marble)),
+        Box::new(sphere(&point3(1.0, -0.2, -1.0), 0.3, &wax)),
+    ]);
+
+    let mut cam = test_camera();
+    cam.look_from = point3(0.0, 0.4, 1.0);
+    cam.look_at = point3(0.0, -0.2, -1.0);
+    cam.vfov = 50.0;
+
+    assert_matches_golden("subsurface", &cam.render_buffers(&world).beauty);
+}
+
+#[test]
+fn golden_volume() {
+    // A forward scattering cloud of a few overlapping puffs with wispy edges next to a fire
+    // whose flame is hottest at the bottom of its core.
+    let puffs = [(0.35, 0.4, 0.5, 0.25), (0.6, 0.45, 0.45, 0.3), (0.5, 0.65, 0.55, 0.2)];
+    let cloud = Grid::from_fn(32, 24, 32, |p| {
+        let density: f64 = puffs
+            .iter()
+            .map(|(x, y, z, r)| {
+                let d = (*p - point3(*x, *y, *z)).length() / r;
+                (1.0 - d * d).max(0.0)
+            })
+            .sum();
+        let wisps = 0.5 + 0.5 * (17.0 * p.x()).sin() * (13.0 * p.y()).sin() * (11.0 * p.z()).sin();
+        (density * wisps).min(1.0)
+    })
+    .unwrap();
+    let flame = |p: &Vec3| {
+        let radius = 0.35 * (1.0 - p.y());
+        let d = ((p.x() - 0.5).powi(2) + (p.z() - 0.5).powi(2)).sqrt();
+        (1.0 - d / radius).max(0.0)
+    };
+    let fire_density = Grid::from_fn(16, 24, 16, flame).unwrap();
+    let temperature = Grid::from_fn(16, 24, 16, |p| flame(p).powi(2) * (1.0 - p.y())).unwrap();
+
+    let world = HittableList::new(vec![
+        Box::new(sphere(&point3(0.0, -100.5, -1.0), 100.0, &lambertian(color(0.5, 0.5, 0.5)))),
+        Box::new(volume(
+            std::sync::Arc::new(cloud),
+            Aabb::from_points(&point3(-1.2, -0.3, -1.6), &point3(0.2, 0.75, -0.4)),
+            30.0,
+            color(0.95, 0.95, 0.95),
+            PhaseFunction::HenyeyGreenstein(0.6),
+        )),
+        Box::new(emissive_volume(
+            std::sync::Arc::new(fire_density),
+            Aabb::from_points(&point3(0.35, -0.5, -1.3), &point3(0.95, 0.5, -0.7)),
+            8.0,
+            color(0.3, 0.3, 0.3),
+            PhaseFunction::Isotropic,
+            std::sync::Arc::new(temperature),
+            color(12.0, 4.0, 1.0),
+        )),
+    ]);
+
+    let mut cam = test_camera();
+    cam.look_from = point3(0.0, 0.3, 1.0);
+    cam.look_at = point3(0.0, 0.0, -1.0);
+    cam.vfov = 55.0;
+
+    assert_matches_golden("volume", &cam.render_buffers(&world).beauty);
+}
+
+#[test]
+fn golden_sky() {
+    // Spheres on the ground in the afternoon, lit by the sky and the sun behind on the right,
+    // which casts their shadows towards the camera.
+    let world = HittableList::new(vec![
+        Box::new(sphere(&point3(0.0, -1000.5, -1.0), 1000.0, &lambertian(color(0.4, 0.35, 0.3)))),
+        Box::new(sphere(&point3(-0.9, -0.2, -1.2), 0.3, &lambertian(color(0.8, 0.2, 0.1)))),
+        Box::new(sphere(&point3(0.0, -0.1, -1.4), 0.4, &metal(color(0.9, 0.9, 0.9), 0.05))),
+        Box::new(sphere(&point3(0.9, -0.2, -1.2), 0.3, &dielectric(1.5))),
+    ]);
+
+    let mut cam = test_camera();
+    cam.sky = Some(Sky::new(sun_direction(25.0, 140.0), 3.0, color(0.3, 0.3, 0.3)));
+    cam.look_from = point3(0.0, 0.3, 1.0);
+    cam.look_at = point3(0.0, 0.0, -1.0);
+    cam.vfov = 55.0;
+
+    assert_matches_golden("sky", &cam.render_buffers(&world).beauty);
+}
+
+#[test]
+fn golden_lights() {
+    // Spheres in a dark dome, lit from above by a spot light with a soft edge and from the left
+    // by a warm point light. Both cast hard shadows.
+    let world = HittableList::new(vec![
+        Box::new(sphere(&point3(0.0, -1000.5, -1.0), 1000.0, &lambertian(color(0.6, 0.6, 0.6)))),
+        Box::new(sphere(&point3(0.0, 0.0, -1.0), 20.0, &lambertian(color(0.2, 0.2, 0.2)))),
+        Box::new(sphere(&point3(-0.6, -0.2, -1.2), 0.3, &lambertian(color(0.2, 0.4, 0.8)))),
+        Box::new(sphere(&point3(0.3, -0.1, -1.4), 0.4, &metal(color(0.9, 0.9, 0.9), 0.2))),
+        Box::new(sphere(&point3(0.9, -0.3, -0.9), 0.2, &dielectric(1.5))),
+    ]);
+
+    let mut cam = test_camera();
+    cam.lights = vec![
+        spot_light(point3(0.2, 2.0, -1.2), vec3(0.0, -1.0, 0.0), color(8.0, 8.0, 8.0), 15.0, 30.0),
+        point_light(point3(-1.5, 0.3, -0.3), color(1.5, 0.9, 0.4)),
+    ];
+    cam.look_from = point3(0.0, 0.4, 1.0);
+    cam.look_at = point3(0.0, -0.1, -1.0);
+    cam.vfov = 50.0;
+
+    assert_matches_golden("lights", &cam.render_buffers(&world).beauty);
+}
+
+#[test]
+fn golden_bdpt() {
+    // Glass spheres in a dark dome focus a spot light on the floor, rendered with bidirectional
+    // path tracing, which finds the caustics below the spheres that path tracing misses.
+    let world = HittableList::new(vec![
+        Box::new(sphere(&point3(0.0, -1000.5, -1.0), 1000.0, &lambertian(color(0.6, 0.6, 0.6)))),
+        Box::new(sphere(&point3(0.0, 0.0, -1.0), 20.0, &lambertian(color(0.2, 0.2, 0.2)))),
+        Box::new(sphere(&point3(-0.45, -0.1, -1.2), 0.3, &dielectric(1.5))),
+        Box::new(sphere(&point3(0.4, -0.2, -1.0), 0.2, &dielectric(1.5))),
+        Box::new(sphere(&point3(0.1, -0.3, -1.6), 0.2, &lambertian(color(0.8, 0.3, 0.2)))),
+    ]);
+
+    let mut cam = test_camera();
+    cam.integrator = Integrator::Bidirectional;
+    cam.lights = vec![spot_light(point3(0.6, 2.0, -0.4), vec3(-0.3, -1.0, -0.3), color(10.0, 10.0, 10.0), 25.0, 40.0)];
+    cam.look_from = point3(0.0, 0.6, 1.0);
+    cam.look_at = point3(0.0, -0.3, -1.0);
+    cam.vfov = 50.0;
+
+    assert_matches_golden("bdpt", &cam.render_buffers(&world).beauty);
+}
@@ -1,15 +1,45 @@
+use std::collections::HashMap;
+
+use crate::aabb::Aabb;
 use crate::interval::Interval;
+use crate::light::{Light, Shape};
 use crate::ray::Ray;
 use crate::vec3::*;
 use crate::Material;
 
-#[derive(Debug, Default, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct HitRecord {
     pub p: Vec3,
     pub normal: Vec3,
     pub mat: Material,
     pub t: f64,
+    pub u: f64,
+    pub v: f64,
+    pub dpdu: Vec3, // Derivatives of the position along the surface coordinates, zero if unknown
+    pub dpdv: Vec3,
     pub front_face: bool,
+    pub object_id: usize,
+    pub vertex_color: Vec3,
+    pub light: Option<usize>, // Index of the area light hit in the scene, see `SceneIndex`
+}
+
+impl Default for HitRecord {
+    fn default() -> Self {
+        HitRecord {
+            p: Vec3::default(),
+            normal: Vec3::default(),
+            mat: Material::default(),
+            t: 0.0,
+            u: 0.0,
+            v: 0.0,
+            dpdu: Vec3::default(),
+            dpdv: Vec3::default(),
+            front_face: false,
+            object_id: 0,
+            vertex_color: vec3(1.0, 1.0, 1.0),
+            light: None,
+        }
+    }
 }
 
 impl HitRecord {
@@ -19,13 +49,105 @@ impl HitRecord {
 
         self.front_face = dot(&r.direction(), outward_normal) < 0.0;
         if self.front_face {
-            self.normal = outward_normal.clone();
+            self.normal = *outward_normal;
         } else {
-            self.normal = -outward_normal.clone();
+            self.normal = -*outward_normal;
         }
     }
+
+    pub fn tangents(&self) -> (Vec3, Vec3) {
+        // The derivatives along the surface coordinates. Where a surface doesn't provide them,
+        // a frame around the normal with the tangent on circles around the y axis.
+        if !self.dpdu.near_zero() && !self.dpdv.near_zero() {
+            return (self.dpdu, self.dpdv);
+        }
+        let axis = if self.normal.y().abs() > 0.999 { vec3(1.0, 0.0, 0.0) } else { vec3(0.0, 1.0, 0.0) };
+        let tangent = unit_vector(&cross(&axis, &self.normal));
+        (tangent, cross(&self.normal, &tangent))
+    }
+}
+
+/// Part of a ray inside of a closed shape, from where it enters to where it leaves.
+#[derive(Debug, Default, Clone)]
+pub struct HitSpan {
+    pub enter: HitRecord,
+    pub exit: HitRecord,
 }
 
 pub trait Hittable {
     fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool;
+
+    fn hit_spans(&self, _r: &Ray, _ray_t: Interval) -> Option<Vec<HitSpan>> {
+        // Closed shapes return all spans of the ray inside of them, sorted along the ray. Only
+        // boundaries within `ray_t` need to be exact, the ones outside may be reported at
+        // infinity. Shapes without an inside (the default) return None.
+        None
+    }
+
+    fn occluded(&self, r: &Ray, ray_t: Interval) -> bool {
+        // Whether anything blocks the ray within the interval, for shadow rays. Unlike `hit` it
+        // may stop at any hit instead of looking for the closest one.
+        self.hit(r, ray_t, &mut HitRecord::default())
+    }
+
+    fn index_scene(&mut self, _scene: &mut SceneIndex) {
+        // Registers what the object is made of with the scene it's added to. Objects without
+        // materials (the default) have nothing to register.
+    }
+
+    fn bounding_box(&self) -> Aabb;
+}
+
+/// Numbering of the materials of a scene and its area lights, collected while objects are
+/// added to a `HittableList`. Materials get ids in the order they are found, starting at 1, so
+/// the material id pass only depends on the scene and not on what else was built before.
+#[derive(Debug, Default, Clone)]
+pub struct SceneIndex {
+    material_ids: HashMap<usize, usize>, // Ids by the key of the material
+    assignment: u64,                     // Hash of the ids in the order the objects registered them
+    area_lights: Vec<Light>,
+    partial: bool, // Indexing shapes of which only a part is in the scene
+}
+
+impl SceneIndex {
+    pub fn add_material(&mut self, mat: &Material) {
+        // The default material keeps id 0.
+        let id = if mat.key() == 0 {
+            0
+        } else {
+            let next = self.material_ids.len() + 1;
+            *self.material_ids.entry(mat.key()).or_insert(next)
+        };
+        self.assignment = (self.assignment ^ id as u64).wrapping_mul(0x100000001b3);
+    }
+
+    pub fn material_id(&self, mat: &Material) -> usize {
+        self.material_ids.get(&mat.key()).copied().unwrap_or(0)
+    }
+
+    pub fn add_area_light(&mut self, mat: &Material, shape: Shape) -> Option<usize> {
+        // Index of the light if the shape made of the material is one. Hits report it in
+        // `HitRecord::light`.
+        let radiance = mat.area_light_radiance().filter(|_| !self.partial && shape.area() > 0.0)?;
+        self.area_lights.push(Light::Area { shape, radiance });
+        Some(self.area_lights.len() - 1)
+    }
+
+    pub fn area_lights(&self) -> &[Light] {
+        &self.area_lights
+    }
+
+    pub fn fingerprint(&self) -> String {
+        // Which objects share which materials and where the area lights are. Unlike the keys of
+        // the materials this is the same whenever the scene is built.
+        format!("{} {} {:?}", self.material_ids.len(), self.assignment, self.area_lights)
+    }
+
+    pub fn index_partial(&mut self, index: impl FnOnce(&mut SceneIndex)) {
+        // Indexes shapes of which only a part ends up in the scene, e.g. the operands of CSG.
+        // Their surfaces can't be sampled as lights, they only glow when rays hit them.
+        let partial = std::mem::replace(&mut self.partial, true);
+        index(self);
+        self.partial = partial;
+    }
 }
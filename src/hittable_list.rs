@@ -1,22 +1,53 @@
-use crate::hittable::{HitRecord, Hittable};
+use crate::aabb::{Aabb, AABB_EMPTY};
+use crate::hittable::{HitRecord, Hittable, SceneIndex};
+use crate::material::Material;
 use crate::interval::{interval, Interval};
+use crate::light::Light;
 use crate::ray::Ray;
 
 pub struct HittableList {
     pub objects: Vec<Box<dyn Hittable>>,
+    scene: SceneIndex,
 }
 
 impl HittableList {
     pub fn new(objects: Vec<Box<dyn Hittable>>) -> Self {
-        Self { objects }
+        let mut list = Self {
+            objects: Vec::new(),
+            scene: SceneIndex::default(),
+        };
+        for object in objects {
+            list.add(object);
+        }
+        list
     }
 
-    pub fn add(&mut self, object: Box<dyn Hittable>) {
+    pub fn add(&mut self, mut object: Box<dyn Hittable>) {
+        object.index_scene(&mut self.scene);
         self.objects.push(object);
     }
 
     pub fn clear(&mut self) {
         self.objects.clear();
+        self.scene = SceneIndex::default();
+    }
+
+    pub fn material_id(&self, mat: &Material) -> usize {
+        // Id of the material in this scene for the material id pass, 0 for unknown materials.
+        self.scene.material_id(mat)
+    }
+
+    pub fn area_lights(&self) -> &[Light] {
+        // Emissive surfaces of the scene, indexed by `HitRecord::light`.
+        self.scene.area_lights()
+    }
+
+    pub fn fingerprint(&self) -> String {
+        // Description of the scene that changes when objects are added, removed, moved or given
+        // other materials, for telling it apart from the one a checkpoint was rendered of. Edits
+        // of the parameters of a material keep it.
+        let bounds: Vec<Aabb> = self.objects.iter().map(|object| object.bounding_box()).collect();
+        format!("{:?} {}", bounds, self.scene.fingerprint())
     }
 }
 
@@ -26,19 +57,74 @@ impl Hittable for HittableList {
         let mut hit_anything = false;
         let mut closest_so_far = ray_t.max;
 
-        for object in self.objects.iter() {
+        for (index, object) in self.objects.iter().enumerate() {
             if object.hit(r, interval(ray_t.min, closest_so_far), &mut temp_rec) {
                 hit_anything = true;
                 closest_so_far = temp_rec.t;
 
                 rec.p = temp_rec.p;
                 rec.normal = temp_rec.normal;
-                rec.mat = temp_rec.mat;
+                rec.mat = temp_rec.mat.clone();
                 rec.t = temp_rec.t;
+                rec.u = temp_rec.u;
+                rec.v = temp_rec.v;
+                rec.dpdu = temp_rec.dpdu;
+                rec.dpdv = temp_rec.dpdv;
                 rec.front_face = temp_rec.front_face;
+                rec.object_id = index;
+                rec.vertex_color = temp_rec.vertex_color;
+                rec.light = temp_rec.light;
             }
         }
 
         hit_anything
     }
+
+    fn occluded(&self, r: &Ray, ray_t: Interval) -> bool {
+        self.objects.iter().any(|object| object.occluded(r, ray_t))
+    }
+
+    fn index_scene(&mut self, scene: &mut SceneIndex) {
+        // Lists inside of a scene are numbered with the scene.
+        for object in &mut self.objects {
+            object.index_scene(scene);
+        }
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.objects
+            .iter()
+            .fold(AABB_EMPTY, |bbox, object| Aabb::from_boxes(&bbox, &object.bounding_box()))
+    }
+}
+
+#[test]
+fn material_id_test() {
+    use crate::color::color;
+    use crate::material::{dielectric, lambertian};
+    use crate::sphere::sphere;
+    use crate::vec3::point3;
+
+    // Materials are numbered in the order the scene lists them, whatever was built before, and
+    // copies of a material share its id.
+    let _unused = [lambertian(color(0.1, 0.1, 0.1)), dielectric(1.3)];
+    let (red, glass) = (lambertian(color(0.8, 0.1, 0.1)), dielectric(1.5));
+    let scene = |materials: [&Material; 3]| {
+        let objects: Vec<Box<dyn Hittable>> = materials
+            .iter()
+            .enumerate()
+            .map(|(i, mat)| Box::new(sphere(&point3(i as f64, 0.0, 0.0), 0.4, mat)) as Box<dyn Hittable>)
+            .collect();
+        HittableList::new(objects)
+    };
+    let world = scene([&glass, &red, &glass]);
+    assert_eq!((world.material_id(&glass), world.material_id(&red)), (1, 2));
+    assert_eq!(world.material_id(&Material::default()), 0);
+    let other = scene([&red, &red, &glass]);
+    assert_eq!((other.material_id(&red), other.material_id(&glass)), (1, 2));
+
+    // Lists within a scene are numbered with it.
+    let mut nested = HittableList::new(vec![Box::new(sphere(&point3(0.0, 0.0, 0.0), 1.0, &red))]);
+    nested.add(Box::new(world));
+    assert_eq!((nested.material_id(&red), nested.material_id(&glass)), (1, 2));
 }
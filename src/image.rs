@@ -0,0 +1,230 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::color::{color, color_to_bytes};
+use crate::vec3::*;
+
+/// Simple linear RGB image buffer, stored row by row starting at the top left pixel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Vec3>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize) -> Image {
+        Image {
+            width,
+            height,
+            pixels: vec![Vec3::default(); width * height],
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Vec3 {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, pixel: Vec3) {
+        self.pixels[y * self.width + x] = pixel;
+    }
+
+    pub fn write_ppm(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        // Gamma corrected 8 bit output, the same encoding as the image written to stdout.
+        let mut out = BufWriter::new(File::create(path)?);
+        write!(out, "P3\n{} {}\n255\n", self.width, self.height)?;
+        for pixel in &self.pixels {
+            let [r, g, b] = color_to_bytes(pixel);
+            writeln!(out, "{} {} {}", r, g, b)?;
+        }
+        out.flush()
+    }
+
+    pub fn write_pfm(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        // Portable float map: linear 32 bit floats, little endian (negative scale), with the
+        // rows stored from the bottom to the top.
+        let mut out = BufWriter::new(File::create(path)?);
+        write!(out, "PF\n{} {}\n-1.0\n", self.width, self.height)?;
+        for y in (0..self.height).rev() {
+            for x in 0..self.width {
+                let pixel = self.get(x, y);
+                for c in 0..3 {
+                    out.write_all(&(pixel[c] as f32).to_le_bytes())?;
+                }
+            }
+        }
+        out.flush()
+    }
+
+    pub fn read_pfm(path: impl AsRef<Path>) -> io::Result<Image> {
+        Image::parse_pfm(&fs::read(path)?)
+    }
+
+    pub fn parse_pfm(bytes: &[u8]) -> io::Result<Image> {
+        let mut pos = 0;
+        let magic = next_token(bytes, &mut pos)?;
+        let channels = match magic.as_str() {
+            "Pf" => 1,
+            "PF" => 3,
+            _ => return Err(invalid_data(format!("unsupported float map format '{}'", magic))),
+        };
+
+        let width = parse_usize(&next_token(bytes, &mut pos)?)?;
+        let height = parse_usize(&next_token(bytes, &mut pos)?)?;
+        let scale_token = next_token(bytes, &mut pos)?;
+        let scale = scale_token
+            .parse::<f64>()
+            .map_err(|_| invalid_data(format!("invalid scale '{}'", scale_token)))?;
+        pos += 1;
+
+        let raster = bytes
+            .get(pos..pos + width * height * channels * 4)
+            .ok_or_else(|| invalid_data("truncated raster data".to_string()))?;
+        let samples: Vec<f64> = raster
+            .chunks(4)
+            .map(|b| {
+                let b = [b[0], b[1], b[2], b[3]];
+                let value = if scale < 0.0 { f32::from_le_bytes(b) } else { f32::from_be_bytes(b) };
+                value as f64
+            })
+            .collect();
+
+        let mut image = Image::new(width, height);
+        for (index, c) in samples.chunks(channels).enumerate() {
+            let (x, y) = (index % width, height - 1 - index / width);
+            if channels == 1 {
+                image.set(x, y, color(c[0], c[0], c[0]));
+            } else {
+                image.set(x, y, color(c[0], c[1], c[2]));
+            }
+        }
+        Ok(image)
+    }
+
+    pub fn read_ppm(path: impl AsRef<Path>) -> io::Result<Image> {
+        Image::parse_ppm(&fs::read(path)?)
+    }
+
+    pub fn parse_ppm(bytes: &[u8]) -> io::Result<Image> {
+        // Reads ASCII (P2/P3) and binary (P5/P6) netpbm images. Values are scaled to [0,1],
+        // grey images are expanded to three equal channels.
+        let mut pos = 0;
+        let magic = next_token(bytes, &mut pos)?;
+        let (binary, channels) = match magic.as_str() {
+            "P2" => (false, 1),
+            "P3" => (false, 3),
+            "P5" => (true, 1),
+            "P6" => (true, 3),
+            _ => return Err(invalid_data(format!("unsupported netpbm format '{}'", magic))),
+        };
+
+        let width = parse_usize(&next_token(bytes, &mut pos)?)?;
+        let height = parse_usize(&next_token(bytes, &mut pos)?)?;
+        let max_value = parse_usize(&next_token(bytes, &mut pos)?)?;
+        if max_value == 0 || max_value > 65535 {
+            return Err(invalid_data(format!("invalid maximum value {}", max_value)));
+        }
+
+        let sample_count = width * height * channels;
+        let mut samples = Vec::with_capacity(sample_count);
+
+        if binary {
+            // Exactly one whitespace character separates the header from the raster.
+            pos += 1;
+            let bytes_per_sample = if max_value < 256 { 1 } else { 2 };
+            let raster = bytes
+                .get(pos..pos + sample_count * bytes_per_sample)
+                .ok_or_else(|| invalid_data("truncated raster data".to_string()))?;
+            for chunk in raster.chunks(bytes_per_sample) {
+                let value = chunk.iter().fold(0usize, |acc, b| (acc << 8) | *b as usize);
+                samples.push(value as f64 / max_value as f64);
+            }
+        } else {
+            for _ in 0..sample_count {
+                let value = parse_usize(&next_token(bytes, &mut pos)?)?;
+                samples.push(value as f64 / max_value as f64);
+            }
+        }
+
+        let pixels = samples
+            .chunks(channels)
+            .map(|c| {
+                if channels == 1 {
+                    color(c[0], c[0], c[0])
+                } else {
+                    color(c[0], c[1], c[2])
+                }
+            })
+            .collect();
+
+        Ok(Image {
+            width,
+            height,
+            pixels,
+        })
+    }
+}
+
+pub(crate) fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+pub(crate) fn parse_usize(token: &str) -> io::Result<usize> {
+    token
+        .parse::<usize>()
+        .map_err(|_| invalid_data(format!("expected a number, found '{}'", token)))
+}
+
+pub(crate) fn next_token(bytes: &[u8], pos: &mut usize) -> io::Result<String> {
+    // Skip whitespace and '#' comments, then read up to the next whitespace.
+    loop {
+        match bytes.get(*pos) {
+            Some(b'#') => {
+                while *pos < bytes.len() && bytes[*pos] != b'\n' {
+                    *pos += 1;
+                }
+            }
+            Some(b) if b.is_ascii_whitespace() => *pos += 1,
+            Some(_) => break,
+            None => return Err(invalid_data("unexpected end of file".to_string())),
+        }
+    }
+
+    let start = *pos;
+    while *pos < bytes.len() && !bytes[*pos].is_ascii_whitespace() {
+        *pos += 1;
+    }
+    Ok(String::from_utf8_lossy(&bytes[start..*pos]).into_owned())
+}
+
+#[test]
+fn parse_ppm_test() {
+    let ascii = b"P3\n# comment\n2 1\n255\n255 0 0  0 0 255\n";
+    let image = Image::parse_ppm(ascii).unwrap();
+    assert_eq!(image.width, 2);
+    assert_eq!(image.height, 1);
+    assert_eq!(image.get(0, 0), color(1.0, 0.0, 0.0));
+    assert_eq!(image.get(1, 0), color(0.0, 0.0, 1.0));
+
+    let binary = b"P5 2 1 255\n\xff\x00";
+    let image = Image::parse_ppm(binary).unwrap();
+    assert_eq!(image.get(0, 0), color(1.0, 1.0, 1.0));
+    assert_eq!(image.get(1, 0), color(0.0, 0.0, 0.0));
+
+    assert!(Image::parse_ppm(b"P6 2 2 255\n\x00").is_err());
+}
+
+#[test]
+fn pfm_round_trip_test() {
+    let mut image = Image::new(2, 3);
+    image.set(1, 0, color(0.25, -1.5, 1000.0));
+    image.set(0, 2, color(0.125, 0.0, 3.0));
+
+    let path = std::env::temp_dir().join(format!("pfm_round_trip_test_{}.pfm", std::process::id()));
+    image.write_pfm(&path).unwrap();
+    let read_back = Image::read_pfm(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(image, read_back);
+}
@@ -1,9 +1,8 @@
-use std::f64::{INFINITY, NEG_INFINITY};
-
 pub fn interval(min: f64, max: f64) -> Interval {
     Interval { min, max }
 }
 
+#[derive(Debug, Clone, Copy)]
 pub struct Interval {
     pub min: f64,
     pub max: f64,
@@ -12,13 +11,18 @@ pub struct Interval {
 impl Default for Interval {
     fn default() -> Self {
         Self {
-            min: INFINITY,
-            max: NEG_INFINITY,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
         }
     }
 }
 
 impl Interval {
+    pub fn enclosing(a: &Interval, b: &Interval) -> Interval {
+        // The tightest interval enclosing both intervals.
+        interval(a.min.min(b.min), a.max.max(b.max))
+    }
+
     pub fn size(&self) -> f64 {
         self.max - self.min
     }
@@ -31,6 +35,11 @@ impl Interval {
         self.min < x && x < self.max
     }
 
+    pub fn expand(&self, delta: f64) -> Interval {
+        let padding = delta / 2.0;
+        interval(self.min - padding, self.max + padding)
+    }
+
     pub fn clamp(&self, x: f64) -> f64 {
         if x < self.min {
             return self.min;
@@ -43,10 +52,10 @@ impl Interval {
 }
 
 pub const INTERVAL_EMPTY: Interval = Interval {
-    min: INFINITY,
-    max: NEG_INFINITY,
+    min: f64::INFINITY,
+    max: f64::NEG_INFINITY,
 };
 pub const INTERVAL_UNIVERSE: Interval = Interval {
-    min: NEG_INFINITY,
-    max: INFINITY,
+    min: f64::NEG_INFINITY,
+    max: f64::INFINITY,
 };
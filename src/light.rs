@@ -0,0 +1,604 @@
+use std::f64::consts::PI;
+
+use crate::aabb::Aabb;
+use crate::color::luminance;
+use crate::rtweekend::random_double;
+use crate::vec3::*;
+
+/// Light source sampled directly at every scattering (next event estimation), in addition to
+/// being found by the scattered rays. Both estimates are combined with multiple importance
+/// sampling.
+#[derive(Debug, Clone)]
+pub enum Light {
+    // Light from infinitely far away within the cone of directions around `direction`, like
+    // the sun disk.
+    DistantDisk { direction: Vec3, cos_max: f64, radiance: Vec3 },
+    // Surface of an emissive object in the scene glowing with the same radiance into all
+    // directions, on both of its sides. Registered by the objects made of `diffuse_light`.
+    Area { shape: Shape, radiance: Vec3 },
+    // The lights below are delta lights, which no scattered ray can find. They only light
+    // surfaces that can be sampled towards lights, not mirrors or glass.
+    //
+    // Light from a single point, falling off with the squared distance.
+    Point { position: Vec3, intensity: Vec3 },
+    // Point light shining into a cone around `direction`, with the full intensity within the
+    // inner angle and falling off smoothly to zero at the outer one.
+    Spot {
+        position: Vec3,
+        direction: Vec3,
+        intensity: Vec3,
+        cos_inner: f64,
+        cos_outer: f64,
+    },
+    // Parallel light from infinitely far away in `direction`, with the irradiance it gives on
+    // a surface facing it.
+    Directional { direction: Vec3, irradiance: Vec3 },
+}
+
+/// Surface of an area light.
+#[derive(Debug, Clone, Copy)]
+pub enum Shape {
+    Sphere { center: Vec3, radius: f64 },
+    Triangle { p0: Vec3, p1: Vec3, p2: Vec3 },
+}
+
+impl Shape {
+    pub fn area(&self) -> f64 {
+        match *self {
+            Shape::Sphere { radius, .. } => 4.0 * PI * radius * radius,
+            Shape::Triangle { p0, p1, p2 } => cross(&(p1 - p0), &(p2 - p0)).length() / 2.0,
+        }
+    }
+
+    pub fn bounding_box(&self) -> Aabb {
+        match *self {
+            Shape::Sphere { center, radius } => {
+                let extent = vec3(radius, radius, radius);
+                Aabb::from_points(&(center - extent), &(center + extent))
+            }
+            Shape::Triangle { p0, p1, p2 } => Aabb::from_boxes(&Aabb::from_points(&p0, &p1), &Aabb::from_points(&p2, &p2)),
+        }
+    }
+
+    pub fn normal_at(&self, p: &Vec3) -> Vec3 {
+        // Outward normal of a sphere, the normal following the winding order of a triangle.
+        match *self {
+            Shape::Sphere { center, .. } => unit_vector(&(*p - center)),
+            Shape::Triangle { p0, p1, p2 } => unit_vector(&cross(&(p1 - p0), &(p2 - p0))),
+        }
+    }
+
+    fn sample_point(&self) -> Vec3 {
+        // Point uniformly distributed over the surface.
+        match *self {
+            Shape::Sphere { center, radius } => center + radius * random_unit_vector(),
+            Shape::Triangle { p0, p1, p2 } => {
+                let (s, t) = (random_double().sqrt(), random_double());
+                (1.0 - s) * p0 + s * (1.0 - t) * p1 + s * t * p2
+            }
+        }
+    }
+
+    fn intersect(&self, origin: &Vec3, direction: &Vec3) -> Option<f64> {
+        // Distance along the unit direction to the first point of the surface in front.
+        match *self {
+            Shape::Sphere { center, radius } => {
+                let oc = center - *origin;
+                let h = dot(direction, &oc);
+                let discriminant = h * h - (oc.length_squared() - radius * radius);
+                if discriminant < 0.0 {
+                    return None;
+                }
+                let sqrtd = discriminant.sqrt();
+                [h - sqrtd, h + sqrtd].into_iter().find(|t| *t > 0.0)
+            }
+            Shape::Triangle { p0, p1, p2 } => {
+                let (e1, e2) = (p1 - p0, p2 - p0);
+                let pvec = cross(direction, &e2);
+                let det = dot(&e1, &pvec);
+                if det.abs() < 1e-12 {
+                    return None;
+                }
+                let tvec = *origin - p0;
+                let b1 = dot(&tvec, &pvec) / det;
+                let qvec = cross(&tvec, &e1);
+                let b2 = dot(direction, &qvec) / det;
+                let t = dot(&e2, &qvec) / det;
+                (b1 >= 0.0 && b2 >= 0.0 && b1 + b2 <= 1.0 && t > 0.0).then_some(t)
+            }
+        }
+    }
+
+    fn visible_cone(&self, p: &Vec3) -> Option<(Vec3, f64, f64)> {
+        // Axis and cosine of the half angle of the cone a sphere fills seen from outside of it,
+        // with the distance to its center.
+        match *self {
+            Shape::Sphere { center, radius } => {
+                let (axis, distance) = towards(p, &center);
+                (distance > radius).then(|| (axis, (1.0 - (radius / distance).powi(2)).sqrt(), distance))
+            }
+            Shape::Triangle { .. } => None,
+        }
+    }
+}
+
+pub fn point_light(position: Vec3, intensity: Vec3) -> Light {
+    Light::Point { position, intensity }
+}
+
+pub fn spot_light(position: Vec3, direction: Vec3, intensity: Vec3, inner_angle: f64, outer_angle: f64) -> Light {
+    // Angles in degrees from the axis of the cone.
+    let outer_angle = outer_angle.max(inner_angle);
+    Light::Spot {
+        position,
+        direction: unit_vector(&direction),
+        intensity,
+        cos_inner: inner_angle.to_radians().cos(),
+        cos_outer: outer_angle.to_radians().cos(),
+    }
+}
+
+pub fn directional_light(direction: Vec3, irradiance: Vec3) -> Light {
+    // `direction` is the one the light travels in.
+    Light::Directional {
+        direction: -unit_vector(&direction),
+        irradiance,
+    }
+}
+
+/// Direction towards a light with the radiance arriving from it and the density of sampling
+/// the direction, per solid angle. For delta lights the density is 1 and the radiance is the
+/// irradiance the light gives on a surface facing it.
+#[derive(Debug, Clone, Copy)]
+pub struct LightSample {
+    pub direction: Vec3,
+    pub distance: f64, // Distance to the light for shadow rays, infinite for distant lights
+    pub radiance: Vec3,
+    pub pdf: f64,
+}
+
+/// Ray of light leaving a light with the radiance it carries (the intensity for point and spot
+/// lights, the irradiance for directional light) and the densities of sampling its origin, per
+/// area, and its direction, per solid angle. Delta distributions have a density of 1.
+#[derive(Debug, Clone, Copy)]
+pub struct EmissionSample {
+    pub origin: Vec3,
+    pub direction: Vec3,
+    pub radiance: Vec3,
+    pub pdf_position: f64,
+    pub pdf_direction: f64,
+}
+
+impl Light {
+    pub fn sample(&self, p: &Vec3) -> LightSample {
+        match *self {
+            Light::DistantDisk {
+                direction,
+                cos_max,
+                radiance,
+            } => {
+                let cos_theta = 1.0 - random_double() * (1.0 - cos_max);
+                LightSample {
+                    direction: direction_around(&direction, cos_theta, 2.0 * PI * random_double()),
+                    distance: f64::INFINITY,
+                    radiance,
+                    pdf: 1.0 / (2.0 * PI * (1.0 - cos_max)),
+                }
+            }
+            Light::Area { shape, radiance } => {
+                // Spheres are sampled within the cone they fill, so that no sample lands on the
+                // far side. Everything else is sampled by area, converted to solid angle.
+                if let Some((axis, cos_max, center_distance)) = shape.visible_cone(p) {
+                    let cos_theta = 1.0 - random_double() * (1.0 - cos_max);
+                    let direction = direction_around(&axis, cos_theta, 2.0 * PI * random_double());
+                    // Directions along the border of the cone may just miss the sphere
+                    // numerically, they touch it at the tangent point.
+                    return LightSample {
+                        direction,
+                        distance: shape.intersect(p, &direction).unwrap_or(center_distance * cos_max),
+                        radiance,
+                        pdf: 1.0 / (2.0 * PI * (1.0 - cos_max)),
+                    };
+                }
+                let point = shape.sample_point();
+                let (direction, distance) = towards(p, &point);
+                let cos_light = dot(&shape.normal_at(&point), &direction).abs();
+                LightSample {
+                    direction,
+                    distance,
+                    radiance,
+                    pdf: if cos_light > 0.0 { distance * distance / (cos_light * shape.area()) } else { 0.0 },
+                }
+            }
+            Light::Point { position, intensity } => {
+                let (direction, distance) = towards(p, &position);
+                LightSample {
+                    direction,
+                    distance,
+                    radiance: intensity / (distance * distance),
+                    pdf: 1.0,
+                }
+            }
+            Light::Spot {
+                position,
+                direction: axis,
+                intensity,
+                cos_inner,
+                cos_outer,
+            } => {
+                let (direction, distance) = towards(p, &position);
+                let falloff = spot_falloff(dot(&-direction, &axis), cos_inner, cos_outer);
+                LightSample {
+                    direction,
+                    distance,
+                    radiance: falloff * intensity / (distance * distance),
+                    pdf: 1.0,
+                }
+            }
+            Light::Directional { direction, irradiance } => LightSample {
+                direction,
+                distance: f64::INFINITY,
+                radiance: irradiance,
+                pdf: 1.0,
+            },
+        }
+    }
+
+    pub fn sample_emission(&self, scene_center: &Vec3, scene_radius: f64) -> EmissionSample {
+        // Light leaving the light source, for paths traced from the lights. Light from far away
+        // starts on a disk facing it just outside of the sphere around the scene.
+        let from_far_away = |direction: Vec3, radiance: Vec3, pdf_direction: f64| {
+            let offset = direction_around(&direction, 0.0, 2.0 * PI * random_double());
+            EmissionSample {
+                origin: *scene_center + scene_radius * (random_double().sqrt() * offset - direction),
+                direction,
+                radiance,
+                pdf_position: 1.0 / (PI * scene_radius * scene_radius),
+                pdf_direction,
+            }
+        };
+        match *self {
+            Light::DistantDisk {
+                direction,
+                cos_max,
+                radiance,
+            } => {
+                let cos_theta = 1.0 - random_double() * (1.0 - cos_max);
+                let towards_light = direction_around(&direction, cos_theta, 2.0 * PI * random_double());
+                from_far_away(-towards_light, radiance, 1.0 / (2.0 * PI * (1.0 - cos_max)))
+            }
+            Light::Directional { direction, irradiance } => from_far_away(-direction, irradiance, 1.0),
+            Light::Area { shape, radiance } => {
+                // Points spread evenly over the surface, the light leaving them to either side
+                // cosine weighted. The radiance is weighted by the cosine as well, to give the
+                // intensity per area.
+                let origin = shape.sample_point();
+                let side = if random_double() < 0.5 { 1.0 } else { -1.0 };
+                let cos_theta = random_double().sqrt();
+                EmissionSample {
+                    origin,
+                    direction: direction_around(&(side * shape.normal_at(&origin)), cos_theta, 2.0 * PI * random_double()),
+                    radiance: cos_theta * radiance,
+                    pdf_position: 1.0 / shape.area(),
+                    pdf_direction: cos_theta / (2.0 * PI),
+                }
+            }
+            Light::Point { position, intensity } => EmissionSample {
+                origin: position,
+                direction: random_unit_vector(),
+                radiance: intensity,
+                pdf_position: 1.0,
+                pdf_direction: 1.0 / (4.0 * PI),
+            },
+            Light::Spot {
+                position,
+                direction: axis,
+                intensity,
+                cos_inner,
+                cos_outer,
+            } => {
+                let cos_theta = 1.0 - random_double() * (1.0 - cos_outer);
+                EmissionSample {
+                    origin: position,
+                    direction: direction_around(&axis, cos_theta, 2.0 * PI * random_double()),
+                    radiance: spot_falloff(cos_theta, cos_inner, cos_outer) * intensity,
+                    pdf_position: 1.0,
+                    pdf_direction: 1.0 / (2.0 * PI * (1.0 - cos_outer)),
+                }
+            }
+        }
+    }
+
+    pub fn emission_pdf(&self, origin: &Vec3, direction: &Vec3) -> f64 {
+        // Density of `sample_emission` sending light into the direction from the origin on a
+        // point, spot or area light, per solid angle.
+        match *self {
+            Light::Area { shape, .. } => dot(&shape.normal_at(origin), &unit_vector(direction)).abs() / (2.0 * PI),
+            Light::Point { .. } => 1.0 / (4.0 * PI),
+            Light::Spot {
+                direction: axis,
+                cos_outer,
+                ..
+            } if dot(&unit_vector(direction), &axis) >= cos_outer => 1.0 / (2.0 * PI * (1.0 - cos_outer)),
+            _ => 0.0,
+        }
+    }
+
+    pub fn emission_position_pdf(&self) -> f64 {
+        // Density of `sample_emission` starting at its origin, per area on area lights. Point
+        // and spot lights always start at their position.
+        match *self {
+            Light::Area { shape, .. } => 1.0 / shape.area(),
+            _ => 1.0,
+        }
+    }
+
+    pub fn normal_at(&self, p: &Vec3) -> Vec3 {
+        // Normal of an area light at the point on it, zero for lights without a surface.
+        match *self {
+            Light::Area { shape, .. } => shape.normal_at(p),
+            _ => Vec3::default(),
+        }
+    }
+
+    pub fn power(&self, scene_radius: f64) -> f64 {
+        // Luminance of the total power emitted, for choosing between lights. Light from far away
+        // counts as far as it falls onto the scene within the given radius.
+        let area = PI * scene_radius * scene_radius;
+        match *self {
+            Light::DistantDisk { cos_max, radiance, .. } => {
+                area * 2.0 * PI * (1.0 - cos_max) * luminance(&radiance)
+            }
+            Light::Point { intensity, .. } => 4.0 * PI * luminance(&intensity),
+            Light::Spot {
+                intensity,
+                cos_inner,
+                cos_outer,
+                ..
+            } => 2.0 * PI * (1.0 - (cos_inner + cos_outer) / 2.0) * luminance(&intensity),
+            Light::Directional { irradiance, .. } => area * luminance(&irradiance),
+            Light::Area { shape, radiance } => 2.0 * PI * shape.area() * luminance(&radiance),
+        }
+    }
+
+    pub fn is_infinite(&self) -> bool {
+        // Lights from infinitely far away, without a position.
+        matches!(self, Light::DistantDisk { .. } | Light::Directional { .. })
+    }
+
+    pub fn is_delta(&self) -> bool {
+        !matches!(self, Light::DistantDisk { .. } | Light::Area { .. })
+    }
+
+    pub fn pdf(&self, p: &Vec3, direction: &Vec3) -> f64 {
+        // Density of `sample` producing the direction at `p`.
+        let direction = unit_vector(direction);
+        match *self {
+            Light::DistantDisk { direction: axis, cos_max, .. } if dot(&direction, &axis) >= cos_max => {
+                1.0 / (2.0 * PI * (1.0 - cos_max))
+            }
+            Light::Area { shape, .. } => {
+                if let Some((axis, cos_max, _)) = shape.visible_cone(p) {
+                    return if dot(&direction, &axis) >= cos_max { 1.0 / (2.0 * PI * (1.0 - cos_max)) } else { 0.0 };
+                }
+                let Some(distance) = shape.intersect(p, &direction) else {
+                    return 0.0;
+                };
+                let cos_light = dot(&shape.normal_at(&(*p + distance * direction)), &direction).abs();
+                if cos_light > 0.0 {
+                    distance * distance / (cos_light * shape.area())
+                } else {
+                    0.0
+                }
+            }
+            _ => 0.0,
+        }
+    }
+
+    pub fn escaped(&self, direction: &Vec3) -> Vec3 {
+        // Radiance arriving along rays leaving the scene in `direction`.
+        match *self {
+            Light::DistantDisk {
+                direction: axis,
+                cos_max,
+                radiance,
+            } if dot(&unit_vector(direction), &axis) >= cos_max => radiance,
+            _ => Vec3::default(),
+        }
+    }
+}
+
+fn spot_falloff(cos_theta: f64, cos_inner: f64, cos_outer: f64) -> f64 {
+    // Smoothstep from the outer to the inner angle of a spot light.
+    if cos_theta >= cos_inner {
+        1.0
+    } else if cos_theta <= cos_outer {
+        0.0
+    } else {
+        let x = (cos_theta - cos_outer) / (cos_inner - cos_outer);
+        x * x * (3.0 - 2.0 * x)
+    }
+}
+
+fn towards(p: &Vec3, position: &Vec3) -> (Vec3, f64) {
+    // Unit direction and distance from `p` to `position`.
+    let offset = *position - *p;
+    let distance = offset.length();
+    (offset / distance, distance)
+}
+
+pub(crate) fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    // Weight of a sample of a strategy with density `pdf` when another strategy with density
+    // `other_pdf` could have produced it as well (Veach's power heuristic with exponent 2).
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    if a + b <= 0.0 {
+        0.0
+    } else {
+        a / (a + b)
+    }
+}
+
+#[test]
+fn distant_disk_test() {
+    // Samples stay within the cone, uniformly distributed over its solid angle.
+    let axis = unit_vector(&vec3(0.3, 1.0, -0.2));
+    let cos_max = (2.0f64).to_radians().cos();
+    let light = Light::DistantDisk {
+        direction: axis,
+        cos_max,
+        radiance: vec3(1.0, 1.0, 1.0),
+    };
+    let mut mean_cos = 0.0;
+    for _ in 0..10_000 {
+        let sample = light.sample(&Vec3::default());
+        assert!(dot(&sample.direction, &axis) >= cos_max - 1e-12);
+        assert_eq!(light.pdf(&Vec3::default(), &sample.direction), sample.pdf);
+        mean_cos += dot(&sample.direction, &axis) / 10_000.0;
+    }
+    assert!((mean_cos - (1.0 + cos_max) / 2.0).abs() < 1e-5);
+    assert_eq!(light.escaped(&-axis), Vec3::default());
+    assert_eq!(light.pdf(&Vec3::default(), &-axis), 0.0);
+    assert_eq!(power_heuristic(3.0, 1.0), 0.9);
+    assert!(!light.is_delta());
+}
+
+#[test]
+fn delta_lights_test() {
+    // Delta lights are sampled with density 1 and give the irradiance on a surface facing them:
+    // falling off with the squared distance for point and spot lights, constant for directional
+    // light. Spot lights fade out between the inner and outer angle.
+    let p = point3(1.0, 0.0, 0.0);
+    let point = point_light(point3(1.0, 2.0, 0.0), vec3(4.0, 8.0, 12.0));
+    let sample = point.sample(&p);
+    assert!(point.is_delta() && sample.pdf == 1.0 && sample.distance == 2.0);
+    assert_eq!((sample.direction, sample.radiance), (vec3(0.0, 1.0, 0.0), vec3(1.0, 2.0, 3.0)));
+    assert_eq!(point.pdf(&p, &sample.direction), 0.0);
+    assert_eq!(point.escaped(&sample.direction), Vec3::default());
+
+    let spot = spot_light(point3(0.0, 1.0, 0.0), vec3(0.0, -1.0, 0.0), vec3(1.0, 1.0, 1.0), 20.0, 40.0);
+    let at_angle = |degrees: f64| spot.sample(&point3(degrees.to_radians().tan(), 0.0, 0.0)).radiance.x();
+    assert_eq!(at_angle(0.0), 1.0);
+    assert!((at_angle(15.0) - 15.0f64.to_radians().cos().powi(2)).abs() < 1e-12);
+    assert!(at_angle(25.0) > at_angle(35.0) && at_angle(35.0) > 0.0);
+    assert_eq!(at_angle(45.0), 0.0);
+
+    let directional = directional_light(vec3(0.0, -2.0, 0.0), vec3(1.0, 1.0, 1.0));
+    let sample = directional.sample(&p);
+    assert_eq!((sample.direction, sample.radiance), (vec3(0.0, 1.0, 0.0), vec3(1.0, 1.0, 1.0)));
+    assert!(sample.distance.is_infinite());
+}
+
+#[test]
+fn point_light_test() {
+    use crate::camera::Camera;
+    use crate::color::color;
+    use crate::hittable::Hittable;
+    use crate::hittable_list::HittableList;
+    use crate::material::lambertian;
+    use crate::sphere::sphere;
+
+    // A diffuse ground below a point light reflects albedo / pi * I * cos(theta) / d^2. Point
+    // lights need no random numbers, so the light alone is the difference to the same render
+    // without it.
+    let objects: Vec<Box<dyn Hittable>> = vec![Box::new(sphere(
+        &point3(0.0, -1000.0, 0.0),
+        1000.0,
+        &lambertian(color(0.5, 0.5, 0.5)),
+    ))];
+    let world = HittableList::new(objects);
+
+    let mut cam = Camera::default();
+    cam.image_width = 8;
+    cam.samples_per_pixel = 4;
+    cam.seed = Some(5);
+    cam.vfov = 2.0;
+    cam.look_from = point3(0.0, 1.0, 0.0);
+    cam.look_at = point3(0.0, 0.0, 0.0);
+    cam.v_up = vec3(0.0, 0.0, -1.0);
+    let unlit = cam.render_buffers(&world).beauty;
+    cam.lights = vec![point_light(point3(0.5, 2.0, 0.0), vec3(4.0, 4.0, 4.0))];
+    let lit = cam.render_buffers(&world).beauty;
+
+    let d2: f64 = 0.25 + 4.0;
+    let expected = 0.5 / PI * 4.0 * (2.0 / d2.sqrt()) / d2;
+    for (lit, unlit) in lit.pixels.iter().zip(unlit.pixels.iter()) {
+        let light = *lit - *unlit;
+        assert!((light.x() - expected).abs() < 0.01 * expected, "{} instead of {}", light.x(), expected);
+    }
+}
+
+#[test]
+fn area_light_test() {
+    // Sampled directions point at the surface at the sampled distance, with the density `pdf`
+    // gives them. Spheres seen from outside are sampled within the cone they fill, from inside
+    // their surface covers all directions.
+    let radiance = vec3(1.0, 1.0, 1.0);
+    let sphere = Light::Area {
+        shape: Shape::Sphere {
+            center: point3(0.0, 2.0, 0.0),
+            radius: 0.5,
+        },
+        radiance,
+    };
+    let triangle = Light::Area {
+        shape: Shape::Triangle {
+            p0: point3(-1.0, 1.0, -1.0),
+            p1: point3(1.0, 1.0, -1.0),
+            p2: point3(0.0, 1.5, 1.0),
+        },
+        radiance,
+    };
+    for (light, p) in [(&sphere, Vec3::default()), (&sphere, point3(0.1, 2.0, 0.0)), (&triangle, Vec3::default())] {
+        let Light::Area { shape, .. } = *light else { unreachable!() };
+        let mut solid_angle = 0.0;
+        for _ in 0..10_000 {
+            let sample = light.sample(&p);
+            assert!(sample.pdf > 0.0 && sample.radiance == radiance);
+            let distance = shape.intersect(&p, &sample.direction).unwrap();
+            assert!((distance - sample.distance).abs() < 1e-9 * distance.max(1.0), "{} instead of {}", sample.distance, distance);
+            assert!((light.pdf(&p, &sample.direction) - sample.pdf).abs() < 1e-9 * sample.pdf);
+            solid_angle += 1.0 / sample.pdf / 10_000.0;
+        }
+        if let Some((_, cos_max, _)) = shape.visible_cone(&p) {
+            assert!((solid_angle - 2.0 * PI * (1.0 - cos_max)).abs() < 1e-9);
+        } else if matches!(shape, Shape::Sphere { .. }) {
+            assert!((solid_angle - 4.0 * PI).abs() < 0.05 * 4.0 * PI, "{}", solid_angle);
+        }
+    }
+    assert_eq!(sphere.pdf(&Vec3::default(), &vec3(0.0, -1.0, 0.0)), 0.0);
+    assert_eq!(triangle.pdf(&Vec3::default(), &vec3(0.0, -1.0, 0.0)), 0.0);
+    assert!(!sphere.is_delta() && !sphere.is_infinite());
+}
+
+#[test]
+fn area_light_render_test() {
+    use crate::camera::Camera;
+    use crate::color::color;
+    use crate::hittable::Hittable;
+    use crate::hittable_list::HittableList;
+    use crate::material::{diffuse_light, lambertian};
+    use crate::sphere::sphere;
+
+    // A diffuse ground right below a glowing sphere of radius r at the distance d of its center
+    // reflects albedo * L * r^2 / d^2. A black dome keeps out the sky.
+    let objects: Vec<Box<dyn Hittable>> = vec![
+        Box::new(sphere(&point3(0.0, -1000.0, 0.0), 1000.0, &lambertian(color(0.5, 0.5, 0.5)))),
+        Box::new(sphere(&point3(0.0, 0.0, 0.0), 20.0, &lambertian(color(0.0, 0.0, 0.0)))),
+        Box::new(sphere(&point3(0.0, 1.0, 0.0), 0.5, &diffuse_light(color(4.0, 4.0, 4.0)))),
+    ];
+    let world = HittableList::new(objects);
+    assert_eq!(world.area_lights().len(), 1);
+
+    let mut cam = Camera::default();
+    cam.image_width = 8;
+    cam.samples_per_pixel = 64;
+    cam.seed = Some(5);
+    cam.vfov = 1.0;
+    cam.look_from = point3(2.0, 1.0, 0.0);
+    cam.look_at = point3(0.0, 0.0, 0.0);
+    let image = cam.render_buffers(&world).beauty;
+
+    let mean = image.pixels.iter().map(|p| p.x()).sum::<f64>() / image.pixels.len() as f64;
+    let expected = 0.5 * 4.0 * 0.25;
+    assert!((mean - expected).abs() < 0.02 * expected, "{} instead of {}", mean, expected);
+}
@@ -0,0 +1,652 @@
+use std::f64::consts::PI;
+
+use crate::aabb::{Aabb, AABB_EMPTY};
+use crate::light::Light;
+use crate::rtweekend::random_double;
+use crate::vec3::*;
+
+/// How the lights are sampled at every scattering. Sampling all of them costs a shadow ray per
+/// light, with many lights it's better to pick one of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LightSampling {
+    All,     // Every light with its own shadow ray
+    Uniform, // One light, all equally likely
+    Power,   // One light, proportional to the power it emits
+    Tree,    // One light, proportional to its estimated contribution at the shading point
+}
+
+pub const ALL_LIGHT_SAMPLINGS: [LightSampling; 4] =
+    [LightSampling::All, LightSampling::Uniform, LightSampling::Power, LightSampling::Tree];
+
+impl LightSampling {
+    pub fn name(&self) -> &'static str {
+        match self {
+            LightSampling::All => "all",
+            LightSampling::Uniform => "uniform",
+            LightSampling::Power => "power",
+            LightSampling::Tree => "tree",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<LightSampling> {
+        ALL_LIGHT_SAMPLINGS.iter().copied().find(|sampling| sampling.name() == name)
+    }
+}
+
+/// Chooses the light sampled at a shading point with position `p` and normal `n`. The normal
+/// is zero for points in volumes, which receive light from all directions.
+#[derive(Debug)]
+pub enum LightSampler {
+    All,
+    Uniform(usize),
+    Power(AliasTable),
+    Tree(LightTree),
+}
+
+impl LightSampler {
+    pub fn new(sampling: LightSampling, lights: &[Light], scene_bounds: &Aabb) -> LightSampler {
+        match sampling {
+            LightSampling::All => LightSampler::All,
+            LightSampling::Uniform => LightSampler::Uniform(lights.len()),
+            LightSampling::Power => {
+                let radius = bounding_radius(scene_bounds);
+                let powers: Vec<f64> = lights.iter().map(|light| light.power(radius)).collect();
+                LightSampler::Power(AliasTable::new(&powers))
+            }
+            LightSampling::Tree => LightSampler::Tree(LightTree::new(lights)),
+        }
+    }
+
+    pub fn sample(&self, p: &Vec3, n: &Vec3) -> Option<(usize, f64)> {
+        // Index of the chosen light and the probability of choosing it, none if no light can
+        // contribute. Not for sampling all lights.
+        match self {
+            LightSampler::All => None,
+            LightSampler::Uniform(count) => {
+                let index = ((random_double() * *count as f64) as usize).min(count.checked_sub(1)?);
+                Some((index, 1.0 / *count as f64))
+            }
+            LightSampler::Power(table) => {
+                let index = table.sample(random_double())?;
+                Some((index, table.pmf(index)))
+            }
+            LightSampler::Tree(tree) => tree.sample(p, n),
+        }
+    }
+
+    pub fn pmf(&self, p: &Vec3, n: &Vec3, index: usize) -> f64 {
+        // Probability of `sample` choosing the light, 1 when sampling all of them.
+        match self {
+            LightSampler::All => 1.0,
+            LightSampler::Uniform(count) => 1.0 / *count as f64,
+            LightSampler::Power(table) => table.pmf(index),
+            LightSampler::Tree(tree) => tree.pmf(p, n, index),
+        }
+    }
+}
+
+/// Samples indices proportional to their weights in constant time with Walker's alias method.
+/// Every bin holds the probability of keeping its own index and the index it passes the rest
+/// to.
+#[derive(Debug, Clone)]
+pub struct AliasTable {
+    bins: Vec<(f64, usize)>,
+    pmf: Vec<f64>,
+}
+
+impl AliasTable {
+    pub fn new(weights: &[f64]) -> AliasTable {
+        // Vose's construction. Without any positive weight all indices are equally likely.
+        let total: f64 = weights.iter().map(|w| w.max(0.0)).sum();
+        let pmf: Vec<f64> = if total > 0.0 {
+            weights.iter().map(|w| w.max(0.0) / total).collect()
+        } else {
+            vec![1.0 / weights.len() as f64; weights.len()]
+        };
+
+        let n = pmf.len();
+        let mut bins: Vec<(f64, usize)> = (0..n).map(|i| (1.0, i)).collect();
+        let mut scaled: Vec<f64> = pmf.iter().map(|p| p * n as f64).collect();
+        let (mut small, mut large): (Vec<usize>, Vec<usize>) = (0..n).partition(|i| scaled[*i] < 1.0);
+        while let (Some(s), Some(&l)) = (small.pop(), large.last()) {
+            bins[s] = (scaled[s], l);
+            scaled[l] -= 1.0 - scaled[s];
+            if scaled[l] < 1.0 {
+                large.pop();
+                small.push(l);
+            }
+        }
+        // What's left over has a probability of 1 up to rounding.
+        AliasTable { bins, pmf }
+    }
+
+    pub fn len(&self) -> usize {
+        self.pmf.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pmf.is_empty()
+    }
+
+    pub fn sample(&self, u: f64) -> Option<usize> {
+        // Index for a uniform random number `u` in [0, 1).
+        let x = u * self.len() as f64;
+        let i = (x as usize).min(self.len().checked_sub(1)?);
+        let (keep, alias) = self.bins[i];
+        Some(if x - (i as f64) < keep { i } else { alias })
+    }
+
+    pub fn pmf(&self, index: usize) -> f64 {
+        self.pmf[index]
+    }
+}
+
+/// Bounds of the light emitted by a group of lights: where they are, how much they emit and
+/// in which directions, after Conty Estevez and Kulla, "Importance Sampling of Many Lights with
+/// Adaptive Tree Splitting" (2018), as in pbrt-v4.
+#[derive(Debug, Clone, Copy)]
+struct LightBounds {
+    bounds: Aabb,
+    phi: f64,         // Power emitted
+    w: Vec3,          // Axis of the cone of emission directions
+    cos_theta_o: f64, // Spread of the light axes around `w`
+    cos_theta_e: f64, // Angle beyond the axes that light is still emitted into
+}
+
+impl LightBounds {
+    fn of(light: &Light) -> Option<LightBounds> {
+        // None for infinite lights, which have no position.
+        match *light {
+            Light::Point { position, .. } => Some(LightBounds {
+                bounds: Aabb::from_points(&position, &position),
+                phi: light.power(0.0),
+                w: vec3(0.0, 0.0, 1.0),
+                cos_theta_o: -1.0,
+                cos_theta_e: 0.0,
+            }),
+            Light::Spot {
+                position,
+                direction,
+                intensity,
+                cos_inner,
+                cos_outer,
+            } => Some(LightBounds {
+                bounds: Aabb::from_points(&position, &position),
+                // The power of a point light with the same intensity, as the tree needs an
+                // upper bound of the intensity in any direction.
+                phi: Light::Point { position, intensity }.power(0.0),
+                w: direction,
+                cos_theta_o: cos_inner,
+                cos_theta_e: (cos_outer.acos() - cos_inner.acos()).cos(),
+            }),
+            // Area lights glow to both sides of their surfaces, into all directions.
+            Light::Area { shape, .. } => Some(LightBounds {
+                bounds: shape.bounding_box(),
+                phi: light.power(0.0),
+                w: vec3(0.0, 0.0, 1.0),
+                cos_theta_o: -1.0,
+                cos_theta_e: 0.0,
+            }),
+            Light::DistantDisk { .. } | Light::Directional { .. } => None,
+        }
+    }
+
+    fn union(&self, other: &LightBounds) -> LightBounds {
+        let (w, cos_theta_o) = cone_union((self.w, self.cos_theta_o), (other.w, other.cos_theta_o));
+        LightBounds {
+            bounds: Aabb::from_boxes(&self.bounds, &other.bounds),
+            phi: self.phi + other.phi,
+            w,
+            cos_theta_o,
+            cos_theta_e: self.cos_theta_e.min(other.cos_theta_e),
+        }
+    }
+
+    fn importance(&self, p: &Vec3, n: &Vec3) -> f64 {
+        // Upper bound of the light arriving at `p` over the squared distance, reduced by the
+        // smallest angle between the emission cone and the direction to `p`, and by the
+        // smallest angle between the directions to the lights and the normal.
+        let center = box_center(&self.bounds);
+        let distance2 = (*p - center).length_squared();
+        let d2 = distance2.max(box_diagonal(&self.bounds).length() / 2.0);
+
+        let to_p = unit_vector(&(*p - center));
+        let cos_theta_w = dot(&self.w, &to_p);
+        let sin_theta_w = safe_sqrt(1.0 - cos_theta_w * cos_theta_w);
+
+        // Half angle of the cone of directions from `p` enclosing the bounding sphere of the box.
+        let radius2 = (box_diagonal(&self.bounds) / 2.0).length_squared();
+        let cos_theta_b = if distance2 <= radius2 {
+            -1.0
+        } else {
+            safe_sqrt(1.0 - radius2 / distance2)
+        };
+        let sin_theta_b = safe_sqrt(1.0 - cos_theta_b * cos_theta_b);
+
+        let sin_theta_o = safe_sqrt(1.0 - self.cos_theta_o * self.cos_theta_o);
+        let cos_theta_x = cos_sub_clamped(sin_theta_w, cos_theta_w, sin_theta_o, self.cos_theta_o);
+        let sin_theta_x = sin_sub_clamped(sin_theta_w, cos_theta_w, sin_theta_o, self.cos_theta_o);
+        let cos_theta_p = cos_sub_clamped(sin_theta_x, cos_theta_x, sin_theta_b, cos_theta_b);
+        if cos_theta_p <= self.cos_theta_e {
+            return 0.0;
+        }
+
+        let mut importance = self.phi * cos_theta_p / d2;
+        if !n.near_zero() {
+            let cos_theta_i = dot(&-to_p, n).abs();
+            let sin_theta_i = safe_sqrt(1.0 - cos_theta_i * cos_theta_i);
+            importance *= cos_sub_clamped(sin_theta_i, cos_theta_i, sin_theta_b, cos_theta_b);
+        }
+        importance.max(0.0)
+    }
+}
+
+/// Bounding volume hierarchy over the lights with a position, traversed towards the children
+/// with the larger importance at the shading point. Each node splits its lights in half along
+/// the longest axis of its bounding box. Infinite lights are chosen next to the tree, each as
+/// likely as the whole tree.
+#[derive(Debug)]
+pub struct LightTree {
+    root: Option<LightNode>,
+    infinite: Vec<usize>, // Indices of the infinite lights
+    trails: Vec<u64>,     // Per light the branches from the root to its leaf, one bit per level
+}
+
+#[derive(Debug)]
+enum LightNode {
+    Leaf { bounds: LightBounds, light: usize },
+    Interior { bounds: LightBounds, children: Box<[LightNode; 2]> },
+}
+
+impl LightNode {
+    fn new(mut lights: Vec<(usize, LightBounds)>, depth: u32, trail: u64, trails: &mut [u64]) -> LightNode {
+        if lights.len() == 1 {
+            let (light, bounds) = lights[0];
+            trails[light] = trail;
+            return LightNode::Leaf { bounds, light };
+        }
+        assert!(depth < 64, "light tree too deep");
+
+        let centers = lights.iter().fold(AABB_EMPTY, |bbox, (_, bounds)| {
+            let center = box_center(&bounds.bounds);
+            Aabb::from_boxes(&bbox, &Aabb::from_points(&center, &center))
+        });
+        let axis = centers.longest_axis();
+        lights.sort_by(|(_, a), (_, b)| box_center(&a.bounds)[axis].total_cmp(&box_center(&b.bounds)[axis]));
+        let right = lights.split_off(lights.len() / 2);
+
+        let left = LightNode::new(lights, depth + 1, trail, trails);
+        let right = LightNode::new(right, depth + 1, trail | (1 << depth), trails);
+        LightNode::Interior {
+            bounds: left.bounds().union(right.bounds()),
+            children: Box::new([left, right]),
+        }
+    }
+
+    fn bounds(&self) -> &LightBounds {
+        match self {
+            LightNode::Leaf { bounds, .. } | LightNode::Interior { bounds, .. } => bounds,
+        }
+    }
+}
+
+impl LightTree {
+    pub fn new(lights: &[Light]) -> LightTree {
+        let mut infinite = Vec::new();
+        let mut bounded = Vec::new();
+        for (index, light) in lights.iter().enumerate() {
+            match LightBounds::of(light) {
+                Some(bounds) if bounds.phi > 0.0 => bounded.push((index, bounds)),
+                Some(_) => {}
+                None => infinite.push(index),
+            }
+        }
+        let mut trails = vec![0; lights.len()];
+        let root = (!bounded.is_empty()).then(|| LightNode::new(bounded, 0, 0, &mut trails));
+        LightTree { root, infinite, trails }
+    }
+
+    fn infinite_probability(&self) -> f64 {
+        // Probability of choosing one of the infinite lights instead of the tree.
+        let count = self.infinite.len() as f64;
+        count / (count + if self.root.is_some() { 1.0 } else { 0.0 }).max(1.0)
+    }
+
+    pub fn sample(&self, p: &Vec3, n: &Vec3) -> Option<(usize, f64)> {
+        let p_infinite = self.infinite_probability();
+        let mut u = random_double();
+        if u < p_infinite {
+            let index = ((u / p_infinite * self.infinite.len() as f64) as usize).min(self.infinite.len() - 1);
+            return Some((self.infinite[index], p_infinite / self.infinite.len() as f64));
+        }
+        u = (u - p_infinite) / (1.0 - p_infinite);
+
+        let mut node = self.root.as_ref()?;
+        let mut pmf = 1.0 - p_infinite;
+        loop {
+            match node {
+                LightNode::Leaf { bounds, light } => {
+                    return (bounds.importance(p, n) > 0.0).then_some((*light, pmf));
+                }
+                LightNode::Interior { children, .. } => {
+                    let p_left = left_probability(children, p, n)?;
+                    if u < p_left {
+                        u /= p_left;
+                        pmf *= p_left;
+                        node = &children[0];
+                    } else {
+                        u = ((u - p_left) / (1.0 - p_left)).min(1.0 - f64::EPSILON);
+                        pmf *= 1.0 - p_left;
+                        node = &children[1];
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn pmf(&self, p: &Vec3, n: &Vec3, index: usize) -> f64 {
+        // Probability of `sample` choosing the light, following its trail down the tree.
+        let p_infinite = self.infinite_probability();
+        if self.infinite.contains(&index) {
+            return p_infinite / self.infinite.len() as f64;
+        }
+        let Some(mut node) = self.root.as_ref() else {
+            return 0.0;
+        };
+        let mut trail = self.trails[index];
+        let mut pmf = 1.0 - p_infinite;
+        loop {
+            match node {
+                LightNode::Leaf { bounds, light } => {
+                    return if *light == index && bounds.importance(p, n) > 0.0 { pmf } else { 0.0 };
+                }
+                LightNode::Interior { children, .. } => {
+                    let Some(p_left) = left_probability(children, p, n) else {
+                        return 0.0;
+                    };
+                    if trail & 1 == 0 {
+                        pmf *= p_left;
+                        node = &children[0];
+                    } else {
+                        pmf *= 1.0 - p_left;
+                        node = &children[1];
+                    }
+                    trail >>= 1;
+                }
+            }
+        }
+    }
+}
+
+fn left_probability(children: &[LightNode; 2], p: &Vec3, n: &Vec3) -> Option<f64> {
+    // Probability of descending into the first child, none if neither child can contribute.
+    let left = children[0].bounds().importance(p, n);
+    let right = children[1].bounds().importance(p, n);
+    if left + right <= 0.0 {
+        return None;
+    }
+    Some(left / (left + right))
+}
+
+fn cone_union(a: (Vec3, f64), b: (Vec3, f64)) -> (Vec3, f64) {
+    // Smallest cone of directions around an axis containing both cones.
+    let (theta_a, theta_b) = (a.1.clamp(-1.0, 1.0).acos(), b.1.clamp(-1.0, 1.0).acos());
+    let theta_d = dot(&a.0, &b.0).clamp(-1.0, 1.0).acos();
+    if (theta_d + theta_b).min(PI) <= theta_a {
+        return a;
+    }
+    if (theta_d + theta_a).min(PI) <= theta_b {
+        return b;
+    }
+    let theta_o = (theta_a + theta_d + theta_b) / 2.0;
+    if theta_o >= PI {
+        return (a.0, -1.0);
+    }
+    let axis = cross(&a.0, &b.0);
+    if axis.near_zero() {
+        return (a.0, -1.0);
+    }
+    // Rotate the axis of `a` towards `b` by the difference of the angles (Rodrigues).
+    let (k, theta_r) = (unit_vector(&axis), theta_o - theta_a);
+    let w = a.0 * theta_r.cos() + cross(&k, &a.0) * theta_r.sin() + k * dot(&k, &a.0) * (1.0 - theta_r.cos());
+    (unit_vector(&w), theta_o.cos())
+}
+
+fn cos_sub_clamped(sin_a: f64, cos_a: f64, sin_b: f64, cos_b: f64) -> f64 {
+    // cos(max(0, a - b)) from the sines and cosines of the angles.
+    if cos_a > cos_b {
+        1.0
+    } else {
+        cos_a * cos_b + sin_a * sin_b
+    }
+}
+
+fn sin_sub_clamped(sin_a: f64, cos_a: f64, sin_b: f64, cos_b: f64) -> f64 {
+    // sin(max(0, a - b)) from the sines and cosines of the angles.
+    if cos_a > cos_b {
+        0.0
+    } else {
+        sin_a * cos_b - cos_a * sin_b
+    }
+}
+
+fn safe_sqrt(x: f64) -> f64 {
+    x.max(0.0).sqrt()
+}
+
+fn box_center(bbox: &Aabb) -> Vec3 {
+    point3(
+        (bbox.x.min + bbox.x.max) / 2.0,
+        (bbox.y.min + bbox.y.max) / 2.0,
+        (bbox.z.min + bbox.z.max) / 2.0,
+    )
+}
+
+fn box_diagonal(bbox: &Aabb) -> Vec3 {
+    vec3(bbox.x.size(), bbox.y.size(), bbox.z.size())
+}
+
+fn bounding_radius(bbox: &Aabb) -> f64 {
+    // Radius of the sphere around the box, 0 for an empty scene.
+    let diagonal = box_diagonal(bbox);
+    if diagonal.x() >= 0.0 && diagonal.y() >= 0.0 && diagonal.z() >= 0.0 {
+        diagonal.length() / 2.0
+    } else {
+        0.0
+    }
+}
+
+#[test]
+fn alias_table_test() {
+    // Indices are sampled with the frequencies of their weights, zero weights never.
+    let weights = [1.0, 0.0, 3.0, 0.5, 2.5, 1.0];
+    let table = AliasTable::new(&weights);
+    let mut counts = [0usize; 6];
+    const N: usize = 80_000;
+    for k in 0..N {
+        counts[table.sample((k as f64 + 0.5) / N as f64).unwrap()] += 1;
+    }
+    for (i, count) in counts.iter().enumerate() {
+        assert!((table.pmf(i) - weights[i] / 8.0).abs() < 1e-12);
+        assert!((*count as f64 / N as f64 - table.pmf(i)).abs() < 1e-3, "{:?}", counts);
+    }
+    assert_eq!(AliasTable::new(&[0.0, 0.0]).pmf(1), 0.5);
+    assert_eq!(AliasTable::new(&[]).sample(0.5), None);
+}
+
+#[test]
+fn light_tree_test() {
+    use crate::color::color;
+    use crate::light::{directional_light, point_light, spot_light};
+    use crate::rtweekend::random_double_intv;
+
+    // A grid of point lights, a spot light shining away from the shading points and the sun.
+    // The probabilities of choosing the lights add up to 1 and match how often they are chosen,
+    // the closer of two equal lights is the more likely one and the spot light is never chosen.
+    let mut lights = Vec::new();
+    for i in 0..6 {
+        for k in 0..5 {
+            let intensity = color(1.0 + i as f64, 1.0, 1.0 + k as f64);
+            lights.push(point_light(point3(i as f64 * 2.0 - 5.0, 3.0, k as f64 * 2.0 - 4.0), intensity));
+        }
+    }
+    lights.push(spot_light(point3(0.0, 2.0, 0.0), vec3(0.0, 1.0, 0.0), color(100.0, 100.0, 100.0), 20.0, 30.0));
+    lights.push(directional_light(vec3(0.0, -1.0, 0.0), color(1.0, 1.0, 1.0)));
+    let spot = lights.len() - 2;
+    let tree = LightTree::new(&lights);
+
+    for _ in 0..4 {
+        let p = point3(random_double_intv(-6.0, 6.0), 0.0, random_double_intv(-6.0, 6.0));
+        let n = if random_double() < 0.5 { vec3(0.0, 1.0, 0.0) } else { Vec3::default() };
+        let total: f64 = (0..lights.len()).map(|i| tree.pmf(&p, &n, i)).sum();
+        assert!((total - 1.0).abs() < 1e-9, "{}", total);
+        assert_eq!(tree.pmf(&p, &n, spot), 0.0);
+
+        let mut counts = vec![0usize; lights.len()];
+        const N: usize = 40_000;
+        for _ in 0..N {
+            let (index, pmf) = tree.sample(&p, &n).unwrap();
+            assert!((pmf - tree.pmf(&p, &n, index)).abs() < 1e-12);
+            counts[index] += 1;
+        }
+        for (i, count) in counts.iter().enumerate() {
+            let pmf = tree.pmf(&p, &n, i);
+            assert!((*count as f64 / N as f64 - pmf).abs() < 4.0 * (pmf / N as f64).sqrt() + 1e-3);
+        }
+    }
+    let near = LightTree::new(&[point_light(point3(1.0, 1.0, 0.0), vec3(1.0, 1.0, 1.0)), point_light(point3(-4.0, 1.0, 0.0), vec3(1.0, 1.0, 1.0))]);
+    assert!(near.pmf(&point3(1.0, 0.0, 0.0), &vec3(0.0, 1.0, 0.0), 0) > 0.9);
+}
+
+#[test]
+fn light_sampling_test() {
+    use crate::camera::Camera;
+    use crate::color::color;
+    use crate::hittable::Hittable;
+    use crate::hittable_list::HittableList;
+    use crate::image::Image;
+    use crate::light::point_light;
+    use crate::material::lambertian;
+    use crate::sphere::sphere;
+
+    // Many dim point lights above a diffuse ground, seen from above. Choosing a single light
+    // converges to the same image as sampling all lights, and the tree gets there with the least
+    // noise, followed by choosing by power. A black dome keeps out the sky, so only the direct
+    // light of the lamps is left.
+    let objects: Vec<Box<dyn Hittable>> = vec![
+        Box::new(sphere(&point3(0.0, -1000.0, 0.0), 1000.0, &lambertian(color(0.5, 0.5, 0.5)))),
+        Box::new(sphere(&point3(0.0, 0.0, 0.0), 20.0, &lambertian(color(0.0, 0.0, 0.0)))),
+    ];
+    let world = HittableList::new(objects);
+    let mut lights = Vec::new();
+    for i in 0..16 {
+        for k in 0..16 {
+            let x = (i as f64 - 7.5) * 0.6;
+            let z = (k as f64 - 7.5) * 0.6;
+            let intensity = if (i + k) % 5 == 0 { 0.2 } else { 0.02 };
+            lights.push(point_light(point3(x, 0.4 + 0.1 * ((i * k) % 3) as f64, z), color(intensity, intensity, intensity)));
+        }
+    }
+
+    let mut cam = Camera::default();
+    cam.image_width = 16;
+    cam.aspect_ratio = 1.0;
+    cam.max_depth = 1;
+    cam.seed = Some(11);
+    cam.vfov = 60.0;
+    cam.look_from = point3(0.0, 6.0, 0.0);
+    cam.look_at = point3(0.0, 0.0, 0.0);
+    cam.v_up = vec3(0.0, 0.0, -1.0);
+    cam.lights = lights;
+    let mut render = |sampling: LightSampling, samples: usize| {
+        cam.light_sampling = sampling;
+        cam.samples_per_pixel = samples;
+        cam.render_buffers(&world).beauty
+    };
+    let mean = |image: &Image| image.pixels.iter().map(|p| p.x()).sum::<f64>() / image.pixels.len() as f64;
+    let error = |image: &Image, reference: &Image| {
+        let sum: f64 = image.pixels.iter().zip(reference.pixels.iter()).map(|(a, b)| (a.x() - b.x()).powi(2)).sum();
+        (sum / image.pixels.len() as f64).sqrt()
+    };
+
+    let reference = render(LightSampling::All, 4);
+    for sampling in [LightSampling::Power, LightSampling::Tree] {
+        let converged = render(sampling, 512);
+        assert!((mean(&converged) - mean(&reference)).abs() < 0.02 * mean(&reference), "{}", sampling.name());
+    }
+    let errors: Vec<f64> = [LightSampling::Uniform, LightSampling::Power, LightSampling::Tree]
+        .iter()
+        .map(|sampling| error(&render(*sampling, 4), &reference))
+        .collect();
+    assert!(errors[2] < errors[1] && errors[1] < errors[0], "{:?}", errors);
+}
+
+#[test]
+fn area_light_sampling_test() {
+    use crate::camera::Camera;
+    use crate::color::color;
+    use crate::hittable::Hittable;
+    use crate::hittable_list::HittableList;
+    use crate::image::Image;
+    use crate::material::{diffuse_light, lambertian};
+    use crate::mesh::{mesh, MeshData};
+    use crate::sphere::sphere;
+
+    // Many small glowing quads high above a diffuse ground, which is seen from below them. Each
+    // of their triangles is an area light, and the samplers compare as for point lights.
+    let mut objects: Vec<Box<dyn Hittable>> = vec![
+        Box::new(sphere(&point3(0.0, -1000.0, 0.0), 1000.0, &lambertian(color(0.5, 0.5, 0.5)))),
+        Box::new(sphere(&point3(0.0, 0.0, 0.0), 20.0, &lambertian(color(0.0, 0.0, 0.0)))),
+    ];
+    for i in 0..10 {
+        for k in 0..10 {
+            let (x, z) = ((i as f64 - 4.5) * 0.8, (k as f64 - 4.5) * 0.8);
+            let y = 2.0 + 0.1 * ((i * k) % 3) as f64;
+            let emit = if (i + k) % 5 == 0 { 4.0 } else { 0.4 };
+            let data = MeshData {
+                positions: vec![
+                    point3(x - 0.1, y, z - 0.1),
+                    point3(x + 0.1, y, z - 0.1),
+                    point3(x + 0.1, y, z + 0.1),
+                    point3(x - 0.1, y, z + 0.1),
+                ],
+                triangles: vec![[0, 1, 2], [0, 2, 3]],
+                ..Default::default()
+            };
+            objects.push(Box::new(mesh(data, &diffuse_light(color(emit, emit, emit)))));
+        }
+    }
+    let world = HittableList::new(objects);
+    assert_eq!(world.area_lights().len(), 200);
+
+    let mut cam = Camera::default();
+    cam.image_width = 8;
+    cam.aspect_ratio = 1.0;
+    cam.max_depth = 2;
+    cam.seed = Some(11);
+    cam.vfov = 90.0;
+    cam.look_from = point3(0.0, 1.5, 0.0);
+    cam.look_at = point3(0.0, 0.0, 0.0);
+    cam.v_up = vec3(0.0, 0.0, -1.0);
+    let mut render = |sampling: LightSampling, samples: usize| {
+        cam.light_sampling = sampling;
+        cam.samples_per_pixel = samples;
+        cam.render_buffers(&world).beauty
+    };
+    let mean = |image: &Image| image.pixels.iter().map(|p| p.x()).sum::<f64>() / image.pixels.len() as f64;
+    let error = |image: &Image, reference: &Image| {
+        let sum: f64 = image.pixels.iter().zip(reference.pixels.iter()).map(|(a, b)| (a.x() - b.x()).powi(2)).sum();
+        (sum / image.pixels.len() as f64).sqrt()
+    };
+
+    let reference = render(LightSampling::All, 16);
+    for sampling in [LightSampling::Power, LightSampling::Tree] {
+        let converged = render(sampling, 256);
+        assert!((mean(&converged) - mean(&reference)).abs() < 0.02 * mean(&reference), "{}", sampling.name());
+    }
+    let errors: Vec<f64> = [LightSampling::Uniform, LightSampling::Power, LightSampling::Tree]
+        .iter()
+        .map(|sampling| error(&render(*sampling, 4), &reference))
+        .collect();
+    assert!(errors[2] < errors[1] && errors[1] < errors[0], "{:?}", errors);
+}
@@ -1,13 +1,45 @@
+pub mod aabb;
+pub mod aov;
+pub mod aperture;
+pub mod bdpt;
+pub mod bvh;
 pub mod camera;
+pub mod checkpoint;
+pub mod cli;
 pub mod color;
+pub mod csg;
+pub mod denoise;
+pub mod gltf_import;
 pub mod hittable;
 pub mod hittable_list;
+pub mod image;
 pub mod interval;
+pub mod light;
+pub mod light_sampler;
 pub mod material;
+pub mod medium;
+pub mod mesh;
+pub mod openexr;
+pub mod ply;
+pub mod preview;
+pub mod principled;
+pub mod quadric;
 pub mod ray;
 pub mod rtweekend;
+pub mod sdf;
+pub mod sky;
+pub mod spectrum;
 pub mod sphere;
+pub mod stats;
+pub mod stl;
+pub mod texture;
 pub mod vec3;
+pub mod volume;
+
+#[cfg(test)]
+mod golden_tests;
+#[cfg(test)]
+mod material_tests;
 
 use camera::Camera;
 use color::color;
@@ -19,6 +51,15 @@ use sphere::sphere;
 use vec3::*;
 
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        if let Err(err) = cli::run(&args) {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let material_ground = lambertian(color(0.5, 0.5, 0.5));
     let mut world_vec: Vec<Box<dyn Hittable>> = vec![
         Box::new(sphere(&point3(0.0, -1000.0, -1.0), 1000.0, &material_ground)),
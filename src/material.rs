@@ -1,91 +1,611 @@
+use std::f64::consts::PI;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
 use crate::color::color;
 use crate::hittable::HitRecord;
+use crate::medium::{Medium, PhaseFunction};
+use crate::principled::{Principled, PrincipledBsdf};
 use crate::ray::Ray;
 use crate::rtweekend::random_double;
-use crate::{dot, random_unit_vector, reflect, refract, unit_vector, Vec3};
+use crate::spectrum::{Dispersion, SampledWavelengths, Spectrum, SODIUM_D, WAVELENGTHS};
+use crate::texture::{solid_color, Texture};
+use crate::{cross, dot, random_unit_vector, reflect, refract, unit_vector, Vec3};
 
 #[derive(Debug, Clone, Copy)]
 pub enum MaterialType {
     Lambertian,
     Metal,
     Dielectric,
+    MetallicRoughness,
+    Coated,
+    Principled,
+    Subsurface,
+    Volume,
+    DiffuseLight,
     OtherMaterial,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct Material {
-    albedo: Vec3,
+    albedo: Texture,
+    metallic_roughness: Texture,
     mat_type: MaterialType,
     fuzz: f64,
     refraction_index: f64,
+    dispersion: Option<Dispersion>,
+    film: Option<ThinFilm>,
+    base: Option<Arc<Material>>, // Material below the coat of coated materials
+    principled: Option<Arc<Principled>>,
+    bump: Option<Bump>,
+    medium: Option<Arc<Medium>>, // Medium inside of the surface, or scattering in volumes
+    emission: Option<Texture>,
+    key: usize, // Identifies the material and its copies, scenes number them (see `SceneIndex`)
+}
+
+/// Thin transparent layer on a surface, e.g. soap or oil. Light reflected at its top and at its
+/// bottom interferes, which depending on the wavelength amplifies or cancels the reflection.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ThinFilm {
+    pub thickness: f64, // In nanometers
+    pub refraction_index: f64,
+}
+
+/// Surface detail changing the shading normal without changing the geometry.
+#[derive(Debug, Clone)]
+pub enum Bump {
+    // Normals in the tangent frame of the surface encoded as colors, with x along the
+    // derivative of the position along u and y along v. The texture has to hold linear values.
+    NormalMap(Texture),
+    // Heights above the surface in the red channel, multiplied by the scale.
+    HeightMap { height: Texture, scale: f64 },
+}
+
+// Every constructed material gets its own key, 0 is left for the default material.
+static NEXT_MATERIAL_KEY: AtomicUsize = AtomicUsize::new(1);
+
+fn next_material_key() -> usize {
+    NEXT_MATERIAL_KEY.fetch_add(1, Ordering::Relaxed)
 }
 
 impl Default for Material {
     fn default() -> Self {
         Material {
-            albedo: Vec3::default(),
+            albedo: Texture::default(),
+            metallic_roughness: Texture::default(),
             mat_type: MaterialType::Lambertian,
             fuzz: 0.0,
             refraction_index: 0.0,
+            dispersion: None,
+            film: None,
+            base: None,
+            principled: None,
+            bump: None,
+            medium: None,
+            emission: None,
+            key: 0,
         }
     }
 }
 
 pub fn lambertian(albedo: Vec3) -> Material {
+    lambertian_texture(solid_color(albedo))
+}
+
+pub fn lambertian_texture(albedo: Texture) -> Material {
     Material {
-        albedo: albedo,
+        albedo,
+        metallic_roughness: Texture::default(),
         mat_type: MaterialType::Lambertian,
         fuzz: 0.0,
         refraction_index: 0.0,
+        dispersion: None,
+        film: None,
+        base: None,
+        principled: None,
+        bump: None,
+        medium: None,
+        emission: None,
+        key: next_material_key(),
     }
 }
 
 pub fn metal(albedo: Vec3, fuzz: f64) -> Material {
     if fuzz.abs() < 1.0 {
         Material {
-            albedo: albedo,
+            albedo: solid_color(albedo),
+            metallic_roughness: Texture::default(),
             mat_type: MaterialType::Metal,
-            fuzz: fuzz,
+            fuzz,
             refraction_index: 0.0,
+            dispersion: None,
+            film: None,
+            base: None,
+            principled: None,
+            bump: None,
+            medium: None,
+            emission: None,
+            key: next_material_key(),
         }
     } else {
         Material {
-            albedo: albedo,
+            albedo: solid_color(albedo),
+            metallic_roughness: Texture::default(),
             mat_type: MaterialType::Metal,
             fuzz: 1.0,
             refraction_index: 0.0,
+            dispersion: None,
+            film: None,
+            base: None,
+            principled: None,
+            bump: None,
+            medium: None,
+            emission: None,
+            key: next_material_key(),
         }
     }
 }
 
 pub fn dielectric(refraction: f64) -> Material {
     Material {
-        albedo: color(0.0, 0.0, 0.0),
+        albedo: solid_color(color(0.0, 0.0, 0.0)),
+        metallic_roughness: Texture::default(),
         mat_type: MaterialType::Dielectric,
         fuzz: 0.0,
         refraction_index: refraction,
+        dispersion: None,
+        film: None,
+        base: None,
+        principled: None,
+        bump: None,
+        medium: None,
+        emission: None,
+        key: next_material_key(),
+    }
+}
+
+pub fn dispersive_dielectric(dispersion: Dispersion) -> Material {
+    // Glass with a refractive index depending on the wavelength, which splits white light into
+    // its colors in spectral renders. RGB renders use the index at the sodium D line.
+    Material {
+        dispersion: Some(dispersion),
+        ..dielectric(dispersion.refraction_index(SODIUM_D))
+    }
+}
+
+pub fn thin_film(material: Material, thickness: f64, refraction_index: f64) -> Material {
+    // Puts a film of the given thickness in nanometers on a dielectric (soap bubble) or a
+    // metal (oxide layers, oil on a dark surface). Other materials are returned unchanged.
+    if !matches!(material.mat_type, MaterialType::Dielectric | MaterialType::Metal) {
+        return material;
+    }
+    Material {
+        film: Some(ThinFilm {
+            thickness,
+            refraction_index,
+        }),
+        key: next_material_key(),
+        ..material
+    }
+}
+
+pub fn normal_map(material: Material, normals: Texture) -> Material {
+    Material {
+        bump: Some(Bump::NormalMap(normals)),
+        key: next_material_key(),
+        ..material
+    }
+}
+
+pub fn bump_map(material: Material, height: Texture, scale: f64) -> Material {
+    Material {
+        bump: Some(Bump::HeightMap { height, scale }),
+        key: next_material_key(),
+        ..material
+    }
+}
+
+pub fn clearcoat(base: Material, refraction_index: f64, roughness: f64) -> Material {
+    // Clear varnish over an opaque base, e.g. car paint. The coat reflects by its Fresnel
+    // reflectance, the light getting through scatters at the base and loses the part reflected
+    // back at the coat on its way out.
+    Material {
+        albedo: Texture::default(),
+        metallic_roughness: Texture::default(),
+        mat_type: MaterialType::Coated,
+        fuzz: roughness.clamp(0.0, 1.0),
+        refraction_index,
+        dispersion: None,
+        film: None,
+        base: Some(Arc::new(base)),
+        principled: None,
+        bump: None,
+        medium: None,
+        emission: None,
+        key: next_material_key(),
+    }
+}
+
+pub fn metallic_roughness(base_color: Vec3, metallic: f64, roughness: f64) -> Material {
+    metallic_roughness_texture(solid_color(base_color), solid_color(color(0.0, roughness, metallic)))
+}
+
+pub fn metallic_roughness_texture(base_color: Texture, metallic_roughness: Texture) -> Material {
+    // Metallic-roughness material as used by glTF. The green channel of the second texture
+    // holds the roughness, the blue channel the metalness.
+    Material {
+        albedo: base_color,
+        metallic_roughness,
+        mat_type: MaterialType::MetallicRoughness,
+        fuzz: 0.0,
+        refraction_index: 0.0,
+        dispersion: None,
+        film: None,
+        base: None,
+        principled: None,
+        bump: None,
+        medium: None,
+        emission: None,
+        key: next_material_key(),
+    }
+}
+
+pub fn principled(parameters: Principled) -> Material {
+    // One material for most surfaces, see `Principled` for the parameters.
+    Material {
+        albedo: parameters.base_color.clone(),
+        metallic_roughness: Texture::default(),
+        mat_type: MaterialType::Principled,
+        fuzz: 0.0,
+        refraction_index: parameters.refraction_index,
+        dispersion: None,
+        film: None,
+        base: None,
+        principled: Some(Arc::new(parameters)),
+        bump: None,
+        medium: None,
+        emission: None,
+        key: next_material_key(),
+    }
+}
+
+pub fn subsurface(albedo: Vec3, mean_free_path: Vec3, refraction_index: f64) -> Material {
+    // Translucent material like skin, wax or marble: light refracts into the closed surface,
+    // scatters around inside and leaves it somewhere else. The albedo is the fraction of light
+    // surviving each scattering, the mean free path per channel the average distance between
+    // two scatterings in scene units.
+    Material {
+        albedo: solid_color(albedo),
+        metallic_roughness: Texture::default(),
+        mat_type: MaterialType::Subsurface,
+        fuzz: 0.0,
+        refraction_index,
+        dispersion: None,
+        film: None,
+        base: None,
+        principled: None,
+        bump: None,
+        medium: Some(Arc::new(Medium::from_albedo(albedo, mean_free_path, PhaseFunction::Isotropic))),
+        emission: None,
+        key: next_material_key(),
+    }
+}
+
+pub fn volume_scattering(albedo: Vec3, phase: PhaseFunction, emission: Option<Texture>) -> Material {
+    // Collisions with the particles of a volume, see `volume::Volume`. The scattered light
+    // follows the phase function, volumes emitting light (fire) add the emission.
+    Material {
+        albedo: solid_color(albedo),
+        metallic_roughness: Texture::default(),
+        mat_type: MaterialType::Volume,
+        fuzz: 0.0,
+        refraction_index: 0.0,
+        dispersion: None,
+        film: None,
+        base: None,
+        principled: None,
+        bump: None,
+        medium: Some(Arc::new(Medium::new(Vec3::default(), Vec3::default(), phase))),
+        emission,
+        key: next_material_key(),
+    }
+}
+
+pub fn diffuse_light(emit: Vec3) -> Material {
+    // Surface glowing evenly into all directions on both of its sides, without reflecting any
+    // light. Spheres and meshes made of it are sampled as area lights.
+    Material {
+        albedo: Texture::default(),
+        metallic_roughness: Texture::default(),
+        mat_type: MaterialType::DiffuseLight,
+        fuzz: 0.0,
+        refraction_index: 0.0,
+        dispersion: None,
+        film: None,
+        base: None,
+        principled: None,
+        bump: None,
+        medium: None,
+        emission: Some(solid_color(emit)),
+        key: next_material_key(),
     }
 }
 
 impl Material {
     pub fn new(albedo: Vec3, mat_type: MaterialType, fuzz: f64, refr: f64) -> Material {
         Material {
-            albedo: albedo,
-            mat_type: mat_type,
-            fuzz: fuzz,
+            albedo: solid_color(albedo),
+            metallic_roughness: Texture::default(),
+            mat_type,
+            fuzz,
             refraction_index: refr,
+            dispersion: None,
+            film: None,
+            base: None,
+            principled: None,
+            bump: None,
+            medium: None,
+            emission: None,
+            key: next_material_key(),
         }
     }
 
-    pub fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> (bool, Vec3, Ray) {
+    pub fn key(&self) -> usize {
+        self.key
+    }
+
+    pub fn albedo(&self, rec: &HitRecord) -> Vec3 {
+        // Surface color as seen by the albedo render pass, clear glass counts as white.
         match self.mat_type {
-            MaterialType::Lambertian => self.scatter_lambertian(rec),
-            MaterialType::Metal => self.scatter_metal(r_in, rec),
-            MaterialType::Dielectric => self.scatter_dielectric(r_in, rec),
+            MaterialType::Dielectric => color(1.0, 1.0, 1.0),
+            MaterialType::Coated => self.base().albedo(rec),
+            _ => self.albedo.value(rec),
+        }
+    }
+
+    pub fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> (bool, Vec3, Ray) {
+        match (self.mat_type, &self.film) {
+            (MaterialType::Lambertian, _) => self.scatter_lambertian(rec),
+            (MaterialType::Metal, None) => self.scatter_metal(r_in, rec),
+            (MaterialType::Metal, Some(film)) => {
+                let albedo = self.albedo.value(rec);
+                let (scatter_bool, attenuation, scattered) =
+                    self.scatter_metal_film(film, r_in, rec, RGB_WAVELENGTHS, [albedo.x(), albedo.y(), albedo.z()]);
+                (scatter_bool, color(attenuation[0], attenuation[1], attenuation[2]), scattered)
+            }
+            (MaterialType::Dielectric, None) => self.scatter_dielectric(r_in, rec, self.refraction_index),
+            (MaterialType::Dielectric, Some(film)) => {
+                let (scatter_bool, attenuation, scattered) =
+                    self.scatter_dielectric_film(film, r_in, rec, RGB_WAVELENGTHS, SODIUM_D);
+                (scatter_bool, color(attenuation[0], attenuation[1], attenuation[2]), scattered)
+            }
+            (MaterialType::MetallicRoughness, _) => self.scatter_metallic_roughness(r_in, rec),
+            (MaterialType::Principled, _) => self.scatter_principled(r_in, rec),
+            (MaterialType::Subsurface, _) => self.scatter_dielectric(r_in, rec, self.refraction_index),
+            (MaterialType::Volume, _) => self.scatter_volume(r_in, rec),
+            (MaterialType::Coated, _) => match self.coat_reflection(r_in, rec) {
+                Some((scatter_bool, scattered)) => (scatter_bool, color(1.0, 1.0, 1.0), scattered),
+                None => {
+                    let (scatter_bool, attenuation, scattered) = self.base().scatter(r_in, rec);
+                    (scatter_bool, attenuation * self.coat_transmittance(rec, &scattered), scattered)
+                }
+            },
             _ => (false, Vec3::default(), Ray::default()),
         }
     }
 
+    pub fn shading_normal(&self, rec: &HitRecord) -> Vec3 {
+        // The normal of the hit record with the normal or bump map applied, facing the same
+        // side as the normal.
+        let normal = rec.normal;
+        match &self.bump {
+            None => normal,
+            Some(Bump::NormalMap(normals)) => {
+                let (dpdu, dpdv) = rec.tangents();
+                let tangent = unit_vector(&(dpdu - dot(&dpdu, &normal) * normal));
+                let bitangent = dpdv - dot(&dpdv, &normal) * normal - dot(&dpdv, &tangent) * tangent;
+                let bitangent = if bitangent.near_zero() { cross(&normal, &tangent) } else { unit_vector(&bitangent) };
+                let n = 2.0 * normals.value(rec) - 1.0;
+                unit_vector(&(n.x() * tangent + n.y() * bitangent + n.z() * normal))
+            }
+            Some(Bump::HeightMap { height, scale }) => {
+                // The surface displaced along the normal by the height, its derivatives along u
+                // and v gain the derivative of the height, the normal is their cross product.
+                const DELTA: f64 = 1e-4;
+                let height_at = |du: f64, dv: f64| {
+                    let shifted = HitRecord {
+                        p: rec.p,
+                        normal,
+                        u: rec.u + du,
+                        v: rec.v + dv,
+                        vertex_color: rec.vertex_color,
+                        ..HitRecord::default()
+                    };
+                    scale * height.value(&shifted).x()
+                };
+                let dhdu = (height_at(DELTA, 0.0) - height_at(-DELTA, 0.0)) / (2.0 * DELTA);
+                let dhdv = (height_at(0.0, DELTA) - height_at(0.0, -DELTA)) / (2.0 * DELTA);
+
+                let (dpdu, dpdv) = rec.tangents();
+                let orientation = dot(&cross(&dpdu, &dpdv), &normal).signum();
+                let bumped = cross(&(dpdu + dhdu * normal), &(dpdv + dhdv * normal));
+                orientation * unit_vector(&bumped)
+            }
+        }
+    }
+
+    pub fn interior_medium(&self, rec: &HitRecord) -> Option<Arc<Medium>> {
+        // The medium a ray hitting the inside of the surface has travelled through.
+        match self.mat_type {
+            MaterialType::Subsurface if !rec.front_face => self.medium.clone(),
+            _ => None,
+        }
+    }
+
+    pub fn emitted(&self, rec: &HitRecord) -> Vec3 {
+        match &self.emission {
+            Some(emission) => emission.value(rec),
+            None => Vec3::default(),
+        }
+    }
+
+    pub fn area_light_radiance(&self) -> Option<Vec3> {
+        // Radiance of surfaces made of the material if they are area lights.
+        match (self.mat_type, &self.emission) {
+            (MaterialType::DiffuseLight, Some(Texture::Solid(radiance))) if !radiance.near_zero() => Some(*radiance),
+            _ => None,
+        }
+    }
+
+    pub fn is_volume(&self) -> bool {
+        // Scattering in a medium, without a surface.
+        matches!(self.mat_type, MaterialType::Volume)
+    }
+
+    pub fn is_dispersive(&self) -> bool {
+        self.dispersion.is_some()
+    }
+
+    pub fn scatter_spectral(&self, r_in: &Ray, rec: &HitRecord, wavelengths: &SampledWavelengths) -> (bool, Spectrum, Ray) {
+        // Scattering at the sampled wavelengths. Only dispersion and thin films depend on the
+        // wavelength, for everything else the RGB attenuation is upsampled. Dispersive glass
+        // refracts the hero wavelength.
+        match (self.mat_type, &self.film) {
+            (MaterialType::Metal, Some(film)) => {
+                let albedo = wavelengths.upsample(&self.albedo.value(rec));
+                self.scatter_metal_film(film, r_in, rec, wavelengths.lambda, albedo)
+            }
+            (MaterialType::Dielectric, Some(film)) => {
+                self.scatter_dielectric_film(film, r_in, rec, wavelengths.lambda, wavelengths.hero())
+            }
+            (MaterialType::Dielectric, None) => {
+                let refraction_index = self.refraction_index_at(wavelengths.hero());
+                let (scatter_bool, attenuation, scattered) = self.scatter_dielectric(r_in, rec, refraction_index);
+                (scatter_bool, wavelengths.upsample(&attenuation), scattered)
+            }
+            (MaterialType::Coated, _) => match self.coat_reflection(r_in, rec) {
+                Some((scatter_bool, scattered)) => (scatter_bool, [1.0; WAVELENGTHS], scattered),
+                None => {
+                    let (scatter_bool, attenuation, scattered) = self.base().scatter_spectral(r_in, rec, wavelengths);
+                    let transmittance = self.coat_transmittance(rec, &scattered);
+                    (scatter_bool, attenuation.map(|a| a * transmittance), scattered)
+                }
+            },
+            _ => {
+                let (scatter_bool, attenuation, scattered) = self.scatter(r_in, rec);
+                (scatter_bool, wavelengths.upsample(&attenuation), scattered)
+            }
+        }
+    }
+
+    fn base(&self) -> &Material {
+        self.base.as_ref().expect("coated material without base")
+    }
+
+    pub(crate) fn principled_bsdf(&self, rec: &HitRecord) -> PrincipledBsdf {
+        PrincipledBsdf::new(self.principled.as_ref().expect("principled material without parameters"), rec)
+    }
+
+    fn refraction_index_at(&self, lambda: f64) -> f64 {
+        match self.dispersion {
+            Some(dispersion) => dispersion.refraction_index(lambda),
+            None => self.refraction_index,
+        }
+    }
+
+    pub fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> Option<Vec3> {
+        // The BSDF times the cosine to the normal for light scattered towards `direction`, what
+        // `scatter` returns as attenuation times `scattering_pdf` on average. Materials with
+        // specular (delta) parts give `None`, lights are only found by scattering off them.
+        let direction = unit_vector(direction);
+        let cos_theta = dot(&rec.normal, &direction);
+        let scattered = Ray::new(&rec.p, &direction);
+        match (self.mat_type, &self.film) {
+            (MaterialType::Lambertian, _) => Some(self.albedo.value(rec) * (cos_theta.max(0.0) / PI)),
+            (MaterialType::Metal, None) if self.fuzz > 0.0 => {
+                let pdf = if cos_theta > 0.0 { self.scattering_pdf(r_in, rec, &scattered) } else { 0.0 };
+                Some(self.albedo.value(rec) * pdf)
+            }
+            (MaterialType::MetallicRoughness, _) => {
+                let (metallic, roughness, specular_probability) = self.metallic_roughness_lobes(r_in, rec);
+                if roughness <= 0.0 {
+                    return None;
+                }
+                if cos_theta <= 0.0 {
+                    return Some(Vec3::default());
+                }
+                let base_color = self.albedo.value(rec);
+                let reflected = unit_vector(&reflect(&r_in.direction(), &rec.normal));
+                let specular = (metallic * base_color + (specular_probability - metallic) * color(1.0, 1.0, 1.0))
+                    * fuzzy_reflection_pdf(dot(&direction, &reflected), roughness);
+                Some(specular + (1.0 - specular_probability) * base_color * (cos_theta / PI))
+            }
+            (MaterialType::Coated, _) if self.fuzz > 0.0 => {
+                let base = self.base().eval(r_in, rec, &direction)?;
+                if cos_theta <= 0.0 {
+                    return Some(Vec3::default());
+                }
+                let cos_i = dot(&-unit_vector(&r_in.direction()), &rec.normal).clamp(0.0, 1.0);
+                let coat_probability = dielectric_reflectance(cos_i, 1.0 / self.refraction_index);
+                let reflected = unit_vector(&reflect(&r_in.direction(), &rec.normal));
+                let coat = coat_probability * fuzzy_reflection_pdf(dot(&direction, &reflected), self.fuzz);
+                Some(color(coat, coat, coat) + (1.0 - coat_probability) * self.coat_transmittance(rec, &scattered) * base)
+            }
+            (MaterialType::Principled, _) => Some(self.principled_bsdf(rec).eval(&-r_in.direction(), &direction)),
+            (MaterialType::Volume, _) => {
+                let phase = self.phase().eval(dot(&unit_vector(&r_in.direction()), &direction));
+                Some(self.albedo.value(rec) * phase)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        // Density (per solid angle) of the directions sampled by `scatter`. Specular scattering
+        // (smooth metal, dielectric) follows a delta distribution and has no density.
+        let direction = unit_vector(&scattered.direction());
+        match self.mat_type {
+            MaterialType::Lambertian => {
+                let cos_theta = dot(&rec.normal, &direction);
+                if cos_theta < 0.0 {
+                    0.0
+                } else {
+                    cos_theta / PI
+                }
+            }
+            MaterialType::Metal if self.fuzz > 0.0 => {
+                let reflected = unit_vector(&reflect(&r_in.direction(), &rec.normal));
+                fuzzy_reflection_pdf(dot(&direction, &reflected), self.fuzz)
+            }
+            MaterialType::MetallicRoughness => {
+                let (_, roughness, specular_probability) = self.metallic_roughness_lobes(r_in, rec);
+                let cos_theta = dot(&rec.normal, &direction);
+                let diffuse = if cos_theta < 0.0 { 0.0 } else { cos_theta / PI };
+                let specular = if roughness > 0.0 {
+                    let reflected = unit_vector(&reflect(&r_in.direction(), &rec.normal));
+                    fuzzy_reflection_pdf(dot(&direction, &reflected), roughness)
+                } else {
+                    0.0
+                };
+                (1.0 - specular_probability) * diffuse + specular_probability * specular
+            }
+            MaterialType::Coated => {
+                let cos_theta = dot(&-unit_vector(&r_in.direction()), &rec.normal).clamp(0.0, 1.0);
+                let coat_probability = dielectric_reflectance(cos_theta, 1.0 / self.refraction_index);
+                let coat = if self.fuzz > 0.0 {
+                    let reflected = unit_vector(&reflect(&r_in.direction(), &rec.normal));
+                    fuzzy_reflection_pdf(dot(&direction, &reflected), self.fuzz)
+                } else {
+                    0.0
+                };
+                coat_probability * coat + (1.0 - coat_probability) * self.base().scattering_pdf(r_in, rec, scattered)
+            }
+            MaterialType::Principled => self.principled_bsdf(rec).pdf(&-r_in.direction(), &direction),
+            MaterialType::Volume => self.phase().eval(dot(&unit_vector(&r_in.direction()), &direction)),
+            _ => 0.0,
+        }
+    }
+
     fn scatter_lambertian(&self, rec: &HitRecord) -> (bool, Vec3, Ray) {
         let mut scatter_direction = rec.normal + random_unit_vector();
 
@@ -93,30 +613,160 @@ impl Material {
             scatter_direction = rec.normal;
         }
 
-        let scattered = &mut Ray::new(&rec.p, &scatter_direction);
-        let attenuation = self.albedo;
-        return (true, attenuation, *scattered);
+        let scattered = Ray::new(&rec.p, &scatter_direction);
+        let attenuation = self.albedo.value(rec);
+        (true, attenuation, scattered)
+    }
+
+    fn scatter_volume(&self, r_in: &Ray, rec: &HitRecord) -> (bool, Vec3, Ray) {
+        let scattered = Ray::new(&rec.p, &self.phase().sample(&r_in.direction()));
+        (true, self.albedo.value(rec), scattered)
+    }
+
+    fn phase(&self) -> PhaseFunction {
+        self.medium.as_ref().expect("volume material without medium").phase
     }
 
     fn scatter_metal(&self, r_in: &Ray, rec: &HitRecord) -> (bool, Vec3, Ray) {
         let mut reflected = reflect(&r_in.direction(), &rec.normal);
         reflected = unit_vector(&reflected) + (self.fuzz * random_unit_vector());
 
-        let scattered = &mut Ray::new(&rec.p, &reflected);
-        let attenuation = self.albedo;
+        let scattered = Ray::new(&rec.p, &reflected);
+        let attenuation = self.albedo.value(rec);
         let scatter_bool = dot(&scattered.direction(), &rec.normal) > 0.0;
-        return (scatter_bool, attenuation, *scattered);
+        (scatter_bool, attenuation, scattered)
     }
 
-    fn scatter_dielectric(&self, r_in: &Ray, rec: &HitRecord) -> (bool, Vec3, Ray) {
-        let attenuation = color(1.0, 1.0, 1.0);
+    fn metallic_roughness_lobes(&self, r_in: &Ray, rec: &HitRecord) -> (f64, f64, f64) {
+        // Metalness, roughness and the probability of the specular lobe. Dielectric surfaces
+        // reflect specularly with a Fresnel reflectance of 4% at normal incidence.
+        let channels = self.metallic_roughness.value(rec);
+        let roughness = channels.y().clamp(0.0, 1.0);
+        let metallic = channels.z().clamp(0.0, 1.0);
+        let cos_theta = dot(&-unit_vector(&r_in.direction()), &rec.normal).clamp(0.0, 1.0);
+        let fresnel = reflectance(cos_theta, 1.5);
+        (metallic, roughness, metallic + (1.0 - metallic) * fresnel)
+    }
+
+    fn scatter_metallic_roughness(&self, r_in: &Ray, rec: &HitRecord) -> (bool, Vec3, Ray) {
+        // One lobe is chosen at random, its weight divided by the probability of choosing it
+        // gives the attenuation.
+        let base_color = self.albedo.value(rec);
+        let (metallic, roughness, specular_probability) = self.metallic_roughness_lobes(r_in, rec);
+
+        if random_double() < specular_probability {
+            let reflected = unit_vector(&reflect(&r_in.direction(), &rec.normal)) + roughness * random_unit_vector();
+            let scattered = Ray::new(&rec.p, &reflected);
+            let white = color(1.0, 1.0, 1.0);
+            let attenuation = (metallic * base_color + (specular_probability - metallic) * white) / specular_probability;
+            let scatter_bool = dot(&scattered.direction(), &rec.normal) > 0.0;
+            return (scatter_bool, attenuation, scattered);
+        }
 
-        let ri;
-        if rec.front_face {
-            ri = 1.0 / self.refraction_index;
+        // The diffuse lobe has the weight (1 - metallic) (1 - fresnel) base_color.
+        self.scatter_lambertian(rec)
+    }
+
+    fn scatter_principled(&self, r_in: &Ray, rec: &HitRecord) -> (bool, Vec3, Ray) {
+        // The BSDF of the sampled direction over the density of all lobes, so directions that
+        // several lobes can produce are weighted correctly.
+        let bsdf = self.principled_bsdf(rec);
+        let wo = -r_in.direction();
+        let Some(wi) = bsdf.sample(&wo) else {
+            return (false, Vec3::default(), Ray::default());
+        };
+        let scattered = Ray::new(&rec.p, &wi);
+        let pdf = bsdf.pdf(&wo, &wi);
+        let value = bsdf.eval(&wo, &wi);
+        if pdf <= 0.0 || value.near_zero() {
+            return (false, Vec3::default(), scattered);
+        }
+        (true, value / pdf, scattered)
+    }
+
+    fn scatter_metal_film<const N: usize>(
+        &self,
+        film: &ThinFilm,
+        r_in: &Ray,
+        rec: &HitRecord,
+        lambdas: [f64; N],
+        albedo: [f64; N],
+    ) -> (bool, [f64; N], Ray) {
+        // The same directions as the bare metal, its reflectance is taken as the reflectance
+        // below the film.
+        let (scatter_bool, _, scattered) = self.scatter_metal(r_in, rec);
+        let cos_theta = dot(&-unit_vector(&r_in.direction()), &rec.normal).clamp(0.0, 1.0);
+        let mut attenuation = [0.0; N];
+        for ((a, lambda), reflectance) in attenuation.iter_mut().zip(lambdas).zip(albedo) {
+            *a = thin_film_reflectance(cos_theta, 1.0, film, Substrate::Metal(reflectance), lambda);
+        }
+        (scatter_bool, attenuation, scattered)
+    }
+
+    fn scatter_dielectric_film<const N: usize>(
+        &self,
+        film: &ThinFilm,
+        r_in: &Ray,
+        rec: &HitRecord,
+        lambdas: [f64; N],
+        refraction_lambda: f64,
+    ) -> (bool, [f64; N], Ray) {
+        // The film is on the outside of the glass. Reflection is chosen with the reflectance
+        // averaged over the wavelengths, the attenuations divided by the probability keep the
+        // colors of the interference. The film is too thin to shift the refracted ray.
+        let unit_direction = unit_vector(&r_in.direction());
+        let cos_theta = dot(&-unit_direction, &rec.normal).min(1.0);
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+        let refraction_index = self.refraction_index_at(refraction_lambda);
+        let ri = if rec.front_face { 1.0 / refraction_index } else { refraction_index };
+
+        if ri * sin_theta > 1.0 {
+            return (true, [1.0; N], Ray::new(&rec.p, &reflect(&unit_direction, &rec.normal)));
+        }
+
+        let mut reflectance = [0.0; N];
+        for (r, lambda) in reflectance.iter_mut().zip(lambdas) {
+            let n = self.refraction_index_at(lambda);
+            let (outside, substrate) = if rec.front_face { (1.0, n) } else { (n, 1.0) };
+            *r = thin_film_reflectance(cos_theta, outside, film, Substrate::Dielectric(substrate), lambda);
+        }
+        let probability = reflectance.iter().sum::<f64>() / N as f64;
+
+        if random_double() < probability {
+            let scattered = Ray::new(&rec.p, &reflect(&unit_direction, &rec.normal));
+            (true, reflectance.map(|r| r / probability), scattered)
         } else {
-            ri = self.refraction_index;
+            let scattered = Ray::new(&rec.p, &refract(&unit_direction, &rec.normal, ri));
+            (true, reflectance.map(|r| (1.0 - r) / (1.0 - probability)), scattered)
         }
+    }
+
+    fn coat_reflection(&self, r_in: &Ray, rec: &HitRecord) -> Option<(bool, Ray)> {
+        // Reflection at the coat, chosen with its Fresnel reflectance as probability.
+        let cos_theta = dot(&-unit_vector(&r_in.direction()), &rec.normal).clamp(0.0, 1.0);
+        if random_double() >= dielectric_reflectance(cos_theta, 1.0 / self.refraction_index) {
+            return None;
+        }
+        let reflected = unit_vector(&reflect(&r_in.direction(), &rec.normal)) + self.fuzz * random_unit_vector();
+        let scattered = Ray::new(&rec.p, &reflected);
+        Some((dot(&scattered.direction(), &rec.normal) > 0.0, scattered))
+    }
+
+    fn coat_transmittance(&self, rec: &HitRecord, scattered: &Ray) -> f64 {
+        // Fraction of the light scattered by the base leaving through the coat. What the coat
+        // reflects back inside is dropped, so no energy is gained.
+        let cos_theta = dot(&unit_vector(&scattered.direction()), &rec.normal).clamp(0.0, 1.0);
+        1.0 - dielectric_reflectance(cos_theta, 1.0 / self.refraction_index)
+    }
+
+    fn scatter_dielectric(&self, r_in: &Ray, rec: &HitRecord, refraction_index: f64) -> (bool, Vec3, Ray) {
+        let attenuation = color(1.0, 1.0, 1.0);
+
+        let ri = if rec.front_face {
+            1.0 / refraction_index
+        } else {
+            refraction_index
+        };
 
         let unit_direction = unit_vector(&r_in.direction());
         let neg_unit_direction = -unit_direction;
@@ -124,17 +774,108 @@ impl Material {
         let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
 
         let cannot_refract = ri * sin_theta > 1.0;
-        let direction;
 
-        if cannot_refract || reflectance(cos_theta, ri) > random_double() {
-            direction = reflect(&unit_direction, &rec.normal);
+        let direction = if cannot_refract || dielectric_reflectance(cos_theta, ri) > random_double() {
+            reflect(&unit_direction, &rec.normal)
         } else {
-            direction = refract(&unit_direction, &rec.normal, ri);
+            refract(&unit_direction, &rec.normal, ri)
+        };
+
+        let scattered = Ray::new(&rec.p, &direction);
+        (true, attenuation, scattered)
+    }
+}
+
+pub(crate) fn dielectric_reflectance(cos_theta: f64, ri: f64) -> f64 {
+    // Fraction of light reflected at the interface for the incident angle `cos_theta` and the
+    // ratio of refractive indices `ri`, including total internal reflection.
+    let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+    if ri * sin_theta > 1.0 {
+        return 1.0;
+    }
+
+    // Schlick's approximation needs the angle on the optically thinner side of the interface,
+    // which is the refracted angle when the ray leaves the denser medium. With that the
+    // reflectance is the same for both directions through the interface.
+    if ri > 1.0 {
+        let cos_refracted = (1.0 - ri * ri * sin_theta * sin_theta).sqrt();
+        return reflectance(cos_refracted, ri);
+    }
+    reflectance(cos_theta, ri)
+}
+
+// Representative wavelengths of the RGB channels for effects computed per wavelength.
+const RGB_WAVELENGTHS: [f64; 3] = [630.0, 532.0, 465.0];
+
+/// What lies below a thin film.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Substrate {
+    Dielectric(f64), // Refractive index
+    Metal(f64),      // Reflectance
+}
+
+pub(crate) fn thin_film_reflectance(
+    cos_theta: f64,
+    outside_index: f64,
+    film: &ThinFilm,
+    substrate: Substrate,
+    lambda: f64,
+) -> f64 {
+    // Airy summation of the waves reflected back and forth inside the film, averaged over both
+    // polarizations. A metal substrate is approximated by the amplitude of its reflectance with
+    // a phase shift of pi, like a dielectric of very high index.
+    let (n1, n2) = (outside_index, film.refraction_index);
+    let sin_squared = (1.0 - cos_theta * cos_theta).max(0.0);
+    let cos_inside = |n: f64| {
+        let sin_n = n1 * n1 / (n * n) * sin_squared;
+        (sin_n < 1.0).then(|| (1.0 - sin_n).sqrt())
+    };
+    let Some(cos2) = cos_inside(n2) else {
+        return 1.0;
+    };
+    let (rs23, rp23) = match substrate {
+        Substrate::Metal(reflectance) => {
+            let r = -reflectance.clamp(0.0, 1.0).sqrt();
+            (r, r)
         }
+        Substrate::Dielectric(n3) => {
+            let Some(cos3) = cos_inside(n3) else {
+                return 1.0;
+            };
+            (fresnel_s(n2, cos2, n3, cos3), fresnel_p(n2, cos2, n3, cos3))
+        }
+    };
+    let (rs12, rp12) = (fresnel_s(n1, cos_theta, n2, cos2), fresnel_p(n1, cos_theta, n2, cos2));
+
+    let phase = 4.0 * PI * n2 * film.thickness * cos2 / lambda;
+    let airy = |r12: f64, r23: f64| {
+        let interference = 2.0 * r12 * r23 * phase.cos();
+        (r12 * r12 + r23 * r23 + interference) / (1.0 + r12 * r12 * r23 * r23 + interference)
+    };
+    0.5 * (airy(rs12, rs23) + airy(rp12, rp23))
+}
+
+// Fresnel amplitude reflection coefficients for s and p polarized light.
+fn fresnel_s(n1: f64, cos1: f64, n2: f64, cos2: f64) -> f64 {
+    (n1 * cos1 - n2 * cos2) / (n1 * cos1 + n2 * cos2)
+}
+
+fn fresnel_p(n1: f64, cos1: f64, n2: f64, cos2: f64) -> f64 {
+    (n2 * cos1 - n1 * cos2) / (n2 * cos1 + n1 * cos2)
+}
 
-        let scattered = &mut Ray::new(&rec.p, &direction);
-        return (true, attenuation, *scattered);
+fn fuzzy_reflection_pdf(cos_theta: f64, fuzz: f64) -> f64 {
+    // Fuzzy metal scatters towards points uniformly distributed on a sphere of radius `fuzz`
+    // around the tip of the mirrored direction. A direction at angle theta to the mirrored one
+    // meets that sphere at distances t = cos_theta +- sqrt(cos_theta^2 - (1 - fuzz^2)); mapping
+    // the area density 1/(4 pi fuzz^2) of both points to solid angle gives the density below.
+    // It includes the directions below the surface, which get absorbed.
+    let c = 1.0 - fuzz * fuzz;
+    let discriminant = cos_theta * cos_theta - c;
+    if cos_theta <= 0.0 || discriminant <= 0.0 {
+        return 0.0;
     }
+    (2.0 * cos_theta * cos_theta - c) / (2.0 * PI * fuzz * discriminant.sqrt())
 }
 
 fn reflectance(cosine: f64, refraction_index: f64) -> f64 {
@@ -144,3 +885,36 @@ fn reflectance(cosine: f64, refraction_index: f64) -> f64 {
 
     r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
 }
+
+#[test]
+fn shading_normal_test() {
+    use crate::image::Image;
+    use crate::texture::image_texture;
+    use crate::vec3::vec3;
+
+    let rec = |mat: Material| HitRecord {
+        normal: vec3(0.0, 0.0, 1.0),
+        dpdu: vec3(2.0, 0.0, 0.0),
+        dpdv: vec3(0.0, 1.0, 0.0),
+        u: 0.4,
+        v: 0.3,
+        mat,
+        ..HitRecord::default()
+    };
+    let plain = lambertian(color(0.5, 0.5, 0.5));
+    assert_eq!(plain.shading_normal(&rec(plain.clone())), vec3(0.0, 0.0, 1.0));
+
+    // Flat normal maps keep the normal, others turn it towards the tangents.
+    let flat = normal_map(plain.clone(), solid_color(color(0.5, 0.5, 1.0)));
+    assert!((flat.shading_normal(&rec(flat.clone())) - vec3(0.0, 0.0, 1.0)).length() < 1e-12);
+    let tilted = normal_map(plain.clone(), solid_color(color(1.0, 0.5, 0.5)));
+    assert!((tilted.shading_normal(&rec(tilted.clone())) - vec3(1.0, 0.0, 0.0)).length() < 1e-12);
+
+    // Between the pixel centers of a black and a white pixel the height grows by 2 per unit of
+    // u. With the scale and dp/du that tilts the normal to (-0.1, 0, 1).
+    let mut ramp = Image::new(2, 1);
+    ramp.set(1, 0, color(1.0, 1.0, 1.0));
+    let bumpy = bump_map(plain, image_texture(Arc::new(ramp), color(1.0, 1.0, 1.0)), 0.1);
+    let normal = bumpy.shading_normal(&rec(bumpy.clone()));
+    assert!((normal - unit_vector(&vec3(-0.1, 0.0, 1.0))).length() < 1e-9);
+}
@@ -0,0 +1,609 @@
+// Statistical tests of the material sampling routines: chi-square goodness-of-fit tests of the
+// sampled directions against `scattering_pdf`, white furnace tests for energy conservation and
+// reciprocity checks. All tests use fixed seeds, so they are deterministic.
+
+use std::f64::consts::PI;
+
+use crate::color::color;
+use crate::hittable::{HitRecord, Hittable};
+use crate::interval::interval;
+use crate::material::{
+    clearcoat, dielectric, dielectric_reflectance, lambertian, metal, metallic_roughness, principled, thin_film,
+    thin_film_reflectance, volume_scattering, Material, Substrate, ThinFilm,
+};
+use crate::medium::PhaseFunction;
+use crate::principled::Principled;
+use crate::ray::Ray;
+use crate::rtweekend::{random_double, seed_random};
+use crate::sphere::sphere;
+use crate::texture::{scalar_texture, solid_color};
+use crate::vec3::*;
+
+const SAMPLE_COUNT: usize = 500_000;
+const COS_BINS: usize = 16;
+const PHI_BINS: usize = 32;
+const SIGNIFICANCE: f64 = 0.01;
+const MIN_EXPECTED: f64 = 5.0;
+
+/// Orthonormal frame around `axis`, directions are binned by (cos theta, phi) in this frame.
+struct Frame {
+    s: Vec3,
+    t: Vec3,
+    axis: Vec3,
+}
+
+impl Frame {
+    fn new(axis: &Vec3) -> Frame {
+        let axis = unit_vector(axis);
+        let helper = if axis.x().abs() > 0.9 { vec3(0.0, 1.0, 0.0) } else { vec3(1.0, 0.0, 0.0) };
+        let s = unit_vector(&cross(&axis, &helper));
+        let t = cross(&axis, &s);
+        Frame { s, t, axis }
+    }
+
+    fn to_local(&self, d: &Vec3) -> (f64, f64) {
+        let d = unit_vector(d);
+        let phi = dot(&d, &self.t).atan2(dot(&d, &self.s));
+        (dot(&d, &self.axis), if phi < 0.0 { phi + 2.0 * PI } else { phi })
+    }
+
+    fn to_world(&self, cos_theta: f64, phi: f64) -> Vec3 {
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        sin_theta * phi.cos() * self.s + sin_theta * phi.sin() * self.t + cos_theta * self.axis
+    }
+}
+
+fn surface_hit(normal: &Vec3, material: &Material, front_face: bool) -> HitRecord {
+    HitRecord {
+        normal: unit_vector(normal),
+        mat: material.clone(),
+        front_face,
+        ..HitRecord::default()
+    }
+}
+
+/// Chi-square test of the directions produced by `sample` against the density `pdf`. The cos
+/// theta bins cover [cos_min, 1] around the frame axis, no sample may fall outside of it.
+fn chi_square_test(
+    name: &str,
+    frame: &Frame,
+    cos_min: f64,
+    mut sample: impl FnMut() -> Vec3,
+    pdf: impl Fn(&Vec3) -> f64,
+) {
+    let bin_index = |cos_theta: f64, phi: f64| {
+        let u = ((cos_theta - cos_min) / (1.0 - cos_min) * COS_BINS as f64) as usize;
+        let v = (phi / (2.0 * PI) * PHI_BINS as f64) as usize;
+        u.min(COS_BINS - 1) * PHI_BINS + v.min(PHI_BINS - 1)
+    };
+
+    let mut observed = vec![0.0; COS_BINS * PHI_BINS];
+    for _ in 0..SAMPLE_COUNT {
+        let (cos_theta, phi) = frame.to_local(&sample());
+        assert!(cos_theta >= cos_min - 1e-9, "{}: sample outside of the expected range", name);
+        observed[bin_index(cos_theta, phi)] += 1.0;
+    }
+
+    // Integrate the density over each bin. Substituting cos theta = a + s^2 removes the inverse
+    // square root singularity a density may have at the lower edge a of a bin.
+    const STEPS: usize = 24;
+    let mut expected = vec![0.0; COS_BINS * PHI_BINS];
+    for (index, e) in expected.iter_mut().enumerate() {
+        let a = cos_min + (1.0 - cos_min) * (index / PHI_BINS) as f64 / COS_BINS as f64;
+        let b = cos_min + (1.0 - cos_min) * (index / PHI_BINS + 1) as f64 / COS_BINS as f64;
+        let phi_0 = 2.0 * PI * (index % PHI_BINS) as f64 / PHI_BINS as f64;
+        let (ds, dphi) = ((b - a).sqrt() / STEPS as f64, 2.0 * PI / (PHI_BINS * STEPS) as f64);
+
+        let mut integral = 0.0;
+        for i in 0..STEPS {
+            let s = (i as f64 + 0.5) * ds;
+            for k in 0..STEPS {
+                let phi = phi_0 + (k as f64 + 0.5) * dphi;
+                integral += pdf(&frame.to_world(a + s * s, phi)) * 2.0 * s * ds * dphi;
+            }
+        }
+        *e = integral * SAMPLE_COUNT as f64;
+    }
+
+    let total: f64 = expected.iter().sum();
+    assert!(
+        (total / SAMPLE_COUNT as f64 - 1.0).abs() < 1e-3,
+        "{}: density integrates to {}",
+        name,
+        total / SAMPLE_COUNT as f64
+    );
+
+    // Pool bins with too few expected samples, the chi-square approximation needs enough of them.
+    let mut bins: Vec<(f64, f64)> = expected.into_iter().zip(observed).collect();
+    bins.sort_by(|x, y| x.0.partial_cmp(&y.0).unwrap());
+    let (mut pooled_expected, mut pooled_observed) = (0.0, 0.0);
+    let mut chi_square = 0.0;
+    let mut dof = 0;
+    for (e, o) in bins {
+        if e < MIN_EXPECTED {
+            pooled_expected += e;
+            pooled_observed += o;
+            continue;
+        }
+        chi_square += (o - e) * (o - e) / e;
+        dof += 1;
+    }
+    if pooled_expected > 0.0 || pooled_observed > 0.0 {
+        let e = pooled_expected.max(1e-9);
+        chi_square += (pooled_observed - e) * (pooled_observed - e) / e;
+        dof += 1;
+    }
+    dof -= 1;
+
+    let p_value = gamma_q(dof as f64 / 2.0, chi_square / 2.0);
+    assert!(
+        p_value > SIGNIFICANCE,
+        "{}: sampled directions don't follow the pdf (chi^2 = {:.1}, dof = {}, p = {:.2e})",
+        name,
+        chi_square,
+        dof,
+        p_value
+    );
+}
+
+fn ln_gamma(x: f64) -> f64 {
+    // Lanczos approximation.
+    const COEFFICIENTS: [f64; 6] = [
+        76.18009172947146,
+        -86.50532032941677,
+        24.01409824083091,
+        -1.231739572450155,
+        0.1208650973866179e-2,
+        -0.5395239384953e-5,
+    ];
+    let tmp = x + 5.5 - (x + 0.5) * (x + 5.5).ln();
+    let mut series = 1.000000000190015;
+    for (j, c) in COEFFICIENTS.iter().enumerate() {
+        series += c / (x + 1.0 + j as f64);
+    }
+    -tmp + (2.5066282746310005 * series / x).ln()
+}
+
+fn gamma_q(a: f64, x: f64) -> f64 {
+    // Regularized upper incomplete gamma function, the chi-square survival function is
+    // Q(dof / 2, chi^2 / 2).
+    if x <= 0.0 {
+        return 1.0;
+    }
+    let prefactor = (-x + a * x.ln() - ln_gamma(a)).exp();
+
+    if x < a + 1.0 {
+        let (mut sum, mut term, mut n) = (1.0 / a, 1.0 / a, a);
+        while term.abs() > sum.abs() * 1e-15 {
+            n += 1.0;
+            term *= x / n;
+            sum += term;
+        }
+        return 1.0 - sum * prefactor;
+    }
+
+    // Continued fraction (modified Lentz).
+    let tiny = 1e-300;
+    let mut b = x + 1.0 - a;
+    let mut c = 1.0 / tiny;
+    let mut d = 1.0 / b;
+    let mut h = d;
+    for i in 1..1000 {
+        let an = -(i as f64) * (i as f64 - a);
+        b += 2.0;
+        d = an * d + b;
+        if d.abs() < tiny {
+            d = tiny;
+        }
+        c = b + an / c;
+        if c.abs() < tiny {
+            c = tiny;
+        }
+        d = 1.0 / d;
+        let delta = d * c;
+        h *= delta;
+        if (delta - 1.0).abs() < 1e-15 {
+            break;
+        }
+    }
+    h * prefactor
+}
+
+#[test]
+fn gamma_q_test() {
+    // Chi-square survival function for 2 degrees of freedom is exp(-x / 2).
+    for x in [0.1, 1.0, 5.0, 20.0] {
+        assert!((gamma_q(1.0, x / 2.0) - (-x / 2.0).exp()).abs() < 1e-10);
+    }
+    // 95% quantile of the chi-square distribution with 10 degrees of freedom.
+    assert!((gamma_q(5.0, 18.307 / 2.0) - 0.05).abs() < 1e-4);
+}
+
+#[test]
+fn lambertian_chi_square_test() {
+    seed_random(1);
+    let material = lambertian(color(0.5, 0.5, 0.5));
+    let rec = surface_hit(&vec3(1.0, 2.0, 3.0), &material, true);
+    let r_in = Ray::new(&point3(0.0, 0.0, 5.0), &vec3(0.3, -0.2, -1.0));
+
+    chi_square_test(
+        "lambertian",
+        &Frame::new(&rec.normal),
+        0.0,
+        || material.scatter(&r_in, &rec).2.direction(),
+        |d| material.scattering_pdf(&r_in, &rec, &Ray::new(&rec.p, d)),
+    );
+}
+
+#[test]
+fn metal_chi_square_test() {
+    for (seed, fuzz) in [(2, 0.1), (3, 0.5), (4, 1.0)] {
+        seed_random(seed);
+        let material = metal(color(0.9, 0.9, 0.9), fuzz);
+        let rec = surface_hit(&vec3(0.0, 1.0, 0.2), &material, true);
+        let r_in = Ray::new(&point3(0.0, 5.0, 0.0), &vec3(1.0, -1.0, 0.5));
+        let reflected = reflect(&unit_vector(&r_in.direction()), &rec.normal);
+
+        chi_square_test(
+            &format!("metal with fuzz {}", fuzz),
+            &Frame::new(&reflected),
+            (1.0 - fuzz * fuzz).sqrt(),
+            || material.scatter(&r_in, &rec).2.direction(),
+            |d| material.scattering_pdf(&r_in, &rec, &Ray::new(&rec.p, d)),
+        );
+    }
+}
+
+#[test]
+fn metallic_roughness_chi_square_test() {
+    // Mixture of the diffuse and the specular lobe, binned around the mirrored direction over
+    // the whole sphere. The roughness is chosen so the edge of the specular lobe, where its
+    // density has a singularity, falls on a bin boundary.
+    for (seed, metallic, roughness) in [(8, 0.0, 1.0), (9, 0.5, 0.75f64.sqrt())] {
+        seed_random(seed);
+        let material = metallic_roughness(color(0.9, 0.5, 0.2), metallic, roughness);
+        let rec = surface_hit(&vec3(0.0, 1.0, 0.2), &material, true);
+        let r_in = Ray::new(&point3(0.0, 5.0, 0.0), &vec3(1.0, -0.3, 0.5));
+        let reflected = reflect(&unit_vector(&r_in.direction()), &rec.normal);
+
+        chi_square_test(
+            &format!("metallic {} roughness {:.2}", metallic, roughness),
+            &Frame::new(&reflected),
+            -1.0,
+            || material.scatter(&r_in, &rec).2.direction(),
+            |d| material.scattering_pdf(&r_in, &rec, &Ray::new(&rec.p, d)),
+        );
+    }
+}
+
+#[test]
+fn clearcoat_chi_square_test() {
+    // Mixture of the coat and the diffuse base, binned like the metallic-roughness mixture.
+    for (seed, roughness) in [(10, 1.0), (11, 0.75f64.sqrt())] {
+        seed_random(seed);
+        let material = clearcoat(lambertian(color(0.2, 0.3, 0.8)), 1.5, roughness);
+        let rec = surface_hit(&vec3(0.0, 1.0, 0.2), &material, true);
+        let r_in = Ray::new(&point3(0.0, 5.0, 0.0), &vec3(1.0, -0.3, 0.5));
+        let reflected = reflect(&unit_vector(&r_in.direction()), &rec.normal);
+
+        chi_square_test(
+            &format!("clearcoat roughness {:.2}", roughness),
+            &Frame::new(&reflected),
+            -1.0,
+            || material.scatter(&r_in, &rec).2.direction(),
+            |d| material.scattering_pdf(&r_in, &rec, &Ray::new(&rec.p, d)),
+        );
+    }
+}
+
+fn principled_materials(base_color: Vec3) -> Vec<(&'static str, Material)> {
+    let base = Principled {
+        base_color: solid_color(base_color),
+        roughness: scalar_texture(0.7),
+        ..Principled::default()
+    };
+    vec![
+        ("principled plastic", principled(base.clone())),
+        (
+            "principled brushed metal",
+            principled(Principled {
+                metallic: scalar_texture(1.0),
+                anisotropy: scalar_texture(0.5),
+                ..base.clone()
+            }),
+        ),
+        (
+            "principled coated cloth",
+            principled(Principled {
+                metallic: scalar_texture(0.3),
+                sheen: scalar_texture(1.0),
+                clearcoat: scalar_texture(1.0),
+                clearcoat_roughness: scalar_texture(0.5),
+                ..base.clone()
+            }),
+        ),
+        (
+            "principled rough glass",
+            principled(Principled {
+                transmission: scalar_texture(0.8),
+                ..base.clone()
+            }),
+        ),
+    ]
+}
+
+#[test]
+fn principled_chi_square_test() {
+    // All lobes together over the whole sphere, from both sides of the glass.
+    for (seed, (name, material)) in (12..).zip(principled_materials(color(0.9, 0.5, 0.2))) {
+        let faces: &[bool] = if name.contains("glass") { &[true, false] } else { &[true] };
+        for &front_face in faces {
+            seed_random(seed);
+            let rec = surface_hit(&vec3(0.0, 1.0, 0.2), &material, front_face);
+            let r_in = Ray::new(&point3(0.0, 5.0, 0.0), &vec3(0.6, -1.0, 0.4));
+            let reflected = reflect(&unit_vector(&r_in.direction()), &rec.normal);
+
+            chi_square_test(
+                &format!("{}, front face {}", name, front_face),
+                &Frame::new(&reflected),
+                -1.0,
+                || material.scatter(&r_in, &rec).2.direction(),
+                |d| material.scattering_pdf(&r_in, &rec, &Ray::new(&rec.p, d)),
+            );
+        }
+    }
+}
+
+#[test]
+fn thin_film_test() {
+    // Without thickness the film has no effect, the reflectance is the exact Fresnel
+    // reflectance of the glass (averaged over both polarizations).
+    let no_film = ThinFilm {
+        thickness: 0.0,
+        refraction_index: 1.33,
+    };
+    for cos_theta in [1.0f64, 0.8, 0.5, 0.2] {
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+        let cos_t = (1.0 - (sin_theta / 1.5) * (sin_theta / 1.5)).sqrt();
+        let rs = (cos_theta - 1.5 * cos_t) / (cos_theta + 1.5 * cos_t);
+        let rp = (1.5 * cos_theta - cos_t) / (1.5 * cos_theta + cos_t);
+        let fresnel = 0.5 * (rs * rs + rp * rp);
+        let reflectance = thin_film_reflectance(cos_theta, 1.0, &no_film, Substrate::Dielectric(1.5), 500.0);
+        assert!((reflectance - fresnel).abs() < 1e-12);
+    }
+
+    // A quarter wave coating with the geometric mean index cancels the reflection of its
+    // wavelength at normal incidence, and only of that one.
+    let index = 1.5f64.sqrt();
+    let coating = ThinFilm {
+        thickness: 550.0 / (4.0 * index),
+        refraction_index: index,
+    };
+    assert!(thin_film_reflectance(1.0, 1.0, &coating, Substrate::Dielectric(1.5), 550.0) < 1e-12);
+    assert!(thin_film_reflectance(1.0, 1.0, &coating, Substrate::Dielectric(1.5), 400.0) > 0.002);
+
+    // A soap film cancels the reflection of light with twice its optical thickness as
+    // wavelength, which leaves the complementary color.
+    let soap = |thickness| ThinFilm {
+        thickness,
+        refraction_index: 1.33,
+    };
+    let blue_to_red = |film: &ThinFilm| {
+        thin_film_reflectance(1.0, 1.0, film, Substrate::Dielectric(1.0), 450.0)
+            / thin_film_reflectance(1.0, 1.0, film, Substrate::Dielectric(1.0), 650.0)
+    };
+    assert!(blue_to_red(&soap(450.0 / (2.0 * 1.33))) < 1e-6);
+    assert!(blue_to_red(&soap(650.0 / (2.0 * 1.33))) > 1e6);
+}
+
+#[test]
+fn dielectric_fresnel_test() {
+    // The fraction of reflected samples has to match the reflectance, from outside and inside.
+    seed_random(5);
+    let material = dielectric(1.5);
+    let n = 200_000;
+    for front_face in [true, false] {
+        for cos_theta in [0.95, 0.7, 0.3, 0.1] {
+            let ri = if front_face { 1.0 / 1.5 } else { 1.5 };
+            let rec = surface_hit(&vec3(0.0, 0.0, 1.0), &material, front_face);
+            let frame = Frame::new(&rec.normal);
+            let r_in = Ray::new(&point3(0.0, 0.0, 1.0), &-frame.to_world(cos_theta, 0.3));
+
+            let reflected = (0..n)
+                .filter(|_| dot(&material.scatter(&r_in, &rec).2.direction(), &rec.normal) > 0.0)
+                .count() as f64;
+
+            let p = dielectric_reflectance(cos_theta, ri);
+            let sigma = (n as f64 * p * (1.0 - p)).sqrt().max(1.0);
+            assert!(
+                (reflected - n as f64 * p).abs() < 5.0 * sigma,
+                "front face {}, cos theta {}: {} reflections, expected {}",
+                front_face,
+                cos_theta,
+                reflected,
+                n as f64 * p
+            );
+        }
+    }
+}
+
+fn furnace_radiance(material: &Material, paths: usize) -> f64 {
+    // A sphere inside a uniform white environment: every path escaping to the environment
+    // gathers a radiance of one, so a lossless material reproduces the environment exactly.
+    let object = sphere(&point3(0.0, 0.0, 0.0), 1.0, material);
+    let mut total = 0.0;
+
+    for _ in 0..paths {
+        let target = 0.9 * random_in_unit_disk();
+        let mut ray = Ray::new(&(target + vec3(0.0, 0.0, 5.0)), &vec3(0.0, 0.0, -1.0));
+        let mut throughput = color(1.0, 1.0, 1.0);
+
+        for _ in 0..1000 {
+            let mut rec = HitRecord::default();
+            if !object.hit(&ray, interval(0.001, f64::INFINITY), &mut rec) {
+                total += throughput.x();
+                break;
+            }
+            let (scatter, attenuation, scattered) = material.scatter(&ray, &rec);
+            if !scatter {
+                break;
+            }
+            throughput = throughput * attenuation;
+            ray = scattered;
+        }
+    }
+
+    total / paths as f64
+}
+
+#[test]
+fn white_furnace_test() {
+    seed_random(6);
+    let white = color(1.0, 1.0, 1.0);
+
+    for (name, material) in [
+        ("lambertian", lambertian(white)),
+        ("smooth metal", metal(white, 0.0)),
+        ("dielectric", dielectric(1.5)),
+    ] {
+        let radiance = furnace_radiance(&material, 20_000);
+        assert!((radiance - 1.0).abs() < 1e-9, "{} reflects {} of the light", name, radiance);
+    }
+
+    // Fuzzy metal absorbs the directions it scatters below the surface, but never gains energy.
+    let radiance = furnace_radiance(&metal(white, 0.5), 20_000);
+    assert!(radiance <= 1.0 && radiance > 0.8, "fuzzy metal reflects {} of the light", radiance);
+
+    // A film on a perfect mirror doesn't change its reflectance, on glass it only reflects what
+    // it doesn't transmit. Other materials don't take a film.
+    let radiance = furnace_radiance(&thin_film(metal(white, 0.0), 300.0, 1.4), 20_000);
+    assert!((radiance - 1.0).abs() < 1e-9, "mirror with film reflects {} of the light", radiance);
+    let radiance = furnace_radiance(&thin_film(dielectric(1.5), 300.0, 1.33), 20_000);
+    assert!((radiance - 1.0).abs() < 0.02, "glass with film reflects {} of the light", radiance);
+    let plain = lambertian(white);
+    assert_eq!(thin_film(plain.clone(), 300.0, 1.4).key(), plain.key());
+
+    // The coat reflects part of the light scattered by the base back inside, which is lost.
+    let radiance = furnace_radiance(&clearcoat(lambertian(white), 1.5, 0.0), 20_000);
+    assert!(radiance <= 1.0 && radiance > 0.85, "clearcoat reflects {} of the light", radiance);
+
+    // Smooth principled glass and metal are lossless. Rough microfacets lose the light they
+    // mask, which takes a large share at grazing angles, and the diffuse lobe loses what the
+    // specular reflection takes. Sheen adds light at grazing angles, like in the Disney BRDF.
+    for (name, transmission, metallic) in [("glass", 1.0, 0.0), ("mirror", 0.0, 1.0)] {
+        let material = principled(Principled {
+            base_color: solid_color(white),
+            roughness: scalar_texture(0.0),
+            metallic: scalar_texture(metallic),
+            transmission: scalar_texture(transmission),
+            ..Principled::default()
+        });
+        let radiance = furnace_radiance(&material, 20_000);
+        assert!((radiance - 1.0).abs() < 1e-3, "smooth principled {} reflects {} of the light", name, radiance);
+    }
+    for (name, material) in principled_materials(white) {
+        if name.contains("cloth") {
+            continue;
+        }
+        let radiance = furnace_radiance(&material, 20_000);
+        assert!(radiance < 1.0 && radiance > 0.5, "white {} reflects {} of the light", name, radiance);
+    }
+}
+
+#[test]
+fn reciprocity_test() {
+    seed_random(7);
+    let normal = unit_vector(&vec3(0.2, 1.0, -0.3));
+    let frame = Frame::new(&normal);
+
+    for _ in 0..1000 {
+        let wi = frame.to_world(random_double(), 2.0 * PI * random_double());
+        let wo = frame.to_world(random_double(), 2.0 * PI * random_double());
+        let (cos_i, cos_o) = (dot(&wi, &normal), dot(&wo, &normal));
+
+        // Lambertian BRDF f = pdf / cos theta_o is symmetric.
+        let material = lambertian(color(0.5, 0.5, 0.5));
+        let rec = surface_hit(&normal, &material, true);
+        let forward = material.scattering_pdf(&Ray::new(&wi, &-wi), &rec, &Ray::new(&rec.p, &wo)) / cos_o;
+        let backward = material.scattering_pdf(&Ray::new(&wo, &-wo), &rec, &Ray::new(&rec.p, &wi)) / cos_i;
+        assert!((forward - backward).abs() < 1e-9);
+
+        // The fuzzy metal lobe only depends on the angle to the mirror direction, which is the
+        // same for both directions of travel.
+        let material = metal(color(0.5, 0.5, 0.5), 0.7);
+        let rec = surface_hit(&normal, &material, true);
+        let forward = material.scattering_pdf(&Ray::new(&wi, &-wi), &rec, &Ray::new(&rec.p, &wo));
+        let backward = material.scattering_pdf(&Ray::new(&wo, &-wo), &rec, &Ray::new(&rec.p, &wi));
+        assert!((forward - backward).abs() < 1e-9 * forward.max(1.0));
+
+        // The principled BSDF times the cosine of the light direction, over that cosine.
+        for (name, material) in principled_materials(color(0.9, 0.5, 0.2)) {
+            let rec = surface_hit(&normal, &material, true);
+            let bsdf = |wo: &Vec3, wi: &Vec3| material.principled_bsdf(&rec).eval(wo, wi) / dot(wi, &normal);
+            let (forward, backward) = (bsdf(&wo, &wi), bsdf(&wi, &wo));
+            assert!((forward - backward).length() < 1e-9 * forward.length().max(1.0), "{} isn't reciprocal", name);
+        }
+
+        // Refraction is reversible and the reflectance is the same from both sides.
+        let eta = 1.5;
+        let refracted = refract(&-wi, &normal, 1.0 / eta);
+        let cos_t = dot(&-refracted, &normal);
+        let back = refract(&-refracted, &-normal, eta);
+        assert!((back - wi).length() < 1e-9);
+        assert!((dielectric_reflectance(cos_i, 1.0 / eta) - dielectric_reflectance(cos_t, eta)).abs() < 1e-9);
+    }
+}
+
+
+#[test]
+fn eval_test() {
+    // Light sampling weights directions by `eval` over the density of the light sample, the
+    // scattered rays by their attenuation. Both have to agree on average: the attenuation of
+    // sampled directions and eval over pdf of the same directions, also weighted by a function
+    // of the direction.
+    seed_random(21);
+    let r_in = Ray::new(&point3(0.0, 1.0, 0.0), &vec3(0.6, -1.0, 0.4));
+    let normal = vec3(0.0, 1.0, 0.2);
+    let mut materials = vec![
+        ("lambertian", lambertian(color(0.8, 0.5, 0.2))),
+        ("fuzzy metal", metal(color(0.9, 0.6, 0.3), 0.4)),
+        ("metallic roughness", metallic_roughness(color(0.7, 0.2, 0.2), 0.4, 0.5)),
+        ("rough clearcoat", clearcoat(lambertian(color(0.1, 0.3, 0.8)), 1.5, 0.3)),
+        ("volume", volume_scattering(color(0.9, 0.8, 0.7), PhaseFunction::HenyeyGreenstein(0.5), None)),
+    ];
+    materials.extend(principled_materials(color(0.9, 0.5, 0.2)));
+    assert!(dielectric(1.5).eval(&r_in, &surface_hit(&normal, &dielectric(1.5), true), &normal).is_none());
+
+    const N: usize = 200_000;
+    for (name, material) in materials {
+        let rec = surface_hit(&normal, &material, true);
+        let (mut scattered_sum, mut evaluated_sum) = ([Vec3::default(); 2], [Vec3::default(); 2]);
+        for _ in 0..N {
+            let (scatter, attenuation, scattered) = material.scatter(&r_in, &rec);
+            if !scatter && scattered.direction().near_zero() {
+                continue;
+            }
+            let direction = unit_vector(&scattered.direction());
+            let pdf = material.scattering_pdf(&r_in, &rec, &scattered);
+            let value = material.eval(&r_in, &rec, &direction).expect("no eval");
+            let weights = [1.0, direction.x().max(0.0)];
+            for (k, weight) in weights.iter().enumerate() {
+                if scatter {
+                    scattered_sum[k] = scattered_sum[k] + *weight * attenuation / N as f64;
+                }
+                if pdf > 0.0 {
+                    evaluated_sum[k] = evaluated_sum[k] + *weight * value / (pdf * N as f64);
+                }
+            }
+        }
+        for (scattered, evaluated) in scattered_sum.iter().zip(evaluated_sum) {
+            assert!(
+                (*scattered - evaluated).length() < 0.01,
+                "{}: scattering gives {:?}, eval {:?}",
+                name,
+                scattered,
+                evaluated
+            );
+        }
+    }
+}
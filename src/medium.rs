@@ -0,0 +1,211 @@
+use std::f64::consts::PI;
+
+use crate::color::color;
+use crate::hittable::{HitRecord, Hittable};
+use crate::hittable_list::HittableList;
+use crate::interval::interval;
+use crate::ray::Ray;
+use crate::rtweekend::random_double;
+use crate::stats::{count_ray, RayKind};
+use crate::vec3::*;
+
+// Upper bound on the scattering events of one random walk, walks in media with an albedo close
+// to 1 are otherwise only ended by leaving the medium.
+const MAX_WALK_STEPS: usize = 4096;
+
+/// Angular distribution of the light scattered in a medium.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PhaseFunction {
+    Isotropic,
+    // Henyey-Greenstein with the mean cosine of the scattering angle in (-1, 1): positive values
+    // scatter forward like clouds, negative ones back.
+    HenyeyGreenstein(f64),
+}
+
+impl PhaseFunction {
+    pub fn eval(&self, cos_theta: f64) -> f64 {
+        // Density per solid angle of scattering by the angle with cosine `cos_theta` between
+        // the incoming and the scattered direction.
+        match *self {
+            PhaseFunction::Isotropic => 1.0 / (4.0 * PI),
+            PhaseFunction::HenyeyGreenstein(g) => {
+                let denominator = 1.0 + g * g - 2.0 * g * cos_theta;
+                (1.0 - g * g) / (4.0 * PI * denominator * denominator.sqrt())
+            }
+        }
+    }
+
+    pub fn sample(&self, direction: &Vec3) -> Vec3 {
+        // Samples the scattered direction for light travelling along `direction`, by the
+        // density of `eval`.
+        match *self {
+            PhaseFunction::Isotropic => random_unit_vector(),
+            PhaseFunction::HenyeyGreenstein(g) if g.abs() < 1e-3 => random_unit_vector(),
+            PhaseFunction::HenyeyGreenstein(g) => {
+                // Inverted cumulative distribution of the cosine, the azimuth is uniform.
+                let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * random_double());
+                let cos_theta = ((1.0 + g * g - s * s) / (2.0 * g)).clamp(-1.0, 1.0);
+                direction_around(direction, cos_theta, 2.0 * PI * random_double())
+            }
+        }
+    }
+}
+
+/// Homogeneous participating medium with absorption and scattering coefficients per color
+/// channel, in inverse scene units.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Medium {
+    pub sigma_a: Vec3,
+    pub sigma_s: Vec3,
+    pub phase: PhaseFunction,
+}
+
+impl Medium {
+    pub fn new(sigma_a: Vec3, sigma_s: Vec3, phase: PhaseFunction) -> Medium {
+        Medium { sigma_a, sigma_s, phase }
+    }
+
+    pub fn from_albedo(albedo: Vec3, mean_free_path: Vec3, phase: PhaseFunction) -> Medium {
+        // The single scattering albedo is the fraction of the interactions that scatter, the
+        // mean free path the average distance between interactions.
+        let sigma_t = color(1.0 / mean_free_path.x(), 1.0 / mean_free_path.y(), 1.0 / mean_free_path.z());
+        Medium {
+            sigma_a: (color(1.0, 1.0, 1.0) - albedo) * sigma_t,
+            sigma_s: albedo * sigma_t,
+            phase,
+        }
+    }
+
+    pub fn sigma_t(&self) -> Vec3 {
+        self.sigma_a + self.sigma_s
+    }
+
+    pub fn transmittance(&self, distance: f64) -> Vec3 {
+        let sigma_t = self.sigma_t();
+        color(
+            (-sigma_t.x() * distance).exp(),
+            (-sigma_t.y() * distance).exp(),
+            (-sigma_t.z() * distance).exp(),
+        )
+    }
+
+    pub(crate) fn random_walk(&self, ray: &mut Ray, rec: &mut HitRecord, world: &HittableList) -> Option<Vec3> {
+        // Follows light entering the medium along `ray` up to its first hit `rec` from scattering
+        // to scattering until it reaches a surface again. On return `ray` and `rec` are the last
+        // segment of the walk and its hit, the result is the weight of the path through the
+        // medium. Paths absorbed or lost through an open boundary give `None`.
+        //
+        // The distances are sampled for one channel picked at random. The other channels are
+        // weighted by the densities of the whole walk under all channels (the balance heuristic
+        // over the walk), so each channel can have its own mean free path. `ratios` holds the
+        // walk density under each channel relative to the sampled one.
+        let sigma_t = self.sigma_t();
+        let channel = ((3.0 * random_double()) as usize).min(2);
+        let mut weight = color(1.0, 1.0, 1.0);
+        let mut ratios = color(1.0, 1.0, 1.0);
+        let estimate = |weight: Vec3, ratios: Vec3| weight / ((ratios.x() + ratios.y() + ratios.z()) / 3.0);
+        for _ in 0..MAX_WALK_STEPS {
+            let speed = ray.direction().length();
+            let segment = rec.t * speed;
+            let distance = -(1.0 - random_double()).ln() / sigma_t[channel];
+            if distance >= segment {
+                let transmittance = self.transmittance(segment);
+                weight = weight * transmittance / transmittance[channel];
+                ratios = ratios * transmittance / transmittance[channel];
+                return Some(estimate(weight, ratios));
+            }
+
+            let transmittance = self.transmittance(distance);
+            let density = sigma_t * transmittance;
+            weight = weight * self.sigma_s * transmittance / density[channel];
+            ratios = ratios * density / density[channel];
+
+            // The phase function is sampled by its density, so it leaves the weight unchanged.
+            // The walk continues with a probability following the weight.
+            let current = estimate(weight, ratios);
+            let survival = current.x().max(current.y()).max(current.z()).min(1.0);
+            if random_double() >= survival {
+                return None;
+            }
+            weight = weight / survival;
+
+            let direction = self.phase.sample(&unit_vector(&ray.direction()));
+            *ray = Ray::new(&ray.at(distance / speed), &direction);
+            *rec = HitRecord::default();
+            count_ray(RayKind::Secondary);
+            // Scattering happens away from surfaces, no offset against self intersection needed.
+            if !world.hit(ray, interval(1e-9, f64::INFINITY), rec) {
+                return None;
+            }
+        }
+        None
+    }
+}
+
+#[test]
+fn random_walk_test() {
+    use crate::material::lambertian;
+    use crate::rtweekend::seed_random;
+    use crate::sphere::sphere;
+
+    // Without absorption all light entering a closed medium leaves it again, whatever the
+    // mean free paths of the channels.
+    let world = HittableList::new(vec![Box::new(sphere(
+        &point3(0.0, 0.0, 0.0),
+        1.0,
+        &lambertian(color(0.5, 0.5, 0.5)),
+    ))]);
+    let white = Medium::from_albedo(color(1.0, 1.0, 1.0), color(0.1, 0.3, 1.0), PhaseFunction::Isotropic);
+    let gray = Medium::from_albedo(color(0.9, 0.6, 0.3), color(0.2, 0.2, 0.2), PhaseFunction::Isotropic);
+
+    seed_random(11);
+    const N: usize = 20_000;
+    let mut sums = [color(0.0, 0.0, 0.0); 2];
+    for _ in 0..N {
+        for (sum, medium) in sums.iter_mut().zip([&white, &gray]) {
+            let mut ray = Ray::new(&point3(0.0, -1.0, 0.0), &vec3(0.0, 2.0, 0.0));
+            let mut rec = HitRecord::default();
+            assert!(world.hit(&ray, interval(0.001, f64::INFINITY), &mut rec));
+            if let Some(weight) = medium.random_walk(&mut ray, &mut rec, &world) {
+                assert!(!rec.front_face);
+                *sum = *sum + weight / N as f64;
+            }
+        }
+    }
+    let [white_sum, gray_sum] = sums;
+    for channel in 0..3 {
+        assert!((white_sum[channel] - 1.0).abs() < 0.03, "{:?}", white_sum);
+    }
+    // Absorption loses more light the lower the albedo.
+    assert!(gray_sum.x() < 0.95 && gray_sum.x() > gray_sum.y() && gray_sum.y() > gray_sum.z() && gray_sum.z() > 0.0);
+}
+
+#[test]
+fn phase_function_test() {
+    // The densities integrate to 1 over the sphere and the sampled directions have the mean
+    // cosine g, both for the density and for the samples.
+    let direction = unit_vector(&vec3(0.3, -1.0, 0.5));
+    for phase in [
+        PhaseFunction::Isotropic,
+        PhaseFunction::HenyeyGreenstein(0.7),
+        PhaseFunction::HenyeyGreenstein(-0.4),
+    ] {
+        let g = match phase {
+            PhaseFunction::Isotropic => 0.0,
+            PhaseFunction::HenyeyGreenstein(g) => g,
+        };
+        const STEPS: usize = 100_000;
+        let (mut total, mut mean) = (0.0, 0.0);
+        for i in 0..STEPS {
+            let cos_theta = -1.0 + 2.0 * (i as f64 + 0.5) / STEPS as f64;
+            let density = 2.0 * PI * phase.eval(cos_theta) * 2.0 / STEPS as f64;
+            total += density;
+            mean += cos_theta * density;
+        }
+        assert!((total - 1.0).abs() < 1e-4, "{:?} integrates to {}", phase, total);
+        assert!((mean - g).abs() < 1e-4, "{:?} has mean cosine {}", phase, mean);
+
+        let samples: f64 = (0..STEPS).map(|_| dot(&phase.sample(&direction), &direction)).sum();
+        assert!((samples / STEPS as f64 - g).abs() < 0.01, "{:?} samples {}", phase, samples / STEPS as f64);
+    }
+}
@@ -0,0 +1,380 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::bvh::BvhNode;
+use crate::hittable::{HitRecord, Hittable, SceneIndex};
+use crate::image::invalid_data;
+use crate::interval::Interval;
+use crate::light::Shape;
+use crate::ray::Ray;
+use crate::stats::{count_intersection_test, Primitive};
+use crate::texture::Texture;
+use crate::vec3::*;
+use crate::Material;
+
+/// Triangle mesh as read from a file. Normals, colors and texture coordinates are either empty
+/// or given for every vertex.
+#[derive(Debug, Default, Clone)]
+pub struct MeshData {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub colors: Vec<Vec3>,
+    pub uvs: Vec<(f64, f64)>,
+    pub triangles: Vec<[usize; 3]>,
+}
+
+impl MeshData {
+    pub fn transform(&mut self, scale: f64, offset: &Vec3) {
+        // Uniform scale followed by a translation, which leaves the normals unchanged.
+        for p in self.positions.iter_mut() {
+            *p = scale * *p + *offset;
+        }
+    }
+
+    pub fn validate(&self) -> io::Result<()> {
+        let vertex_count = self.positions.len();
+        for (name, count) in [
+            ("normals", self.normals.len()),
+            ("colors", self.colors.len()),
+            ("texture coordinates", self.uvs.len()),
+        ] {
+            if count != 0 && count != vertex_count {
+                return Err(invalid_data(format!(
+                    "{} {} for {} vertices",
+                    count, name, vertex_count
+                )));
+            }
+        }
+        for (index, triangle) in self.triangles.iter().enumerate() {
+            if let Some(vertex) = triangle.iter().find(|&&v| v >= vertex_count) {
+                return Err(invalid_data(format!(
+                    "triangle {} refers to vertex {}, but there are only {} vertices",
+                    index, vertex, vertex_count
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn compute_normals(&mut self) {
+        // Smooth vertex normals, the normals of the adjacent triangles weighted by their area.
+        let mut normals = vec![Vec3::default(); self.positions.len()];
+        for &[i0, i1, i2] in &self.triangles {
+            let p0 = self.positions[i0];
+            let normal = cross(&(self.positions[i1] - p0), &(self.positions[i2] - p0));
+            for i in [i0, i1, i2] {
+                normals[i] = normals[i] + normal;
+            }
+        }
+        self.normals = normals
+            .into_iter()
+            .map(|n| if n.near_zero() { n } else { unit_vector(&n) })
+            .collect();
+    }
+
+    pub fn subdivide(&mut self) {
+        // Splits every triangle into four at the midpoints of its edges. Neighbors share the
+        // midpoint of their common edge, so no cracks open when the vertices move apart.
+        let mut midpoints: HashMap<(usize, usize), usize> = HashMap::new();
+        let mut triangles = Vec::with_capacity(4 * self.triangles.len());
+        for [i0, i1, i2] in std::mem::take(&mut self.triangles) {
+            let mut midpoint = |a: usize, b: usize| {
+                *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                    self.positions.push(0.5 * (self.positions[a] + self.positions[b]));
+                    if !self.normals.is_empty() {
+                        self.normals.push(unit_vector(&(self.normals[a] + self.normals[b])));
+                    }
+                    if !self.colors.is_empty() {
+                        self.colors.push(0.5 * (self.colors[a] + self.colors[b]));
+                    }
+                    if !self.uvs.is_empty() {
+                        let (uv_a, uv_b) = (self.uvs[a], self.uvs[b]);
+                        self.uvs.push((0.5 * (uv_a.0 + uv_b.0), 0.5 * (uv_a.1 + uv_b.1)));
+                    }
+                    self.positions.len() - 1
+                })
+            };
+            let (m01, m12, m20) = (midpoint(i0, i1), midpoint(i1, i2), midpoint(i2, i0));
+            triangles.extend([[i0, m01, m20], [m01, i1, m12], [m20, m12, i2], [m01, m12, m20]]);
+        }
+        self.triangles = triangles;
+    }
+
+    pub fn displace(&mut self, height: &Texture, scale: f64, subdivisions: usize) {
+        // Displacement mapping: the mesh is subdivided, then every vertex is moved along its
+        // normal by the height texture (red channel) at its texture coordinates times the scale.
+        // The normals are recomputed for the displaced surface.
+        if self.normals.is_empty() {
+            self.compute_normals();
+        }
+        for _ in 0..subdivisions {
+            self.subdivide();
+        }
+        for index in 0..self.positions.len() {
+            let (u, v) = self.uvs.get(index).copied().unwrap_or((0.0, 0.0));
+            let rec = HitRecord {
+                p: self.positions[index],
+                normal: self.normals[index],
+                u,
+                v,
+                vertex_color: self.colors.get(index).copied().unwrap_or(vec3(1.0, 1.0, 1.0)),
+                ..HitRecord::default()
+            };
+            self.positions[index] = self.positions[index] + scale * height.value(&rec).x() * self.normals[index];
+        }
+        self.compute_normals();
+    }
+}
+
+pub fn read_mesh<P: AsRef<Path>>(path: P) -> io::Result<MeshData> {
+    // Chooses the loader by the file extension.
+    let path = path.as_ref();
+    match path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase()).as_deref() {
+        Some("ply") => crate::ply::read_ply(path),
+        Some("stl") => crate::stl::read_stl(path),
+        _ => Err(invalid_data(format!("unknown mesh format of '{}'", path.display()))),
+    }
+}
+
+pub fn mesh(data: MeshData, material: &Material) -> Mesh {
+    Mesh::new(data, material)
+}
+
+struct SharedMesh {
+    data: MeshData,
+    mat: Material,
+}
+
+/// Triangle mesh placed in the scene, its triangles are kept in a bounding volume hierarchy.
+pub struct Mesh {
+    bvh: Option<BvhNode>,
+}
+
+impl Mesh {
+    pub fn new(data: MeshData, material: &Material) -> Mesh {
+        let triangle_count = data.triangles.len();
+        let shared = Arc::new(SharedMesh { data, mat: material.clone() });
+        let triangles: Vec<Box<dyn Hittable>> = (0..triangle_count)
+            .map(|index| {
+                Box::new(Triangle {
+                    mesh: shared.clone(),
+                    index,
+                    light: None,
+                }) as Box<dyn Hittable>
+            })
+            .collect();
+
+        let bvh = if triangles.is_empty() {
+            None
+        } else {
+            Some(BvhNode::new(triangles))
+        };
+        Mesh { bvh }
+    }
+}
+
+impl Hittable for Mesh {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        match &self.bvh {
+            Some(bvh) => bvh.hit(r, ray_t, rec),
+            None => false,
+        }
+    }
+
+    fn index_scene(&mut self, scene: &mut SceneIndex) {
+        if let Some(bvh) = &mut self.bvh {
+            bvh.index_scene(scene);
+        }
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        match &self.bvh {
+            Some(bvh) => bvh.bounding_box(),
+            None => Aabb::default(),
+        }
+    }
+}
+
+struct Triangle {
+    mesh: Arc<SharedMesh>,
+    index: usize,
+    light: Option<usize>, // Index of the area light in the scene for glowing meshes
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        // Möller-Trumbore intersection, giving the barycentric coordinates (b1, b2) of the hit.
+        count_intersection_test(Primitive::Triangle);
+        let data = &self.mesh.data;
+        let [i0, i1, i2] = data.triangles[self.index];
+        let (p0, p1, p2) = (data.positions[i0], data.positions[i1], data.positions[i2]);
+
+        let e1 = p1 - p0;
+        let e2 = p2 - p0;
+        let pvec = cross(&r.direction(), &e2);
+        let det = dot(&e1, &pvec);
+        if det.abs() < 1e-12 {
+            return false;
+        }
+        let inv_det = 1.0 / det;
+
+        let tvec = r.origin() - p0;
+        let b1 = dot(&tvec, &pvec) * inv_det;
+        if !(0.0..=1.0).contains(&b1) {
+            return false;
+        }
+        let qvec = cross(&tvec, &e1);
+        let b2 = dot(&r.direction(), &qvec) * inv_det;
+        if b2 < 0.0 || b1 + b2 > 1.0 {
+            return false;
+        }
+        let t = dot(&e2, &qvec) * inv_det;
+        if !ray_t.surrounds(t) {
+            return false;
+        }
+
+        let b0 = 1.0 - b1 - b2;
+        let interpolate = |a: Vec3, b: Vec3, c: Vec3| b0 * a + b1 * b + b2 * c;
+
+        rec.t = t;
+        rec.p = r.at(t);
+        rec.mat = self.mesh.mat.clone();
+        rec.light = self.light;
+        let geometric_normal = unit_vector(&cross(&e1, &e2));
+        rec.set_face_normal(r, &geometric_normal);
+
+        if !data.normals.is_empty() {
+            // Smooth shading, turned to the side of the geometric normal in case the vertex
+            // normals disagree with the winding order.
+            let mut shading_normal = unit_vector(&interpolate(data.normals[i0], data.normals[i1], data.normals[i2]));
+            if dot(&shading_normal, &geometric_normal) < 0.0 {
+                shading_normal = -shading_normal;
+            }
+            rec.normal = if rec.front_face { shading_normal } else { -shading_normal };
+        }
+
+        if data.uvs.is_empty() {
+            (rec.u, rec.v) = (b1, b2);
+            (rec.dpdu, rec.dpdv) = (e1, e2);
+        } else {
+            let (uv0, uv1, uv2) = (data.uvs[i0], data.uvs[i1], data.uvs[i2]);
+            (rec.u, rec.v) = (b0 * uv0.0 + b1 * uv1.0 + b2 * uv2.0, b0 * uv0.1 + b1 * uv1.1 + b2 * uv2.1);
+
+            // Solves e1 = du1 dpdu + dv1 dpdv and e2 = du2 dpdu + dv2 dpdv.
+            let (du1, dv1) = (uv1.0 - uv0.0, uv1.1 - uv0.1);
+            let (du2, dv2) = (uv2.0 - uv0.0, uv2.1 - uv0.1);
+            let det = du1 * dv2 - dv1 * du2;
+            (rec.dpdu, rec.dpdv) = if det.abs() < 1e-12 {
+                (Vec3::default(), Vec3::default())
+            } else {
+                ((dv2 * e1 - dv1 * e2) / det, (du1 * e2 - du2 * e1) / det)
+            };
+        }
+
+        rec.vertex_color = if data.colors.is_empty() {
+            vec3(1.0, 1.0, 1.0)
+        } else {
+            interpolate(data.colors[i0], data.colors[i1], data.colors[i2])
+        };
+
+        true
+    }
+
+    fn index_scene(&mut self, scene: &mut SceneIndex) {
+        scene.add_material(&self.mesh.mat);
+        let data = &self.mesh.data;
+        let [i0, i1, i2] = data.triangles[self.index];
+        let shape = Shape::Triangle {
+            p0: data.positions[i0],
+            p1: data.positions[i1],
+            p2: data.positions[i2],
+        };
+        self.light = scene.add_area_light(&self.mesh.mat, shape);
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let data = &self.mesh.data;
+        let [i0, i1, i2] = data.triangles[self.index];
+        let bbox = Aabb::from_points(&data.positions[i0], &data.positions[i1]);
+        Aabb::from_boxes(&bbox, &Aabb::from_points(&data.positions[i2], &data.positions[i2])).padded()
+    }
+}
+
+#[test]
+fn mesh_test() {
+    use crate::interval::interval;
+    use crate::material::lambertian;
+
+    // A unit square in the xy plane from two triangles, with a red and a blue corner.
+    let data = MeshData {
+        positions: vec![
+            point3(0.0, 0.0, 0.0),
+            point3(1.0, 0.0, 0.0),
+            point3(1.0, 1.0, 0.0),
+            point3(0.0, 1.0, 0.0),
+        ],
+        colors: vec![
+            vec3(1.0, 0.0, 0.0),
+            vec3(1.0, 1.0, 1.0),
+            vec3(0.0, 0.0, 1.0),
+            vec3(1.0, 1.0, 1.0),
+        ],
+        triangles: vec![[0, 1, 2], [0, 2, 3]],
+        ..MeshData::default()
+    };
+    assert!(data.validate().is_ok());
+    let square = mesh(data, &lambertian(vec3(0.5, 0.5, 0.5)));
+
+    let full = interval(0.001, f64::INFINITY);
+    let mut rec = HitRecord::default();
+    let r = Ray::new(&point3(0.25, 0.75, 1.0), &vec3(0.0, 0.0, -1.0));
+    assert!(square.hit(&r, full, &mut rec));
+    assert_eq!(rec.t, 1.0);
+    assert!(rec.front_face);
+    assert_eq!(rec.normal, vec3(0.0, 0.0, 1.0));
+    assert!((rec.vertex_color - vec3(0.75, 0.5, 0.75)).length() < 1e-12);
+
+    // Diagonal hit between the two triangles, and a miss just outside of the square.
+    assert!(square.hit(&Ray::new(&point3(0.5, 0.5, 1.0), &vec3(0.0, 0.0, -1.0)), full, &mut rec));
+    assert!(!square.hit(&Ray::new(&point3(1.01, 0.5, 1.0), &vec3(0.0, 0.0, -1.0)), full, &mut rec));
+
+    let broken = MeshData {
+        positions: vec![point3(0.0, 0.0, 0.0)],
+        triangles: vec![[0, 0, 1]],
+        ..MeshData::default()
+    };
+    assert!(broken.validate().is_err());
+}
+
+#[test]
+fn displace_test() {
+    use crate::color::color;
+    use crate::texture::solid_color;
+
+    // A square subdivided twice has 5 x 5 vertices, shared by neighboring triangles.
+    let mut square = MeshData {
+        positions: vec![
+            point3(0.0, 0.0, 0.0),
+            point3(1.0, 0.0, 0.0),
+            point3(1.0, 1.0, 0.0),
+            point3(0.0, 1.0, 0.0),
+        ],
+        uvs: vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)],
+        triangles: vec![[0, 1, 2], [0, 2, 3]],
+        ..MeshData::default()
+    };
+    square.displace(&solid_color(color(0.5, 0.5, 0.5)), 0.2, 2);
+    assert_eq!(square.positions.len(), 25);
+    assert_eq!(square.triangles.len(), 32);
+    assert!(square.validate().is_ok());
+    assert!(square.uvs.contains(&(0.25, 0.75)));
+
+    // A constant height lifts the square along its normal, which stays the same.
+    for (p, n) in square.positions.iter().zip(&square.normals) {
+        assert!((p.z() - 0.1).abs() < 1e-12);
+        assert!((*n - vec3(0.0, 0.0, 1.0)).length() < 1e-12);
+    }
+}
@@ -0,0 +1,183 @@
+use std::io;
+
+use exr::prelude::*;
+
+use crate::aov::{Aov, RenderBuffers, ALL_AOVS};
+use crate::image::{invalid_data, Image};
+use crate::vec3::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExrPixelType {
+    Half,
+    Float,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExrCompression {
+    None,
+    Zip,
+    Piz,
+}
+
+/// Settings for writing the beauty image and all render passes into one OpenEXR file.
+#[derive(Debug, Clone)]
+pub struct ExrOptions {
+    pub path: String,
+    pub pixel_type: ExrPixelType,
+    pub compression: ExrCompression,
+}
+
+pub fn exr_options(path: &str) -> ExrOptions {
+    ExrOptions {
+        path: path.to_string(),
+        pixel_type: ExrPixelType::Half,
+        compression: ExrCompression::Zip,
+    }
+}
+
+// Channel names of a pass inside the file. The beauty image uses the plain R, G, B channels,
+// passes are stored as layers `<pass>.<channel>`, e.g. `albedo.R` or `depth.Z`.
+fn channel_names(aov: Aov) -> &'static [&'static str] {
+    match aov {
+        Aov::Depth => &["Z"],
+        Aov::Cost => &["Y"],
+        Aov::MaterialId | Aov::ObjectId => &["id"],
+        _ => &["R", "G", "B"],
+    }
+}
+
+fn layer_channel_name(aov: Aov, channel: &str) -> String {
+    format!("{}.{}", aov.name(), channel)
+}
+
+pub fn write_exr(options: &ExrOptions, buffers: &RenderBuffers) -> io::Result<()> {
+    let mut channels: Vec<AnyChannel<FlatSamples>> = Vec::new();
+
+    for (c, name) in ["R", "G", "B"].iter().enumerate() {
+        channels.push(AnyChannel::new(*name, samples(&buffers.beauty, c, options.pixel_type)));
+    }
+
+    for (aov, image) in &buffers.aovs {
+        for (c, name) in channel_names(*aov).iter().enumerate() {
+            // Ids are stored as exact integers, depth and cost always in full precision, as half
+            // floats lose too much of it far away from the camera or for expensive pixels.
+            let data = match aov {
+                Aov::MaterialId | Aov::ObjectId => {
+                    FlatSamples::U32(image.pixels.iter().map(|p| p[c] as u32).collect())
+                }
+                Aov::Depth | Aov::Cost => samples(image, c, ExrPixelType::Float),
+                _ => samples(image, c, options.pixel_type),
+            };
+            channels.push(AnyChannel::new(layer_channel_name(*aov, name).as_str(), data));
+        }
+    }
+
+    let compression = match options.compression {
+        ExrCompression::None => Compression::Uncompressed,
+        ExrCompression::Zip => Compression::ZIP16,
+        ExrCompression::Piz => Compression::PIZ,
+    };
+    let encoding = Encoding {
+        compression,
+        ..Encoding::default()
+    };
+
+    let layer = Layer::new(
+        (buffers.beauty.width, buffers.beauty.height),
+        LayerAttributes::default(),
+        encoding,
+        AnyChannels::sort(SmallVec::from_vec(channels)),
+    );
+
+    exr::prelude::Image::from_layer(layer)
+        .write()
+        .to_file(&options.path)
+        .map_err(io::Error::other)
+}
+
+fn samples(image: &Image, channel: usize, pixel_type: ExrPixelType) -> FlatSamples {
+    match pixel_type {
+        ExrPixelType::Half => FlatSamples::F16(image.pixels.iter().map(|p| f16::from_f64(p[channel])).collect()),
+        ExrPixelType::Float => FlatSamples::F32(image.pixels.iter().map(|p| p[channel] as f32).collect()),
+    }
+}
+
+pub fn read_exr(path: &str) -> io::Result<RenderBuffers> {
+    // Reads a file written by `write_exr` back into render buffers. Passes are recognized by
+    // their layer name, unknown channels are ignored.
+    let file = read()
+        .no_deep_data()
+        .largest_resolution_level()
+        .all_channels()
+        .first_valid_layer()
+        .all_attributes()
+        .from_file(path)
+        .map_err(io::Error::other)?;
+
+    let layer = &file.layer_data;
+    let (width, height) = (layer.size.width(), layer.size.height());
+    let find = |name: &str| layer.channel_data.list.iter().find(|c| c.name.eq(name));
+
+    let beauty_channels: Vec<_> = ["R", "G", "B"].iter().map(|name| find(name)).collect();
+    if beauty_channels.iter().any(|c| c.is_none()) {
+        return Err(invalid_data(format!("'{}' has no R, G, B channels", path)));
+    }
+
+    let mut aovs = Vec::new();
+    for aov in ALL_AOVS {
+        let names = channel_names(aov);
+        let channels: Vec<_> = names.iter().map(|name| find(&layer_channel_name(aov, name))).collect();
+        if channels.iter().all(|c| c.is_some()) {
+            aovs.push(aov);
+        }
+    }
+
+    let mut buffers = RenderBuffers::new(width, height, &aovs);
+    fill(&mut buffers.beauty, &beauty_channels.into_iter().flatten().collect::<Vec<_>>());
+    for (aov, image) in buffers.aovs.iter_mut() {
+        let channels: Vec<_> = channel_names(*aov)
+            .iter()
+            .filter_map(|name| find(&layer_channel_name(*aov, name)))
+            .collect();
+        fill(image, &channels);
+    }
+
+    Ok(buffers)
+}
+
+fn fill(image: &mut Image, channels: &[&AnyChannel<FlatSamples>]) {
+    // Scalar channels are repeated into all three components.
+    for (index, pixel) in image.pixels.iter_mut().enumerate() {
+        let value = |c: usize| channels[c.min(channels.len() - 1)].sample_data.value_by_flat_index(index).to_f32() as f64;
+        *pixel = vec3(value(0), value(1), value(2));
+    }
+}
+
+#[test]
+fn exr_round_trip_test() {
+    use crate::color::color;
+
+    let mut buffers = RenderBuffers::new(3, 2, &[Aov::Albedo, Aov::Depth, Aov::ObjectId]);
+    buffers.beauty.set(2, 1, color(0.5, 2.0, 100.0));
+    buffers.aovs[0].1.set(0, 1, color(0.25, 0.5, 0.75));
+    buffers.aovs[1].1.set(1, 0, color(123.456, 123.456, 123.456));
+    buffers.aovs[2].1.set(2, 0, color(70000.0, 70000.0, 70000.0));
+
+    for compression in [ExrCompression::None, ExrCompression::Zip, ExrCompression::Piz] {
+        let path = std::env::temp_dir().join(format!("exr_round_trip_test_{}.exr", std::process::id()));
+        let options = ExrOptions {
+            path: path.to_string_lossy().into_owned(),
+            pixel_type: ExrPixelType::Half,
+            compression,
+        };
+        write_exr(&options, &buffers).unwrap();
+        let read_back = read_exr(&options.path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(read_back.beauty, buffers.beauty);
+        assert_eq!(read_back.aov(Aov::Albedo), buffers.aov(Aov::Albedo));
+        assert_eq!(read_back.aov(Aov::ObjectId), buffers.aov(Aov::ObjectId));
+        let depth = read_back.aov(Aov::Depth).unwrap().get(1, 0).x();
+        assert!((depth - 123.456).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,475 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::color::srgb_to_linear;
+use crate::image::invalid_data;
+use crate::mesh::MeshData;
+use crate::vec3::*;
+
+// Reader for PLY (polygon file format) meshes in ASCII and binary encoding. Vertex positions,
+// normals, colors and texture coordinates are read, polygons are split into triangles. Other
+// elements and properties are skipped.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ScalarType {
+    Int8,
+    UInt8,
+    Int16,
+    UInt16,
+    Int32,
+    UInt32,
+    Float32,
+    Float64,
+}
+
+impl ScalarType {
+    fn from_name(name: &str) -> Option<ScalarType> {
+        match name {
+            "char" | "int8" => Some(ScalarType::Int8),
+            "uchar" | "uint8" => Some(ScalarType::UInt8),
+            "short" | "int16" => Some(ScalarType::Int16),
+            "ushort" | "uint16" => Some(ScalarType::UInt16),
+            "int" | "int32" => Some(ScalarType::Int32),
+            "uint" | "uint32" => Some(ScalarType::UInt32),
+            "float" | "float32" => Some(ScalarType::Float32),
+            "double" | "float64" => Some(ScalarType::Float64),
+            _ => None,
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            ScalarType::Int8 | ScalarType::UInt8 => 1,
+            ScalarType::Int16 | ScalarType::UInt16 => 2,
+            ScalarType::Int32 | ScalarType::UInt32 | ScalarType::Float32 => 4,
+            ScalarType::Float64 => 8,
+        }
+    }
+
+    fn color_scale(&self) -> f64 {
+        // Integer colors use their full range, floating point ones are in [0,1].
+        match self {
+            ScalarType::UInt8 | ScalarType::Int8 => 255.0,
+            ScalarType::UInt16 | ScalarType::Int16 => 65535.0,
+            ScalarType::UInt32 | ScalarType::Int32 => u32::MAX as f64,
+            ScalarType::Float32 | ScalarType::Float64 => 1.0,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Property {
+    name: String,
+    scalar: ScalarType,
+    // Type of the item count of list properties.
+    list_count: Option<ScalarType>,
+}
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+struct Header {
+    format: Format,
+    elements: Vec<Element>,
+    body_start: usize,
+}
+
+pub fn read_ply<P: AsRef<Path>>(path: P) -> io::Result<MeshData> {
+    parse_ply(&fs::read(path)?)
+}
+
+pub fn parse_ply(bytes: &[u8]) -> io::Result<MeshData> {
+    let header = parse_header(bytes)?;
+    let mut reader = Reader {
+        bytes,
+        pos: header.body_start,
+        format: header.format,
+    };
+
+    let mut mesh = MeshData::default();
+    for element in header.elements.iter() {
+        let result = match element.name.as_str() {
+            "vertex" => read_vertices(&mut reader, element, &mut mesh),
+            "face" => read_faces(&mut reader, element, &mut mesh),
+            _ => (0..element.count).try_for_each(|index| {
+                skip_element(&mut reader, element).map_err(|e| element_error(element, index, e))
+            }),
+        };
+        result?;
+    }
+
+    mesh.validate()?;
+    Ok(mesh)
+}
+
+fn parse_header(bytes: &[u8]) -> io::Result<Header> {
+    let end_marker = b"end_header";
+    let end = bytes
+        .windows(end_marker.len())
+        .position(|w| w == end_marker)
+        .ok_or_else(|| invalid_data("no 'end_header' in the PLY header".to_string()))?;
+    let mut body_start = end + end_marker.len();
+    while body_start < bytes.len() && bytes[body_start] != b'\n' {
+        body_start += 1;
+    }
+    body_start += 1;
+
+    let text = std::str::from_utf8(&bytes[..end]).map_err(|_| invalid_data("PLY header isn't text".to_string()))?;
+    let mut lines = text.lines().enumerate();
+    match lines.next() {
+        Some((_, line)) if line.trim() == "ply" => {}
+        _ => return Err(invalid_data("not a PLY file".to_string())),
+    }
+
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    for (index, line) in lines {
+        let error = |message: String| invalid_data(format!("PLY header line {}: {}", index + 1, message));
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        match tokens.as_slice() {
+            [] | ["comment", ..] | ["obj_info", ..] => {}
+            ["format", name, version] => {
+                if *version != "1.0" {
+                    return Err(error(format!("unsupported version {}", version)));
+                }
+                format = Some(match *name {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::BinaryLittleEndian,
+                    "binary_big_endian" => Format::BinaryBigEndian,
+                    _ => return Err(error(format!("unknown format '{}'", name))),
+                });
+            }
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .map_err(|_| error(format!("invalid element count '{}'", count)))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count_type, item_type, name] => {
+                let property = Property {
+                    name: name.to_string(),
+                    scalar: scalar_type(item_type).map_err(error)?,
+                    list_count: Some(scalar_type(count_type).map_err(error)?),
+                };
+                elements
+                    .last_mut()
+                    .ok_or_else(|| error("property before the first element".to_string()))?
+                    .properties
+                    .push(property);
+            }
+            ["property", scalar, name] => {
+                let property = Property {
+                    name: name.to_string(),
+                    scalar: scalar_type(scalar).map_err(error)?,
+                    list_count: None,
+                };
+                elements
+                    .last_mut()
+                    .ok_or_else(|| error("property before the first element".to_string()))?
+                    .properties
+                    .push(property);
+            }
+            _ => return Err(error(format!("can't parse '{}'", line.trim()))),
+        }
+    }
+
+    let format = format.ok_or_else(|| invalid_data("PLY header has no format".to_string()))?;
+    Ok(Header {
+        format,
+        elements,
+        body_start,
+    })
+}
+
+fn scalar_type(name: &str) -> Result<ScalarType, String> {
+    ScalarType::from_name(name).ok_or_else(|| format!("unknown property type '{}'", name))
+}
+
+fn element_error(element: &Element, index: usize, e: io::Error) -> io::Error {
+    invalid_data(format!("{} {} of {}: {}", element.name, index, element.count, e))
+}
+
+fn read_vertices(reader: &mut Reader, element: &Element, mesh: &mut MeshData) -> io::Result<()> {
+    let find = |names: &[&str]| element.properties.iter().position(|p| names.contains(&p.name.as_str()));
+    let position = [find(&["x"]), find(&["y"]), find(&["z"])];
+    let normal = [find(&["nx"]), find(&["ny"]), find(&["nz"])];
+    let color = [
+        find(&["red", "r", "diffuse_red"]),
+        find(&["green", "g", "diffuse_green"]),
+        find(&["blue", "b", "diffuse_blue"]),
+    ];
+    let uv = [
+        find(&["s", "u", "texture_u", "texture_s"]),
+        find(&["t", "v", "texture_v", "texture_t"]),
+    ];
+
+    let [Some(x), Some(y), Some(z)] = position else {
+        return Err(invalid_data("vertex element without x, y and z properties".to_string()));
+    };
+    let normal = match normal {
+        [Some(nx), Some(ny), Some(nz)] => Some([nx, ny, nz]),
+        _ => None,
+    };
+    let color = match color {
+        [Some(r), Some(g), Some(b)] => Some([r, g, b]),
+        _ => None,
+    };
+    let uv = match uv {
+        [Some(u), Some(v)] => Some([u, v]),
+        _ => None,
+    };
+
+    let mut values = vec![0.0; element.properties.len()];
+    for index in 0..element.count {
+        read_scalars(reader, element, &mut values).map_err(|e| element_error(element, index, e))?;
+
+        mesh.positions.push(point3(values[x], values[y], values[z]));
+        if let Some([nx, ny, nz]) = normal {
+            mesh.normals.push(vec3(values[nx], values[ny], values[nz]));
+        }
+        if let Some(channels) = color {
+            let [r, g, b] = channels.map(|c| srgb_to_linear(values[c] / element.properties[c].scalar.color_scale()));
+            mesh.colors.push(vec3(r, g, b));
+        }
+        if let Some([u, v]) = uv {
+            mesh.uvs.push((values[u], values[v]));
+        }
+    }
+    Ok(())
+}
+
+fn read_scalars(reader: &mut Reader, element: &Element, values: &mut [f64]) -> io::Result<()> {
+    // Reads one element with only scalar properties used, lists are skipped.
+    for (value, property) in values.iter_mut().zip(element.properties.iter()) {
+        match property.list_count {
+            Some(count_type) => {
+                let count = reader.read_count(count_type)?;
+                reader.skip(property.scalar, count)?;
+            }
+            None => *value = reader.read(property.scalar)?,
+        }
+    }
+    Ok(())
+}
+
+fn read_faces(reader: &mut Reader, element: &Element, mesh: &mut MeshData) -> io::Result<()> {
+    let indices = element
+        .properties
+        .iter()
+        .position(|p| p.list_count.is_some() && (p.name == "vertex_indices" || p.name == "vertex_index"))
+        .ok_or_else(|| invalid_data("face element without a vertex_indices list".to_string()))?;
+
+    let mut polygon = Vec::new();
+    for index in 0..element.count {
+        let result: io::Result<()> = element.properties.iter().enumerate().try_for_each(|(i, property)| {
+            match property.list_count {
+                Some(count_type) if i == indices => {
+                    let count = reader.read_count(count_type)?;
+                    polygon.clear();
+                    for _ in 0..count {
+                        let vertex = reader.read(property.scalar)?;
+                        if vertex < 0.0 || vertex.fract() != 0.0 {
+                            return Err(invalid_data(format!("invalid vertex index {}", vertex)));
+                        }
+                        polygon.push(vertex as usize);
+                    }
+                    if polygon.len() < 3 {
+                        return Err(invalid_data(format!("polygon with {} vertices", polygon.len())));
+                    }
+                    Ok(())
+                }
+                Some(count_type) => {
+                    let count = reader.read_count(count_type)?;
+                    reader.skip(property.scalar, count)
+                }
+                None => reader.read(property.scalar).map(|_| ()),
+            }
+        });
+        result.map_err(|e| element_error(element, index, e))?;
+
+        // Fan triangulation, fine for the convex polygons found in scans.
+        for i in 1..polygon.len() - 1 {
+            mesh.triangles.push([polygon[0], polygon[i], polygon[i + 1]]);
+        }
+    }
+    Ok(())
+}
+
+fn skip_element(reader: &mut Reader, element: &Element) -> io::Result<()> {
+    for property in element.properties.iter() {
+        match property.list_count {
+            Some(count_type) => {
+                let count = reader.read_count(count_type)?;
+                reader.skip(property.scalar, count)?;
+            }
+            None => reader.skip(property.scalar, 1)?,
+        }
+    }
+    Ok(())
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    format: Format,
+}
+
+impl Reader<'_> {
+    fn read(&mut self, scalar: ScalarType) -> io::Result<f64> {
+        if self.format == Format::Ascii {
+            return self.read_ascii();
+        }
+
+        let size = scalar.size();
+        let chunk = self
+            .bytes
+            .get(self.pos..self.pos + size)
+            .ok_or_else(|| invalid_data("unexpected end of file".to_string()))?;
+        self.pos += size;
+
+        let mut buffer = [0u8; 8];
+        buffer[..size].copy_from_slice(chunk);
+        if self.format == Format::BinaryBigEndian {
+            buffer[..size].reverse();
+        }
+        let value = match scalar {
+            ScalarType::Int8 => buffer[0] as i8 as f64,
+            ScalarType::UInt8 => buffer[0] as f64,
+            ScalarType::Int16 => i16::from_le_bytes([buffer[0], buffer[1]]) as f64,
+            ScalarType::UInt16 => u16::from_le_bytes([buffer[0], buffer[1]]) as f64,
+            ScalarType::Int32 => i32::from_le_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]) as f64,
+            ScalarType::UInt32 => u32::from_le_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]) as f64,
+            ScalarType::Float32 => f32::from_le_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]) as f64,
+            ScalarType::Float64 => f64::from_le_bytes(buffer),
+        };
+        Ok(value)
+    }
+
+    fn read_ascii(&mut self) -> io::Result<f64> {
+        while self.pos < self.bytes.len() && self.bytes[self.pos].is_ascii_whitespace() {
+            self.pos += 1;
+        }
+        let start = self.pos;
+        while self.pos < self.bytes.len() && !self.bytes[self.pos].is_ascii_whitespace() {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            return Err(invalid_data("unexpected end of file".to_string()));
+        }
+
+        let token = String::from_utf8_lossy(&self.bytes[start..self.pos]);
+        token
+            .parse()
+            .map_err(|_| invalid_data(format!("invalid number '{}'", token)))
+    }
+
+    fn read_count(&mut self, scalar: ScalarType) -> io::Result<usize> {
+        let count = self.read(scalar)?;
+        if count < 0.0 || count.fract() != 0.0 {
+            return Err(invalid_data(format!("invalid list length {}", count)));
+        }
+        Ok(count as usize)
+    }
+
+    fn skip(&mut self, scalar: ScalarType, count: usize) -> io::Result<()> {
+        if self.format == Format::Ascii {
+            for _ in 0..count {
+                self.read_ascii()?;
+            }
+            return Ok(());
+        }
+
+        let end = self.pos + count * scalar.size();
+        if end > self.bytes.len() {
+            return Err(invalid_data("unexpected end of file".to_string()));
+        }
+        self.pos = end;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+const ASCII_QUAD: &str = "ply
+format ascii 1.0
+comment two triangles as a quad
+element vertex 4
+property float x
+property float y
+property float z
+property uchar red
+property uchar green
+property uchar blue
+element face 1
+property list uchar int vertex_indices
+end_header
+0 0 0 255 0 0
+1 0 0 0 255 0
+1 1 0 0 0 255
+0 1 0 255 255 255
+4 0 1 2 3
+";
+
+#[test]
+fn parse_ply_test() {
+    let mesh = parse_ply(ASCII_QUAD.as_bytes()).unwrap();
+    assert_eq!(mesh.positions.len(), 4);
+    assert_eq!(mesh.positions[2], point3(1.0, 1.0, 0.0));
+    assert_eq!(mesh.colors[1], vec3(0.0, 1.0, 0.0));
+    assert_eq!(mesh.triangles, vec![[0, 1, 2], [0, 2, 3]]);
+    assert!(mesh.normals.is_empty() && mesh.uvs.is_empty());
+
+    // The same quad in big endian binary, with normals and an extra element.
+    let mut binary = b"ply\nformat binary_big_endian 1.0\nelement vertex 4\nproperty double x\n\
+        property double y\nproperty double z\nproperty float nx\nproperty float ny\nproperty float nz\n\
+        element face 1\nproperty list uchar uint vertex_indices\nelement material 1\nproperty uchar index\n\
+        end_header\n"
+        .to_vec();
+    for p in mesh.positions.iter() {
+        for c in [p.x(), p.y(), p.z()] {
+            binary.extend(c.to_be_bytes());
+        }
+        for c in [0.0f32, 0.0, 1.0] {
+            binary.extend(c.to_be_bytes());
+        }
+    }
+    binary.push(4);
+    for i in 0..4u32 {
+        binary.extend(i.to_be_bytes());
+    }
+    binary.push(7);
+    let binary_mesh = parse_ply(&binary).unwrap();
+    assert_eq!(binary_mesh.positions, mesh.positions);
+    assert_eq!(binary_mesh.normals[3], vec3(0.0, 0.0, 1.0));
+    assert_eq!(binary_mesh.triangles, mesh.triangles);
+
+    // Truncated and malformed files report where they went wrong.
+    let error = |bytes: &[u8]| parse_ply(bytes).unwrap_err().to_string();
+    assert_eq!(error(&binary[..binary.len() - 3]), "face 0 of 1: unexpected end of file");
+    let truncated = &ASCII_QUAD[..ASCII_QUAD.find("0 1 0 255").unwrap()];
+    assert_eq!(error(truncated.as_bytes()), "vertex 3 of 4: unexpected end of file");
+    assert_eq!(
+        error(ASCII_QUAD.replace("4 0 1 2 3", "4 0 1 2 9").as_bytes()),
+        "triangle 1 refers to vertex 9, but there are only 4 vertices"
+    );
+    assert_eq!(
+        error(ASCII_QUAD.replace("1 1 0 0 0 255", "1 one 0 0 0 255").as_bytes()),
+        "vertex 2 of 4: invalid number 'one'"
+    );
+    assert_eq!(
+        error(ASCII_QUAD.replace("property uchar red", "property byte red").as_bytes()),
+        "PLY header line 8: unknown property type 'byte'"
+    );
+}
@@ -0,0 +1,265 @@
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use crate::color::color_to_bytes;
+use crate::image::Image;
+use crate::vec3::*;
+
+/// How the preview is drawn. Half blocks work in any terminal with 24 bit color, sixel
+/// graphics need a terminal supporting them (e.g. xterm -ti vt340, mlterm, WezTerm, foot).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PreviewMode {
+    HalfBlock,
+    Sixel,
+}
+
+/// Live preview of a render in progress, drawn to stderr as the image goes to stdout.
+#[derive(Debug, Clone, Copy)]
+pub struct PreviewOptions {
+    pub mode: PreviewMode,
+    pub width: usize,       // Width in terminal columns for half blocks, in pixels for sixel
+    pub refresh: Duration,  // Minimum time between two redraws
+}
+
+impl Default for PreviewOptions {
+    fn default() -> Self {
+        Self {
+            mode: PreviewMode::HalfBlock,
+            width: 80,
+            refresh: Duration::from_millis(500),
+        }
+    }
+}
+
+/// Progress of a render, shown below the preview.
+#[derive(Debug, Clone, Copy)]
+pub struct Progress {
+    pub completed_pixels: usize,
+    pub total_pixels: usize,
+    pub started_pixels: usize, // Pixels already completed when this run started, e.g. resumed
+    pub samples_per_pixel: usize,
+    pub rays: u64,             // Rays traced in this run
+}
+
+pub struct Preview {
+    options: PreviewOptions,
+    start: Instant,
+    last_draw: Option<Instant>,
+    lines: usize, // Text lines of the last half block drawing, to draw over it
+}
+
+impl Preview {
+    pub fn new(options: PreviewOptions) -> Preview {
+        Preview {
+            options,
+            start: Instant::now(),
+            last_draw: None,
+            lines: 0,
+        }
+    }
+
+    pub fn update(&mut self, image: &Image, progress: &Progress) {
+        // Redraws when the refresh interval has passed and always for the finished image.
+        let finished = progress.completed_pixels == progress.total_pixels;
+        if !finished && self.last_draw.is_some_and(|t| t.elapsed() < self.options.refresh) {
+            return;
+        }
+        self.last_draw = Some(Instant::now());
+
+        let mut out = String::new();
+        match self.options.mode {
+            PreviewMode::HalfBlock => {
+                if self.lines > 0 {
+                    let _ = write!(out, "\x1b[{}A\r", self.lines);
+                }
+                let picture = half_blocks(image, self.options.width);
+                self.lines = picture.lines().count() + 1;
+                out.push_str(&picture);
+            }
+            PreviewMode::Sixel => {
+                // The height of a sixel image in text lines depends on the font, so the cursor
+                // position is saved before the first drawing and restored for the next ones.
+                out.push_str(if self.lines == 0 { "\x1b7" } else { "\x1b8" });
+                self.lines = 1;
+                out.push_str(&sixel(image, self.options.width));
+                out.push('\n');
+            }
+        }
+        out.push_str(&status_line(progress, self.start.elapsed()));
+        out.push_str("\x1b[K\n");
+
+        let mut stderr = io::stderr().lock();
+        let _ = stderr.write_all(out.as_bytes());
+        let _ = stderr.flush();
+    }
+}
+
+pub fn status_line(progress: &Progress, elapsed: Duration) -> String {
+    let seconds = elapsed.as_secs_f64();
+    let done = progress.completed_pixels - progress.started_pixels;
+    let remaining = progress.total_pixels - progress.completed_pixels;
+    let eta = if done > 0 {
+        format_duration(seconds * remaining as f64 / done as f64)
+    } else {
+        String::from("--:--")
+    };
+    let rays_per_second = if seconds > 0.0 { progress.rays as f64 / seconds } else { 0.0 };
+    // Samples taken so far, averaged over the whole image.
+    let spp = (progress.completed_pixels * progress.samples_per_pixel) as f64 / progress.total_pixels.max(1) as f64;
+
+    format!(
+        "{:5.1}%  {:.1}/{} spp  {:.2} Mrays/s  elapsed {}  ETA {}",
+        100.0 * progress.completed_pixels as f64 / progress.total_pixels.max(1) as f64,
+        spp,
+        progress.samples_per_pixel,
+        rays_per_second / 1e6,
+        format_duration(seconds),
+        eta
+    )
+}
+
+fn format_duration(seconds: f64) -> String {
+    let seconds = seconds.round() as u64;
+    if seconds >= 3600 {
+        format!("{}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+    } else {
+        format!("{:02}:{:02}", seconds / 60, seconds % 60)
+    }
+}
+
+fn downsample(image: &Image, width: usize, height: usize) -> Image {
+    // Averages the block of source pixels covered by each preview pixel.
+    let mut small = Image::new(width, height);
+    let range = |i: usize, size: usize, source_size: usize| {
+        let start = (i * source_size / size).min(source_size - 1);
+        start..((i + 1) * source_size / size).clamp(start + 1, source_size)
+    };
+    for y in 0..height {
+        let rows = range(y, height, image.height);
+        for x in 0..width {
+            let columns = range(x, width, image.width);
+            let mut sum = Vec3::default();
+            for sy in rows.clone() {
+                for sx in columns.clone() {
+                    sum = sum + image.get(sx, sy);
+                }
+            }
+            small.set(x, y, sum / (rows.len() * columns.len()) as f64);
+        }
+    }
+    small
+}
+
+fn preview_size(image: &Image, width: usize) -> (usize, usize) {
+    // Never larger than the image, keeping its aspect ratio. Empty images give an empty preview.
+    if image.width == 0 || image.height == 0 {
+        return (0, 0);
+    }
+    let width = width.clamp(1, image.width.max(1));
+    let height = (image.height as f64 * width as f64 / image.width.max(1) as f64).round() as usize;
+    (width, height.max(1))
+}
+
+pub fn half_blocks(image: &Image, columns: usize) -> String {
+    // Every character cell shows two pixels above each other: the upper half block in the
+    // foreground color and the background color below it. Cells are about twice as high as
+    // wide, which makes the pixels square.
+    let (width, height) = preview_size(image, columns);
+    let small = downsample(image, width, height + height % 2);
+
+    let mut out = String::new();
+    for y in (0..small.height).step_by(2) {
+        for x in 0..width {
+            let [r, g, b] = color_to_bytes(&small.get(x, y));
+            let [br, bg, bb] = color_to_bytes(&small.get(x, y + 1));
+            let _ = write!(out, "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m\u{2580}", r, g, b, br, bg, bb);
+        }
+        out.push_str("\x1b[0m\n");
+    }
+    out
+}
+
+pub fn sixel(image: &Image, width: usize) -> String {
+    // Colors are reduced to a 6x6x6 cube palette. Sixel data is written in bands of six
+    // pixel rows, one pass over the band for each color used in it.
+    let (width, height) = preview_size(image, width);
+    let small = downsample(image, width, height);
+    let indices: Vec<usize> = small
+        .pixels
+        .iter()
+        .map(|p| {
+            let [r, g, b] = color_to_bytes(p).map(|c| (c as usize * 6) / 256);
+            r * 36 + g * 6 + b
+        })
+        .collect();
+
+    let mut out = String::from("\x1bPq");
+    let _ = write!(out, "\"1;1;{};{}", width, height);
+    for index in 0..216 {
+        let level = |l: usize| l * 100 / 5;
+        let _ = write!(out, "#{};2;{};{};{}", index, level(index / 36), level(index / 6 % 6), level(index % 6));
+    }
+
+    for band in (0..height).step_by(6) {
+        let rows = band..(band + 6).min(height);
+        let mut used: Vec<usize> = rows.clone().flat_map(|y| indices[y * width..(y + 1) * width].iter().copied()).collect();
+        used.sort_unstable();
+        used.dedup();
+
+        for (n, index) in used.iter().enumerate() {
+            if n > 0 {
+                out.push('$');
+            }
+            let _ = write!(out, "#{}", index);
+            for x in 0..width {
+                let bits = rows
+                    .clone()
+                    .enumerate()
+                    .filter(|(_, y)| indices[y * width + x] == *index)
+                    .fold(0u8, |bits, (bit, _)| bits | (1 << bit));
+                out.push((63 + bits) as char);
+            }
+        }
+        out.push('-');
+    }
+    out.push_str("\x1b\\");
+    out
+}
+
+#[test]
+fn preview_test() {
+    use crate::color::color;
+
+    // Left column red over blue, right column white over black.
+    let mut image = Image::new(2, 2);
+    image.set(0, 0, color(1.0, 0.0, 0.0));
+    image.set(0, 1, color(0.0, 0.0, 1.0));
+    image.set(1, 0, color(1.0, 1.0, 1.0));
+
+    let blocks = half_blocks(&image, 2);
+    assert_eq!(blocks.lines().count(), 1);
+    assert!(blocks.starts_with("\x1b[38;2;255;0;0m\x1b[48;2;0;0;255m\u{2580}"));
+    assert!(blocks.contains("\x1b[38;2;255;255;255m\x1b[48;2;0;0;0m\u{2580}"));
+
+    // Red is palette entry 180, blue 5, white 215 and black 0. The band has two rows, bit 0 for
+    // the top row and bit 1 for the bottom row.
+    let picture = sixel(&image, 2);
+    assert!(picture.starts_with("\x1bPq\"1;1;2;2#0;2;0;0;0"));
+    assert!(picture.ends_with("#0?A$#5A?$#180@?$#215?@-\x1b\\"));
+
+    // Nothing to show of an empty image.
+    assert_eq!(half_blocks(&Image::new(0, 3), 2), "");
+    assert_eq!(sixel(&Image::new(4, 0), 2), sixel(&Image::new(0, 0), 2));
+    assert!(sixel(&Image::new(0, 0), 2).starts_with("\x1bPq\"1;1;0;0#"));
+
+    let progress = Progress {
+        completed_pixels: 50,
+        total_pixels: 100,
+        started_pixels: 0,
+        samples_per_pixel: 10,
+        rays: 2_000_000,
+    };
+    let status = status_line(&progress, Duration::from_secs(10));
+    assert_eq!(status, " 50.0%  5.0/10 spp  0.20 Mrays/s  elapsed 00:10  ETA 00:10");
+}
@@ -0,0 +1,347 @@
+use std::f64::consts::PI;
+
+use crate::color::{color, luminance};
+use crate::hittable::HitRecord;
+use crate::material::dielectric_reflectance;
+use crate::rtweekend::random_double;
+use crate::texture::{scalar_texture, solid_color, Texture};
+use crate::vec3::*;
+
+/// Parameters of the principled material, after the Disney BRDF of Burley, "Physically Based
+/// Shading at Disney" (2012) and its extension to transmission (2015). All parameters but the
+/// index of refraction can be textured, scalar parameters are read from the red channel.
+#[derive(Debug, Clone)]
+pub struct Principled {
+    pub base_color: Texture,
+    pub metallic: Texture,
+    pub roughness: Texture,
+    pub specular: Texture, // Reflectance of opaque dielectrics, 0.5 is that of the refractive index
+    pub sheen: Texture,    // Extra reflection at grazing angles, for cloth
+    pub clearcoat: Texture,
+    pub clearcoat_roughness: Texture,
+    pub transmission: Texture, // Fraction of the dielectric part that is glass instead of diffuse
+    pub anisotropy: Texture,   // Stretches the highlights along the tangent
+    pub refraction_index: f64,
+}
+
+impl Default for Principled {
+    fn default() -> Self {
+        Self {
+            base_color: solid_color(color(0.8, 0.8, 0.8)),
+            metallic: scalar_texture(0.0),
+            roughness: scalar_texture(0.5),
+            specular: scalar_texture(0.5),
+            sheen: scalar_texture(0.0),
+            clearcoat: scalar_texture(0.0),
+            clearcoat_roughness: scalar_texture(0.03),
+            transmission: scalar_texture(0.0),
+            anisotropy: scalar_texture(0.0),
+            refraction_index: 1.5,
+        }
+    }
+}
+
+/// The principled BSDF at a hit, in the shading frame of the hit: the normal is the z axis and
+/// points to the side the ray came from.
+///
+/// It has four lobes: diffuse with sheen, specular reflection of the metal and the opaque
+/// dielectric, glass reflecting and refracting with its Fresnel reflectance, and the clearcoat
+/// on top of everything. A lobe is sampled with a probability estimating its share of the
+/// reflected light, the sampled direction is weighted with the density of all lobes together.
+pub(crate) struct PrincipledBsdf {
+    tangent: Vec3,
+    bitangent: Vec3,
+    normal: Vec3,
+    base_color: Vec3,
+    metallic: f64,
+    specular: f64,
+    sheen: f64,
+    clearcoat: f64,
+    transmission: f64,
+    eta: f64,             // Refractive index behind the surface over the one in front of it
+    refraction_index: f64,
+    surface: Ggx,
+    coat: Ggx,
+}
+
+impl PrincipledBsdf {
+    pub(crate) fn new(parameters: &Principled, rec: &HitRecord) -> PrincipledBsdf {
+        let scalar = |texture: &Texture| texture.value(rec).x().clamp(0.0, 1.0);
+
+        // Anisotropic highlights are stretched along the derivative of the position along u.
+        let normal = rec.normal;
+        let (dpdu, _) = rec.tangents();
+        let tangent = unit_vector(&(dpdu - dot(&dpdu, &normal) * normal));
+        let bitangent = cross(&normal, &tangent);
+
+        let roughness = scalar(&parameters.roughness);
+        let aspect = (1.0 - 0.9 * scalar(&parameters.anisotropy)).sqrt();
+        let clearcoat_roughness = scalar(&parameters.clearcoat_roughness);
+        let refraction_index = parameters.refraction_index;
+
+        PrincipledBsdf {
+            tangent,
+            bitangent,
+            normal,
+            base_color: parameters.base_color.value(rec),
+            metallic: scalar(&parameters.metallic),
+            specular: scalar(&parameters.specular),
+            sheen: scalar(&parameters.sheen),
+            clearcoat: scalar(&parameters.clearcoat),
+            transmission: scalar(&parameters.transmission),
+            eta: if rec.front_face { refraction_index } else { 1.0 / refraction_index },
+            refraction_index,
+            surface: Ggx::new(roughness * roughness / aspect, roughness * roughness * aspect),
+            coat: Ggx::new(clearcoat_roughness * clearcoat_roughness, clearcoat_roughness * clearcoat_roughness),
+        }
+    }
+
+    fn to_local(&self, v: &Vec3) -> Vec3 {
+        vec3(dot(v, &self.tangent), dot(v, &self.bitangent), dot(v, &self.normal))
+    }
+
+    fn to_world(&self, v: &Vec3) -> Vec3 {
+        v.x() * self.tangent + v.y() * self.bitangent + v.z() * self.normal
+    }
+
+    fn opaque_fresnel(&self, cos_theta: f64) -> f64 {
+        // Opaque dielectrics are always seen from outside.
+        (2.0 * self.specular * dielectric_reflectance(cos_theta, 1.0 / self.refraction_index)).min(1.0)
+    }
+
+    fn glass_fresnel(&self, cos_theta: f64) -> f64 {
+        dielectric_reflectance(cos_theta, 1.0 / self.eta)
+    }
+
+    fn specular_fresnel(&self, cos_theta: f64) -> Vec3 {
+        // Metals reflect their base color at normal incidence, towards white at grazing angles.
+        let metal = self.base_color + (color(1.0, 1.0, 1.0) - self.base_color) * (1.0 - cos_theta).powi(5);
+        let dielectric = (1.0 - self.metallic) * (1.0 - self.transmission) * self.opaque_fresnel(cos_theta);
+        self.metallic * metal + color(dielectric, dielectric, dielectric)
+    }
+
+    fn coat_fresnel(&self, cos_theta: f64) -> f64 {
+        // The clearcoat is a layer of index 1.5 (a reflectance of 4% at normal incidence).
+        self.clearcoat * (0.04 + 0.96 * (1.0 - cos_theta.clamp(0.0, 1.0)).powi(5))
+    }
+
+    fn lobe_weights(&self, wo: &Vec3) -> [f64; 4] {
+        // Probabilities of sampling the diffuse, specular, glass and clearcoat lobes, from their
+        // reflectance seen from the outgoing direction.
+        let cos_o = wo.z();
+        let dielectric = 1.0 - self.metallic;
+        let below_coat = 1.0 - self.coat_fresnel(cos_o);
+        let glass_fresnel = self.glass_fresnel(cos_o);
+        let weights = [
+            below_coat
+                * dielectric
+                * ((1.0 - self.transmission) * luminance(&self.base_color) * (1.0 - self.opaque_fresnel(cos_o))
+                    + 0.25 * self.sheen),
+            below_coat * luminance(&self.specular_fresnel(cos_o)),
+            below_coat
+                * dielectric
+                * self.transmission
+                * (glass_fresnel + (1.0 - glass_fresnel) * luminance(&self.base_color)),
+            self.coat_fresnel(cos_o),
+        ];
+        let total: f64 = weights.iter().sum();
+        if total <= 0.0 {
+            return [0.0; 4];
+        }
+        weights.map(|w| w / total)
+    }
+
+    /// BSDF times the cosine of the incoming direction, for the directions `wo` towards the
+    /// viewer and `wi` towards the light.
+    pub(crate) fn eval(&self, wo: &Vec3, wi: &Vec3) -> Vec3 {
+        let (wo, wi) = (self.to_local(&unit_vector(wo)), self.to_local(&unit_vector(wi)));
+        let (cos_o, cos_i) = (wo.z(), wi.z());
+        if cos_o <= 0.0 || cos_i == 0.0 {
+            return Vec3::default();
+        }
+        let dielectric = 1.0 - self.metallic;
+        let below_coat = (1.0 - self.coat_fresnel(cos_o)) * (1.0 - self.coat_fresnel(cos_i.abs()));
+
+        if cos_i < 0.0 {
+            // Refraction through the glass microfacets, Walter et al., "Microfacet Models for
+            // Refraction through Rough Surfaces" (2007).
+            let Some(m) = refraction_half_vector(&wo, &wi, self.eta) else {
+                return Vec3::default();
+            };
+            let (cos_om, cos_im) = (dot(&wo, &m), dot(&wi, &m));
+            let denominator = (cos_im + cos_om / self.eta).powi(2);
+            let btdf = self.surface.d(&m) * self.surface.g(&wo, &wi) * (cos_im * cos_om).abs() / (cos_o * denominator);
+            let weight = below_coat * dielectric * self.transmission * (1.0 - self.glass_fresnel(cos_om)) * btdf;
+            return weight * self.base_color;
+        }
+
+        let h = unit_vector(&(wo + wi));
+        let cos_d = dot(&wo, &h);
+        let diffuse = (1.0 - self.transmission)
+            * (1.0 - self.opaque_fresnel(cos_o))
+            * (1.0 - self.opaque_fresnel(cos_i))
+            * cos_i
+            / PI
+            * self.base_color;
+        let sheen = self.sheen * (1.0 - cos_d).powi(5) * cos_i;
+        let microfacet = |ggx: &Ggx| ggx.d(&h) * ggx.g(&wo, &wi) / (4.0 * cos_o);
+        let surface = microfacet(&self.surface);
+        let specular = surface * self.specular_fresnel(cos_d);
+        let glass = dielectric * self.transmission * self.glass_fresnel(cos_d) * surface;
+        let coat = self.coat_fresnel(cos_d) * microfacet(&self.coat);
+
+        let layer = dielectric * (diffuse + color(sheen, sheen, sheen)) + specular + color(glass, glass, glass);
+        below_coat * layer + color(coat, coat, coat)
+    }
+
+    /// Density of the directions returned by `sample` over the whole sphere, per solid angle.
+    pub(crate) fn pdf(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        let (wo, wi) = (self.to_local(&unit_vector(wo)), self.to_local(&unit_vector(wi)));
+        if wo.z() <= 0.0 {
+            return 0.0;
+        }
+        let [diffuse, specular, glass, coat] = self.lobe_weights(&wo);
+
+        let mut pdf = diffuse * wi.z().max(0.0) / PI;
+        let h = wo + wi;
+        if !h.near_zero() {
+            let h = unit_vector(&h);
+            let reflection = |ggx: &Ggx| ggx.visible_pdf(&wo, &h) / (4.0 * dot(&wo, &h));
+            if h.z() > 0.0 && dot(&wo, &h) > 0.0 {
+                let surface = reflection(&self.surface);
+                pdf += specular * surface + glass * self.glass_fresnel(dot(&wo, &h)) * surface;
+                pdf += coat * reflection(&self.coat);
+            }
+        }
+        if let Some(m) = refraction_half_vector(&wo, &wi, self.eta) {
+            let (cos_om, cos_im) = (dot(&wo, &m), dot(&wi, &m));
+            let jacobian = cos_im.abs() / (cos_im + cos_om / self.eta).powi(2);
+            pdf += glass * (1.0 - self.glass_fresnel(cos_om)) * self.surface.visible_pdf(&wo, &m) * jacobian;
+        }
+        pdf
+    }
+
+    /// Samples a direction towards the light. It may lie on the wrong side of the surface,
+    /// where the BSDF is zero.
+    pub(crate) fn sample(&self, wo: &Vec3) -> Option<Vec3> {
+        let wo = self.to_local(&unit_vector(wo));
+        if wo.z() <= 0.0 {
+            return None;
+        }
+        let [diffuse, specular, glass, coat] = self.lobe_weights(&wo);
+        if diffuse + specular + glass + coat == 0.0 {
+            return None;
+        }
+        let u = random_double();
+
+        let wi = if u < diffuse {
+            let direction = vec3(0.0, 0.0, 1.0) + random_unit_vector();
+            if direction.near_zero() {
+                vec3(0.0, 0.0, 1.0)
+            } else {
+                direction
+            }
+        } else if u < diffuse + specular {
+            reflect(&-wo, &self.surface.sample_visible(&wo))
+        } else if u < diffuse + specular + glass {
+            let m = self.surface.sample_visible(&wo);
+            let cos_om = dot(&wo, &m);
+            if random_double() < self.glass_fresnel(cos_om) {
+                reflect(&-wo, &m)
+            } else {
+                // Total internal reflection has a Fresnel reflectance of one, so the refracted
+                // direction exists here.
+                let sin_squared = (1.0 - cos_om * cos_om) / (self.eta * self.eta);
+                let cos_t = (1.0 - sin_squared).max(0.0).sqrt();
+                -wo / self.eta + (cos_om / self.eta - cos_t) * m
+            }
+        } else {
+            reflect(&-wo, &self.coat.sample_visible(&wo))
+        };
+        Some(self.to_world(&wi))
+    }
+}
+
+fn refraction_half_vector(wo: &Vec3, wi: &Vec3, eta: f64) -> Option<Vec3> {
+    // Microfacet normal refracting `wo` into `wi`, on the side of the surface normal. None if
+    // no microfacet facing `wo` does that.
+    let m = *wo + eta * *wi;
+    if m.near_zero() {
+        return None;
+    }
+    let m = unit_vector(&m);
+    let m = if m.z() < 0.0 { -m } else { m };
+    (dot(wo, &m) > 0.0 && dot(wi, &m) < 0.0).then_some(m)
+}
+
+/// Anisotropic GGX (Trowbridge-Reitz) microfacet distribution with the height correlated Smith
+/// masking function, in the shading frame.
+#[derive(Debug, Clone, Copy)]
+struct Ggx {
+    alpha_x: f64,
+    alpha_y: f64,
+}
+
+impl Ggx {
+    fn new(alpha_x: f64, alpha_y: f64) -> Ggx {
+        // Perfectly smooth surfaces would make the distribution a delta, it is kept just rough
+        // enough for the densities to stay finite.
+        Ggx {
+            alpha_x: alpha_x.max(1e-3),
+            alpha_y: alpha_y.max(1e-3),
+        }
+    }
+
+    fn d(&self, m: &Vec3) -> f64 {
+        if m.z() <= 0.0 {
+            return 0.0;
+        }
+        let (x, y) = (m.x() / self.alpha_x, m.y() / self.alpha_y);
+        let t = x * x + y * y + m.z() * m.z();
+        1.0 / (PI * self.alpha_x * self.alpha_y * t * t)
+    }
+
+    fn lambda(&self, w: &Vec3) -> f64 {
+        let cos_squared = w.z() * w.z();
+        if cos_squared == 0.0 {
+            return f64::INFINITY;
+        }
+        let (x, y) = (self.alpha_x * w.x(), self.alpha_y * w.y());
+        (((x * x + y * y) / cos_squared + 1.0).sqrt() - 1.0) / 2.0
+    }
+
+    fn g1(&self, w: &Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    fn g(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    fn visible_pdf(&self, wo: &Vec3, m: &Vec3) -> f64 {
+        // Density of the microfacet normals seen from `wo`.
+        self.g1(wo) * dot(wo, m).max(0.0) * self.d(m) / wo.z()
+    }
+
+    fn sample_visible(&self, wo: &Vec3) -> Vec3 {
+        // Heitz, "Sampling the GGX Distribution of Visible Normals" (2018): the view direction is
+        // stretched to the unit roughness configuration, where the visible normals are sampled
+        // on a projected disk.
+        let v = unit_vector(&vec3(self.alpha_x * wo.x(), self.alpha_y * wo.y(), wo.z()));
+        let length_squared = v.x() * v.x() + v.y() * v.y();
+        let t1 = if length_squared > 0.0 {
+            vec3(-v.y(), v.x(), 0.0) / length_squared.sqrt()
+        } else {
+            vec3(1.0, 0.0, 0.0)
+        };
+        let t2 = cross(&v, &t1);
+
+        let r = random_double().sqrt();
+        let phi = 2.0 * PI * random_double();
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + v.z());
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+        let n = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * v;
+        unit_vector(&vec3(self.alpha_x * n.x(), self.alpha_y * n.y(), n.z().max(1e-9)))
+    }
+}
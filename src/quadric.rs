@@ -0,0 +1,597 @@
+use std::f64::consts::PI;
+
+use crate::aabb::{aabb, Aabb};
+use crate::hittable::{HitRecord, HitSpan, Hittable, SceneIndex};
+use crate::interval::{interval, Interval};
+use crate::ray::Ray;
+use crate::stats::{count_intersection_test, Primitive};
+use crate::vec3::*;
+use crate::Material;
+
+// Analytic primitives placed along an axis. All of them are closed solids: the surfaces of
+// revolution are capped by flat disks and the capsule by half spheres, so they can be combined
+// with CSG. Intersections are computed in a local frame with the axis along z.
+
+pub fn cylinder(base: &Vec3, top: &Vec3, radius: f64, material: &Material) -> Quadric {
+    Quadric::revolution(base, top, 0.0, 0.0, radius * radius, material)
+}
+
+pub fn cone(base: &Vec3, top: &Vec3, base_radius: f64, top_radius: f64, material: &Material) -> Quadric {
+    // A top radius of zero gives a pointed cone, others a truncated one.
+    let height = (*top - *base).length();
+    let slope = (top_radius - base_radius) / height;
+    Quadric::revolution(
+        base,
+        top,
+        slope * slope,
+        2.0 * base_radius * slope,
+        base_radius * base_radius,
+        material,
+    )
+}
+
+pub fn paraboloid(vertex: &Vec3, top: &Vec3, radius: f64, material: &Material) -> Quadric {
+    let height = (*top - *vertex).length();
+    Quadric::revolution(vertex, top, 0.0, radius * radius / height, 0.0, material)
+}
+
+pub fn hyperboloid(base: &Vec3, top: &Vec3, waist_radius: f64, end_radius: f64, material: &Material) -> Quadric {
+    // Hyperboloid of one sheet, narrowest halfway between the end caps.
+    // r(z)^2 = waist^2 + k (z - h/2)^2
+    let height = (*top - *base).length();
+    let k = (end_radius * end_radius - waist_radius * waist_radius) / (0.25 * height * height);
+    Quadric::revolution(
+        base,
+        top,
+        k,
+        -k * height,
+        waist_radius * waist_radius + 0.25 * k * height * height,
+        material,
+    )
+}
+
+pub fn torus(center: &Vec3, axis: &Vec3, major_radius: f64, minor_radius: f64, material: &Material) -> Quadric {
+    Quadric::new(
+        Frame::new(center, axis),
+        Shape::Torus {
+            major_radius,
+            minor_radius,
+        },
+        material,
+    )
+}
+
+pub fn capsule(a: &Vec3, b: &Vec3, radius: f64, material: &Material) -> Quadric {
+    let height = (*b - *a).length();
+    Quadric::new(Frame::new(a, &(*b - *a)), Shape::Capsule { radius, height }, material)
+}
+
+/// Orthonormal frame of a primitive, with `w` along its axis.
+#[derive(Debug, Clone, Copy)]
+struct Frame {
+    origin: Vec3,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+}
+
+impl Frame {
+    fn new(origin: &Vec3, axis: &Vec3) -> Frame {
+        let w = unit_vector(axis);
+        let a = if w.x().abs() > 0.9 { vec3(0.0, 1.0, 0.0) } else { vec3(1.0, 0.0, 0.0) };
+        let v = unit_vector(&cross(&w, &a));
+        let u = cross(&v, &w);
+        Frame { origin: *origin, u, v, w }
+    }
+
+    fn vector_to_local(&self, d: &Vec3) -> Vec3 {
+        vec3(dot(d, &self.u), dot(d, &self.v), dot(d, &self.w))
+    }
+
+    fn point_to_local(&self, p: &Vec3) -> Vec3 {
+        self.vector_to_local(&(*p - self.origin))
+    }
+
+    fn vector_to_world(&self, d: &Vec3) -> Vec3 {
+        d.x() * self.u + d.y() * self.v + d.z() * self.w
+    }
+
+    fn point_to_world(&self, p: &Vec3) -> Vec3 {
+        self.origin + self.vector_to_world(p)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Shape {
+    // Surface of revolution x^2 + y^2 = a z^2 + b z + c for 0 <= z <= height, closed by caps.
+    Revolution { a: f64, b: f64, c: f64, height: f64 },
+    Torus { major_radius: f64, minor_radius: f64 },
+    Capsule { radius: f64, height: f64 },
+}
+
+/// Where the line of a ray crosses the surface, in local coordinates.
+#[derive(Debug, Clone, Copy)]
+struct Crossing {
+    t: f64,
+    normal: Vec3,
+    u: f64,
+    v: f64,
+    dpdu: Vec3,
+    dpdv: Vec3,
+}
+
+pub struct Quadric {
+    frame: Frame,
+    shape: Shape,
+    mat: Material,
+}
+
+impl Quadric {
+    fn new(frame: Frame, shape: Shape, material: &Material) -> Quadric {
+        Quadric {
+            frame,
+            shape,
+            mat: material.clone(),
+        }
+    }
+
+    fn revolution(base: &Vec3, top: &Vec3, a: f64, b: f64, c: f64, material: &Material) -> Quadric {
+        let height = (*top - *base).length();
+        Quadric::new(Frame::new(base, &(*top - *base)), Shape::Revolution { a, b, c, height }, material)
+    }
+
+    fn crossings(&self, r: &Ray) -> Vec<Crossing> {
+        // All crossings of the whole line, sorted along the ray. Entries and exits alternate.
+        count_intersection_test(Primitive::Quadric);
+        let o = self.frame.point_to_local(&r.origin());
+        let d = self.frame.vector_to_local(&r.direction());
+
+        let mut crossings = match self.shape {
+            Shape::Revolution { a, b, c, height } => revolution_crossings(&o, &d, a, b, c, height),
+            Shape::Torus {
+                major_radius,
+                minor_radius,
+            } => torus_crossings(&o, &d, major_radius, minor_radius),
+            Shape::Capsule { radius, height } => capsule_crossings(&o, &d, radius, height),
+        };
+        crossings.sort_by(|x, y| x.t.total_cmp(&y.t));
+        crossings
+    }
+
+    fn hit_record(&self, r: &Ray, crossing: &Crossing) -> HitRecord {
+        let mut rec = HitRecord {
+            t: crossing.t,
+            p: r.at(crossing.t),
+            u: crossing.u,
+            v: crossing.v,
+            dpdu: self.frame.vector_to_world(&crossing.dpdu),
+            dpdv: self.frame.vector_to_world(&crossing.dpdv),
+            mat: self.mat.clone(),
+            ..HitRecord::default()
+        };
+        let outward_normal = unit_vector(&self.frame.vector_to_world(&crossing.normal));
+        rec.set_face_normal(r, &outward_normal);
+        rec
+    }
+}
+
+impl Hittable for Quadric {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        match self.crossings(r).iter().find(|c| ray_t.surrounds(c.t)) {
+            Some(crossing) => {
+                *rec = self.hit_record(r, crossing);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn hit_spans(&self, r: &Ray, _ray_t: Interval) -> Option<Vec<HitSpan>> {
+        let crossings = self.crossings(r);
+        let spans = crossings
+            .chunks_exact(2)
+            .map(|pair| HitSpan {
+                enter: self.hit_record(r, &pair[0]),
+                exit: self.hit_record(r, &pair[1]),
+            })
+            .collect();
+        Some(spans)
+    }
+
+    fn index_scene(&mut self, scene: &mut SceneIndex) {
+        scene.add_material(&self.mat);
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let local = match self.shape {
+            Shape::Revolution { a, b, c, height } => {
+                // The squared radius is a parabola in z, its maximum is at an end or the vertex.
+                let radius_squared = |z: f64| a * z * z + b * z + c;
+                let mut max = radius_squared(0.0).max(radius_squared(height));
+                if a < 0.0 && (0.0..=height).contains(&(-b / (2.0 * a))) {
+                    max = max.max(radius_squared(-b / (2.0 * a)));
+                }
+                let r = max.max(0.0).sqrt();
+                aabb(interval(-r, r), interval(-r, r), interval(0.0, height))
+            }
+            Shape::Torus {
+                major_radius,
+                minor_radius,
+            } => {
+                let r = major_radius + minor_radius;
+                aabb(interval(-r, r), interval(-r, r), interval(-minor_radius, minor_radius))
+            }
+            Shape::Capsule { radius, height } => aabb(
+                interval(-radius, radius),
+                interval(-radius, radius),
+                interval(-radius, height + radius),
+            ),
+        };
+        local.transformed(|p| self.frame.point_to_world(p))
+    }
+}
+
+fn azimuth(p: &Vec3) -> f64 {
+    // Angle around the axis, mapped to [0,1].
+    (p.y().atan2(p.x()) + PI) / (2.0 * PI)
+}
+
+fn azimuth_derivative(p: &Vec3) -> Vec3 {
+    // Derivative of the point along the azimuth u.
+    2.0 * PI * vec3(-p.y(), p.x(), 0.0)
+}
+
+fn along_axis(p: &Vec3, radius_derivative: f64) -> Vec3 {
+    // Derivative of a point on a surface of revolution along the axis, given the derivative of
+    // its distance from the axis.
+    let radius_squared = p.x() * p.x() + p.y() * p.y();
+    if radius_squared < 1e-24 {
+        return Vec3::default();
+    }
+    let radius = radius_squared.sqrt();
+    vec3(radius_derivative * p.x() / radius, radius_derivative * p.y() / radius, 1.0)
+}
+
+fn revolution_crossings(o: &Vec3, d: &Vec3, a: f64, b: f64, c: f64, height: f64) -> Vec<Crossing> {
+    let radius_squared = |z: f64| a * z * z + b * z + c;
+    let mut crossings = Vec::with_capacity(4);
+
+    // Side: (ox + t dx)^2 + (oy + t dy)^2 = r((oz + t dz))^2
+    let qa = d.x() * d.x() + d.y() * d.y() - a * d.z() * d.z();
+    let qh = o.x() * d.x() + o.y() * d.y() - a * o.z() * d.z() - 0.5 * b * d.z();
+    let qc = o.x() * o.x() + o.y() * o.y() - radius_squared(o.z());
+
+    let roots = if qa.abs() < 1e-12 {
+        // Rays parallel to the axis or to a line on a cone.
+        if qh.abs() < 1e-12 {
+            vec![]
+        } else {
+            vec![-qc / (2.0 * qh)]
+        }
+    } else {
+        let discriminant = qh * qh - qa * qc;
+        if discriminant <= 0.0 {
+            vec![]
+        } else {
+            let sqrtd = discriminant.sqrt();
+            vec![(-qh - sqrtd) / qa, (-qh + sqrtd) / qa]
+        }
+    };
+
+    for t in roots {
+        let p = *o + t * *d;
+        if (0.0..=height).contains(&p.z()) {
+            crossings.push(Crossing {
+                t,
+                normal: vec3(p.x(), p.y(), -(a * p.z() + 0.5 * b)),
+                u: azimuth(&p),
+                v: p.z() / height,
+                dpdu: azimuth_derivative(&p),
+                dpdv: height * along_axis(&p, (a * p.z() + 0.5 * b) / p.x().hypot(p.y())),
+            });
+        }
+    }
+
+    // Caps, skipped where the surface closes in a point.
+    for (z, normal) in [(0.0, vec3(0.0, 0.0, -1.0)), (height, vec3(0.0, 0.0, 1.0))] {
+        let cap_radius_squared = radius_squared(z);
+        if cap_radius_squared <= 0.0 || d.z() == 0.0 {
+            continue;
+        }
+        let t = (z - o.z()) / d.z();
+        let p = *o + t * *d;
+        if p.x() * p.x() + p.y() * p.y() <= cap_radius_squared {
+            let cap_radius = cap_radius_squared.sqrt();
+            crossings.push(Crossing {
+                t,
+                normal,
+                u: 0.5 + 0.5 * p.x() / cap_radius,
+                v: 0.5 + 0.5 * p.y() / cap_radius,
+                dpdu: vec3(2.0 * cap_radius, 0.0, 0.0),
+                dpdv: vec3(0.0, 2.0 * cap_radius, 0.0),
+            });
+        }
+    }
+
+    crossings
+}
+
+fn capsule_crossings(o: &Vec3, d: &Vec3, radius: f64, height: f64) -> Vec<Crossing> {
+    let mut crossings = Vec::with_capacity(2);
+    let uv = |p: &Vec3| (azimuth(p), (p.z() + radius) / (height + 2.0 * radius));
+
+    // Cylinder between the end points.
+    let qa = d.x() * d.x() + d.y() * d.y();
+    let qh = o.x() * d.x() + o.y() * d.y();
+    let qc = o.x() * o.x() + o.y() * o.y() - radius * radius;
+    let discriminant = qh * qh - qa * qc;
+    if qa > 0.0 && discriminant >= 0.0 {
+        let sqrtd = discriminant.sqrt();
+        for t in [(-qh - sqrtd) / qa, (-qh + sqrtd) / qa] {
+            let p = *o + t * *d;
+            if (0.0..=height).contains(&p.z()) {
+                let (u, v) = uv(&p);
+                crossings.push(Crossing {
+                    t,
+                    normal: vec3(p.x(), p.y(), 0.0),
+                    u,
+                    v,
+                    dpdu: azimuth_derivative(&p),
+                    dpdv: vec3(0.0, 0.0, height + 2.0 * radius),
+                });
+            }
+        }
+    }
+
+    // Half spheres beyond the end points.
+    for (z, outside) in [(0.0, -1.0), (height, 1.0)] {
+        let center = point3(0.0, 0.0, z);
+        let oc = center - *o;
+        let a = d.length_squared();
+        let h = dot(d, &oc);
+        let c = oc.length_squared() - radius * radius;
+        let discriminant = h * h - a * c;
+        if discriminant <= 0.0 {
+            continue;
+        }
+        let sqrtd = discriminant.sqrt();
+        for t in [(h - sqrtd) / a, (h + sqrtd) / a] {
+            let p = *o + t * *d;
+            if outside * (p.z() - z) > 0.0 {
+                let (u, v) = uv(&p);
+                crossings.push(Crossing {
+                    t,
+                    normal: p - center,
+                    u,
+                    v,
+                    dpdu: azimuth_derivative(&p),
+                    dpdv: (height + 2.0 * radius) * along_axis(&p, -(p.z() - z) / p.x().hypot(p.y())),
+                });
+            }
+        }
+    }
+
+    crossings
+}
+
+fn torus_crossings(o: &Vec3, d: &Vec3, major_radius: f64, minor_radius: f64) -> Vec<Crossing> {
+    // The quartic is solved for the distance s along the normalized direction, measured from
+    // the point of the line closest to the center. This keeps the coefficients well conditioned
+    // for far away ray origins.
+    let direction_length = d.length();
+    let dn = *d / direction_length;
+    let s0 = -dot(o, &dn);
+    let oc = *o + s0 * dn;
+
+    let bound = major_radius + minor_radius;
+    if oc.length_squared() > bound * bound {
+        return vec![];
+    }
+
+    // (|p|^2 + R^2 - r^2)^2 = 4 R^2 (px^2 + py^2) with |p|^2 = s^2 + |oc|^2, as oc is normal
+    // to the direction.
+    let r2 = major_radius * major_radius;
+    let q = oc.length_squared() + r2 - minor_radius * minor_radius;
+    let coefficients = [
+        q * q - 4.0 * r2 * (oc.x() * oc.x() + oc.y() * oc.y()),
+        -8.0 * r2 * (oc.x() * dn.x() + oc.y() * dn.y()),
+        2.0 * q - 4.0 * r2 * (dn.x() * dn.x() + dn.y() * dn.y()),
+        0.0,
+        1.0,
+    ];
+
+    polynomial_roots(&coefficients, -bound, bound)
+        .into_iter()
+        .map(|s| {
+            let p = oc + s * dn;
+            let ring = p.x().hypot(p.y());
+            let tube_center = vec3(p.x(), p.y(), 0.0) * (major_radius / ring);
+            Crossing {
+                t: (s0 + s) / direction_length,
+                normal: p - tube_center,
+                u: azimuth(&p),
+                v: (p.z().atan2(ring - major_radius) + PI) / (2.0 * PI),
+                dpdu: azimuth_derivative(&p),
+                dpdv: 2.0 * PI * vec3(-p.z() * p.x() / ring, -p.z() * p.y() / ring, ring - major_radius),
+            }
+        })
+        .collect()
+}
+
+fn polynomial_roots(coefficients: &[f64], lo: f64, hi: f64) -> Vec<f64> {
+    // Real roots within [lo, hi] of the polynomial with the given coefficients, lowest order
+    // first. Between the roots of its derivative the polynomial is monotonic, so each of those
+    // intervals holds at most one root, which is found by bisection. Roots of even multiplicity
+    // (grazing hits) are not reported.
+    let degree = coefficients.len() - 1;
+    if degree == 0 {
+        return vec![];
+    }
+    if degree == 1 {
+        let root = -coefficients[0] / coefficients[1];
+        return if (lo..=hi).contains(&root) { vec![root] } else { vec![] };
+    }
+
+    let derivative: Vec<f64> = (1..=degree).map(|i| i as f64 * coefficients[i]).collect();
+    let eval = |x: f64| coefficients.iter().rev().fold(0.0, |acc, c| acc * x + c);
+
+    let mut bounds = vec![lo];
+    bounds.extend(polynomial_roots(&derivative, lo, hi));
+    bounds.push(hi);
+
+    let mut roots = Vec::new();
+    for pair in bounds.windows(2) {
+        let (mut a, mut b) = (pair[0], pair[1]);
+        let (fa, fb) = (eval(a), eval(b));
+        if fa == 0.0 {
+            if roots.last() != Some(&a) {
+                roots.push(a);
+            }
+            continue;
+        }
+        if fa * fb > 0.0 {
+            continue;
+        }
+
+        let rising = fa < 0.0;
+        for _ in 0..64 {
+            let mid = 0.5 * (a + b);
+            if (eval(mid) < 0.0) == rising {
+                a = mid;
+            } else {
+                b = mid;
+            }
+        }
+        roots.push(0.5 * (a + b));
+    }
+    roots
+}
+
+#[cfg(test)]
+fn test_spans(shape: &Quadric, r: &Ray) -> Vec<(f64, f64)> {
+    shape
+        .hit_spans(r, interval(0.001, f64::INFINITY))
+        .unwrap()
+        .iter()
+        .map(|s| (s.enter.t, s.exit.t))
+        .collect()
+}
+
+#[cfg(test)]
+fn assert_spans(actual: &[(f64, f64)], expected: &[(f64, f64)]) {
+    assert_eq!(actual.len(), expected.len(), "{:?} != {:?}", actual, expected);
+    for (a, e) in actual.iter().zip(expected) {
+        assert!((a.0 - e.0).abs() < 1e-9 && (a.1 - e.1).abs() < 1e-9, "{:?} != {:?}", actual, expected);
+    }
+}
+
+#[test]
+fn polynomial_roots_test() {
+    // (x + 2)(x - 0.5)(x - 1)(x - 3)
+    let roots = polynomial_roots(&[-3.0, 8.5, -4.0, -2.5, 1.0], -10.0, 10.0);
+    assert_eq!(roots.len(), 4);
+    for (root, expected) in roots.iter().zip([-2.0, 0.5, 1.0, 3.0]) {
+        assert!((root - expected).abs() < 1e-12);
+    }
+    assert_eq!(polynomial_roots(&[-3.0, 8.5, -4.0, -2.5, 1.0], 0.0, 2.0).len(), 2);
+}
+
+#[test]
+fn quadric_test() {
+    let material = crate::material::lambertian(vec3(0.5, 0.5, 0.5));
+    let across = Ray::new(&point3(0.0, 0.5, 5.0), &vec3(0.0, 0.0, -1.0));
+    let along = Ray::new(&point3(0.0, 5.0, 0.0), &vec3(0.0, -1.0, 0.0));
+
+    // Upright shapes from y = 0 to y = 1.
+    let base = point3(0.0, 0.0, 0.0);
+    let top = point3(0.0, 1.0, 0.0);
+    assert_spans(&test_spans(&cylinder(&base, &top, 1.0, &material), &across), &[(4.0, 6.0)]);
+    assert_spans(&test_spans(&cylinder(&base, &top, 1.0, &material), &along), &[(4.0, 5.0)]);
+    assert_spans(&test_spans(&cone(&base, &top, 1.0, 0.0, &material), &across), &[(4.5, 5.5)]);
+    let off_axis = Ray::new(&point3(0.5, 5.0, 0.0), &vec3(0.0, -1.0, 0.0));
+    assert_spans(&test_spans(&cone(&base, &top, 1.0, 0.0, &material), &off_axis), &[(4.5, 5.0)]);
+    let a = 0.5f64.sqrt();
+    assert_spans(&test_spans(&paraboloid(&base, &top, 1.0, &material), &across), &[(5.0 - a, 5.0 + a)]);
+    assert_spans(&test_spans(&hyperboloid(&base, &top, 0.5, 1.0, &material), &across), &[(4.5, 5.5)]);
+
+    // The waist of a hyperboloid splits a ray between its end caps, close to the surface.
+    let upwards = Ray::new(&point3(0.0, -1.0, 0.9), &vec3(0.0, 1.0, 0.0));
+    let spans = test_spans(&hyperboloid(&base, &top, 0.5, 1.0, &material), &upwards);
+    assert_eq!(spans.len(), 2);
+    assert!((spans[0].0 - 1.0).abs() < 1e-9 && (spans[1].1 - 2.0).abs() < 1e-9);
+
+    // Torus lying in the xz plane, crossed through both sides of its tube and through the hole.
+    let ring = torus(&base, &top, 1.0, 0.25, &material);
+    let through = Ray::new(&point3(0.0, 0.0, 5.0), &vec3(0.0, 0.0, -1.0));
+    assert_spans(&test_spans(&ring, &through), &[(3.75, 4.25), (5.75, 6.25)]);
+    assert_spans(&test_spans(&ring, &along), &[]);
+
+    let pill = capsule(&base, &top, 0.5, &material);
+    assert_spans(&test_spans(&pill, &along), &[(3.5, 5.5)]);
+    assert_spans(&test_spans(&pill, &across), &[(4.5, 5.5)]);
+
+    // Hits report the outward normal, and the bounding box encloses the shape.
+    let mut rec = HitRecord::default();
+    assert!(ring.hit(&through, interval(4.0, f64::INFINITY), &mut rec));
+    assert_eq!(rec.t, 4.25);
+    assert!(!rec.front_face);
+    assert!((rec.normal - vec3(0.0, 0.0, 1.0)).length() < 1e-9);
+    let bbox = cylinder(&base, &point3(1.0, 1.0, 0.0), 0.5, &material).bounding_box();
+    for p in [point3(0.0, 0.0, 0.5), point3(1.0, 1.0, -0.5), point3(-0.35, 0.35, 0.0)] {
+        assert!(bbox.x.contains(p.x()) && bbox.y.contains(p.y()) && bbox.z.contains(p.z()));
+    }
+}
+
+#[test]
+fn surface_derivatives_test() {
+    use crate::mesh::{mesh, MeshData};
+    use crate::sphere::sphere;
+
+    // Moving the hit point by a small step in u and v has to match the derivatives.
+    let material = crate::material::lambertian(vec3(0.5, 0.5, 0.5));
+    let base = point3(0.0, -0.5, 0.0);
+    let top = point3(0.2, 0.6, 0.1);
+    let triangle = MeshData {
+        positions: vec![point3(-1.0, -1.0, 0.0), point3(1.0, -0.8, 0.2), point3(0.0, 1.0, -0.1)],
+        uvs: vec![(0.1, 0.2), (0.9, 0.1), (0.4, 0.8)],
+        triangles: vec![[0, 1, 2]],
+        ..MeshData::default()
+    };
+    let objects: Vec<(&str, Box<dyn Hittable>)> = vec![
+        ("sphere", Box::new(sphere(&point3(0.0, 0.0, 0.0), 0.8, &material))),
+        ("cylinder", Box::new(cylinder(&base, &top, 0.7, &material))),
+        ("cone", Box::new(cone(&base, &top, 0.9, 0.3, &material))),
+        ("hyperboloid", Box::new(hyperboloid(&base, &top, 0.5, 0.8, &material))),
+        ("torus", Box::new(torus(&point3(0.0, 0.0, 0.0), &vec3(0.3, 1.0, 0.2), 0.6, 0.3, &material))),
+        ("capsule", Box::new(capsule(&base, &top, 0.4, &material))),
+        ("triangle", Box::new(mesh(triangle, &material))),
+    ];
+
+    let hit = |object: &dyn Hittable, target: &Vec3| {
+        let mut rec = HitRecord::default();
+        let r = Ray::new(&point3(0.1, 0.2, 5.0), &(*target - point3(0.1, 0.2, 5.0)));
+        assert!(object.hit(&r, interval(0.001, f64::INFINITY), &mut rec));
+        rec
+    };
+    for (name, object) in objects {
+        for target in [point3(0.45, 0.1, 0.0), point3(-0.25, -0.1, 0.0), point3(0.2, 0.0, 0.0)] {
+            let rec = hit(object.as_ref(), &target);
+            let (dpdu, dpdv) = (rec.dpdu, rec.dpdv);
+            assert!(!dpdu.near_zero() && !dpdv.near_zero(), "{}: no derivatives", name);
+
+            // A nearby hit, its offset predicted from the change of the surface coordinates.
+            let other = hit(object.as_ref(), &(rec.p + 1e-4 * unit_vector(&(dpdu + 0.5 * dpdv))));
+            let offset = other.p - rec.p;
+            let (du, dv) = (other.u - rec.u, other.v - rec.v);
+            let predicted = du * dpdu + dv * dpdv;
+            assert!(
+                (predicted - offset).length() < 1e-2 * offset.length(),
+                "{}: moved by {} for the derivatives {} and {}",
+                name,
+                offset,
+                dpdu,
+                dpdv
+            );
+        }
+    }
+}
@@ -1,18 +1,70 @@
-use rand::Rng;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha12Rng;
+use std::cell::RefCell;
 use std::f64::consts::PI;
 
+thread_local! {
+    // Every thread draws from its own generator, which can be reseeded for reproducible renders.
+    // This is the generator behind rand's StdRng, used directly as its state can be saved.
+    static RNG: RefCell<ChaCha12Rng> = RefCell::new(ChaCha12Rng::from_entropy());
+}
+
+/// Complete state of the random generator of a thread, to continue its sequence later.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RandomState {
+    pub seed: [u8; 32],
+    pub stream: u64,
+    pub word_pos: u128,
+}
+
 #[inline(always)]
 pub fn degrees_to_radians(degrees: f64) -> f64 {
     degrees * PI / 180.0
 }
 
+pub fn seed_random(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = ChaCha12Rng::seed_from_u64(seed));
+}
+
+pub fn random_state() -> RandomState {
+    RNG.with(|rng| {
+        let rng = rng.borrow();
+        RandomState {
+            seed: rng.get_seed(),
+            stream: rng.get_stream(),
+            word_pos: rng.get_word_pos(),
+        }
+    })
+}
+
+pub fn restore_random_state(state: &RandomState) {
+    RNG.with(|rng| {
+        let mut restored = ChaCha12Rng::from_seed(state.seed);
+        restored.set_stream(state.stream);
+        restored.set_word_pos(state.word_pos);
+        *rng.borrow_mut() = restored;
+    });
+}
+
 #[inline(always)]
 pub fn random_double() -> f64 {
-    let mut rng = rand::thread_rng();
-    rng.gen::<f64>()
+    RNG.with(|rng| rng.borrow_mut().gen::<f64>())
 }
 
 #[inline(always)]
 pub fn random_double_intv(min: f64, max: f64) -> f64 {
     min + (max - min) * random_double()
 }
+
+#[test]
+fn restore_random_state_test() {
+    seed_random(7);
+    random_double();
+    let state = random_state();
+    let expected: Vec<f64> = (0..5).map(|_| random_double()).collect();
+
+    seed_random(8);
+    restore_random_state(&state);
+    let continued: Vec<f64> = (0..5).map(|_| random_double()).collect();
+    assert_eq!(continued, expected);
+}
@@ -0,0 +1,464 @@
+use crate::aabb::{Aabb, AABB_UNIVERSE};
+use crate::hittable::{HitRecord, HitSpan, Hittable, SceneIndex};
+use crate::interval::Interval;
+use crate::ray::Ray;
+use crate::stats::{count_intersection_test, Primitive};
+use crate::vec3::*;
+use crate::Material;
+
+/// Signed distance field: negative inside the shape, positive outside. For sphere tracing the
+/// returned value must never overestimate the distance to the surface.
+pub trait Sdf {
+    fn distance(&self, p: &Vec3) -> f64;
+}
+
+// Primitives, all centered at the origin.
+
+pub struct SdfSphere {
+    radius: f64,
+}
+
+pub fn sdf_sphere(radius: f64) -> SdfSphere {
+    SdfSphere { radius }
+}
+
+impl Sdf for SdfSphere {
+    fn distance(&self, p: &Vec3) -> f64 {
+        p.length() - self.radius
+    }
+}
+
+pub struct RoundBox {
+    half_size: Vec3,
+    radius: f64,
+}
+
+pub fn round_box(half_size: Vec3, radius: f64) -> RoundBox {
+    // `half_size` is measured to the rounded surface, the edges are rounded by `radius`.
+    RoundBox { half_size, radius }
+}
+
+impl Sdf for RoundBox {
+    fn distance(&self, p: &Vec3) -> f64 {
+        let q = vec3(p.x().abs(), p.y().abs(), p.z().abs()) - self.half_size + self.radius;
+        let outside = vec3(q.x().max(0.0), q.y().max(0.0), q.z().max(0.0)).length();
+        let inside = q.x().max(q.y()).max(q.z()).min(0.0);
+        outside + inside - self.radius
+    }
+}
+
+pub struct Torus {
+    major_radius: f64,
+    minor_radius: f64,
+}
+
+pub fn torus(major_radius: f64, minor_radius: f64) -> Torus {
+    // Torus lying in the xz plane.
+    Torus {
+        major_radius,
+        minor_radius,
+    }
+}
+
+impl Sdf for Torus {
+    fn distance(&self, p: &Vec3) -> f64 {
+        let ring = (p.x() * p.x() + p.z() * p.z()).sqrt() - self.major_radius;
+        (ring * ring + p.y() * p.y()).sqrt() - self.minor_radius
+    }
+}
+
+pub struct Mandelbulb {
+    power: f64,
+    iterations: usize,
+}
+
+pub fn mandelbulb(power: f64, iterations: usize) -> Mandelbulb {
+    // The classic bulb has power 8 and fits into the unit sphere (about 1.2 with low powers).
+    Mandelbulb { power, iterations }
+}
+
+impl Sdf for Mandelbulb {
+    fn distance(&self, p: &Vec3) -> f64 {
+        // Distance estimator from the running derivative of the fractal iteration
+        // z -> z^power + p in spherical coordinates.
+        let mut z = *p;
+        let mut dr = 1.0;
+        let mut r = z.length();
+
+        for _ in 0..self.iterations {
+            if !(1e-12..=2.0).contains(&r) {
+                break;
+            }
+            let theta = (z.z() / r).acos() * self.power;
+            let phi = z.y().atan2(z.x()) * self.power;
+            dr = r.powf(self.power - 1.0) * self.power * dr + 1.0;
+
+            let zr = r.powf(self.power);
+            z = zr * vec3(theta.sin() * phi.cos(), phi.sin() * theta.sin(), theta.cos()) + *p;
+            r = z.length();
+        }
+
+        if r < 1e-12 {
+            return 0.0;
+        }
+        0.5 * r.ln() * r / dr
+    }
+}
+
+// Combinators
+
+pub struct Union {
+    a: Box<dyn Sdf>,
+    b: Box<dyn Sdf>,
+}
+
+pub fn union(a: Box<dyn Sdf>, b: Box<dyn Sdf>) -> Union {
+    Union { a, b }
+}
+
+impl Sdf for Union {
+    fn distance(&self, p: &Vec3) -> f64 {
+        self.a.distance(p).min(self.b.distance(p))
+    }
+}
+
+pub struct Intersection {
+    a: Box<dyn Sdf>,
+    b: Box<dyn Sdf>,
+}
+
+pub fn intersection(a: Box<dyn Sdf>, b: Box<dyn Sdf>) -> Intersection {
+    Intersection { a, b }
+}
+
+impl Sdf for Intersection {
+    fn distance(&self, p: &Vec3) -> f64 {
+        self.a.distance(p).max(self.b.distance(p))
+    }
+}
+
+pub struct Subtraction {
+    a: Box<dyn Sdf>,
+    b: Box<dyn Sdf>,
+}
+
+pub fn subtraction(a: Box<dyn Sdf>, b: Box<dyn Sdf>) -> Subtraction {
+    // Shape `a` with shape `b` carved out of it.
+    Subtraction { a, b }
+}
+
+impl Sdf for Subtraction {
+    fn distance(&self, p: &Vec3) -> f64 {
+        self.a.distance(p).max(-self.b.distance(p))
+    }
+}
+
+pub struct SmoothUnion {
+    a: Box<dyn Sdf>,
+    b: Box<dyn Sdf>,
+    k: f64,
+}
+
+pub fn smooth_union(a: Box<dyn Sdf>, b: Box<dyn Sdf>, k: f64) -> SmoothUnion {
+    // Blends both shapes together within a distance of about `k`.
+    SmoothUnion { a, b, k }
+}
+
+impl Sdf for SmoothUnion {
+    fn distance(&self, p: &Vec3) -> f64 {
+        // Polynomial smooth minimum.
+        let (d1, d2) = (self.a.distance(p), self.b.distance(p));
+        if self.k <= 0.0 {
+            return d1.min(d2);
+        }
+        let h = (0.5 + 0.5 * (d2 - d1) / self.k).clamp(0.0, 1.0);
+        d2 + (d1 - d2) * h - self.k * h * (1.0 - h)
+    }
+}
+
+pub struct Repetition {
+    shape: Box<dyn Sdf>,
+    period: Vec3,
+}
+
+pub fn repeat(shape: Box<dyn Sdf>, period: Vec3) -> Repetition {
+    // Infinite repetition of `shape` with the given spacing per axis, 0 disables an axis. The
+    // shape has to fit into one cell for the distance to stay correct.
+    Repetition { shape, period }
+}
+
+impl Sdf for Repetition {
+    fn distance(&self, p: &Vec3) -> f64 {
+        let wrap = |x: f64, period: f64| {
+            if period <= 0.0 {
+                x
+            } else {
+                x - period * (x / period).round()
+            }
+        };
+        let q = vec3(
+            wrap(p.x(), self.period.x()),
+            wrap(p.y(), self.period.y()),
+            wrap(p.z(), self.period.z()),
+        );
+        self.shape.distance(&q)
+    }
+}
+
+pub struct Twist {
+    shape: Box<dyn Sdf>,
+    rate: f64,
+}
+
+pub fn twist(shape: Box<dyn Sdf>, rate: f64) -> Twist {
+    // Rotates the shape around the y axis by `rate` radians per unit of height. This bends
+    // the distance field, so sphere tracing needs a step scale below one (see `SdfObject`).
+    Twist { shape, rate }
+}
+
+impl Sdf for Twist {
+    fn distance(&self, p: &Vec3) -> f64 {
+        let angle = self.rate * p.y();
+        let (s, c) = angle.sin_cos();
+        let q = vec3(c * p.x() - s * p.z(), p.y(), s * p.x() + c * p.z());
+        self.shape.distance(&q)
+    }
+}
+
+pub struct Translation {
+    shape: Box<dyn Sdf>,
+    offset: Vec3,
+}
+
+pub fn translate(shape: Box<dyn Sdf>, offset: Vec3) -> Translation {
+    Translation { shape, offset }
+}
+
+impl Sdf for Translation {
+    fn distance(&self, p: &Vec3) -> f64 {
+        self.shape.distance(&(*p - self.offset))
+    }
+}
+
+// Hittable
+
+const MAX_STEPS: usize = 512;
+const HIT_EPSILON: f64 = 1e-5;
+const NORMAL_EPSILON: f64 = 1e-5;
+const CROSSING_STEP: f64 = 1e-4;
+
+pub fn sdf_object(sdf: Box<dyn Sdf>, material: &Material) -> SdfObject {
+    SdfObject::new(sdf, material)
+}
+
+/// Renders a signed distance field by sphere tracing.
+pub struct SdfObject {
+    sdf: Box<dyn Sdf>,
+    mat: Material,
+    step_scale: f64,
+    max_distance: f64,
+    bounds: Aabb,
+}
+
+impl SdfObject {
+    pub fn new(sdf: Box<dyn Sdf>, material: &Material) -> SdfObject {
+        SdfObject {
+            sdf,
+            mat: material.clone(),
+            step_scale: 1.0,
+            max_distance: 1000.0,
+            bounds: AABB_UNIVERSE,
+        }
+    }
+
+    pub fn step_scale(mut self, step_scale: f64) -> SdfObject {
+        // Fraction of the distance bound used per step, needed for fields that overestimate
+        // the distance like twisted shapes and fractal estimators.
+        self.step_scale = step_scale;
+        self
+    }
+
+    pub fn max_distance(mut self, max_distance: f64) -> SdfObject {
+        // Marching stops after this distance if the ray interval doesn't end earlier.
+        self.max_distance = max_distance;
+        self
+    }
+
+    pub fn bounds(mut self, bounds: Aabb) -> SdfObject {
+        // Box around the surface. Fields in general can't be bounded automatically, so it
+        // defaults to all of space.
+        self.bounds = bounds;
+        self
+    }
+
+    fn normal(&self, p: &Vec3) -> Vec3 {
+        // Gradient of the field from four samples on a tetrahedron.
+        let h = NORMAL_EPSILON;
+        let k0 = vec3(1.0, -1.0, -1.0);
+        let k1 = vec3(-1.0, -1.0, 1.0);
+        let k2 = vec3(-1.0, 1.0, -1.0);
+        let k3 = vec3(1.0, 1.0, 1.0);
+        let gradient = k0 * self.sdf.distance(&(*p + h * k0))
+            + k1 * self.sdf.distance(&(*p + h * k1))
+            + k2 * self.sdf.distance(&(*p + h * k2))
+            + k3 * self.sdf.distance(&(*p + h * k3));
+        unit_vector(&gradient)
+    }
+
+    fn hit_record(&self, r: &Ray, t: f64, p: &Vec3) -> HitRecord {
+        let mut rec = HitRecord {
+            t,
+            p: *p,
+            mat: self.mat.clone(),
+            ..HitRecord::default()
+        };
+        let outward_normal = self.normal(p);
+        rec.set_face_normal(r, &outward_normal);
+        rec
+    }
+}
+
+impl Hittable for SdfObject {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        count_intersection_test(Primitive::Sdf);
+        let direction_length = r.direction().length();
+        let direction = r.direction() / direction_length;
+
+        // March in units of distance, the interval is in units of the ray parameter.
+        let mut t = ray_t.min * direction_length;
+        let t_max = (ray_t.max * direction_length).min(self.max_distance);
+
+        // Rays starting inside the shape march on the negated field to find the exit.
+        let side = if self.sdf.distance(&(r.origin() + t * direction)) < 0.0 { -1.0 } else { 1.0 };
+
+        for _ in 0..MAX_STEPS {
+            if t > t_max {
+                return false;
+            }
+
+            let p = r.origin() + t * direction;
+            let d = side * self.sdf.distance(&p);
+            if d < HIT_EPSILON {
+                if !ray_t.surrounds(t / direction_length) {
+                    return false;
+                }
+                *rec = self.hit_record(r, t / direction_length, &p);
+                return true;
+            }
+
+            t += d * self.step_scale;
+        }
+
+        false
+    }
+
+    fn hit_spans(&self, r: &Ray, ray_t: Interval) -> Option<Vec<HitSpan>> {
+        // Marches on through the whole interval and records every surface crossing. A span the
+        // ray is already inside of at the start begins at minus infinity, one it doesn't leave
+        // until the end of the interval ends at infinity.
+        count_intersection_test(Primitive::Sdf);
+        let direction_length = r.direction().length();
+        let direction = r.direction() / direction_length;
+
+        let mut t = ray_t.min * direction_length;
+        let t_max = (ray_t.max * direction_length).min(self.max_distance);
+
+        let unbounded = |t: f64| HitRecord {
+            t,
+            mat: self.mat.clone(),
+            ..HitRecord::default()
+        };
+
+        let mut side = if self.sdf.distance(&(r.origin() + t * direction)) < 0.0 { -1.0 } else { 1.0 };
+        let mut enter = if side < 0.0 { Some(unbounded(f64::NEG_INFINITY)) } else { None };
+        let mut spans = Vec::new();
+
+        for _ in 0..MAX_STEPS {
+            if t > t_max {
+                break;
+            }
+
+            let p = r.origin() + t * direction;
+            let d = side * self.sdf.distance(&p);
+            if d < HIT_EPSILON {
+                let rec = self.hit_record(r, t / direction_length, &p);
+                match enter.take() {
+                    Some(enter) => spans.push(HitSpan { enter, exit: rec }),
+                    None => enter = Some(rec),
+                }
+
+                // Step across the surface before marching on from the other side.
+                side = -side;
+                t += CROSSING_STEP;
+                continue;
+            }
+
+            t += d * self.step_scale;
+        }
+
+        if let Some(enter) = enter {
+            spans.push(HitSpan {
+                enter,
+                exit: unbounded(f64::INFINITY),
+            });
+        }
+        Some(spans)
+    }
+
+    fn index_scene(&mut self, scene: &mut SceneIndex) {
+        scene.add_material(&self.mat);
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bounds
+    }
+}
+
+#[test]
+fn sdf_sphere_hit_test() {
+    use crate::hittable::HitRecord;
+    use crate::interval::interval;
+    use crate::material::lambertian;
+
+    let shape = translate(Box::new(sdf_sphere(1.0)), point3(0.0, 0.0, -3.0));
+    let object = sdf_object(Box::new(shape), &lambertian(vec3(0.5, 0.5, 0.5)));
+    let mut rec = HitRecord::default();
+
+    let r = Ray::new(&point3(0.0, 0.0, 0.0), &vec3(0.0, 0.0, -2.0));
+    assert!(object.hit(&r, interval(0.001, f64::INFINITY), &mut rec));
+    assert!((rec.t - 1.0).abs() < 1e-4);
+    assert!((rec.normal - vec3(0.0, 0.0, 1.0)).length() < 1e-4);
+    assert!(rec.front_face);
+
+    // Leaving the sphere from inside.
+    let r = Ray::new(&point3(0.0, 0.0, -3.0), &vec3(1.0, 0.0, 0.0));
+    assert!(object.hit(&r, interval(0.001, f64::INFINITY), &mut rec));
+    assert!((rec.t - 1.0).abs() < 1e-4);
+    assert!(!rec.front_face);
+
+    // Missing it, and hitting it outside of the interval.
+    let r = Ray::new(&point3(0.0, 2.0, 0.0), &vec3(0.0, 0.0, -1.0));
+    assert!(!object.hit(&r, interval(0.001, f64::INFINITY), &mut rec));
+    let r = Ray::new(&point3(0.0, 0.0, 0.0), &vec3(0.0, 0.0, -1.0));
+    assert!(!object.hit(&r, interval(0.001, 1.5), &mut rec));
+}
+
+#[test]
+fn sdf_combinator_test() {
+    let a = || Box::new(sdf_sphere(1.0));
+    let b = || Box::new(translate(Box::new(sdf_sphere(1.0)), point3(1.5, 0.0, 0.0)));
+    let p = point3(0.75, 0.0, 0.0);
+
+    assert!(union(a(), b()).distance(&p) < 0.0);
+    assert!(intersection(a(), b()).distance(&p) < 0.0);
+    assert!(subtraction(a(), b()).distance(&p) > 0.0);
+    assert!(smooth_union(a(), b(), 0.5).distance(&p) < union(a(), b()).distance(&p));
+
+    let ring = point3(2.0, 0.0, 0.0);
+    assert!((torus(2.0, 0.5).distance(&ring) + 0.5).abs() < 1e-12);
+    assert!((twist(Box::new(torus(2.0, 0.5)), 1.0).distance(&ring) + 0.5).abs() < 1e-12);
+    assert!((round_box(vec3(1.0, 1.0, 1.0), 0.1).distance(&ring) - 1.0).abs() < 1e-12);
+    assert!((repeat(a(), vec3(4.0, 0.0, 0.0)).distance(&point3(8.0, 0.0, 0.0)) + 1.0).abs() < 1e-12);
+    assert!(mandelbulb(8.0, 10).distance(&point3(0.0, 0.0, 0.0)) <= 0.0);
+    assert!(mandelbulb(8.0, 10).distance(&point3(0.0, 3.0, 0.0)) > 0.0);
+}
@@ -0,0 +1,232 @@
+use std::f64::consts::PI;
+
+use crate::color::color;
+use crate::light::Light;
+use crate::spectrum::xyz_to_linear_srgb;
+use crate::vec3::*;
+
+// Angular radius of the sun disk seen from the earth.
+const SUN_ANGULAR_RADIUS: f64 = 0.004654;
+// Luminance of the sun outside of the atmosphere in kcd/m^2.
+const SUN_LUMINANCE: f64 = 1.6e6;
+// Radiance per kcd/m^2 of luminance, which makes a white surface lit by the sun high in the sky
+// about 1.
+const LUMINANCE_SCALE: f64 = 0.03;
+// Wavelengths in micrometers the sun's transmittance through the atmosphere is computed for,
+// one per color channel.
+const CHANNEL_WAVELENGTHS: [f64; 3] = [0.68, 0.55, 0.44];
+
+/// Analytic daylight sky after Preetham, Shirley and Smits, "A Practical Analytic Model for
+/// Daylight" (1999), with the sun disk as light source. The turbidity describes the haze, from
+/// 2 for a very clear sky to about 10 for a hazy one. Below the horizon the ground reflects the
+/// light of sun and sky diffusely with the ground albedo.
+#[derive(Debug, Clone)]
+pub struct Sky {
+    pub sun_direction: Vec3,
+    pub turbidity: f64,
+    pub ground_albedo: Vec3,
+    perez: [[f64; 5]; 3], // Distribution coefficients of the luminance Y and the chromaticity x, y
+    zenith: [f64; 3],     // Y, x and y in the zenith
+    sun_radiance: Vec3,
+    ground_radiance: Vec3,
+}
+
+pub fn sun_direction(elevation: f64, azimuth: f64) -> Vec3 {
+    // Direction towards the sun for angles in degrees: the elevation above the horizon and the
+    // azimuth from -z (straight ahead of the default camera) towards +x.
+    let (elevation, azimuth) = (elevation.to_radians(), azimuth.to_radians());
+    vec3(elevation.cos() * azimuth.sin(), elevation.sin(), -elevation.cos() * azimuth.cos())
+}
+
+impl Sky {
+    pub fn new(sun_direction: Vec3, turbidity: f64, ground_albedo: Vec3) -> Sky {
+        let t = turbidity;
+        let sun_direction = unit_vector(&sun_direction);
+        // The model only covers the sun above the horizon.
+        let theta_s = sun_direction.y().clamp(0.0, 1.0).acos().min(PI / 2.0 - 1e-3);
+
+        let perez = [
+            [0.1787 * t - 1.4630, -0.3554 * t + 0.4275, -0.0227 * t + 5.3251, 0.1206 * t - 2.5771, -0.0670 * t + 0.3703],
+            [-0.0193 * t - 0.2592, -0.0665 * t + 0.0008, -0.0004 * t + 0.2125, -0.0641 * t - 0.8989, -0.0033 * t + 0.0452],
+            [-0.0167 * t - 0.2608, -0.0950 * t + 0.0092, -0.0079 * t + 0.2102, -0.0441 * t - 1.6537, -0.0109 * t + 0.0529],
+        ];
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let zenith_luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let chromaticity = |m: [[f64; 4]; 3]| {
+            let [t2, t1, t0] = m.map(|c| c[0] * theta_s.powi(3) + c[1] * theta_s.powi(2) + c[2] * theta_s + c[3]);
+            t * t * t2 + t * t1 + t0
+        };
+        let zenith_x = chromaticity([
+            [0.00166, -0.00375, 0.00209, 0.0],
+            [-0.02903, 0.06377, -0.03202, 0.00394],
+            [0.11693, -0.21196, 0.06052, 0.25886],
+        ]);
+        let zenith_y = chromaticity([
+            [0.00275, -0.00610, 0.00317, 0.0],
+            [-0.04214, 0.08970, -0.04153, 0.00516],
+            [0.15346, -0.26756, 0.06670, 0.26688],
+        ]);
+
+        // Once the sun has set no sunlight is left to reach through the atmosphere.
+        let sun_radiance = if sun_direction.y() < -SUN_ANGULAR_RADIUS {
+            Vec3::default()
+        } else {
+            sun_transmittance(sun_direction.y(), turbidity) * SUN_LUMINANCE * LUMINANCE_SCALE
+        };
+        let mut sky = Sky {
+            sun_direction,
+            turbidity,
+            ground_albedo,
+            perez,
+            zenith: [zenith_luminance, zenith_x, zenith_y],
+            sun_radiance,
+            ground_radiance: Vec3::default(),
+        };
+
+        // Irradiance on the ground from the sun disk and the sky, integrated numerically.
+        let (thetas, phis) = (32, 64);
+        let mut irradiance = sky.sun_radiance * sky.sun_solid_angle() * sun_direction.y().max(0.0);
+        for i in 0..thetas {
+            let cos_theta = (i as f64 + 0.5) / thetas as f64;
+            for k in 0..phis {
+                let direction = direction_around(&vec3(0.0, 1.0, 0.0), cos_theta, 2.0 * PI * k as f64 / phis as f64);
+                irradiance = irradiance + sky.radiance(&direction) * cos_theta * (2.0 * PI / (thetas * phis) as f64);
+            }
+        }
+        sky.ground_radiance = ground_albedo * irradiance / PI;
+        sky
+    }
+
+    pub fn radiance(&self, direction: &Vec3) -> Vec3 {
+        // Radiance of the sky (without the sun disk) or of the ground seen in `direction`.
+        let direction = unit_vector(direction);
+        if direction.y() <= 0.0 {
+            return self.ground_radiance;
+        }
+        let cos_theta = direction.y().max(1e-3);
+        let cos_gamma = dot(&direction, &self.sun_direction).clamp(-1.0, 1.0);
+        let cos_theta_s = self.sun_direction.y().max(1e-3);
+
+        let [luminance, x, y] = [0, 1, 2].map(|i| {
+            let distribution = |cos_theta: f64, cos_gamma: f64| {
+                let [a, b, c, d, e] = self.perez[i];
+                (1.0 + a * (b / cos_theta).exp()) * (1.0 + c * (d * cos_gamma.acos()).exp() + e * cos_gamma * cos_gamma)
+            };
+            self.zenith[i] * distribution(cos_theta, cos_gamma) / distribution(1.0, cos_theta_s)
+        });
+
+        let luminance = luminance.max(0.0) * LUMINANCE_SCALE;
+        let xyz = vec3(x / y * luminance, luminance, (1.0 - x - y) / y * luminance);
+        let rgb = xyz_to_linear_srgb(&xyz);
+        color(rgb.x().max(0.0), rgb.y().max(0.0), rgb.z().max(0.0))
+    }
+
+    pub fn sun_light(&self) -> Option<Light> {
+        // The sun disk as light, none once it has set.
+        if self.sun_direction.y() < -SUN_ANGULAR_RADIUS {
+            return None;
+        }
+        Some(Light::DistantDisk {
+            direction: self.sun_direction,
+            cos_max: SUN_ANGULAR_RADIUS.cos(),
+            radiance: self.sun_radiance,
+        })
+    }
+
+    fn sun_solid_angle(&self) -> f64 {
+        2.0 * PI * (1.0 - SUN_ANGULAR_RADIUS.cos())
+    }
+}
+
+fn sun_transmittance(cos_theta: f64, turbidity: f64) -> Vec3 {
+    // Fraction of the sunlight getting through the atmosphere along the relative optical air
+    // mass of Kasten and Young, attenuated by Rayleigh scattering and by aerosols after
+    // Angstrom's formula with the haze from the turbidity (Preetham et al., appendix). The air
+    // mass formula only holds down to about 6 degrees below the horizon, a sun on or below the
+    // horizon is taken to be on it.
+    let theta = cos_theta.clamp(0.0, 1.0).acos();
+    let air_mass = 1.0 / (cos_theta.max(0.0) + 0.50572 * (96.07995 - theta.to_degrees()).powf(-1.6364));
+    let beta = 0.04608 * turbidity - 0.04586;
+    let [r, g, b] = CHANNEL_WAVELENGTHS.map(|lambda| {
+        let rayleigh = 0.008735 * lambda.powf(-4.08);
+        let aerosol = beta * lambda.powf(-1.3);
+        (-air_mass * (rayleigh + aerosol)).exp()
+    });
+    color(r, g, b)
+}
+
+#[test]
+fn sky_test() {
+    use crate::color::luminance;
+
+    // A clear sky is blue and brightest around the sun, the sun gets redder towards the horizon
+    // and the ground reflects the light of both.
+    let sky = Sky::new(sun_direction(40.0, 30.0), 3.0, color(0.3, 0.3, 0.3));
+    let zenith = sky.radiance(&vec3(0.0, 1.0, 0.0));
+    assert!(zenith.z() > zenith.y() && zenith.y() > zenith.x() && zenith.x() > 0.0);
+    let near_sun = sky.radiance(&direction_around(&sky.sun_direction, 0.99, 0.0));
+    let opposite = sky.radiance(&vec3(-sky.sun_direction.x(), 0.5, -sky.sun_direction.z()));
+    assert!(luminance(&near_sun) > 2.0 * luminance(&opposite));
+
+    let low = Sky::new(sun_direction(3.0, 30.0), 3.0, color(0.3, 0.3, 0.3));
+    let (Some(Light::DistantDisk { radiance: high_sun, .. }), Some(Light::DistantDisk { radiance: low_sun, .. })) =
+        (sky.sun_light(), low.sun_light())
+    else {
+        panic!("the sun is above the horizon");
+    };
+    assert!(high_sun.x() / high_sun.z() < low_sun.x() / low_sun.z());
+    assert!(luminance(&high_sun) > luminance(&low_sun));
+    assert!(Sky::new(sun_direction(-5.0, 0.0), 3.0, color(0.3, 0.3, 0.3)).sun_light().is_none());
+
+    // Well after sunset there's no sunlight left, and sky and ground stay finite.
+    let night = Sky::new(sun_direction(-10.0, 0.0), 3.0, color(0.3, 0.3, 0.3));
+    assert_eq!(night.sun_radiance, Vec3::default());
+    for direction in [vec3(0.0, 1.0, 0.0), vec3(0.5, 0.1, 0.2), vec3(0.0, -1.0, 0.0)] {
+        let radiance = night.radiance(&direction);
+        assert!((0..3).all(|c| radiance[c].is_finite() && radiance[c] >= 0.0), "{:?}", radiance);
+    }
+
+    // A white surface lit by the high sun and the sky is about as bright as the white point.
+    let white = Sky::new(sun_direction(60.0, 0.0), 3.0, color(1.0, 1.0, 1.0));
+    let ground = luminance(&white.radiance(&vec3(0.0, -1.0, 0.0)));
+    assert!(ground > 0.5 && ground < 2.0, "{}", ground);
+    assert_eq!(sky.radiance(&vec3(0.3, -0.2, 0.1)), sky.ground_radiance);
+}
+
+#[test]
+fn sky_lighting_test() {
+    use crate::camera::Camera;
+    use crate::hittable::Hittable;
+    use crate::hittable_list::HittableList;
+    use crate::material::lambertian;
+    use crate::sphere::sphere;
+
+    // Ground seen from above reflects what the sky model assumes for its ground: sun and sky
+    // light found by sampling the sun and by scattering add up without counting the sun twice.
+    let objects: Vec<Box<dyn Hittable>> = vec![Box::new(sphere(
+        &point3(0.0, -1000.0, 0.0),
+        1000.0,
+        &lambertian(color(0.5, 0.5, 0.5)),
+    ))];
+    let world = HittableList::new(objects);
+    let sky = Sky::new(sun_direction(35.0, 60.0), 4.0, color(0.5, 0.5, 0.5));
+
+    let mut cam = Camera::default();
+    cam.image_width = 8;
+    cam.samples_per_pixel = 256;
+    cam.max_depth = 2;
+    cam.seed = Some(3);
+    cam.vfov = 20.0;
+    cam.look_from = point3(0.0, 1.0, 0.0);
+    cam.look_at = point3(0.0, 0.0, 0.0);
+    cam.v_up = vec3(0.0, 0.0, -1.0);
+    cam.sky = Some(sky.clone());
+    let image = cam.render_buffers(&world).beauty;
+
+    let mean = image.pixels.iter().fold(Vec3::default(), |sum, p| sum + *p) / image.pixels.len() as f64;
+    for channel in 0..3 {
+        let expected = sky.ground_radiance[channel];
+        assert!((mean[channel] - expected).abs() < 0.02 * expected, "{:?} instead of {:?}", mean, sky.ground_radiance);
+    }
+}
@@ -0,0 +1,215 @@
+use std::sync::OnceLock;
+
+use crate::color::color;
+use crate::rtweekend::random_double;
+use crate::vec3::*;
+
+// Spectral rendering. Paths carry radiance at a few wavelengths instead of RGB, which gets
+// converted to CIE XYZ and then to linear sRGB for the film.
+
+pub const LAMBDA_MIN: f64 = 380.0;
+pub const LAMBDA_MAX: f64 = 780.0;
+
+/// Number of wavelengths traced together along a path.
+pub const WAVELENGTHS: usize = 4;
+
+/// Values at the sampled wavelengths.
+pub type Spectrum = [f64; WAVELENGTHS];
+
+/// Hero wavelength sampling: the first (hero) wavelength is uniformly distributed, the others
+/// follow at equal distances, wrapping around the visible range. All of them share the path
+/// of the hero wavelength until a wavelength dependent event (dispersion) terminates them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SampledWavelengths {
+    pub lambda: Spectrum,
+}
+
+impl SampledWavelengths {
+    pub fn sample() -> SampledWavelengths {
+        SampledWavelengths::from_hero(LAMBDA_MIN + random_double() * (LAMBDA_MAX - LAMBDA_MIN))
+    }
+
+    pub fn from_hero(hero: f64) -> SampledWavelengths {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let mut lambda = [hero; WAVELENGTHS];
+        for (i, l) in lambda.iter_mut().enumerate().skip(1) {
+            *l = LAMBDA_MIN + (hero - LAMBDA_MIN + i as f64 * range / WAVELENGTHS as f64) % range;
+        }
+        SampledWavelengths { lambda }
+    }
+
+    pub fn hero(&self) -> f64 {
+        self.lambda[0]
+    }
+
+    pub fn upsample(&self, rgb: &Vec3) -> Spectrum {
+        self.lambda.map(|l| rgb_to_spectrum(rgb, l))
+    }
+
+    pub fn to_rgb(&self, values: &Spectrum) -> Vec3 {
+        // Monte Carlo estimate of the XYZ integrals with the uniform wavelength density,
+        // normalized so that a constant spectrum of 1 gives the sRGB white (1, 1, 1).
+        let table = film_table();
+        let pdf = 1.0 / (LAMBDA_MAX - LAMBDA_MIN);
+        let mut xyz = Vec3::default();
+        for (l, value) in self.lambda.iter().zip(values) {
+            xyz = xyz + *value * cie_xyz(*l);
+        }
+        xyz = xyz / (WAVELENGTHS as f64 * pdf * table.y_integral);
+        xyz_to_linear_srgb(&xyz) / table.white
+    }
+}
+
+struct FilmTable {
+    y_integral: f64, // Integral of the y matching function over the sampled range
+    white: Vec3,     // sRGB of the constant spectrum before white balancing
+}
+
+fn film_table() -> &'static FilmTable {
+    static TABLE: OnceLock<FilmTable> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        let steps = 4000;
+        let dl = (LAMBDA_MAX - LAMBDA_MIN) / steps as f64;
+        let mut integral = Vec3::default();
+        for i in 0..steps {
+            integral = integral + cie_xyz(LAMBDA_MIN + (i as f64 + 0.5) * dl) * dl;
+        }
+        FilmTable {
+            y_integral: integral.y(),
+            white: xyz_to_linear_srgb(&(integral / integral.y())),
+        }
+    })
+}
+
+pub fn cie_xyz(lambda: f64) -> Vec3 {
+    // CIE 1931 2 degree color matching functions, as the multi-lobe piecewise Gaussian fit of
+    // Wyman, Sloan and Shirley, "Simple Analytic Approximations to the CIE XYZ Color Matching
+    // Functions" (2013).
+    let g = |mu: f64, sigma_low: f64, sigma_high: f64| {
+        let t = (lambda - mu) / if lambda < mu { sigma_low } else { sigma_high };
+        (-0.5 * t * t).exp()
+    };
+    let x = 1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2);
+    let y = 0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1);
+    let z = 1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8);
+    vec3(x, y, z)
+}
+
+pub fn xyz_to_linear_srgb(xyz: &Vec3) -> Vec3 {
+    color(
+        3.2404542 * xyz.x() - 1.5371385 * xyz.y() - 0.4985314 * xyz.z(),
+        -0.9692660 * xyz.x() + 1.8760108 * xyz.y() + 0.0415560 * xyz.z(),
+        0.0556434 * xyz.x() - 0.2040259 * xyz.y() + 1.0572252 * xyz.z(),
+    )
+}
+
+// Basis spectra of Smits, "An RGB to Spectrum Conversion for Reflectances" (1999), in ten bins
+// of equal width from 380 to 720 nm.
+const SMITS_BINS: usize = 10;
+const SMITS_MAX: f64 = 720.0;
+const SMITS_WHITE: [f64; SMITS_BINS] = [1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000];
+const SMITS_CYAN: [f64; SMITS_BINS] = [0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000];
+const SMITS_MAGENTA: [f64; SMITS_BINS] = [1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959];
+const SMITS_YELLOW: [f64; SMITS_BINS] = [0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840];
+const SMITS_RED: [f64; SMITS_BINS] = [0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149];
+const SMITS_GREEN: [f64; SMITS_BINS] = [0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025];
+const SMITS_BLUE: [f64; SMITS_BINS] = [1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496];
+
+pub fn rgb_to_spectrum(rgb: &Vec3, lambda: f64) -> f64 {
+    // Smits' upsampling: the smallest component as white, the difference between the two
+    // others and the smallest as the secondary color they make up, and the rest as the
+    // largest primary. Beyond 720 nm the last bin continues.
+    let bin = (((lambda - LAMBDA_MIN) / (SMITS_MAX - LAMBDA_MIN) * SMITS_BINS as f64) as usize).min(SMITS_BINS - 1);
+    let (r, g, b) = (rgb.x(), rgb.y(), rgb.z());
+
+    if r <= g && r <= b {
+        let rest = if g <= b {
+            (g - r) * SMITS_CYAN[bin] + (b - g) * SMITS_BLUE[bin]
+        } else {
+            (b - r) * SMITS_CYAN[bin] + (g - b) * SMITS_GREEN[bin]
+        };
+        r * SMITS_WHITE[bin] + rest
+    } else if g <= r && g <= b {
+        let rest = if r <= b {
+            (r - g) * SMITS_MAGENTA[bin] + (b - r) * SMITS_BLUE[bin]
+        } else {
+            (b - g) * SMITS_MAGENTA[bin] + (r - b) * SMITS_RED[bin]
+        };
+        g * SMITS_WHITE[bin] + rest
+    } else {
+        let rest = if r <= g {
+            (r - b) * SMITS_YELLOW[bin] + (g - r) * SMITS_GREEN[bin]
+        } else {
+            (g - b) * SMITS_YELLOW[bin] + (r - g) * SMITS_RED[bin]
+        };
+        b * SMITS_WHITE[bin] + rest
+    }
+}
+
+/// Wavelength dependent refractive index of a dielectric.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dispersion {
+    Cauchy { a: f64, b: f64 },                  // n = a + b / lambda^2, lambda in micrometers
+    Sellmeier { b: [f64; 3], c: [f64; 3] },     // n^2 = 1 + sum b lambda^2 / (lambda^2 - c)
+}
+
+/// Borosilicate crown glass, the common optical glass of lenses and prisms.
+pub const BK7: Dispersion = Dispersion::Sellmeier {
+    b: [1.03961212, 0.231792344, 1.01046945],
+    c: [0.00600069867, 0.0200179144, 103.560653],
+};
+
+/// Dense flint glass (SF11), with a much stronger dispersion.
+pub const DENSE_FLINT: Dispersion = Dispersion::Sellmeier {
+    b: [1.73759695, 0.313747346, 1.89878101],
+    c: [0.013188707, 0.0623068142, 155.23629],
+};
+
+/// Wavelength the refractive index of glass is usually given for, the sodium D line.
+pub const SODIUM_D: f64 = 589.3;
+
+impl Dispersion {
+    pub fn refraction_index(&self, lambda: f64) -> f64 {
+        let micrometers = lambda / 1000.0;
+        let l2 = micrometers * micrometers;
+        match self {
+            Dispersion::Cauchy { a, b } => a + b / l2,
+            Dispersion::Sellmeier { b, c } => {
+                let sum: f64 = b.iter().zip(c).map(|(b, c)| b * l2 / (l2 - c)).sum();
+                (1.0 + sum).sqrt()
+            }
+        }
+    }
+}
+
+#[test]
+fn spectrum_test() {
+    // Hero wavelengths are spread evenly over the range.
+    let wavelengths = SampledWavelengths::from_hero(700.0);
+    assert_eq!(wavelengths.lambda, [700.0, 400.0, 500.0, 600.0]);
+
+    // Grey stays grey, a constant spectrum is the white point.
+    for hero in [380.0, 451.3, 633.0] {
+        let wavelengths = SampledWavelengths::from_hero(hero);
+        let grey = wavelengths.upsample(&color(0.5, 0.5, 0.5));
+        assert!(grey.iter().all(|v| (v - 0.5).abs() < 1e-3));
+    }
+    let samples = 20000;
+    let mut sum = Vec3::default();
+    let mut red = Vec3::default();
+    for i in 0..samples {
+        let hero = LAMBDA_MIN + (i as f64 + 0.5) / samples as f64 * (LAMBDA_MAX - LAMBDA_MIN);
+        let wavelengths = SampledWavelengths::from_hero(hero);
+        sum = sum + wavelengths.to_rgb(&[1.0; WAVELENGTHS]);
+        red = red + wavelengths.to_rgb(&wavelengths.upsample(&color(0.8, 0.1, 0.1)));
+    }
+    assert!((sum / samples as f64 - color(1.0, 1.0, 1.0)).length() < 1e-3);
+    let red = red / samples as f64;
+    assert!(red.x() > 0.6 && red.y() < 0.2 && red.z() < 0.2);
+
+    // Catalog values of BK7 at the sodium D line and normal dispersion: blue bends more.
+    assert!((BK7.refraction_index(SODIUM_D) - 1.5168).abs() < 1e-4);
+    assert!(DENSE_FLINT.refraction_index(450.0) > DENSE_FLINT.refraction_index(650.0));
+    let cauchy = Dispersion::Cauchy { a: 1.5, b: 0.004 };
+    assert!((cauchy.refraction_index(500.0) - 1.516).abs() < 1e-12);
+}
@@ -1,7 +1,10 @@
+use crate::aabb::Aabb;
 use crate::hittable::HitRecord;
-use crate::hittable::Hittable;
+use crate::hittable::{HitSpan, Hittable, SceneIndex};
 use crate::interval::Interval;
+use crate::light::Shape;
 use crate::ray::Ray;
+use crate::stats::{count_intersection_test, Primitive};
 use crate::vec3::*;
 use crate::Material;
 use crate::Vec3;
@@ -14,20 +17,23 @@ pub struct Sphere {
     center: Vec3,
     radius: f64,
     mat: Material,
+    light: Option<usize>, // Index of the area light in the scene for glowing spheres
 }
 
 impl Sphere {
     pub fn new(center: &Vec3, radius: f64, material: &Material) -> Sphere {
         Sphere {
-            center: center.clone(),
-            radius: radius,
+            center: *center,
+            radius,
             mat: material.clone(),
+            light: None,
         }
     }
 }
 
 impl Hittable for Sphere {
     fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        count_intersection_test(Primitive::Sphere);
         let oc = self.center - r.origin();
         let a = r.direction().length_squared();
         let h = dot(&r.direction(), &oc);
@@ -53,8 +59,79 @@ impl Hittable for Sphere {
         rec.p = r.at(rec.t);
         let outward_normal: Vec3 = (rec.p - self.center) / self.radius;
         rec.set_face_normal(r, &outward_normal);
-        rec.mat = self.mat;
+        (rec.u, rec.v) = sphere_uv(&outward_normal);
+        (rec.dpdu, rec.dpdv) = sphere_derivatives(&outward_normal, self.radius);
+        rec.mat = self.mat.clone();
+        rec.light = self.light;
 
-        return true;
+        true
     }
+
+    fn hit_spans(&self, r: &Ray, _ray_t: Interval) -> Option<Vec<HitSpan>> {
+        // Both roots of the whole line through the sphere, independent of the interval.
+        count_intersection_test(Primitive::Sphere);
+        let oc = self.center - r.origin();
+        let a = r.direction().length_squared();
+        let h = dot(&r.direction(), &oc);
+        let c = oc.length_squared() - self.radius * self.radius;
+
+        let discriminant = h * h - a * c;
+        if discriminant <= 0.0 {
+            return Some(Vec::new());
+        }
+
+        let sqrtd = discriminant.sqrt();
+        let mut span = HitSpan::default();
+        for (rec, root) in [(&mut span.enter, (h - sqrtd) / a), (&mut span.exit, (h + sqrtd) / a)] {
+            rec.t = root;
+            rec.p = r.at(root);
+            let outward_normal = (rec.p - self.center) / self.radius;
+            rec.set_face_normal(r, &outward_normal);
+            (rec.u, rec.v) = sphere_uv(&outward_normal);
+            (rec.dpdu, rec.dpdv) = sphere_derivatives(&outward_normal, self.radius);
+            rec.mat = self.mat.clone();
+        }
+
+        Some(vec![span])
+    }
+
+    fn index_scene(&mut self, scene: &mut SceneIndex) {
+        scene.add_material(&self.mat);
+        let shape = Shape::Sphere {
+            center: self.center,
+            radius: self.radius,
+        };
+        self.light = scene.add_area_light(&self.mat, shape);
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let rvec = vec3(self.radius, self.radius, self.radius);
+        Aabb::from_points(&(self.center - rvec), &(self.center + rvec))
+    }
+}
+
+pub(crate) fn sphere_uv(p: &Vec3) -> (f64, f64) {
+    // p: a given point on the sphere of radius one, centered at the origin.
+    // u: returned value [0,1] of angle around the Y axis from X=-1.
+    // v: returned value [0,1] of angle from Y=-1 to Y=+1.
+    //     <1 0 0> yields <0.50 0.50>       <-1  0  0> yields <0.00 0.50>
+    //     <0 1 0> yields <0.50 1.00>       < 0 -1  0> yields <0.50 0.00>
+    //     <0 0 1> yields <0.25 0.50>       < 0  0 -1> yields <0.75 0.50>
+
+    let theta = (-p.y()).acos();
+    let phi = (-p.z()).atan2(p.x()) + std::f64::consts::PI;
+
+    (phi / (2.0 * std::f64::consts::PI), theta / std::f64::consts::PI)
+}
+
+pub(crate) fn sphere_derivatives(p: &Vec3, radius: f64) -> (Vec3, Vec3) {
+    // Derivatives of the point along u and v of `sphere_uv`, for the point p on the unit sphere
+    // scaled by the radius. Both are left zero at the poles, where they degenerate.
+    let s = p.x().hypot(p.z());
+    if s < 1e-12 {
+        return (Vec3::default(), Vec3::default());
+    }
+    let dpdu = 2.0 * std::f64::consts::PI * radius * vec3(p.z(), 0.0, -p.x());
+    let dpdv = std::f64::consts::PI * radius * vec3(-p.x() * p.y() / s, s, -p.y() * p.z() / s);
+    (dpdu, dpdv)
 }
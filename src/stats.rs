@@ -0,0 +1,300 @@
+use std::cell::RefCell;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::color::color;
+use crate::image::Image;
+
+// Render statistics. The counters are kept per thread and are cheap enough to be always on,
+// they add up everything rendered on the thread since the last reset, which every render does
+// when it starts. They only cover the
+// whole render because rendering runs on a single thread, rendering on worker threads would
+// need to collect their counters after the render.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RayKind {
+    Primary,   // Camera rays
+    Secondary, // Scattered rays continuing a path
+    Shadow,    // Visibility tests towards lights
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Primitive {
+    Sphere,
+    Quadric,
+    Triangle,
+    Sdf,
+    Csg,
+    Volume,
+}
+
+pub const ALL_PRIMITIVES: [Primitive; 6] = [
+    Primitive::Sphere,
+    Primitive::Quadric,
+    Primitive::Triangle,
+    Primitive::Sdf,
+    Primitive::Csg,
+    Primitive::Volume,
+];
+
+impl Primitive {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Primitive::Sphere => "sphere",
+            Primitive::Quadric => "quadric",
+            Primitive::Triangle => "triangle",
+            Primitive::Sdf => "sdf",
+            Primitive::Csg => "csg",
+            Primitive::Volume => "volume",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    SceneBuild,
+    Render,
+    Output,
+}
+
+pub const ALL_PHASES: [Phase; 3] = [Phase::SceneBuild, Phase::Render, Phase::Output];
+
+impl Phase {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Phase::SceneBuild => "scene_build",
+            Phase::Render => "render",
+            Phase::Output => "output",
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct RenderStats {
+    pub primary_rays: u64,
+    pub secondary_rays: u64,
+    pub shadow_rays: u64,
+    pub intersection_tests: [u64; ALL_PRIMITIVES.len()],
+    pub bvh_node_visits: u64,
+    pub paths: u64,
+    pub path_segments: u64, // Rays traced over all paths, for the average path length
+    pub phase_times: [Duration; ALL_PHASES.len()],
+}
+
+thread_local! {
+    static STATS: RefCell<RenderStats> = RefCell::new(RenderStats::default());
+}
+
+pub fn count_ray(kind: RayKind) {
+    STATS.with(|s| {
+        let mut s = s.borrow_mut();
+        match kind {
+            RayKind::Primary => s.primary_rays += 1,
+            RayKind::Secondary => s.secondary_rays += 1,
+            RayKind::Shadow => s.shadow_rays += 1,
+        }
+    });
+}
+
+pub fn count_intersection_test(primitive: Primitive) {
+    STATS.with(|s| s.borrow_mut().intersection_tests[primitive as usize] += 1);
+}
+
+pub fn count_bvh_node_visit() {
+    STATS.with(|s| s.borrow_mut().bvh_node_visits += 1);
+}
+
+pub fn count_path(segments: usize) {
+    STATS.with(|s| {
+        let mut s = s.borrow_mut();
+        s.paths += 1;
+        s.path_segments += segments as u64;
+    });
+}
+
+pub fn record_phase(phase: Phase, duration: Duration) {
+    STATS.with(|s| s.borrow_mut().phase_times[phase as usize] += duration);
+}
+
+pub fn render_stats() -> RenderStats {
+    STATS.with(|s| s.borrow().clone())
+}
+
+pub fn reset_stats() {
+    // Starts counting anew, the time spent building the scene for the next render is kept.
+    STATS.with(|s| {
+        let mut s = s.borrow_mut();
+        let scene_build = s.phase_times[Phase::SceneBuild as usize];
+        *s = RenderStats::default();
+        s.phase_times[Phase::SceneBuild as usize] = scene_build;
+    });
+}
+
+pub fn traversal_cost() -> u64 {
+    // Work spent on finding intersections so far, used for the cost of single samples.
+    STATS.with(|s| {
+        let s = s.borrow();
+        s.intersection_tests.iter().sum::<u64>() + s.bvh_node_visits
+    })
+}
+
+impl RenderStats {
+    pub fn rays(&self) -> u64 {
+        self.primary_rays + self.secondary_rays + self.shadow_rays
+    }
+
+    pub fn average_path_length(&self) -> f64 {
+        if self.paths == 0 {
+            return 0.0;
+        }
+        self.path_segments as f64 / self.paths as f64
+    }
+
+    pub fn rays_per_second(&self) -> f64 {
+        let seconds = self.phase_times[Phase::Render as usize].as_secs_f64();
+        if seconds == 0.0 {
+            return 0.0;
+        }
+        self.rays() as f64 / seconds
+    }
+
+    pub fn summary(&self) -> String {
+        let mut s = String::from("Render statistics\n");
+        for phase in ALL_PHASES {
+            let seconds = self.phase_times[phase as usize].as_secs_f64();
+            let _ = writeln!(s, "  {:<22}{:>14.3} s", format!("time {}", phase.name()), seconds);
+        }
+        let rows = [
+            ("primary rays", self.primary_rays),
+            ("secondary rays", self.secondary_rays),
+            ("shadow rays", self.shadow_rays),
+            ("bvh node visits", self.bvh_node_visits),
+        ];
+        for (name, value) in rows {
+            let _ = writeln!(s, "  {:<22}{:>14}", name, value);
+        }
+        let _ = writeln!(s, "  {:<22}{:>14.0}", "rays per second", self.rays_per_second());
+        let _ = writeln!(s, "  {:<22}{:>14.3}", "average path length", self.average_path_length());
+        let _ = writeln!(s, "  intersection tests");
+        for primitive in ALL_PRIMITIVES {
+            let _ = writeln!(s, "    {:<20}{:>14}", primitive.name(), self.intersection_tests[primitive as usize]);
+        }
+        s
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut s = String::from("{\n");
+        let _ = writeln!(s, "  \"primary_rays\": {},", self.primary_rays);
+        let _ = writeln!(s, "  \"secondary_rays\": {},", self.secondary_rays);
+        let _ = writeln!(s, "  \"shadow_rays\": {},", self.shadow_rays);
+        let _ = writeln!(s, "  \"bvh_node_visits\": {},", self.bvh_node_visits);
+        let _ = writeln!(s, "  \"paths\": {},", self.paths);
+        let _ = writeln!(s, "  \"average_path_length\": {},", self.average_path_length());
+        let _ = writeln!(s, "  \"rays_per_second\": {},", self.rays_per_second());
+
+        let tests: Vec<String> = ALL_PRIMITIVES
+            .iter()
+            .map(|p| format!("\"{}\": {}", p.name(), self.intersection_tests[*p as usize]))
+            .collect();
+        let _ = writeln!(s, "  \"intersection_tests\": {{{}}},", tests.join(", "));
+
+        let times: Vec<String> = ALL_PHASES
+            .iter()
+            .map(|p| format!("\"{}\": {}", p.name(), self.phase_times[*p as usize].as_secs_f64()))
+            .collect();
+        let _ = writeln!(s, "  \"phase_seconds\": {{{}}}", times.join(", "));
+        s.push_str("}\n");
+        s
+    }
+
+    pub fn write_json<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_json())
+    }
+}
+
+/// What to report about a render, next to the image.
+#[derive(Debug, Clone)]
+pub struct StatsOptions {
+    pub summary: bool,           // Print the statistics to stderr
+    pub json: Option<String>,    // Path of a JSON report
+    pub heatmap: Option<String>, // Path of a false color image of the traversal cost per pixel
+}
+
+impl Default for StatsOptions {
+    fn default() -> Self {
+        Self {
+            summary: true,
+            json: None,
+            heatmap: None,
+        }
+    }
+}
+
+pub fn heatmap(cost: &Image) -> Image {
+    // Maps the cost per pixel, normalized by the most expensive pixel, from dark blue over red
+    // to yellow and white.
+    let max = cost.pixels.iter().map(|c| c.x()).fold(0.0, f64::max);
+    let ramp = [
+        color(0.0, 0.0, 0.1),
+        color(0.1, 0.1, 0.8),
+        color(0.9, 0.1, 0.2),
+        color(1.0, 0.8, 0.0),
+        color(1.0, 1.0, 1.0),
+    ];
+
+    let mut image = Image::new(cost.width, cost.height);
+    for (pixel, c) in image.pixels.iter_mut().zip(cost.pixels.iter()) {
+        let t = if max > 0.0 { c.x() / max } else { 0.0 };
+        let x = t * (ramp.len() - 1) as f64;
+        let index = (x as usize).min(ramp.len() - 2);
+        let f = x - index as f64;
+        let display = (1.0 - f) * ramp[index] + f * ramp[index + 1];
+        // The ramp is given in display values, write_ppm applies the display gamma.
+        *pixel = display * display;
+    }
+    image
+}
+
+#[test]
+fn render_stats_test() {
+    use crate::camera::Camera;
+    use crate::hittable::Hittable;
+    use crate::hittable_list::HittableList;
+    use crate::material::lambertian;
+    use crate::sphere::sphere;
+    use crate::vec3::*;
+
+    let material = lambertian(color(0.5, 0.5, 0.5));
+    let objects: Vec<Box<dyn Hittable>> = vec![
+        Box::new(sphere(&point3(0.0, 0.0, -1.0), 0.5, &material)),
+        Box::new(sphere(&point3(0.0, -100.5, -1.0), 100.0, &material)),
+    ];
+    let world = HittableList::new(objects);
+
+    let mut cam = Camera::default();
+    cam.image_width = 8;
+    cam.samples_per_pixel = 4;
+    cam.seed = Some(1);
+
+    // Every render counts on its own, after the scene it renders was built.
+    cam.render_buffers(&world);
+    record_phase(Phase::SceneBuild, Duration::from_millis(5));
+    cam.render_buffers(&world);
+    let stats = render_stats();
+    assert_eq!(stats.phase_times[Phase::SceneBuild as usize], Duration::from_millis(5));
+
+    // Every ray is tested against both spheres, and every path starts with a camera ray.
+    assert_eq!(stats.primary_rays, 8 * 8 * 4);
+    assert_eq!(stats.paths, stats.primary_rays);
+    assert_eq!(stats.path_segments, stats.rays());
+    assert_eq!(stats.intersection_tests[Primitive::Sphere as usize], 2 * stats.rays());
+    assert!(stats.secondary_rays > 0 && stats.average_path_length() > 1.0);
+    assert!(stats.phase_times[Phase::Render as usize] > Duration::ZERO);
+
+    let json = stats.to_json();
+    assert!(json.contains(&format!("\"primary_rays\": {},", 8 * 8 * 4)));
+    assert!(json.contains(&format!("\"sphere\": {},", 2 * stats.rays())));
+}
@@ -0,0 +1,229 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::image::invalid_data;
+use crate::mesh::MeshData;
+use crate::vec3::*;
+
+// Reader for STL meshes in ASCII and binary encoding. STL stores every triangle with its own
+// three vertices, the facet normals are ignored in favour of the winding order.
+
+const HEADER_SIZE: usize = 84;
+const TRIANGLE_SIZE: usize = 50;
+
+pub fn read_stl<P: AsRef<Path>>(path: P) -> io::Result<MeshData> {
+    parse_stl(&fs::read(path)?)
+}
+
+pub fn parse_stl(bytes: &[u8]) -> io::Result<MeshData> {
+    // Binary files may start with "solid" too, so they are recognized by their size.
+    if bytes.len() >= HEADER_SIZE {
+        let count = u32::from_le_bytes([bytes[80], bytes[81], bytes[82], bytes[83]]) as usize;
+        if bytes.len() == HEADER_SIZE + count * TRIANGLE_SIZE {
+            return parse_binary(bytes, count);
+        }
+    }
+
+    let is_ascii = bytes.trim_ascii_start().starts_with(b"solid") && std::str::from_utf8(bytes).is_ok();
+    if is_ascii {
+        return parse_ascii(std::str::from_utf8(bytes).unwrap());
+    }
+    if bytes.len() < HEADER_SIZE {
+        return Err(invalid_data("file is too short for a binary STL header".to_string()));
+    }
+
+    let count = u32::from_le_bytes([bytes[80], bytes[81], bytes[82], bytes[83]]) as usize;
+    Err(invalid_data(format!(
+        "binary STL with {} triangles should have {} bytes, but has {}",
+        count,
+        HEADER_SIZE + count * TRIANGLE_SIZE,
+        bytes.len()
+    )))
+}
+
+fn parse_binary(bytes: &[u8], count: usize) -> io::Result<MeshData> {
+    let mut mesh = MeshData::default();
+    for triangle in bytes[HEADER_SIZE..].chunks_exact(TRIANGLE_SIZE) {
+        let value = |i: usize| {
+            let offset = 4 * i;
+            f32::from_le_bytes([triangle[offset], triangle[offset + 1], triangle[offset + 2], triangle[offset + 3]]) as f64
+        };
+
+        // Skips the normal (values 0-2) and the attribute byte count at the end.
+        let first = mesh.positions.len();
+        for vertex in 1..4 {
+            mesh.positions
+                .push(point3(value(3 * vertex), value(3 * vertex + 1), value(3 * vertex + 2)));
+        }
+        mesh.triangles.push([first, first + 1, first + 2]);
+    }
+    debug_assert_eq!(mesh.triangles.len(), count);
+    Ok(mesh)
+}
+
+fn parse_ascii(text: &str) -> io::Result<MeshData> {
+    let mut tokens = Tokens::new(text);
+    tokens.expect("solid")?;
+
+    // The solid name is optional and may consist of several words on the first line.
+    while tokens.peek().is_some_and(|(line, _)| line == 1) {
+        tokens.next_token()?;
+    }
+
+    let mut mesh = MeshData::default();
+    loop {
+        let (line, token) = tokens.next_token()?;
+        match token {
+            "endsolid" => break,
+            "facet" => {}
+            _ => {
+                return Err(invalid_data(format!(
+                    "line {}: expected 'facet' or 'endsolid', found '{}'",
+                    line, token
+                )))
+            }
+        }
+
+        tokens.expect("normal")?;
+        for _ in 0..3 {
+            tokens.number()?;
+        }
+        tokens.expect("outer")?;
+        tokens.expect("loop")?;
+        let first = mesh.positions.len();
+        for _ in 0..3 {
+            tokens.expect("vertex")?;
+            let (x, y, z) = (tokens.number()?, tokens.number()?, tokens.number()?);
+            mesh.positions.push(point3(x, y, z));
+        }
+        tokens.expect("endloop")?;
+        tokens.expect("endfacet")?;
+        mesh.triangles.push([first, first + 1, first + 2]);
+    }
+    Ok(mesh)
+}
+
+/// Whitespace separated tokens with their line numbers, for error messages.
+struct Tokens<'a> {
+    tokens: std::iter::Peekable<Box<dyn Iterator<Item = (usize, &'a str)> + 'a>>,
+    line_count: usize,
+}
+
+impl<'a> Tokens<'a> {
+    fn new(text: &'a str) -> Tokens<'a> {
+        let tokens: Box<dyn Iterator<Item = (usize, &'a str)> + 'a> = Box::new(
+            text.lines()
+                .enumerate()
+                .flat_map(|(index, line)| line.split_whitespace().map(move |token| (index + 1, token))),
+        );
+        Tokens {
+            tokens: tokens.peekable(),
+            line_count: text.lines().count(),
+        }
+    }
+
+    fn peek(&mut self) -> Option<(usize, &'a str)> {
+        self.tokens.peek().copied()
+    }
+
+    fn next_token(&mut self) -> io::Result<(usize, &'a str)> {
+        self.tokens
+            .next()
+            .ok_or_else(|| invalid_data(format!("line {}: unexpected end of file", self.line_count)))
+    }
+
+    fn expect(&mut self, keyword: &str) -> io::Result<()> {
+        let (line, token) = self.next_token()?;
+        if token != keyword {
+            return Err(invalid_data(format!(
+                "line {}: expected '{}', found '{}'",
+                line, keyword, token
+            )));
+        }
+        Ok(())
+    }
+
+    fn number(&mut self) -> io::Result<f64> {
+        let (line, token) = self.next_token()?;
+        token
+            .parse()
+            .map_err(|_| invalid_data(format!("line {}: invalid number '{}'", line, token)))
+    }
+}
+
+#[cfg(test)]
+const ASCII_TETRAHEDRON: &str = "solid tetra hedron
+facet normal 0 0 -1
+  outer loop
+    vertex 0 0 0
+    vertex 0 1 0
+    vertex 1 0 0
+  endloop
+endfacet
+facet normal 0 -1 0
+  outer loop
+    vertex 0 0 0
+    vertex 1 0 0
+    vertex 0 0 1
+  endloop
+endfacet
+facet normal -1 0 0
+  outer loop
+    vertex 0 0 0
+    vertex 0 0 1
+    vertex 0 1 0
+  endloop
+endfacet
+facet normal 1 1 1
+  outer loop
+    vertex 1 0 0
+    vertex 0 1 0
+    vertex 0 0 1
+  endloop
+endfacet
+endsolid tetra hedron
+";
+
+#[test]
+fn parse_stl_test() {
+    let mesh = parse_stl(ASCII_TETRAHEDRON.as_bytes()).unwrap();
+    assert_eq!(mesh.positions.len(), 12);
+    assert_eq!(mesh.triangles.len(), 4);
+    assert_eq!(mesh.positions[11], point3(0.0, 0.0, 1.0));
+    assert_eq!(mesh.triangles[3], [9, 10, 11]);
+
+    // The same triangles in binary, with a header starting with "solid" as some exporters do.
+    let mut binary = b"solid exported in binary".to_vec();
+    binary.resize(80, b' ');
+    binary.extend(4u32.to_le_bytes());
+    for triangle in mesh.triangles.iter() {
+        binary.extend([0u8; 12]);
+        for &vertex in triangle.iter() {
+            let p = mesh.positions[vertex];
+            for c in [p.x(), p.y(), p.z()] {
+                binary.extend((c as f32).to_le_bytes());
+            }
+        }
+        binary.extend([0u8; 2]);
+    }
+    let binary_mesh = parse_stl(&binary).unwrap();
+    assert_eq!(binary_mesh.positions, mesh.positions);
+    assert_eq!(binary_mesh.triangles, mesh.triangles);
+
+    let error = |bytes: &[u8]| parse_stl(bytes).unwrap_err().to_string();
+    assert_eq!(
+        error(&binary[..binary.len() - 10]),
+        "binary STL with 4 triangles should have 284 bytes, but has 274"
+    );
+    let truncated = &ASCII_TETRAHEDRON[..ASCII_TETRAHEDRON.find("endfacet\nendsolid").unwrap()];
+    assert_eq!(error(truncated.as_bytes()), "line 28: unexpected end of file");
+    assert_eq!(
+        error(ASCII_TETRAHEDRON.replacen("vertex 0 1 0", "vertex 0 1", 1).as_bytes()),
+        "line 6: invalid number 'vertex'"
+    );
+    assert_eq!(
+        error(ASCII_TETRAHEDRON.replacen("endloop", "end loop", 1).as_bytes()),
+        "line 7: expected 'endloop', found 'end'"
+    );
+}
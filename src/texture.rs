@@ -0,0 +1,104 @@
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::color::color;
+use crate::hittable::HitRecord;
+use crate::image::Image;
+use crate::vec3::Vec3;
+use crate::volume::{grid_coordinates, Grid};
+
+pub fn solid_color(albedo: Vec3) -> Texture {
+    Texture::Solid(albedo)
+}
+
+pub fn scalar_texture(value: f64) -> Texture {
+    Texture::Solid(color(value, value, value))
+}
+
+pub fn vertex_color() -> Texture {
+    Texture::VertexColor
+}
+
+pub fn image_texture(image: Arc<Image>, factor: Vec3) -> Texture {
+    Texture::Image { image, factor }
+}
+
+/// Color of a surface, looked up for each hit.
+#[derive(Debug, Clone)]
+pub enum Texture {
+    Solid(Vec3),
+    // Colors interpolated from the vertices of a mesh, white for shapes without vertex colors.
+    VertexColor,
+    // Linear color image mapped by the surface coordinates, with v pointing up, and multiplied
+    // by a constant factor.
+    Image { image: Arc<Image>, factor: Vec3 },
+    // Values of a grid stretched over a box, looked up at the hit position and multiplied by a
+    // constant factor.
+    Grid { grid: Arc<Grid>, bounds: Aabb, factor: Vec3 },
+}
+
+impl Default for Texture {
+    fn default() -> Self {
+        Texture::Solid(color(0.0, 0.0, 0.0))
+    }
+}
+
+impl Texture {
+    pub fn value(&self, rec: &HitRecord) -> Vec3 {
+        match self {
+            Texture::Solid(albedo) => *albedo,
+            Texture::VertexColor => rec.vertex_color,
+            Texture::Image { image, factor } => *factor * sample_bilinear(image, rec.u, rec.v),
+            Texture::Grid { grid, bounds, factor } => grid.value(&grid_coordinates(bounds, &rec.p)) * *factor,
+        }
+    }
+}
+
+fn sample_bilinear(image: &Image, u: f64, v: f64) -> Vec3 {
+    // Bilinear interpolation between the pixel centers, the image repeats in both directions.
+    if image.width == 0 || image.height == 0 {
+        return color(1.0, 0.0, 1.0);
+    }
+
+    let x = u * image.width as f64 - 0.5;
+    let y = (1.0 - v) * image.height as f64 - 0.5;
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+
+    let pixel = |i: f64, j: f64| {
+        let i = (i as i64).rem_euclid(image.width as i64) as usize;
+        let j = (j as i64).rem_euclid(image.height as i64) as usize;
+        image.get(i, j)
+    };
+
+    let top = (1.0 - fx) * pixel(x0, y0) + fx * pixel(x0 + 1.0, y0);
+    let bottom = (1.0 - fx) * pixel(x0, y0 + 1.0) + fx * pixel(x0 + 1.0, y0 + 1.0);
+    (1.0 - fy) * top + fy * bottom
+}
+
+#[test]
+fn image_texture_test() {
+    let mut image = Image::new(2, 2);
+    image.set(0, 0, color(1.0, 0.0, 0.0));
+    image.set(1, 0, color(0.0, 1.0, 0.0));
+    image.set(0, 1, color(0.0, 0.0, 1.0));
+    image.set(1, 1, color(1.0, 1.0, 1.0));
+    let texture = image_texture(Arc::new(image), color(1.0, 1.0, 0.5));
+
+    let value = |u: f64, v: f64| {
+        let rec = HitRecord {
+            u,
+            v,
+            ..HitRecord::default()
+        };
+        texture.value(&rec)
+    };
+
+    // Pixel centers, with the first row at the top, and the middle between all four pixels.
+    assert_eq!(value(0.25, 0.75), color(1.0, 0.0, 0.0));
+    assert_eq!(value(0.75, 0.25), color(1.0, 1.0, 0.5));
+    assert_eq!(value(0.5, 0.5), color(0.5, 0.5, 0.25));
+
+    // Wrapping around the left edge blends the first and the last column.
+    assert_eq!(value(0.0, 0.75), color(0.5, 0.5, 0.0));
+}
@@ -105,6 +105,18 @@ impl ops::Mul<Vec3> for Vec3 {
     }
 }
 
+impl ops::Div<Vec3> for Vec3 {
+    type Output = Vec3;
+
+    fn div(self, _rhs: Vec3) -> Vec3 {
+        Vec3 {
+            x: self.x / _rhs.x,
+            y: self.y / _rhs.y,
+            z: self.z / _rhs.z,
+        }
+    }
+}
+
 // Vector functions for Unit Operations
 
 impl ops::Sub<f64> for Vec3 {
@@ -267,6 +279,17 @@ pub fn refract(uv: &Vec3, n: &Vec3, etai_over_etat: f64) -> Vec3 {
     r_out_perp + r_out_parallel
 }
 
+pub fn direction_around(axis: &Vec3, cos_theta: f64, phi: f64) -> Vec3 {
+    // Unit vector at the polar angle with cosine `cos_theta` from `axis` and the azimuth `phi`
+    // around it.
+    let w = unit_vector(axis);
+    let helper = if w.x().abs() > 0.9 { vec3(0.0, 1.0, 0.0) } else { vec3(1.0, 0.0, 0.0) };
+    let u = unit_vector(&cross(&helper, &w));
+    let v = cross(&w, &u);
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    sin_theta * phi.cos() * u + sin_theta * phi.sin() * v + cos_theta * w
+}
+
 // Vector Builder (for using Builder Pattern)
 
 #[derive(Default)]
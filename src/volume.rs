@@ -0,0 +1,327 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::color::color;
+use crate::hittable::{HitRecord, Hittable, SceneIndex};
+use crate::image::{invalid_data, next_token, parse_usize};
+use crate::interval::Interval;
+use crate::material::volume_scattering;
+use crate::medium::PhaseFunction;
+use crate::ray::Ray;
+use crate::rtweekend::random_double;
+use crate::stats::{count_intersection_test, Primitive};
+use crate::texture::Texture;
+use crate::vec3::*;
+use crate::Material;
+
+/// Scalar values on a regular 3D grid, e.g. the density of smoke or the temperature of fire.
+/// Voxel (i, j, k) is at `values[i + nx * (j + ny * k)]`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Grid {
+    pub nx: usize,
+    pub ny: usize,
+    pub nz: usize,
+    values: Vec<f32>,
+    max: f64,
+}
+
+impl Grid {
+    pub fn new(nx: usize, ny: usize, nz: usize, values: Vec<f32>) -> io::Result<Grid> {
+        if values.len() != voxel_count(nx, ny, nz)? {
+            return Err(invalid_data(format!("{} values for a {}x{}x{} grid", values.len(), nx, ny, nz)));
+        }
+        let max = values.iter().fold(0.0, |max: f64, v| max.max(*v as f64));
+        Ok(Grid { nx, ny, nz, values, max })
+    }
+
+    pub fn from_fn(nx: usize, ny: usize, nz: usize, f: impl Fn(&Vec3) -> f64) -> io::Result<Grid> {
+        // Samples `f` at the voxel centers in the unit cube.
+        let mut values = Vec::with_capacity(voxel_count(nx, ny, nz)?);
+        for k in 0..nz {
+            for j in 0..ny {
+                for i in 0..nx {
+                    let p = point3(
+                        (i as f64 + 0.5) / nx as f64,
+                        (j as f64 + 0.5) / ny as f64,
+                        (k as f64 + 0.5) / nz as f64,
+                    );
+                    values.push(f(&p) as f32);
+                }
+            }
+        }
+        Grid::new(nx, ny, nz, values)
+    }
+
+    pub fn read(path: impl AsRef<Path>) -> io::Result<Grid> {
+        Grid::parse(&fs::read(path)?)
+    }
+
+    pub fn parse(bytes: &[u8]) -> io::Result<Grid> {
+        // Raw grid file: the header "VOL <nx> <ny> <nz>" in ASCII, a single whitespace and the
+        // values as little endian 32 bit floats, x varying fastest.
+        let mut pos = 0;
+        let magic = next_token(bytes, &mut pos)?;
+        if magic != "VOL" {
+            return Err(invalid_data(format!("unsupported grid format '{}'", magic)));
+        }
+        let nx = parse_usize(&next_token(bytes, &mut pos)?)?;
+        let ny = parse_usize(&next_token(bytes, &mut pos)?)?;
+        let nz = parse_usize(&next_token(bytes, &mut pos)?)?;
+        pos += 1;
+
+        let size = voxel_count(nx, ny, nz)?
+            .checked_mul(4)
+            .ok_or_else(|| invalid_data(format!("{}x{}x{} grid is too large", nx, ny, nz)))?;
+        let raw = bytes
+            .get(pos..pos.saturating_add(size))
+            .ok_or_else(|| invalid_data("truncated grid data".to_string()))?;
+        let values = raw.chunks(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect();
+        Grid::new(nx, ny, nz, values)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = format!("VOL {} {} {}\n", self.nx, self.ny, self.nz).into_bytes();
+        for value in &self.values {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes
+    }
+
+    pub fn max(&self) -> f64 {
+        self.max
+    }
+
+    pub fn value(&self, p: &Vec3) -> f64 {
+        // Trilinear interpolation between the voxel centers for a point in the unit cube,
+        // clamped to the outer voxels at the border.
+        let coordinate = |x: f64, n: usize| {
+            let x = (x * n as f64 - 0.5).clamp(0.0, (n - 1) as f64);
+            let i = (x as usize).min(n.saturating_sub(2));
+            (i, (i + 1).min(n - 1), x - i as f64)
+        };
+        let (i0, i1, fx) = coordinate(p.x(), self.nx);
+        let (j0, j1, fy) = coordinate(p.y(), self.ny);
+        let (k0, k1, fz) = coordinate(p.z(), self.nz);
+        let at = |i: usize, j: usize, k: usize| self.values[i + self.nx * (j + self.ny * k)] as f64;
+        let lerp = |a: f64, b: f64, t: f64| a + t * (b - a);
+
+        let z0 = lerp(
+            lerp(at(i0, j0, k0), at(i1, j0, k0), fx),
+            lerp(at(i0, j1, k0), at(i1, j1, k0), fx),
+            fy,
+        );
+        let z1 = lerp(
+            lerp(at(i0, j0, k1), at(i1, j0, k1), fx),
+            lerp(at(i0, j1, k1), at(i1, j1, k1), fx),
+            fy,
+        );
+        lerp(z0, z1, fz)
+    }
+}
+
+pub(crate) fn grid_coordinates(bounds: &Aabb, p: &Vec3) -> Vec3 {
+    // Position of `p` relative to the box, (0, 0, 0) at its minimum and (1, 1, 1) at the maximum.
+    vec3(
+        (p.x() - bounds.x.min) / bounds.x.size(),
+        (p.y() - bounds.y.min) / bounds.y.size(),
+        (p.z() - bounds.z.min) / bounds.z.size(),
+    )
+}
+
+/// Heterogeneous participating medium like smoke, clouds or fire, with its density stored in a
+/// grid stretched over a box. Rays are tracked through the box with delta tracking, a hit is a
+/// collision with a particle where the light scatters by the phase function.
+pub struct Volume {
+    density: Arc<Grid>,
+    bounds: Aabb,
+    sigma_t: f64, // Extinction coefficient at density 1, in inverse scene units
+    material: Material,
+}
+
+pub fn volume(density: Arc<Grid>, bounds: Aabb, sigma_t: f64, albedo: Vec3, phase: PhaseFunction) -> Volume {
+    Volume {
+        density,
+        bounds,
+        sigma_t,
+        material: volume_scattering(albedo, phase, None),
+    }
+}
+
+pub fn emissive_volume(
+    density: Arc<Grid>,
+    bounds: Aabb,
+    sigma_t: f64,
+    albedo: Vec3,
+    phase: PhaseFunction,
+    emission: Arc<Grid>,
+    radiance: Vec3,
+) -> Volume {
+    // Fire: the absorbing part of the medium emits `radiance` scaled by the emission grid
+    // (e.g. derived from the temperature), which is stretched over the same box.
+    let factor = (color(1.0, 1.0, 1.0) - albedo) * radiance;
+    Volume {
+        density,
+        bounds,
+        sigma_t,
+        material: volume_scattering(
+            albedo,
+            phase,
+            Some(Texture::Grid {
+                grid: emission,
+                bounds,
+                factor,
+            }),
+        ),
+    }
+}
+
+impl Volume {
+    fn span(&self, r: &Ray, ray_t: Interval) -> Option<Interval> {
+        // Part of the ray inside the box.
+        let mut span = ray_t;
+        for axis in 0..3 {
+            let ax = self.bounds.axis_interval(axis);
+            let adinv = 1.0 / r.direction()[axis];
+            let t0 = (ax.min - r.origin()[axis]) * adinv;
+            let t1 = (ax.max - r.origin()[axis]) * adinv;
+            span.min = span.min.max(t0.min(t1));
+            span.max = span.max.min(t0.max(t1));
+            if span.max <= span.min {
+                return None;
+            }
+        }
+        Some(span)
+    }
+
+    fn density_at(&self, p: &Vec3) -> f64 {
+        self.density.value(&grid_coordinates(&self.bounds, p))
+    }
+
+    fn majorant(&self, r: &Ray) -> f64 {
+        // Upper bound of the extinction per unit of the ray parameter.
+        self.sigma_t * self.density.max() * r.direction().length()
+    }
+
+    pub fn transmittance(&self, r: &Ray, ray_t: Interval) -> f64 {
+        // Fraction of light getting through the volume along the ray, estimated by ratio
+        // tracking: the product of the probabilities of the tentative collisions being null
+        // collisions. Meant for shadow rays, which only need the visibility.
+        let Some(span) = self.span(r, ray_t) else {
+            return 1.0;
+        };
+        let majorant = self.majorant(r);
+        if majorant <= 0.0 {
+            return 1.0;
+        }
+        let mut transmittance = 1.0;
+        let mut t = span.min;
+        loop {
+            t -= (1.0 - random_double()).ln() / majorant;
+            if t >= span.max {
+                return transmittance;
+            }
+            transmittance *= 1.0 - self.density_at(&r.at(t)) / self.density.max();
+        }
+    }
+}
+
+impl Hittable for Volume {
+    fn hit(&self, r: &Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        // Delta tracking: tentative collisions are sampled with the constant majorant, each is
+        // a real one with the probability of the density relative to its maximum.
+        count_intersection_test(Primitive::Volume);
+        let Some(span) = self.span(r, ray_t) else {
+            return false;
+        };
+        let majorant = self.majorant(r);
+        if majorant <= 0.0 {
+            return false;
+        }
+        let mut t = span.min;
+        loop {
+            t -= (1.0 - random_double()).ln() / majorant;
+            if t >= span.max {
+                return false;
+            }
+            let p = r.at(t);
+            if random_double() * self.density.max() < self.density_at(&p) {
+                rec.t = t;
+                rec.p = p;
+                // Collisions have no surface, the normal faces the ray for the render passes.
+                rec.set_face_normal(r, &-unit_vector(&r.direction()));
+                (rec.u, rec.v) = (0.0, 0.0);
+                (rec.dpdu, rec.dpdv) = (Vec3::default(), Vec3::default());
+                rec.mat = self.material.clone();
+                rec.light = None;
+                return true;
+            }
+        }
+    }
+
+    fn index_scene(&mut self, scene: &mut SceneIndex) {
+        scene.add_material(&self.material);
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bounds
+    }
+}
+
+fn voxel_count(nx: usize, ny: usize, nz: usize) -> io::Result<usize> {
+    // Grids need at least one voxel along every axis to interpolate between.
+    match nx.checked_mul(ny).and_then(|n| n.checked_mul(nz)) {
+        Some(0) => Err(invalid_data(format!("empty {}x{}x{} grid", nx, ny, nz))),
+        Some(count) => Ok(count),
+        None => Err(invalid_data(format!("{}x{}x{} grid is too large", nx, ny, nz))),
+    }
+}
+
+#[test]
+fn grid_test() {
+    // Trilinear interpolation reproduces linear functions between the outer voxel centers, and
+    // grids survive the round trip through the file format.
+    let grid = Grid::from_fn(4, 3, 2, |p| p.x() + 2.0 * p.y() - p.z()).unwrap();
+    for p in [point3(0.5, 0.5, 0.5), point3(0.2, 0.7, 0.3), point3(0.8, 0.4, 0.6)] {
+        let p = vec3(p.x().clamp(0.125, 0.875), p.y().clamp(1.0 / 6.0, 5.0 / 6.0), p.z().clamp(0.25, 0.75));
+        assert!((grid.value(&p) - (p.x() + 2.0 * p.y() - p.z())).abs() < 1e-6);
+    }
+    assert!((grid.max() - (0.875 + 2.0 * 5.0 / 6.0 - 0.25)).abs() < 1e-6);
+    assert_eq!(Grid::parse(&grid.to_bytes()).unwrap(), grid);
+    assert!(Grid::parse(b"VOL 2 2 2\n1234").is_err());
+    assert!(Grid::parse(b"VOL 0 0 0\n").is_err());
+    assert!(Grid::parse(format!("VOL {} {} 2\n", usize::MAX / 2, usize::MAX / 2).as_bytes()).is_err());
+    assert!(Grid::new(0, 2, 2, Vec::new()).is_err());
+    assert!(Grid::new(2, 2, 2, vec![0.0; 4]).is_err());
+}
+
+#[test]
+fn volume_transmittance_test() {
+    use crate::interval::interval;
+
+    // Through a constant density the fraction of rays without a collision and the ratio
+    // tracking estimate both match Beer's law, exp(-sigma_t * distance). The dense corner away
+    // from the ray raises the majorant.
+    let density = Arc::new(Grid::from_fn(4, 4, 4, |p| if p.x() > 0.75 && p.y() > 0.75 { 2.0 } else { 0.5 }).unwrap());
+    let bounds = Aabb::from_points(&point3(-1.0, -1.0, -1.0), &point3(1.0, 1.0, 1.0));
+    let fog = volume(density, bounds, 1.2, color(0.8, 0.8, 0.8), PhaseFunction::Isotropic);
+    let ray = Ray::new(&point3(0.0, 0.0, 3.0), &vec3(0.0, 0.0, -0.5));
+    let expected = (-1.2 * 0.5 * 2.0f64).exp();
+
+    const N: usize = 20_000;
+    let mut passed = 0;
+    let mut transmittance = 0.0;
+    for _ in 0..N {
+        let mut rec = HitRecord::default();
+        if fog.hit(&ray, interval(0.001, f64::INFINITY), &mut rec) {
+            assert!((rec.p.x().abs() < 1e-9) && rec.p.z() <= 1.0 && rec.p.z() >= -1.0 && rec.front_face);
+        } else {
+            passed += 1;
+        }
+        transmittance += fog.transmittance(&ray, interval(0.001, f64::INFINITY)) / N as f64;
+    }
+    assert!((passed as f64 / N as f64 - expected).abs() < 0.01);
+    assert!((transmittance - expected).abs() < 0.01);
+}